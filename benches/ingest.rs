@@ -0,0 +1,81 @@
+//! Segment ingestion end to end, from the download off a local server to the storages,
+//! see [`emysound_feeder_rs::bench::Ingestion`].
+//!
+//! Reports segments per second, then the heap allocations per segment, those of the local
+//! server and of the runtime included.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use emysound_feeder_rs::bench::Ingestion;
+
+/// Seconds of audio of a segment, as HLS streams usually serve.
+const SEGMENT_SECONDS: u32 = 10;
+
+/// Segments ingested to count allocations of.
+const COUNTED_SEGMENTS: u64 = 100;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// The system allocator, counting allocations.
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn ingest(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut ingestion = runtime.block_on(Ingestion::new(SEGMENT_SECONDS)).unwrap();
+
+    let mut group = c.benchmark_group("ingest");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("segment", |b| {
+        b.iter_custom(|iters| {
+            runtime.block_on(async {
+                let start = Instant::now();
+                for _ in 0..iters {
+                    ingestion.ingest().await.unwrap();
+                }
+                start.elapsed()
+            })
+        })
+    });
+    group.finish();
+
+    let allocations = runtime.block_on(async {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        for _ in 0..COUNTED_SEGMENTS {
+            ingestion.ingest().await.unwrap();
+        }
+        ALLOCATIONS.load(Ordering::Relaxed) - before
+    });
+    println!(
+        "ingest/segment allocations: {} per segment",
+        allocations / COUNTED_SEGMENTS
+    );
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(10));
+    targets = ingest
+}
+criterion_main!(benches);
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use crate::storage::AudioKind;
+
+/// Drops pending segments of low-priority kinds when ingestion falls behind the stream,
+/// so that the kinds that matter keep up.
+pub struct LoadShedder {
+    max_pending: Option<usize>,
+    /// Kinds to drop, the first one dropped first. Kinds not listed are never dropped.
+    drop_order: Vec<AudioKind>,
+    dropped: HashMap<AudioKind, u64>,
+}
+
+impl LoadShedder {
+    pub fn new(max_pending: Option<usize>, drop_order: Vec<AudioKind>) -> Self {
+        Self {
+            max_pending,
+            drop_order,
+            dropped: HashMap::new(),
+        }
+    }
+
+    /// Removes the oldest segments of the kinds to drop until at most `max_pending` remain.
+    pub fn shed<T, F>(&mut self, mut pending: Vec<T>, kind_of: F) -> Vec<T>
+    where
+        F: Fn(&T) -> AudioKind,
+    {
+        let max_pending = match self.max_pending {
+            Some(max_pending) if pending.len() > max_pending => max_pending,
+            _ => return pending,
+        };
+
+        for &kind in &self.drop_order {
+            while pending.len() > max_pending {
+                match pending.iter().position(|item| kind_of(item) == kind) {
+                    Some(index) => {
+                        pending.remove(index);
+                        *self.dropped.entry(kind).or_default() += 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        log::warn!(
+            "Ingestion falls behind, {} segments pending. Dropped so far: {}",
+            pending.len(),
+            self.drop_order
+                .iter()
+                .map(|kind| format!(
+                    "{} {}",
+                    self.dropped.get(kind).copied().unwrap_or_default(),
+                    kind.to_string()
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LoadShedder;
+    use crate::storage::AudioKind;
+
+    #[test]
+    fn test() {
+        use AudioKind::*;
+
+        let mut shedder = LoadShedder::new(Some(3), vec![Advertisement, Talk]);
+        let pending = vec![Music, Advertisement, Talk, Music, Advertisement, Talk];
+
+        assert_eq!(
+            shedder.shed(pending, |kind| *kind),
+            vec![Music, Music, Talk]
+        );
+        assert_eq!(
+            shedder.shed(vec![Music, Music, Music, Music], |kind| *kind),
+            vec![Music, Music, Music, Music]
+        );
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let mut shedder = LoadShedder::new(None, vec![AudioKind::Advertisement]);
+        let pending = vec![AudioKind::Advertisement; 100];
+        assert_eq!(shedder.shed(pending, |kind| *kind).len(), 100);
+    }
+}
@@ -0,0 +1,154 @@
+//! Ingestion of a segment end to end, for the benchmarks in `benches/`.
+
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use clap::Parser;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+use crate::emysound::{Inserted, QueryResult, TrackInfo};
+use crate::fingerprinter::Fingerprinter;
+use crate::segment_filter::SegmentKey;
+use crate::segment_info::SuggestedSegmentContentKind;
+use crate::storage::{
+    AudioStorage, DiagnosticsStorage, IdMapStorage, KindSource, MatchesStorage, MetadataStorage,
+};
+use crate::wav;
+use crate::{http_client, ingest_segment, Args, IngestState, SegmentDownloadInfo, Storages};
+
+/// sqlite keeps a database of this name in memory only.
+const IN_MEMORY: &str = ":memory:";
+
+const SAMPLE_RATE: u32 = 8000;
+
+/// A music segment served by a local HTTP server and ingested into storages in memory.
+/// emysound is stubbed out, it matches nothing and takes every insert, so each segment is
+/// inserted and stored as new audio.
+pub struct Ingestion {
+    args: Args,
+    client: reqwest::Client,
+    storages: Storages,
+    state: IngestState,
+    info: SegmentDownloadInfo,
+    shutdown: CancellationToken,
+}
+
+impl Ingestion {
+    /// Starts serving a segment of `seconds` of synthetic audio, within the async runtime.
+    pub async fn new(seconds: u32) -> Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await?;
+        let url = format!("http://{}/live/segment.wav", listener.local_addr()?).parse()?;
+        tokio::spawn(serve(listener, Bytes::from(sine_wav(seconds))));
+
+        let args = Args::try_parse_from(["feeder", "http://127.0.0.1/live.m3u8"])?;
+        let client = http_client(&args)?;
+        let storages = Storages {
+            metadata: MetadataStorage::new(&Path::new(IN_MEMORY))?,
+            audio: Box::new(AudioStorage::new(&Path::new(IN_MEMORY))?),
+            matches: MatchesStorage::new(&Path::new(IN_MEMORY))?,
+            id_map: IdMapStorage::new(&Path::new(IN_MEMORY))?,
+            diagnostics: DiagnosticsStorage::new(&Path::new(IN_MEMORY))?,
+        };
+        let state = IngestState::new(&args, &client);
+        let info = SegmentDownloadInfo {
+            key: SegmentKey::new(0, "segment.wav"),
+            url,
+            artist: "Daft Punk".to_owned(),
+            title: "Da Funk".to_owned(),
+            kind: SuggestedSegmentContentKind::Music,
+            kind_source: Some(KindSource::Metadata),
+            duration: Duration::from_secs(seconds.into()),
+            ad_context: None,
+            ids: Default::default(),
+            stream_id: "bench".to_owned(),
+            discontinuity_sequence: 0,
+            discontinuity: false,
+            attributes: Default::default(),
+            album: None,
+            year: None,
+            init_url: None,
+            extension: None,
+            variant: None,
+        };
+
+        Ok(Self {
+            args,
+            client,
+            storages,
+            state,
+            info,
+            shutdown: CancellationToken::new(),
+        })
+    }
+
+    /// Downloads, fingerprints and stores the segment once more.
+    pub async fn ingest(&mut self) -> Result<()> {
+        ingest_segment(
+            &self.args,
+            &self.client,
+            &Unmatched,
+            &self.storages,
+            &mut self.state,
+            &self.info,
+            &self.shutdown,
+        )
+        .await
+    }
+}
+
+/// A fingerprinter which knows no track and learns none.
+struct Unmatched;
+
+#[async_trait]
+impl Fingerprinter for Unmatched {
+    async fn query(&self, _filename: &str, _bytes: &Bytes) -> Result<Vec<QueryResult>> {
+        Ok(Vec::new())
+    }
+
+    async fn insert(&self, _info: TrackInfo, _filename: &str, _bytes: &Bytes) -> Result<Inserted> {
+        Ok(Inserted::New)
+    }
+}
+
+/// Answers every request on `listener` with `body`, keeping connections alive as reqwest
+/// pools them.
+async fn serve(listener: TcpListener, body: Bytes) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(respond(stream, body.clone()));
+    }
+}
+
+async fn respond(mut stream: TcpStream, body: Bytes) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    );
+    let mut request = Vec::new();
+    let mut byte = [0];
+    while stream.read(&mut byte).await? == 1 {
+        request.push(byte[0]);
+        if request.ends_with(b"\r\n\r\n") {
+            stream.write_all(head.as_bytes()).await?;
+            stream.write_all(&body).await?;
+            request.clear();
+        }
+    }
+    Ok(())
+}
+
+/// 16-bit mono WAV of a 440 Hz tone.
+fn sine_wav(seconds: u32) -> Vec<u8> {
+    let samples: Vec<i16> = (0..SAMPLE_RATE * seconds)
+        .map(|n| {
+            let phase = 2.0 * std::f64::consts::PI * 440.0 * f64::from(n) / f64::from(SAMPLE_RATE);
+            (phase.sin() * 0.5 * f64::from(i16::MAX)) as i16
+        })
+        .collect();
+    wav::pcm16(SAMPLE_RATE, 1, &samples)
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+
+use crate::storage::AudioKind;
+
+/// Caps bytes of audio stored per kind within a period, e.g. ads to 500MB a day.
+pub struct ByteBudgets {
+    limits: HashMap<AudioKind, u64>,
+    period: Duration,
+    period_start: Instant,
+    spent: HashMap<AudioKind, u64>,
+}
+
+impl ByteBudgets {
+    /// Kinds without a limit are not capped.
+    pub fn new(limits: &[(AudioKind, u64)], period: Duration) -> Self {
+        Self {
+            limits: limits.iter().copied().collect(),
+            period,
+            period_start: Instant::now(),
+            spent: HashMap::new(),
+        }
+    }
+
+    /// Counts `bytes` against the budget of `kind`, or returns `false` if they don't fit.
+    pub fn try_spend(&mut self, kind: AudioKind, bytes: u64) -> bool {
+        if self.period_start.elapsed() >= self.period {
+            self.period_start = Instant::now();
+            self.spent.clear();
+        }
+
+        let spent = self.spent.entry(kind).or_default();
+        match self.limits.get(&kind) {
+            Some(&limit) if *spent + bytes > limit => false,
+            _ => {
+                *spent += bytes;
+                true
+            }
+        }
+    }
+}
+
+/// Parses `KIND=SIZE`, e.g. `advertisement=500M`, see [`parse_size`].
+pub fn parse_byte_budget(value: &str) -> anyhow::Result<(AudioKind, u64)> {
+    let (kind, size) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected KIND=SIZE"))?;
+
+    Ok((kind.trim().try_into()?, parse_size(size)?))
+}
+
+/// Parses a size in bytes with an optional K, M or G suffix, e.g. `500M`.
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let size = size.trim().to_uppercase();
+    let size = size.strip_suffix('B').unwrap_or(&size);
+    let (digits, multiplier) = match size.chars().last() {
+        Some('K') => (&size[..size.len() - 1], 1 << 10),
+        Some('M') => (&size[..size.len() - 1], 1 << 20),
+        Some('G') => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    let size: u64 = digits
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid size `{digits}`"))?;
+
+    size.checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("Size `{size}` too large"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_byte_budget, parse_size, ByteBudgets};
+    use crate::storage::AudioKind;
+
+    #[test]
+    fn test() {
+        let mut budgets = ByteBudgets::new(
+            &[(AudioKind::Advertisement, 100)],
+            Duration::from_secs(3600),
+        );
+        assert!(budgets.try_spend(AudioKind::Advertisement, 60));
+        assert!(!budgets.try_spend(AudioKind::Advertisement, 60));
+        assert!(budgets.try_spend(AudioKind::Advertisement, 40));
+        assert!(budgets.try_spend(AudioKind::Music, 1_000_000));
+    }
+
+    #[test]
+    fn test_period() {
+        let mut budgets = ByteBudgets::new(&[(AudioKind::Talk, 10)], Duration::ZERO);
+        assert!(budgets.try_spend(AudioKind::Talk, 10));
+        assert!(budgets.try_spend(AudioKind::Talk, 10));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_byte_budget("advertisement=500MB").unwrap(),
+            (AudioKind::Advertisement, 500 << 20)
+        );
+        assert_eq!(
+            parse_byte_budget("music=2g").unwrap(),
+            (AudioKind::Music, 2 << 30)
+        );
+        assert_eq!(
+            parse_byte_budget("talk=123").unwrap(),
+            (AudioKind::Talk, 123)
+        );
+        assert!(parse_byte_budget("talk").is_err());
+        assert!(parse_byte_budget("jazz=1M").is_err());
+        assert!(parse_byte_budget("talk=lots").is_err());
+        assert_eq!(parse_size(" 16k ").unwrap(), 16 << 10);
+        assert!(parse_size("17179869184G").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::emysound::{Inserted, QueryResult, TrackInfo};
+use crate::fingerprinter::Fingerprinter;
+
+/// The error of a call skipped while the circuit is open, told from failures by downcasting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CircuitOpen;
+
+impl Display for CircuitOpen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("emysound is unavailable, the circuit is open")
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A single trial call is in flight, the others are skipped meanwhile.
+    HalfOpen,
+}
+
+/// Stops calling a fingerprinter that keeps failing, see `--emysound-failure-threshold`.
+///
+/// After `threshold` failures in a row the circuit opens, and calls fail with
+/// [`CircuitOpen`] for `cooldown`. The first call after lets a trial through, which closes
+/// the circuit if it succeeds and opens it again if it fails.
+pub struct CircuitBreaker {
+    inner: Box<dyn Fingerprinter>,
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(inner: Box<dyn Fingerprinter>, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            inner,
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    fn admit(&self) -> anyhow::Result<Admitted<'_>> {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => {}
+            State::Open { until } if Instant::now() >= until => {
+                log::info!("emysound circuit half-open, trying one call");
+                *state = State::HalfOpen;
+            }
+            State::Open { .. } | State::HalfOpen => return Err(anyhow::Error::msg(CircuitOpen)),
+        }
+
+        Ok(Admitted {
+            breaker: self,
+            recorded: false,
+        })
+    }
+
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (*state, success) {
+            (State::HalfOpen, true) => {
+                log::info!("emysound circuit closed, the trial call succeeded");
+                State::Closed { failures: 0 }
+            }
+            (_, true) => State::Closed { failures: 0 },
+            (State::HalfOpen, false) => {
+                log::warn!(
+                    "emysound circuit open again for {:?}, the trial call failed",
+                    self.cooldown
+                );
+                self.open()
+            }
+            (State::Closed { failures }, false) if failures + 1 >= self.threshold => {
+                log::warn!(
+                    "emysound circuit open for {:?} after {} failures in a row",
+                    self.cooldown,
+                    failures + 1
+                );
+                self.open()
+            }
+            (State::Closed { failures }, false) => State::Closed {
+                failures: failures + 1,
+            },
+            // A call admitted before the circuit opened.
+            (open @ State::Open { .. }, false) => open,
+        };
+    }
+
+    fn open(&self) -> State {
+        State::Open {
+            until: Instant::now() + self.cooldown,
+        }
+    }
+}
+
+/// A call let through, recorded as a failure if it is dropped unfinished, e.g. timed out.
+struct Admitted<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl Admitted<'_> {
+    fn record<T>(mut self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        self.recorded = true;
+        self.breaker.record(result.is_ok());
+        result
+    }
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker.record(false);
+        }
+    }
+}
+
+#[async_trait]
+impl Fingerprinter for CircuitBreaker {
+    async fn query(&self, filename: &str, bytes: &Bytes) -> anyhow::Result<Vec<QueryResult>> {
+        let admitted = self.admit()?;
+        admitted.record(self.inner.query(filename, bytes).await)
+    }
+
+    async fn insert(
+        &self,
+        info: TrackInfo,
+        filename: &str,
+        bytes: &Bytes,
+    ) -> anyhow::Result<Inserted> {
+        let admitted = self.admit()?;
+        admitted.record(self.inner.insert(info, filename, bytes).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+    use async_trait::async_trait;
+    use bytes::Bytes;
+    use uuid::Uuid;
+
+    use super::{CircuitBreaker, CircuitOpen};
+    use crate::emysound::{Inserted, QueryResult, TrackInfo};
+    use crate::fingerprinter::Fingerprinter;
+
+    #[derive(Default)]
+    struct Flaky {
+        down: AtomicBool,
+        calls: AtomicU32,
+    }
+
+    impl Flaky {
+        fn call<T>(&self, value: T) -> anyhow::Result<T> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                Err(anyhow!("down"))
+            } else {
+                Ok(value)
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Fingerprinter for Arc<Flaky> {
+        async fn query(&self, _: &str, _: &Bytes) -> anyhow::Result<Vec<QueryResult>> {
+            self.call(Vec::new())
+        }
+
+        async fn insert(&self, _: TrackInfo, _: &str, _: &Bytes) -> anyhow::Result<Inserted> {
+            self.call(Inserted::New)
+        }
+    }
+
+    fn is_open<T: std::fmt::Debug>(result: anyhow::Result<T>) -> bool {
+        result.unwrap_err().is::<CircuitOpen>()
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let flaky = Arc::new(Flaky::default());
+        let breaker = CircuitBreaker::new(Box::new(flaky.clone()), 2, Duration::from_millis(50));
+        let segment = Bytes::new();
+        let query = || breaker.query("segment.aac", &segment);
+
+        flaky.down.store(true, Ordering::SeqCst);
+        assert!(!is_open(query().await));
+        assert!(!is_open(query().await));
+        // Open, emysound isn't called.
+        assert!(is_open(query().await));
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 2);
+
+        // A failed trial opens it again.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!is_open(query().await));
+        assert!(is_open(query().await));
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 3);
+
+        flaky.down.store(false, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(query().await.is_ok());
+        assert!(query().await.is_ok());
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 5);
+
+        // Inserts count towards the same circuit.
+        flaky.down.store(true, Ordering::SeqCst);
+        let info = || TrackInfo::new(Uuid::new_v4(), "Artist".to_owned(), "Title".to_owned());
+        assert!(!is_open(
+            breaker.insert(info(), "segment.aac", &segment).await
+        ));
+        assert!(!is_open(query().await));
+        assert!(is_open(
+            breaker.insert(info(), "segment.aac", &segment).await
+        ));
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 7);
+    }
+}
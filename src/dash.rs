@@ -0,0 +1,493 @@
+use std::fmt::Write;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use roxmltree::Node;
+
+/// How far back a live manifest without a timeline is listed, unless it says otherwise.
+const DEFAULT_TIME_SHIFT_BUFFER_SECS: f64 = 30.0;
+
+/// Whether a playlist response is an MPEG-DASH manifest rather than an HLS playlist.
+pub fn is_dash(content_type: Option<&str>, url: &Url) -> bool {
+    content_type.map_or(false, |content_type| {
+        content_type.starts_with("application/dash+xml")
+    }) || url.path().ends_with(".mpd")
+}
+
+/// One segment of the audio representation.
+#[derive(Debug, PartialEq)]
+struct Segment {
+    number: u64,
+    duration: f64,
+    url: Url,
+}
+
+/// Turns an MPD into an HLS media playlist of the segments of its audio representation,
+/// so that the rest of the pipeline handles both alike.
+///
+/// Only the last period is read, and of it the audio representation with the highest bandwidth.
+/// Segments come from a `SegmentTemplate`, with or without a `SegmentTimeline`, or from
+/// a `SegmentList`. A live template without a timeline is placed on the clock with `now`.
+pub fn media_playlist(mpd: &str, url: &Url, now: DateTime<Utc>) -> Result<String> {
+    let document = roxmltree::Document::parse(mpd).context("Parse MPD")?;
+    let root = document.root_element();
+    if !root.has_tag_name("MPD") {
+        bail!("Not an MPD: <{}>", root.tag_name().name());
+    }
+    let live = root.attribute("type") == Some("dynamic");
+
+    let period = children(root, "Period")
+        .last()
+        .ok_or_else(|| anyhow!("MPD has no Period"))?;
+    let (adaptation, representation) = audio_representation(period)?;
+
+    let mut base = url.clone();
+    for node in [root, period, adaptation, representation] {
+        if let Some(href) = child(node, "BaseURL").and_then(|n| n.text()) {
+            base = base.join(href.trim()).context("Invalid BaseURL")?;
+        }
+    }
+
+    let ids = TemplateIds {
+        representation: representation.attribute("id").unwrap_or_default(),
+        bandwidth: representation.attribute("bandwidth").unwrap_or_default(),
+    };
+
+    let (initialization, segments) = if let Some(template) =
+        child(representation, "SegmentTemplate").or_else(|| child(adaptation, "SegmentTemplate"))
+    {
+        let clock = live
+            .then(|| -> Result<f64> {
+                let start = root
+                    .attribute("availabilityStartTime")
+                    .ok_or_else(|| anyhow!("Live MPD has no availabilityStartTime"))?;
+                let start = DateTime::parse_from_rfc3339(start)
+                    .with_context(|| format!("Invalid availabilityStartTime `{start}`"))?;
+                let period_start = optional_duration(period.attribute("start"))?.unwrap_or(0.0);
+                Ok(
+                    (now - start.with_timezone(&Utc)).num_milliseconds() as f64 / 1000.0
+                        - period_start,
+                )
+            })
+            .transpose()?;
+        let window = optional_duration(root.attribute("timeShiftBufferDepth"))?
+            .unwrap_or(DEFAULT_TIME_SHIFT_BUFFER_SECS);
+        let total = optional_duration(period.attribute("duration"))?.or(optional_duration(
+            root.attribute("mediaPresentationDuration"),
+        )?);
+
+        template_segments(template, &ids, &base, clock, window, total)?
+    } else if let Some(list) =
+        child(representation, "SegmentList").or_else(|| child(adaptation, "SegmentList"))
+    {
+        list_segments(list, &base)?
+    } else {
+        bail!("Audio representation has neither a SegmentTemplate nor a SegmentList");
+    };
+
+    render(initialization.as_ref(), &segments, live)
+}
+
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(move |n| n.has_tag_name(name))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+fn is_audio(node: Node) -> bool {
+    node.attribute("contentType") == Some("audio")
+        || node
+            .attribute("mimeType")
+            .map_or(false, |mime| mime.starts_with("audio/"))
+}
+
+fn audio_representation<'a, 'input>(
+    period: Node<'a, 'input>,
+) -> Result<(Node<'a, 'input>, Node<'a, 'input>)> {
+    children(period, "AdaptationSet")
+        .find_map(|adaptation| {
+            children(adaptation, "Representation")
+                .filter(|&representation| is_audio(adaptation) || is_audio(representation))
+                .max_by_key(|representation| {
+                    representation
+                        .attribute("bandwidth")
+                        .and_then(|b| b.parse::<u64>().ok())
+                        .unwrap_or_default()
+                })
+                .map(|representation| (adaptation, representation))
+        })
+        .ok_or_else(|| anyhow!("MPD has no audio representation"))
+}
+
+fn attribute<T: std::str::FromStr>(node: Node, name: &str) -> Result<Option<T>> {
+    node.attribute(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| anyhow!("Invalid {name} `{value}`"))
+        })
+        .transpose()
+}
+
+fn template_segments(
+    template: Node,
+    ids: &TemplateIds,
+    base: &Url,
+    clock: Option<f64>,
+    window: f64,
+    total: Option<f64>,
+) -> Result<(Option<Url>, Vec<Segment>)> {
+    let media = template
+        .attribute("media")
+        .ok_or_else(|| anyhow!("SegmentTemplate has no media"))?;
+    let timescale = attribute::<u64>(template, "timescale")?.unwrap_or(1).max(1) as f64;
+    let start_number = attribute::<u64>(template, "startNumber")?.unwrap_or(1);
+
+    // (time, duration) in timescale units, and the index of the first one.
+    let mut times = Vec::new();
+    let mut first_index = 0;
+    if let Some(timeline) = child(template, "SegmentTimeline") {
+        let entries: Vec<_> = children(timeline, "S").collect();
+        let mut time = 0;
+        for (i, s) in entries.iter().enumerate() {
+            time = attribute(*s, "t")?.unwrap_or(time);
+            let duration: u64 = attribute(*s, "d")?.ok_or_else(|| anyhow!("S has no d"))?;
+            if duration == 0 {
+                bail!("S has zero duration");
+            }
+            // A negative repeat lasts until the next entry, or the live edge for the last one.
+            let repeat: i64 = attribute(*s, "r")?.unwrap_or(0);
+            let count = if repeat >= 0 {
+                repeat as u64 + 1
+            } else {
+                let until = match entries.get(i + 1) {
+                    Some(next) => attribute(*next, "t")?,
+                    None => clock.map(|clock| (clock * timescale) as u64),
+                };
+                until.map_or(1, |until| until.saturating_sub(time) / duration)
+            };
+            for _ in 0..count {
+                times.push((time, duration));
+                time += duration;
+            }
+        }
+    } else {
+        let duration: u64 = attribute(template, "duration")?
+            .ok_or_else(|| anyhow!("SegmentTemplate has neither duration nor timeline"))?;
+        if duration == 0 {
+            bail!("SegmentTemplate has zero duration");
+        }
+        let seconds = duration as f64 / timescale;
+        let (first, end) = match (clock, total) {
+            (Some(clock), _) => {
+                let available = (clock / seconds).floor().max(0.0) as u64;
+                let listed = (window / seconds).ceil().max(1.0) as u64;
+                (available.saturating_sub(listed), available)
+            }
+            (None, Some(total)) => (0, (total / seconds).ceil() as u64),
+            (None, None) => bail!("Static MPD with a SegmentTemplate has no duration"),
+        };
+        times.extend((first..end).map(|index| (index * duration, duration)));
+        first_index = first;
+    }
+
+    let segments = times
+        .iter()
+        .enumerate()
+        .map(|(i, &(time, duration))| {
+            let number = start_number + first_index + i as u64;
+            Ok(Segment {
+                number,
+                duration: duration as f64 / timescale,
+                url: base.join(&ids.expand(media, number, time)?)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let initialization = template
+        .attribute("initialization")
+        .map(|init| -> Result<Url> { Ok(base.join(&ids.expand(init, 0, 0)?)?) })
+        .transpose()?;
+
+    Ok((initialization, segments))
+}
+
+fn list_segments(list: Node, base: &Url) -> Result<(Option<Url>, Vec<Segment>)> {
+    let timescale = attribute::<u64>(list, "timescale")?.unwrap_or(1).max(1) as f64;
+    let duration = attribute::<u64>(list, "duration")?
+        .ok_or_else(|| anyhow!("SegmentList has no duration"))? as f64
+        / timescale;
+    let start_number = attribute::<u64>(list, "startNumber")?.unwrap_or(1);
+
+    let segments = children(list, "SegmentURL")
+        .enumerate()
+        .map(|(i, segment)| {
+            let media = segment
+                .attribute("media")
+                .ok_or_else(|| anyhow!("SegmentURL has no media"))?;
+            Ok(Segment {
+                number: start_number + i as u64,
+                duration,
+                url: base.join(media)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let initialization = child(list, "Initialization")
+        .and_then(|init| init.attribute("sourceURL"))
+        .map(|init| base.join(init))
+        .transpose()?;
+
+    Ok((initialization, segments))
+}
+
+/// Values of the `$RepresentationID$` and `$Bandwidth$` template identifiers.
+struct TemplateIds<'a> {
+    representation: &'a str,
+    bandwidth: &'a str,
+}
+
+impl TemplateIds<'_> {
+    /// Fills in a template like `audio_$Number%05d$.m4s`.
+    fn expand(&self, template: &str, number: u64, time: u64) -> Result<String> {
+        let mut parts = template.split('$');
+        let mut expanded = parts.next().unwrap_or_default().to_owned();
+
+        while let Some(identifier) = parts.next() {
+            let literal = parts
+                .next()
+                .ok_or_else(|| anyhow!("Unterminated identifier in `{template}`"))?;
+
+            let (name, format) = identifier.split_once('%').unwrap_or((identifier, ""));
+            let width = match format {
+                "" => 0,
+                format => format
+                    .strip_prefix('0')
+                    .and_then(|f| f.strip_suffix('d'))
+                    .and_then(|f| f.parse().ok())
+                    .ok_or_else(|| anyhow!("Unsupported format `%{format}` in `{template}`"))?,
+            };
+            match name {
+                "" => expanded.push('$'),
+                "RepresentationID" => expanded.push_str(self.representation),
+                "Bandwidth" => expanded.push_str(self.bandwidth),
+                "Number" => write!(expanded, "{number:0width$}")?,
+                "Time" => write!(expanded, "{time:0width$}")?,
+                _ => bail!("Unknown identifier `${name}$` in `{template}`"),
+            }
+            expanded.push_str(literal);
+        }
+
+        Ok(expanded)
+    }
+}
+
+fn optional_duration(value: Option<&str>) -> Result<Option<f64>> {
+    value.map(parse_duration).transpose()
+}
+
+/// Parses an ISO 8601 duration of days and time, e.g. `PT1H2M3.5S`, into seconds.
+fn parse_duration(value: &str) -> Result<f64> {
+    let invalid = || anyhow!("Invalid duration `{value}`");
+
+    let rest = value.strip_prefix('P').ok_or_else(invalid)?;
+    let (days, time) = rest.split_once('T').unwrap_or((rest, ""));
+
+    let mut seconds = match days {
+        "" => 0.0,
+        days => {
+            days.strip_suffix('D')
+                .and_then(|d| d.parse::<f64>().ok())
+                .ok_or_else(invalid)?
+                * 86400.0
+        }
+    };
+
+    let mut number = String::new();
+    for c in time.chars() {
+        let unit = match c {
+            'H' => 3600.0,
+            'M' => 60.0,
+            'S' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        seconds += number.parse::<f64>().map_err(|_| invalid())? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+
+    Ok(seconds)
+}
+
+fn render(initialization: Option<&Url>, segments: &[Segment], live: bool) -> Result<String> {
+    let first = segments
+        .first()
+        .ok_or_else(|| anyhow!("MPD lists no segments yet"))?;
+    let target_duration = segments
+        .iter()
+        .map(|segment| segment.duration)
+        .fold(0.0, f64::max)
+        .ceil() as u64;
+
+    let mut playlist = String::new();
+    writeln!(playlist, "#EXTM3U")?;
+    writeln!(playlist, "#EXT-X-VERSION:6")?;
+    writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target_duration.max(1))?;
+    writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{}", first.number)?;
+    for segment in segments {
+        if let Some(initialization) = initialization {
+            writeln!(playlist, "#EXT-X-MAP:URI=\"{initialization}\"")?;
+        }
+        writeln!(playlist, "#EXTINF:{:.3},", segment.duration)?;
+        writeln!(playlist, "{}", segment.url)?;
+    }
+    if !live {
+        writeln!(playlist, "#EXT-X-ENDLIST")?;
+    }
+
+    Ok(playlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use hls_m3u8::MediaPlaylist;
+    use reqwest::Url;
+
+    use super::{is_dash, media_playlist, parse_duration};
+
+    fn url() -> Url {
+        "https://example.com/live/stream.mpd".parse().unwrap()
+    }
+
+    #[test]
+    fn test_is_dash() {
+        assert!(is_dash(Some("application/dash+xml"), &url()));
+        assert!(is_dash(None, &url()));
+        let hls: Url = "https://example.com/live/stream.m3u8".parse().unwrap();
+        assert!(!is_dash(Some("application/vnd.apple.mpegurl"), &hls));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT1H2M3.5S").unwrap(), 3723.5);
+        assert_eq!(parse_duration("P1DT30S").unwrap(), 86430.0);
+        assert_eq!(parse_duration("PT0S").unwrap(), 0.0);
+        assert!(parse_duration("1H").is_err());
+        assert!(parse_duration("PT1X").is_err());
+    }
+
+    #[test]
+    fn test_timeline() {
+        let mpd = r#"<?xml version="1.0"?>
+            <MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic"
+                availabilityStartTime="2022-05-01T10:00:00Z">
+              <Period id="1" start="PT0S">
+                <AdaptationSet contentType="video"><Representation id="v" bandwidth="900000"/>
+                </AdaptationSet>
+                <AdaptationSet mimeType="audio/mp4">
+                  <BaseURL>audio/</BaseURL>
+                  <SegmentTemplate timescale="48000" startNumber="10"
+                      initialization="$RepresentationID$_init.mp4"
+                      media="$RepresentationID$_$Number%05d$.m4s">
+                    <SegmentTimeline><S t="0" d="480000" r="2"/></SegmentTimeline>
+                  </SegmentTemplate>
+                  <Representation id="a64" bandwidth="64000"/>
+                  <Representation id="a128" bandwidth="128000"/>
+                </AdaptationSet>
+              </Period>
+            </MPD>"#;
+
+        let playlist = media_playlist(mpd, &url(), Utc::now()).unwrap();
+        let m3u8 = MediaPlaylist::try_from(playlist.as_str()).unwrap();
+        let uris: Vec<_> = m3u8
+            .segments
+            .values()
+            .map(|s| s.uri().to_string())
+            .collect();
+        assert_eq!(
+            uris,
+            [
+                "https://example.com/live/audio/a128_00010.m4s",
+                "https://example.com/live/audio/a128_00011.m4s",
+                "https://example.com/live/audio/a128_00012.m4s",
+            ]
+        );
+        assert_eq!(m3u8.segments.values().next().unwrap().number(), 10);
+        assert!(playlist.contains(r#"MAP:URI="https://example.com/live/audio/a128_init.mp4""#));
+        assert!(!m3u8.has_end_list);
+    }
+
+    #[test]
+    fn test_live_template() {
+        let mpd = r#"<MPD type="dynamic" availabilityStartTime="2022-05-01T10:00:00Z"
+                timeShiftBufferDepth="PT20S">
+              <Period start="PT0S">
+                <AdaptationSet contentType="audio">
+                  <Representation id="aac" bandwidth="96000">
+                    <SegmentTemplate duration="10" media="seg-$Time$.aac"/>
+                  </Representation>
+                </AdaptationSet>
+              </Period>
+            </MPD>"#;
+
+        let now = Utc.ymd(2022, 5, 1).and_hms(10, 1, 5);
+        let playlist = media_playlist(mpd, &url(), now).unwrap();
+        let m3u8 = MediaPlaylist::try_from(playlist.as_str()).unwrap();
+        let uris: Vec<_> = m3u8
+            .segments
+            .values()
+            .map(|s| s.uri().to_string())
+            .collect();
+        assert_eq!(
+            uris,
+            [
+                "https://example.com/live/seg-40.aac",
+                "https://example.com/live/seg-50.aac",
+            ]
+        );
+        // Numbers follow the clock, so a segment keeps its number across polls.
+        assert_eq!(m3u8.segments.values().next().unwrap().number(), 5);
+    }
+
+    #[test]
+    fn test_segment_list() {
+        let mpd = r#"<MPD type="static" mediaPresentationDuration="PT20S">
+              <Period>
+                <AdaptationSet>
+                  <Representation id="mp3" mimeType="audio/mpeg" bandwidth="128000">
+                    <SegmentList duration="10">
+                      <SegmentURL media="https://cdn.example.com/one.mp3"/>
+                      <SegmentURL media="two.mp3"/>
+                    </SegmentList>
+                  </Representation>
+                </AdaptationSet>
+              </Period>
+            </MPD>"#;
+
+        let playlist = media_playlist(mpd, &url(), Utc::now()).unwrap();
+        let m3u8 = MediaPlaylist::try_from(playlist.as_str()).unwrap();
+        assert_eq!(m3u8.segments.num_elements(), 2);
+        assert!(m3u8.has_end_list);
+        assert!(playlist.contains("https://example.com/live/two.mp3"));
+    }
+
+    #[test]
+    fn test_no_audio() {
+        let mpd = r#"<MPD><Period><AdaptationSet contentType="video">
+            <Representation id="v"/></AdaptationSet></Period></MPD>"#;
+        assert!(media_playlist(mpd, &url(), Utc::now()).is_err());
+        assert!(media_playlist("#EXTM3U", &url(), Utc::now()).is_err());
+    }
+}
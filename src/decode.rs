@@ -0,0 +1,475 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use ebur128::{EbuR128, Mode};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Decoded audio as interleaved samples.
+pub struct Pcm {
+    pub sample_rate: u32,
+    pub channels: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Pcm {
+    pub fn duration(&self) -> Duration {
+        let frames = self.samples.len() / self.channels.max(1);
+        Duration::from_secs_f64(frames as f64 / f64::from(self.sample_rate.max(1)))
+    }
+
+    /// The audio before and after `frame`.
+    pub fn split_at(&self, frame: usize) -> [Pcm; 2] {
+        let at = (frame * self.channels).min(self.samples.len());
+        let (before, after) = self.samples.split_at(at);
+        [before, after].map(|samples| Pcm {
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples: samples.to_vec(),
+        })
+    }
+}
+
+/// Decodes the first audio track of a segment, `content_type` helps to tell the container.
+pub fn decode(bytes: &[u8], content_type: &str) -> anyhow::Result<Pcm> {
+    decode_with(bytes, content_type, &FormatOptions::default())
+}
+
+/// Decodes a segment without the encoder delay and padding its container tells of, cut to
+/// `duration`, see `--trim-to-extinf`. A segment shorter than `duration` keeps all its audio.
+pub fn trim(bytes: &[u8], content_type: &str, duration: Duration) -> anyhow::Result<Pcm> {
+    let gapless = FormatOptions {
+        enable_gapless: true,
+        ..FormatOptions::default()
+    };
+    let mut pcm = decode_with(bytes, content_type, &gapless)?;
+
+    let frames = (duration.as_secs_f64() * f64::from(pcm.sample_rate)).round() as usize;
+    pcm.samples.truncate(frames * pcm.channels);
+    Ok(pcm)
+}
+
+fn decode_with(bytes: &[u8], content_type: &str, options: &FormatOptions) -> anyhow::Result<Pcm> {
+    let source = MediaSourceStream::new(Box::new(Cursor::new(bytes.to_vec())), Default::default());
+
+    let mut format = symphonia::default::get_probe()
+        .format(
+            Hint::new().mime_type(content_type),
+            source,
+            options,
+            &MetadataOptions::default(),
+        )
+        .context("Probe format")?
+        .format;
+
+    let track = format
+        .default_track()
+        .ok_or_else(|| anyhow!("No audio track"))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .context("Create decoder")?;
+
+    let mut pcm = Pcm {
+        sample_rate: 0,
+        channels: 0,
+        samples: Vec::new(),
+    };
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e).context("Read packet"),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+
+                pcm.sample_rate = spec.rate;
+                pcm.channels = spec.channels.count();
+                pcm.samples.extend_from_slice(buffer.samples());
+            }
+            // A segment cut mid-frame has a broken first or last packet, the rest is fine.
+            Err(Error::DecodeError(e)) => log::debug!("Skipped undecodable packet: {e}"),
+            Err(e) => return Err(e).context("Decode packet"),
+        }
+    }
+
+    if pcm.samples.is_empty() {
+        anyhow::bail!("No samples decoded");
+    }
+
+    Ok(pcm)
+}
+
+/// 16-bit PCM WAV of `pcm`.
+pub fn wav(pcm: &Pcm) -> Vec<u8> {
+    let samples: Vec<i16> = pcm
+        .samples
+        .iter()
+        .map(|sample| (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16)
+        .collect();
+    crate::wav::pcm16(pcm.sample_rate, pcm.channels as u16, &samples)
+}
+
+/// Integrated EBU R128 loudness, `None` for silence which has no loudness.
+pub fn loudness_lufs(pcm: &Pcm) -> anyhow::Result<Option<f64>> {
+    let mut meter = EbuR128::new(pcm.channels as u32, pcm.sample_rate, Mode::I)?;
+    meter.add_frames_f32(&pcm.samples)?;
+    let loudness = meter.loudness_global()?;
+
+    Ok(loudness.is_finite().then(|| loudness))
+}
+
+/// Time frames of a [`preview`].
+pub const PREVIEW_FRAMES: usize = 64;
+/// Frequency bands of a [`preview`], log-spaced from 50 Hz to 16 kHz or Nyquist.
+pub const PREVIEW_BANDS: usize = 32;
+
+/// A low-res spectrogram for eyeballing captures: `PREVIEW_FRAMES` rows of `PREVIEW_BANDS`
+/// bytes, each the band level mapped from -90..0 dBFS to 0..255.
+pub fn preview(pcm: &Pcm) -> Vec<u8> {
+    let mono = mono(pcm);
+
+    let rate = pcm.sample_rate as f32;
+    let (low, high) = (50f32, (rate / 2.0).min(16000.0));
+    let bands = (0..PREVIEW_BANDS)
+        .map(|band| low * (high / low).powf(band as f32 / (PREVIEW_BANDS - 1) as f32))
+        .collect::<Vec<_>>();
+
+    let frame_len = (mono.len() / PREVIEW_FRAMES).max(1);
+    let mut preview = Vec::with_capacity(PREVIEW_FRAMES * PREVIEW_BANDS);
+    for frame in 0..PREVIEW_FRAMES {
+        let start = (frame * frame_len).min(mono.len());
+        let samples = &mono[start..(start + frame_len).min(mono.len())];
+        preview.extend(bands.iter().map(|&frequency| {
+            let db = 10.0 * goertzel_power(samples, frequency / rate).max(1e-12).log10();
+            ((db + 90.0) / 90.0 * 255.0).clamp(0.0, 255.0) as u8
+        }));
+    }
+    preview
+}
+
+/// What a segment sounds like, see [`classify`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Sound {
+    Speech,
+    Music,
+}
+
+/// Tells speech from music by how 20 ms frames vary: speech alternates voiced and unvoiced
+/// sounds with pauses, so more of its frames are quiet or cross zero often than in music.
+/// `None` for silence, less than a second of audio or anything in between.
+pub fn classify(pcm: &Pcm) -> Option<Sound> {
+    let mono = mono(pcm);
+    let frame_len = (pcm.sample_rate / 50).max(1) as usize;
+    let frames = mono
+        .chunks_exact(frame_len)
+        .map(|frame| {
+            let energy = frame.iter().map(|sample| sample * sample).sum::<f32>();
+            let crossings = frame
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count();
+            (
+                (energy / frame_len as f32).sqrt(),
+                crossings as f32 / frame_len as f32,
+            )
+        })
+        .collect::<Vec<_>>();
+    if frames.len() < 50 {
+        return None;
+    }
+
+    let count = frames.len() as f32;
+    let mean_rms = frames.iter().map(|(rms, _)| rms).sum::<f32>() / count;
+    let mean_zcr = frames.iter().map(|(_, zcr)| zcr).sum::<f32>() / count;
+    if mean_rms < 1e-4 {
+        return None;
+    }
+
+    let ratio_of = |is: &dyn Fn(&(f32, f32)) -> bool| {
+        frames.iter().filter(|frame| is(frame)).count() as f32 / count
+    };
+    let low_energy = ratio_of(&|(rms, _)| *rms < 0.5 * mean_rms);
+    let high_zcr = ratio_of(&|(_, zcr)| *zcr > 1.5 * mean_zcr);
+
+    if low_energy >= 0.3 || high_zcr >= 0.15 {
+        Some(Sound::Speech)
+    } else if low_energy < 0.15 && high_zcr < 0.1 {
+        Some(Sound::Music)
+    } else {
+        None
+    }
+}
+
+/// Windows [`boundary`] compares.
+const BOUNDARY_WINDOW: Duration = Duration::from_millis(100);
+/// Shortest part [`boundary`] splits off, shorter ones are too short to classify.
+const MIN_PART: Duration = Duration::from_secs(2);
+/// How far apart the parts of a [`boundary`] must be, see [`change_score`].
+const MIN_CHANGE_SCORE: f32 = 25.0;
+
+/// Where the content of `pcm` changes, e.g. from an ad to a song, as a frame. That is the
+/// point between 100 ms windows which splits them into the parts whose level and
+/// zero-crossing rate differ the most against how much they vary within each part. `None`
+/// if the parts of no point differ clearly or it has no two parts of `MIN_PART`.
+pub fn boundary(pcm: &Pcm) -> Option<usize> {
+    let mono = mono(pcm);
+    let window = (BOUNDARY_WINDOW.as_secs_f64() * f64::from(pcm.sample_rate)) as usize;
+    if window == 0 {
+        return None;
+    }
+    let windows = mono
+        .chunks_exact(window)
+        .map(|window| {
+            let power = window.iter().map(|sample| sample * sample).sum::<f32>();
+            let crossings = window
+                .windows(2)
+                .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                .count();
+            (
+                10.0 * (power / window.len() as f32 + 1e-10).log10(),
+                crossings as f32 / window.len() as f32,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let min_windows = (MIN_PART.as_secs_f64() / BOUNDARY_WINDOW.as_secs_f64()) as usize;
+    if windows.len() < 2 * min_windows {
+        return None;
+    }
+    let (at, score) = (min_windows..=windows.len() - min_windows)
+        .map(|at| (at, change_score(&windows[..at], &windows[at..])))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    (score >= MIN_CHANGE_SCORE).then(|| at * window)
+}
+
+/// Squared differences of the mean level, in dB, and the mean zero-crossing rate of windows
+/// `a` and `b`, each over the sum of their variances. Variances are floored to what a steady
+/// sound varies by, so that two steady parts differ by their means only.
+fn change_score(a: &[(f32, f32)], b: &[(f32, f32)]) -> f32 {
+    let mean_variance = |values: Vec<f32>| {
+        let count = values.len() as f32;
+        let mean = values.iter().sum::<f32>() / count;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean).powi(2))
+            .sum::<f32>()
+            / count;
+        (mean, variance)
+    };
+    let score = |feature: fn(&(f32, f32)) -> f32, floor: f32| {
+        let (mean_a, variance_a) = mean_variance(a.iter().map(feature).collect());
+        let (mean_b, variance_b) = mean_variance(b.iter().map(feature).collect());
+        (mean_a - mean_b).powi(2) / (variance_a + variance_b).max(floor)
+    };
+
+    score(|(level, _)| *level, 1.0) + score(|(_, crossings)| *crossings, 1e-4)
+}
+
+/// The channels of `pcm` mixed down to one.
+fn mono(pcm: &Pcm) -> Vec<f32> {
+    let channels = pcm.channels.max(1);
+    pcm.samples
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect()
+}
+
+/// Power of `samples` at `frequency` given in cycles per sample, 1.0 for a full-scale sine.
+fn goertzel_power(samples: &[f32], frequency: f32) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+
+    let coefficient = 2.0 * (2.0 * std::f32::consts::PI * frequency).cos();
+    let (mut previous, mut before_previous) = (0f32, 0f32);
+    for &sample in samples {
+        let current = sample + coefficient * previous - before_previous;
+        before_previous = previous;
+        previous = current;
+    }
+
+    let power = previous * previous + before_previous * before_previous
+        - coefficient * previous * before_previous;
+    4.0 * power / (samples.len() * samples.len()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        boundary, classify, decode, loudness_lufs, preview, trim, wav, Pcm, Sound, PREVIEW_BANDS,
+        PREVIEW_FRAMES,
+    };
+
+    /// 16-bit mono WAV of a 1 kHz sine, `amplitude` of full scale.
+    fn sine_wav(amplitude: f64, seconds: u32) -> Vec<u8> {
+        const RATE: u32 = 48000;
+        let samples: Vec<i16> = (0..RATE * seconds)
+            .map(|n| {
+                let phase = 2.0 * std::f64::consts::PI * 1000.0 * f64::from(n) / f64::from(RATE);
+                (phase.sin() * amplitude * f64::from(i16::MAX)) as i16
+            })
+            .collect();
+        crate::wav::pcm16(RATE, 1, &samples)
+    }
+
+    #[test]
+    fn test_loudness() {
+        let pcm = decode(&sine_wav(0.1, 3), "audio/wav").unwrap();
+        assert_eq!(pcm.sample_rate, 48000);
+        assert_eq!(pcm.channels, 1);
+
+        // -20 dBFS sine in a single channel.
+        let loudness = loudness_lufs(&pcm).unwrap().unwrap();
+        assert!((loudness + 23.0).abs() < 0.5, "{loudness}");
+
+        let silence = decode(&sine_wav(0.0, 1), "audio/wav").unwrap();
+        assert_eq!(loudness_lufs(&silence).unwrap(), None);
+    }
+
+    #[test]
+    fn test_trim() {
+        let sine = sine_wav(0.5, 3);
+        let trimmed = trim(&sine, "audio/wav", Duration::from_millis(2500)).unwrap();
+        assert_eq!(trimmed.samples.len(), 120000);
+        assert_eq!(trimmed.duration(), Duration::from_millis(2500));
+
+        // Shorter than declared, nothing to trim.
+        let whole = trim(&sine, "audio/wav", Duration::from_secs(10)).unwrap();
+        assert_eq!(whole.duration(), Duration::from_secs(3));
+
+        let decoded = decode(&wav(&trimmed), "audio/wav").unwrap();
+        assert_eq!((decoded.sample_rate, decoded.channels), (48000, 1));
+        assert_eq!(decoded.samples.len(), trimmed.samples.len());
+        assert!((decoded.samples[12] - trimmed.samples[12]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_garbage() {
+        assert!(decode(b"not audio at all", "audio/aac").is_err());
+    }
+
+    #[test]
+    fn test_preview() {
+        let pcm = decode(&sine_wav(1.0, 1), "audio/wav").unwrap();
+        let preview = preview(&pcm);
+        assert_eq!(preview.len(), PREVIEW_FRAMES * PREVIEW_BANDS);
+
+        // The band nearest to 1 kHz is the loudest one of each frame.
+        let frame = &preview[..PREVIEW_BANDS];
+        let loudest = (0..PREVIEW_BANDS).max_by_key(|&band| frame[band]).unwrap();
+        let frequency = 50f32 * (16000f32 / 50.0).powf(loudest as f32 / 31.0);
+        assert!((700.0..1400.0).contains(&frequency), "{frequency}");
+
+        let silence = Pcm {
+            sample_rate: 8000,
+            channels: 2,
+            samples: vec![0.0; 16000],
+        };
+        assert!(super::preview(&silence).iter().all(|&level| level == 0));
+    }
+
+    #[test]
+    fn test_classify() {
+        const RATE: usize = 16000;
+        let sine = |frequency: f32, n: usize| {
+            (2.0 * std::f32::consts::PI * frequency * n as f32 / RATE as f32).sin() * 0.5
+        };
+
+        let music = (0..RATE * 3).map(|n| sine(440.0, n)).collect();
+        let music = Pcm {
+            sample_rate: RATE as u32,
+            channels: 1,
+            samples: music,
+        };
+        assert_eq!(classify(&music), Some(Sound::Music));
+
+        // Syllables: 200 ms voiced, 100 ms hiss, 150 ms pause.
+        let mut noise = 1u32;
+        let speech = (0..RATE * 3)
+            .map(|n| match n % (RATE * 45 / 100) {
+                t if t < RATE / 5 => sine(200.0, n),
+                t if t < RATE * 3 / 10 => {
+                    noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (noise >> 8) as f32 / (1 << 24) as f32 - 0.5
+                }
+                _ => 0.0,
+            })
+            .collect();
+        let speech = Pcm {
+            sample_rate: RATE as u32,
+            channels: 1,
+            samples: speech,
+        };
+        assert_eq!(classify(&speech), Some(Sound::Speech));
+
+        let silence = Pcm {
+            samples: vec![0.0; RATE * 3],
+            ..music
+        };
+        assert_eq!(classify(&silence), None);
+    }
+
+    #[test]
+    fn test_boundary() {
+        const RATE: usize = 16000;
+        let pcm = |samples: Vec<f32>| Pcm {
+            sample_rate: RATE as u32,
+            channels: 1,
+            samples,
+        };
+        let sine = |frequency: f32, amplitude: f32, seconds: usize| {
+            (0..RATE * seconds).map(move |n| {
+                (2.0 * std::f32::consts::PI * frequency * n as f32 / RATE as f32).sin() * amplitude
+            })
+        };
+
+        // A loud low tone, then a quiet high one.
+        let changing = pcm(sine(440.0, 0.5, 3).chain(sine(3000.0, 0.1, 3)).collect());
+        let at = boundary(&changing).unwrap();
+        assert_eq!(at, RATE * 3);
+        let [before, after] = changing.split_at(at);
+        assert_eq!(before.duration(), Duration::from_secs(3));
+        assert_eq!(after.duration(), Duration::from_secs(3));
+
+        assert_eq!(boundary(&pcm(sine(440.0, 0.5, 6).collect())), None);
+        // Too short to split in parts of two seconds.
+        assert_eq!(
+            boundary(&pcm(sine(440.0, 0.5, 2)
+                .chain(sine(3000.0, 0.1, 1))
+                .collect())),
+            None
+        );
+
+        // Syllables vary a lot from window to window, but alike all along.
+        let mut noise = 1u32;
+        let speech = (0..RATE * 6)
+            .map(|n| match n % (RATE * 45 / 100) {
+                t if t < RATE / 5 => sine(200.0, 0.5, 1).nth(t).unwrap(),
+                t if t < RATE * 3 / 10 => {
+                    noise = noise.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                    (noise >> 8) as f32 / (1 << 24) as f32 - 0.5
+                }
+                _ => 0.0,
+            })
+            .collect();
+        assert_eq!(boundary(&pcm(speech)), None);
+    }
+}
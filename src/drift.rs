@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+/// How the arrival of new segments compares to the durations they declare.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Cadence {
+    /// Number of the last of the new segments.
+    pub last_number: usize,
+    pub segments: usize,
+    /// Sum of the EXTINF durations of the new segments.
+    pub declared: Duration,
+    /// Wall-clock time since the previous segments showed up.
+    pub interval: Duration,
+    /// Wall-clock time since tracking started.
+    pub elapsed: Duration,
+    /// `elapsed` minus the durations declared meanwhile, in seconds. Grows when the station
+    /// sends segments slower than they play, shrinks when faster.
+    pub drift: f64,
+}
+
+struct Baseline {
+    started: Instant,
+    last_arrival: Instant,
+    last_number: usize,
+    declared: Duration,
+}
+
+/// Tracks the wall-clock cadence of new segments against their declared durations,
+/// a drift that keeps growing tells of an encoder clock running off.
+///
+/// Segments are timed when a poll first sees them, so single intervals are only as exact
+/// as the poll interval while the drift accumulated over a session is not.
+#[derive(Default)]
+pub struct DriftTracker {
+    baseline: Option<Baseline>,
+}
+
+impl DriftTracker {
+    /// Observes the numbers and declared durations of the segments of a poll, `None` until
+    /// new segments follow the ones seen before. Gaps and sequence resets restart tracking,
+    /// the missed segments would count as drift otherwise.
+    pub fn observe<I>(&mut self, now: Instant, segments: I) -> Option<Cadence>
+    where
+        I: IntoIterator<Item = (usize, Duration)>,
+    {
+        let mut segments = segments.into_iter().collect::<Vec<_>>();
+        segments.sort_unstable_by_key(|(number, _)| *number);
+        let last_number = segments.last()?.0;
+
+        let baseline = match self.baseline.as_mut() {
+            Some(baseline) if baseline.last_number <= last_number => baseline,
+            _ => return self.restart(now, last_number),
+        };
+
+        let new = segments
+            .iter()
+            .filter(|(number, _)| *number > baseline.last_number)
+            .collect::<Vec<_>>();
+        let first_new = new.first()?.0;
+        if first_new != baseline.last_number + 1 {
+            return self.restart(now, last_number);
+        }
+
+        let declared = new.iter().map(|(_, duration)| *duration).sum::<Duration>();
+        let interval = now - baseline.last_arrival;
+        baseline.declared += declared;
+        baseline.last_arrival = now;
+        baseline.last_number = last_number;
+
+        let elapsed = now - baseline.started;
+        Some(Cadence {
+            last_number,
+            segments: new.len(),
+            declared,
+            interval,
+            elapsed,
+            drift: elapsed.as_secs_f64() - baseline.declared.as_secs_f64(),
+        })
+    }
+
+    fn restart(&mut self, now: Instant, last_number: usize) -> Option<Cadence> {
+        self.baseline = Some(Baseline {
+            started: now,
+            last_arrival: now,
+            last_number,
+            declared: Duration::ZERO,
+        });
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::DriftTracker;
+
+    fn segments(numbers: std::ops::RangeInclusive<usize>) -> Vec<(usize, Duration)> {
+        numbers
+            .map(|number| (number, Duration::from_secs(10)))
+            .collect()
+    }
+
+    #[test]
+    fn test_drift() {
+        let start = Instant::now();
+        let at = |millis: u64| start + Duration::from_millis(millis);
+        let mut tracker = DriftTracker::default();
+
+        assert_eq!(tracker.observe(at(0), segments(1..=5)), None);
+        assert_eq!(tracker.observe(at(5_000), segments(1..=5)), None);
+
+        // Each segment of 10 s arrives 10.1 s after the previous one.
+        let cadence = tracker.observe(at(10_100), segments(2..=6)).unwrap();
+        assert_eq!(cadence.last_number, 6);
+        assert_eq!(cadence.segments, 1);
+        assert_eq!(cadence.interval, Duration::from_millis(10_100));
+        assert!((cadence.drift - 0.1).abs() < 1e-9, "{}", cadence.drift);
+
+        let cadence = tracker.observe(at(30_300), segments(4..=8)).unwrap();
+        assert_eq!(cadence.segments, 2);
+        assert_eq!(cadence.declared, Duration::from_secs(20));
+        assert_eq!(cadence.elapsed, Duration::from_millis(30_300));
+        assert!((cadence.drift - 0.3).abs() < 1e-9, "{}", cadence.drift);
+    }
+
+    #[test]
+    fn test_restart() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let mut tracker = DriftTracker::default();
+
+        assert_eq!(tracker.observe(at(0), segments(1..=5)), None);
+        // Segments 6 and 7 were missed.
+        assert_eq!(tracker.observe(at(30), segments(8..=12)), None);
+        let cadence = tracker.observe(at(40), segments(9..=13)).unwrap();
+        assert_eq!(cadence.elapsed, Duration::from_secs(10));
+        assert_eq!(cadence.drift, 0.0);
+
+        // The sequence restarted.
+        assert_eq!(tracker.observe(at(50), segments(0..=4)), None);
+        assert!(tracker.observe(at(60), segments(1..=5)).is_some());
+        assert_eq!(tracker.observe(at(70), Vec::new()), None);
+    }
+}
@@ -25,11 +25,23 @@ pub fn best_results(results: Vec<QueryResult>) -> Vec<QueryResult> {
         .collect()
 }
 
+/// How far the best result scores above the best one of another track,
+/// `None` without such a rival. Results of the same artist and title don't compete.
+pub fn score_margin(results: &[QueryResult]) -> Option<u8> {
+    let best = results.iter().max_by_key(|r| r.score())?;
+    results
+        .iter()
+        .filter(|r| r.artist() != best.artist() || r.title() != best.title())
+        .map(QueryResult::score)
+        .max()
+        .map(|rival| best.score() - rival)
+}
+
 #[cfg(test)]
 mod tests {
     use uuid::Uuid;
 
-    use crate::emysound::matcher::best_results;
+    use crate::emysound::matcher::{best_results, score_margin};
     use crate::emysound::QueryResult;
 
     #[test]
@@ -46,4 +58,29 @@ mod tests {
 
         println!("{:?}", best_results(vec![single, pair_a, pair_b]));
     }
+
+    #[test]
+    fn test_score_margin() {
+        let make_result = |coverage: f32, title: &str| QueryResult {
+            id: Uuid::new_v4(),
+            coverage,
+            artist: Some("Artist".to_owned()),
+            title: Some(title.to_owned()),
+        };
+
+        assert_eq!(score_margin(&[]), None);
+        assert_eq!(score_margin(&[make_result(0.75, "A")]), None);
+        assert_eq!(
+            score_margin(&[make_result(0.75, "A"), make_result(0.5, "A")]),
+            None
+        );
+        assert_eq!(
+            score_margin(&[
+                make_result(0.5, "B"),
+                make_result(0.75, "A"),
+                make_result(0.625, "A")
+            ]),
+            Some(25)
+        );
+    }
 }
@@ -1,11 +1,16 @@
 mod matcher;
 
 use anyhow::{anyhow, Context};
+use async_trait::async_trait;
 use bytes::Bytes;
 use emycloud_client_rs::MediaSource;
 use uuid::Uuid;
 
+use crate::exit_code::Failure;
+use crate::fingerprinter::Fingerprinter;
+
 use self::matcher::best_results;
+pub use self::matcher::score_margin;
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -91,10 +96,82 @@ impl TrackInfo {
     }
 }
 
-pub async fn insert(info: TrackInfo, filename: &str, bytes: &Bytes) -> anyhow::Result<()> {
+/// Outcome of [`insert`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Inserted {
+    New,
+    /// emysound already had a track with this id, e.g. from an insert retried after a failure.
+    Existing,
+}
+
+/// Inserts a track, treating a conflict on its id as success so that retries are harmless.
+pub async fn insert(info: TrackInfo, filename: &str, bytes: &Bytes) -> anyhow::Result<Inserted> {
     let source = MediaSource::Bytes(filename, bytes);
 
-    emycloud_client_rs::insert(source, info.id, info.artist, info.title)
-        .await
-        .context("EmySound::insert")
+    match emycloud_client_rs::insert(source, info.id, info.artist, info.title).await {
+        Ok(()) => Ok(Inserted::New),
+        Err(e) => {
+            let e = anyhow::Error::from(e);
+            if is_conflict(&e) {
+                log::debug!("EmySound::insert {}: {e:#}", info.id);
+                Ok(Inserted::Existing)
+            } else {
+                Err(e.context("EmySound::insert"))
+            }
+        }
+    }
+}
+
+/// The emysound service as a [`Fingerprinter`].
+pub struct EmySound;
+
+#[async_trait]
+impl Fingerprinter for EmySound {
+    async fn query(&self, filename: &str, bytes: &Bytes) -> anyhow::Result<Vec<QueryResult>> {
+        query(filename, bytes).await.context(Failure::EmySound)
+    }
+
+    async fn insert(
+        &self,
+        info: TrackInfo,
+        filename: &str,
+        bytes: &Bytes,
+    ) -> anyhow::Result<Inserted> {
+        insert(info, filename, bytes)
+            .await
+            .context(Failure::EmySound)
+    }
+}
+
+/// Whether `error` comes from a request emysound answered with `409 Conflict`.
+fn is_conflict(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .any(|cause| cause.status() == Some(reqwest::StatusCode::CONFLICT))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::is_conflict;
+
+    fn failed_request(status: u16) -> anyhow::Error {
+        let response = http::Response::builder()
+            .status(status)
+            .body("Track already exists")
+            .unwrap();
+        let error = reqwest::Response::from(response)
+            .error_for_status()
+            .unwrap_err();
+        anyhow::Error::from(error).context("Request failed")
+    }
+
+    #[test]
+    fn test_is_conflict() {
+        assert!(is_conflict(&failed_request(409)));
+        assert!(!is_conflict(&failed_request(500)));
+        assert!(!is_conflict(&anyhow!("Track already exists: 409 Conflict")));
+    }
 }
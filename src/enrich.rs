@@ -0,0 +1,258 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::io::AsyncWriteExt;
+
+use crate::storage::Metadata;
+
+/// Where the metadata of new tracks goes before it is stored.
+enum Hook {
+    /// A shell command reading the metadata as JSON on stdin and writing it back to stdout.
+    Command(String),
+    /// An endpoint the metadata is POSTed to as JSON, answering with it.
+    Http(reqwest::Client, Url),
+}
+
+/// Enriches the metadata of new tracks from external sources, e.g. MusicBrainz, see
+/// `--enrich-command` and `--enrich-url`.
+///
+/// The hook gets the metadata as a JSON object and answers with one, of which `artist`,
+/// `title`, `album` and `year` replace the stored ones and `external_ids`, an object of
+/// strings, adds ids of other catalogs. Fields left out, `null` or empty stay as they were.
+pub struct Enricher {
+    hook: Hook,
+    timeout: Duration,
+}
+
+impl Enricher {
+    pub fn command(command: String, timeout: Duration) -> Self {
+        Self {
+            hook: Hook::Command(command),
+            timeout,
+        }
+    }
+
+    pub fn http(client: reqwest::Client, url: Url, timeout: Duration) -> Self {
+        Self {
+            hook: Hook::Http(client, url),
+            timeout,
+        }
+    }
+
+    /// `metadata` with what the hook answered, or as it was if the hook failed or took
+    /// longer than the timeout. Ingestion doesn't wait on a failing hook.
+    pub async fn enrich(&self, metadata: Metadata) -> Metadata {
+        let enriched =
+            match tokio::time::timeout(self.timeout, self.call(&request(&metadata))).await {
+                Ok(response) => response.and_then(|response| apply(metadata.clone(), &response)),
+                Err(_) => Err(anyhow!("Timed out after {:?}", self.timeout)),
+            };
+
+        match enriched {
+            Ok(enriched) => enriched,
+            Err(e) => {
+                log::warn!(
+                    "Failed to enrich `{}`/`{}` {}: {e:#}",
+                    metadata.artist(),
+                    metadata.title(),
+                    metadata.id
+                );
+                metadata
+            }
+        }
+    }
+
+    async fn call(&self, request: &Value) -> anyhow::Result<Value> {
+        match &self.hook {
+            Hook::Command(command) => {
+                // Dropped at the timeout, the command must not outlive it.
+                let mut child = tokio::process::Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .with_context(|| format!("Run `{command}`"))?;
+
+                let mut stdin = child.stdin.take().context("No stdin")?;
+                stdin.write_all(request.to_string().as_bytes()).await?;
+                drop(stdin);
+
+                let output = child.wait_with_output().await?;
+                if !output.status.success() {
+                    bail!("`{command}` failed with {}", output.status);
+                }
+                serde_json::from_slice(&output.stdout)
+                    .with_context(|| format!("Parse the output of `{command}`"))
+            }
+            Hook::Http(client, url) => Ok(client
+                .post(url.clone())
+                .json(request)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await
+                .with_context(|| format!("Parse the answer of {url}"))?),
+        }
+    }
+}
+
+/// What the hook gets to know of a new track.
+fn request(metadata: &Metadata) -> Value {
+    let ids = metadata.ids();
+    json!({
+        "id": metadata.id.to_string(),
+        "timestamp": metadata.date().to_rfc3339(),
+        "kind": metadata.kind().to_string(),
+        "artist": metadata.artist(),
+        "title": metadata.title(),
+        "album": metadata.album(),
+        "year": metadata.year(),
+        "stream_id": metadata.stream_id(),
+        "source_url": metadata.source_url(),
+        "media_base_id": ids.media_base_id,
+        "itunes_track_id": ids.itunes_track_id,
+        "amg_track_id": ids.amg_track_id,
+        "amg_artist_id": ids.amg_artist_id,
+        "external_ids": metadata.external_ids(),
+    })
+}
+
+/// `metadata` with the fields set by `response`, see [`Enricher`].
+fn apply(metadata: Metadata, response: &Value) -> anyhow::Result<Metadata> {
+    let response = response
+        .as_object()
+        .ok_or_else(|| anyhow!("Expected a JSON object, got {response}"))?;
+    let text = |field: &str| match response.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) if value.trim().is_empty() => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.trim().to_owned())),
+        Some(value) => Err(anyhow!("Expected a string as `{field}`, got {value}")),
+    };
+
+    let artist = text("artist")?.unwrap_or_else(|| metadata.artist().to_owned());
+    let title = text("title")?.unwrap_or_else(|| metadata.title().to_owned());
+    let album = text("album")?.or_else(|| metadata.album().map(str::to_owned));
+    let year = match response.get("year") {
+        None | Some(Value::Null) => metadata.year(),
+        Some(year) => Some(
+            year.as_i64()
+                .and_then(|year| i32::try_from(year).ok())
+                .ok_or_else(|| anyhow!("Expected a year as `year`, got {year}"))?,
+        ),
+    };
+
+    let mut external_ids = metadata.external_ids().clone();
+    match response.get("external_ids") {
+        None | Some(Value::Null) => {}
+        Some(Value::Object(ids)) => {
+            for (catalog, id) in ids {
+                let id = match id {
+                    Value::Null => continue,
+                    Value::String(id) if id.trim().is_empty() => continue,
+                    Value::String(id) => id.trim().to_owned(),
+                    Value::Number(id) => id.to_string(),
+                    id => bail!("Expected a string as the `{catalog}` id, got {id}"),
+                };
+                external_ids.insert(catalog.clone(), id);
+            }
+        }
+        Some(ids) => bail!("Expected an object as `external_ids`, got {ids}"),
+    }
+
+    Ok(metadata
+        .with_track(artist, title)
+        .with_album(album, year)
+        .with_external_ids(external_ids))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Utc;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::{apply, Enricher};
+    use crate::storage::{AudioKind, Metadata};
+
+    fn metadata() -> Metadata {
+        Metadata::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            AudioKind::Music,
+            "Daft Punk".to_owned(),
+            "Da Funk (Radio Edit)".to_owned(),
+        )
+        .with_album(None, Some(1995))
+    }
+
+    #[test]
+    fn test_apply() {
+        let enriched = apply(
+            metadata(),
+            &json!({
+                "title": "Da Funk",
+                "album": "Homework",
+                "year": null,
+                "external_ids": {"musicbrainz": "8f3471b5", "discogs": 7600, "isrc": ""},
+                "genre": "house",
+            }),
+        )
+        .unwrap();
+        assert_eq!(enriched.artist(), "Daft Punk");
+        assert_eq!(enriched.title(), "Da Funk");
+        assert_eq!(
+            (enriched.album(), enriched.year()),
+            (Some("Homework"), Some(1995))
+        );
+        assert_eq!(enriched.external_ids()["discogs"], "7600");
+        assert_eq!(enriched.external_ids().len(), 2);
+
+        // Hooks that know nothing answer with empty values, which leave the names alone.
+        let unknown = apply(
+            metadata(),
+            &json!({"artist": "", "title": "  ", "album": ""}),
+        )
+        .unwrap();
+        assert_eq!(
+            (unknown.artist(), unknown.title(), unknown.album()),
+            ("Daft Punk", "Da Funk (Radio Edit)", None)
+        );
+
+        assert!(apply(metadata(), &json!([])).is_err());
+        assert!(apply(metadata(), &json!({"artist": 1})).is_err());
+        assert!(apply(metadata(), &json!({"year": "1997"})).is_err());
+        assert!(apply(metadata(), &json!({"external_ids": ["8f3471b5"]})).is_err());
+    }
+
+    async fn enrich(command: &str) -> Metadata {
+        let enricher = Enricher::command(command.to_owned(), Duration::from_secs(5));
+        enricher.enrich(metadata()).await
+    }
+
+    #[tokio::test]
+    async fn test_command() {
+        // The request is valid JSON with the stored names.
+        let enriched =
+            enrich(r#"grep -q '"title":"Da Funk (Radio Edit)"' && echo '{"title": "Da Funk"}'"#)
+                .await;
+        assert_eq!(enriched.title(), "Da Funk");
+
+        for failing in ["cat >/dev/null; exit 1", "cat >/dev/null; echo 'not json'"] {
+            assert_eq!(enrich(failing).await.title(), "Da Funk (Radio Edit)");
+        }
+
+        let slow = Enricher::command("sleep 5".to_owned(), Duration::from_millis(100));
+        assert_eq!(
+            slow.enrich(metadata()).await.title(),
+            "Da Funk (Radio Edit)"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use clap::ArgEnum;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum ErrorPolicy {
+    /// Exit once failures in a row reach the threshold, for a supervisor to restart us
+    FailFast,
+    /// Log failures and keep going no matter what
+    Resilient,
+}
+
+/// Counts consecutive failures and decides, according to the policy, when to give up.
+pub struct FailureTracker {
+    policy: ErrorPolicy,
+    threshold: u32,
+    consecutive: u32,
+}
+
+impl FailureTracker {
+    pub fn new(policy: ErrorPolicy, threshold: u32) -> Self {
+        Self {
+            policy,
+            threshold: threshold.max(1),
+            consecutive: 0,
+        }
+    }
+
+    pub fn success(&mut self) {
+        self.consecutive = 0;
+    }
+
+    /// Logs `error` and returns it back if the process should stop.
+    pub fn failure(&mut self, error: anyhow::Error) -> anyhow::Result<()> {
+        self.consecutive += 1;
+        log::error!("Failure #{} in a row: {error:#}", self.consecutive);
+
+        if self.policy == ErrorPolicy::FailFast && self.consecutive >= self.threshold {
+            Err(error.context(format!("{} consecutive failures", self.consecutive)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::{ErrorPolicy, FailureTracker};
+
+    #[test]
+    fn test_fail_fast() {
+        let mut tracker = FailureTracker::new(ErrorPolicy::FailFast, 2);
+        assert!(tracker.failure(anyhow!("1")).is_ok());
+        tracker.success();
+        assert!(tracker.failure(anyhow!("1")).is_ok());
+        assert!(tracker.failure(anyhow!("2")).is_err());
+    }
+
+    #[test]
+    fn test_resilient() {
+        let mut tracker = FailureTracker::new(ErrorPolicy::Resilient, 1);
+        for _ in 0..10 {
+            assert!(tracker.failure(anyhow!("failure")).is_ok());
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt::Display;
+
+/// Exit codes, shown by `--help` for supervisors and scripts.
+pub const EXIT_CODES: &str = "EXIT CODES:
+    0    Success, or a clean shutdown
+    1    Any other error
+    2    Invalid arguments
+    3    Invalid configuration, e.g. a missing feature or an unreadable file
+    4    The stream server failed beyond `--error-policy`
+    5    A database or audio store failed
+    6    emysound failed or couldn't be reached";
+
+/// Why the feeder stopped, attached to errors as context and told by [`exit_code`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Failure {
+    Config,
+    Network,
+    Storage,
+    EmySound,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Config => f.write_str("Invalid configuration"),
+            Failure::Network => f.write_str("Stream failure"),
+            Failure::Storage => f.write_str("Storage failure"),
+            Failure::EmySound => f.write_str("emysound failure"),
+        }
+    }
+}
+
+/// Exit code of `error`, see [`EXIT_CODES`]. The outermost [`Failure`] in its context
+/// decides, sqlite and HTTP errors without one count as storage and stream failures.
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    let failure = error.downcast_ref::<Failure>().copied().or_else(|| {
+        if error.downcast_ref::<rusqlite::Error>().is_some() {
+            Some(Failure::Storage)
+        } else if error.downcast_ref::<reqwest::Error>().is_some() {
+            Some(Failure::Network)
+        } else {
+            None
+        }
+    });
+
+    match failure {
+        Some(Failure::Config) => 3,
+        Some(Failure::Network) => 4,
+        Some(Failure::Storage) => 5,
+        Some(Failure::EmySound) => 6,
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+
+    use super::{exit_code, Failure};
+
+    #[test]
+    fn test() {
+        assert_eq!(exit_code(&anyhow!("unexpected")), 1);
+
+        let config = anyhow!("No stream URL").context(Failure::Config);
+        assert_eq!(exit_code(&config), 3);
+        assert_eq!(exit_code(&config.context("12 consecutive failures")), 3);
+
+        let emysound = Err::<(), _>(anyhow!("Request failed: 503"))
+            .context("EmySound::query")
+            .context(Failure::EmySound)
+            .context("Ingest 1:segment.aac")
+            .unwrap_err();
+        assert_eq!(exit_code(&emysound), 6);
+
+        let storage = anyhow::Error::from(rusqlite::Error::QueryReturnedNoRows).context("Get");
+        assert_eq!(exit_code(&storage), 5);
+        assert_eq!(exit_code(&storage.context(Failure::Network)), 4);
+    }
+}
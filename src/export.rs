@@ -0,0 +1,131 @@
+use std::io::Write;
+
+use clap::ArgEnum;
+use serde_json::json;
+
+use crate::storage::Metadata;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// A JSON array of objects
+    Json,
+}
+
+const COLUMNS: [&str; 7] = [
+    "timestamp",
+    "kind",
+    "artist",
+    "title",
+    "stream_id",
+    "discontinuity_sequence",
+    "discontinuity",
+];
+
+/// Writes [`COLUMNS`] of each row.
+pub fn write_metadata<W: Write>(
+    writer: W,
+    format: ExportFormat,
+    rows: &[Metadata],
+) -> anyhow::Result<()> {
+    match format {
+        ExportFormat::Csv => write_csv(writer, rows),
+        ExportFormat::Json => write_json(writer, rows),
+    }
+}
+
+fn write_csv<W: Write>(writer: W, rows: &[Metadata]) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(COLUMNS)?;
+
+    for metadata in rows {
+        let discontinuity_sequence = metadata
+            .discontinuity_sequence()
+            .map(|sequence| sequence.to_string())
+            .unwrap_or_default();
+        writer.write_record([
+            metadata.date().to_rfc3339().as_str(),
+            metadata.kind().to_string().as_str(),
+            metadata.artist(),
+            metadata.title(),
+            metadata.stream_id().unwrap_or_default(),
+            discontinuity_sequence.as_str(),
+            if metadata.discontinuity() {
+                "true"
+            } else {
+                "false"
+            },
+        ])?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_json<W: Write>(mut writer: W, rows: &[Metadata]) -> anyhow::Result<()> {
+    let rows = rows
+        .iter()
+        .map(|metadata| {
+            json!({
+                "timestamp": metadata.date().to_rfc3339(),
+                "kind": metadata.kind().to_string(),
+                "artist": metadata.artist(),
+                "title": metadata.title(),
+                "stream_id": metadata.stream_id(),
+                "discontinuity_sequence": metadata.discontinuity_sequence(),
+                "discontinuity": metadata.discontinuity(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer_pretty(&mut writer, &rows)?;
+    writeln!(writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+
+    use super::{write_metadata, ExportFormat};
+    use crate::storage::{AudioKind, Metadata};
+
+    fn rows() -> Vec<Metadata> {
+        vec![Metadata::new(
+            Uuid::new_v4(),
+            Utc.ymd(2022, 5, 1).and_hms(12, 30, 0),
+            AudioKind::Music,
+            "Earth, Wind & Fire".to_owned(),
+            r#"The "Real" Slim Shady"#.to_owned(),
+        )
+        .with_stream_id(Some("kost".to_owned()))
+        .with_discontinuity(Some(2), true)]
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut out = Vec::new();
+        write_metadata(&mut out, ExportFormat::Csv, &rows()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,kind,artist,title,stream_id,discontinuity_sequence,discontinuity\n\
+            2022-05-01T12:30:00+00:00,music,\"Earth, Wind & Fire\",\"The \"\"Real\"\" Slim Shady\",\
+            kost,2,true\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let mut out = Vec::new();
+        write_metadata(&mut out, ExportFormat::Json, &rows()).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(value[0]["artist"], "Earth, Wind & Fire");
+        assert_eq!(value[0]["stream_id"], "kost");
+        assert_eq!(value[0]["discontinuity_sequence"], 2);
+        assert_eq!(value[0]["discontinuity"], true);
+    }
+}
@@ -0,0 +1,173 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+
+/// What a [`FilenameTemplate`] placeholder stands for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// `{time}`, when the segment was ingested, `2022-05-01_10-00-00` in `--timezone`.
+    Time,
+    /// `{number}`, the media sequence number.
+    Number,
+    /// `{kind}`
+    Kind,
+    /// `{artist}`
+    Artist,
+    /// `{title}`
+    Title,
+    /// `{stream}`, see `--stream-id`.
+    Stream,
+    /// `{name}`, the last segment of the URL path, e.g. `segment-41.aac`.
+    Name,
+    /// `{ext}`, the extension of `{name}`.
+    Ext,
+    /// `{id}`, the local id of a stored segment.
+    Id,
+}
+
+impl Field {
+    const ALL: [Field; 9] = [
+        Field::Time,
+        Field::Number,
+        Field::Kind,
+        Field::Artist,
+        Field::Title,
+        Field::Stream,
+        Field::Name,
+        Field::Ext,
+        Field::Id,
+    ];
+
+    fn placeholder(&self) -> &'static str {
+        match self {
+            Field::Time => "time",
+            Field::Number => "number",
+            Field::Kind => "kind",
+            Field::Artist => "artist",
+            Field::Title => "title",
+            Field::Stream => "stream",
+            Field::Name => "name",
+            Field::Ext => "ext",
+            Field::Id => "id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// A file name made of text and `{field}` placeholders, see [`Field`]. `{{` and `}}` stand
+/// for braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilenameTemplate(Vec<Part>);
+
+/// The name segments had before templates, unique within a second by the sequence number.
+pub const DETAILED: &str = "{time}_{number}_{kind}_{artist}_{title}.{name}";
+
+impl FilenameTemplate {
+    pub fn has(&self, field: Field) -> bool {
+        self.0.contains(&Part::Field(field))
+    }
+
+    pub fn render(&self, value: impl Fn(Field) -> String) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => value(*field),
+            })
+            .collect()
+    }
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        DETAILED.parse().expect("Valid template")
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(i) = rest.find(['{', '}']) {
+            text.push_str(&rest[..i]);
+            let (brace, after) = rest[i..].split_at(1);
+            if let Some(after) = after.strip_prefix(brace) {
+                text.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                bail!("Unmatched `}}` in `{template}`, `}}}}` stands for `}}`");
+            }
+
+            let (name, after) = after
+                .split_once('}')
+                .ok_or_else(|| anyhow!("Unclosed `{{` in `{template}`"))?;
+            let field = Field::ALL
+                .into_iter()
+                .find(|field| field.placeholder() == name)
+                .ok_or_else(|| anyhow!("Unknown placeholder `{{{name}}}` in `{template}`"))?;
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(Part::Field(field));
+            rest = after;
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        if parts.is_empty() {
+            bail!("Empty file name template");
+        }
+        Ok(Self(parts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Field, FilenameTemplate};
+
+    fn render(template: &str) -> String {
+        template
+            .parse::<FilenameTemplate>()
+            .unwrap()
+            .render(|field| match field {
+                Field::Artist => "Daft Punk".to_owned(),
+                Field::Title => "Da Funk".to_owned(),
+                Field::Ext => "aac".to_owned(),
+                field => format!("<{field:?}>"),
+            })
+    }
+
+    #[test]
+    fn test() {
+        assert_eq!(
+            render("{artist} - {title}.{ext}"),
+            "Daft Punk - Da Funk.aac"
+        );
+        assert_eq!(render("{{{number}}}"), "{<Number>}");
+        assert_eq!(
+            render(super::DETAILED),
+            "<Time>_<Number>_<Kind>_Daft Punk_Da Funk.<Name>"
+        );
+
+        let template: FilenameTemplate = "{id}.{ext}".parse().unwrap();
+        assert!(template.has(Field::Id));
+        assert!(!template.has(Field::Name));
+
+        assert!("{album}".parse::<FilenameTemplate>().is_err());
+        assert!("{artist".parse::<FilenameTemplate>().is_err());
+        assert!("artist}".parse::<FilenameTemplate>().is_err());
+        assert!("".parse::<FilenameTemplate>().is_err());
+    }
+}
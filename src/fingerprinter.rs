@@ -0,0 +1,23 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+
+use crate::emysound::{Inserted, QueryResult, TrackInfo};
+
+/// A fingerprinting service, finds which known tracks a segment plays and learns new ones.
+///
+/// `filename` names the uploaded audio, emysound shows it as the file name and takes a format
+/// hint from its extension. Inserted tracks are named by the artist and title of their
+/// [`TrackInfo`] instead.
+#[async_trait]
+pub trait Fingerprinter: Send + Sync {
+    /// Best matches of the audio among known tracks.
+    async fn query(&self, filename: &str, bytes: &Bytes) -> anyhow::Result<Vec<QueryResult>>;
+
+    /// Learns a track. An id the service knows already is [`Inserted::Existing`], not an error.
+    async fn insert(
+        &self,
+        info: TrackInfo,
+        filename: &str,
+        bytes: &Bytes,
+    ) -> anyhow::Result<Inserted>;
+}
@@ -0,0 +1,215 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use reqwest::Url;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// Where the list of streams to capture comes from, see `--streams-from`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamsFrom {
+    /// Lists follow one another on stdin, each ending with an empty line.
+    Stdin,
+    /// A file read at start and again at SIGHUP.
+    File(PathBuf),
+}
+
+impl FromStr for StreamsFrom {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.trim() {
+            "-" => StreamsFrom::Stdin,
+            "" => bail!("Expected `-` or a file"),
+            path => StreamsFrom::File(path.into()),
+        })
+    }
+}
+
+/// A stream of the list, its id and playlist.
+pub type FleetStream = (String, Url);
+
+/// Parses a list of streams, a stream per line as `URL` or as `NAME=URL`, `NAME` being its
+/// stream id. The id of a bare URL is its host. Blank lines and `#` comments are skipped.
+pub fn parse_streams(list: &str) -> anyhow::Result<Vec<FleetStream>> {
+    let mut streams: Vec<FleetStream> = Vec::new();
+    for (number, line) in list.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let stream = parse_stream(line).with_context(|| format!("Line {}", number + 1))?;
+        if streams.iter().any(|(id, _)| *id == stream.0) {
+            bail!(
+                "Line {}: stream id `{}` is listed twice",
+                number + 1,
+                stream.0
+            );
+        }
+        streams.push(stream);
+    }
+    Ok(streams)
+}
+
+fn parse_stream(line: &str) -> anyhow::Result<FleetStream> {
+    // `=` isn't allowed in a URL scheme, a line that parses as a URL has no name.
+    if let Ok(url) = Url::parse(line) {
+        let id = url
+            .host_str()
+            .context("Expected a URL with a host")?
+            .to_owned();
+        return Ok((id, url));
+    }
+
+    let (name, url) = line.split_once('=').context("Expected URL or NAME=URL")?;
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Expected NAME=URL, the name is empty");
+    }
+    let url = url.trim();
+    Ok((
+        name.to_owned(),
+        url.parse()
+            .with_context(|| format!("Invalid URL `{url}`"))?,
+    ))
+}
+
+/// Reads the first list of streams and sends each one read after. A list that fails to
+/// read or parse later on is logged and skipped, the streams of the one before go on.
+pub async fn watch(from: &StreamsFrom) -> anyhow::Result<UnboundedReceiver<Vec<FleetStream>>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    match from {
+        StreamsFrom::Stdin => {
+            tokio::spawn(read_stdin(sender));
+        }
+        StreamsFrom::File(path) => {
+            let _ = sender.send(read_file(path).await?);
+            reread_on_sighup(path.clone(), sender)?;
+        }
+    }
+    Ok(receiver)
+}
+
+async fn read_file(path: &Path) -> anyhow::Result<Vec<FleetStream>> {
+    let list = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("Read {}", path.display()))?;
+    parse_streams(&list).with_context(|| format!("Parse {}", path.display()))
+}
+
+#[cfg(unix)]
+fn reread_on_sighup(
+    path: PathBuf,
+    sender: UnboundedSender<Vec<FleetStream>>,
+) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            log::info!("Reading streams from {} again on SIGHUP", path.display());
+            match read_file(&path).await {
+                Ok(streams) => {
+                    if sender.send(streams).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => log::error!("Keeping the streams as they are: {e:#}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn reread_on_sighup(
+    path: PathBuf,
+    _sender: UnboundedSender<Vec<FleetStream>>,
+) -> anyhow::Result<()> {
+    log::warn!(
+        "No SIGHUP on this platform, {} is read once",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Sends each list of stdin. An empty list stops every stream, the end of stdin leaves
+/// the last list running.
+async fn read_stdin(sender: UnboundedSender<Vec<FleetStream>>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut list = String::new();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Failed to read streams from stdin: {e:#}");
+                None
+            }
+        };
+
+        match line {
+            Some(line) if !line.trim().is_empty() => {
+                list.push_str(&line);
+                list.push('\n');
+                continue;
+            }
+            // The end of stdin ends a list too, but only one with streams in it.
+            None if list.is_empty() => break,
+            _ => {}
+        }
+
+        match parse_streams(&std::mem::take(&mut list)) {
+            Ok(streams) => {
+                if sender.send(streams).is_err() {
+                    break;
+                }
+            }
+            Err(e) => log::error!("Keeping the streams as they are, stdin: {e:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_streams, StreamsFrom};
+
+    #[test]
+    fn test() {
+        let streams = parse_streams(
+            "# jazz and rock
+            https://jazz.example.com/live.m3u8?token=a=b
+
+            rock = https://cdn.example.com/rock/live.m3u8
+            ",
+        )
+        .unwrap();
+        let streams = streams
+            .iter()
+            .map(|(id, url)| (id.as_str(), url.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            streams,
+            [
+                (
+                    "jazz.example.com",
+                    "https://jazz.example.com/live.m3u8?token=a=b"
+                ),
+                ("rock", "https://cdn.example.com/rock/live.m3u8"),
+            ]
+        );
+
+        assert!(parse_streams("").unwrap().is_empty());
+        assert!(parse_streams("jazz").is_err());
+        assert!(parse_streams("=https://jazz.example.com/").is_err());
+        assert!(parse_streams("jazz=live.m3u8").is_err());
+        assert!(parse_streams("https://a.example.com/\nhttps://a.example.com/b").is_err());
+
+        assert_eq!("-".parse::<StreamsFrom>().unwrap(), StreamsFrom::Stdin);
+        assert_eq!(
+            "streams.txt".parse::<StreamsFrom>().unwrap(),
+            StreamsFrom::File("streams.txt".into())
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::Read;
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use flate2::read::MultiGzDecoder;
+
+use crate::SegmentTooLarge;
+
+/// Magic bytes of a gzip member. No audio format the feeder stores starts with them.
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Layers of gzip undone at most, a misconfigured CDN may gzip an already gzipped body.
+const MAX_LAYERS: usize = 3;
+
+/// Bytes a body may decompress to, a segment is a few seconds of audio.
+const MAX_DECOMPRESSED: u64 = 64 << 20;
+
+/// Tells gzip-encoded `Content-Encoding`, e.g. `gzip` or `x-gzip`.
+pub fn is_gzip_encoding(content_encoding: &str) -> bool {
+    content_encoding.split(',').any(|coding| {
+        matches!(
+            coding.trim().to_ascii_lowercase().as_str(),
+            "gzip" | "x-gzip"
+        )
+    })
+}
+
+/// The audio in `body`, without gzip wrappers. Bodies are told by their magic bytes, as a
+/// `Content-Encoding: gzip` segment might have been decoded on the way and a double
+/// gzipped one carries a wrapper the header doesn't tell.
+///
+/// Audio beyond `max_bytes` is [`SegmentTooLarge`], see `--max-segment-bytes`.
+pub fn decompress(mut body: Bytes, max_bytes: Option<u64>) -> anyhow::Result<Bytes> {
+    let limit = max_bytes.map_or(MAX_DECOMPRESSED, |max_bytes| {
+        max_bytes.min(MAX_DECOMPRESSED)
+    });
+    for layer in 1..=MAX_LAYERS {
+        if !body.starts_with(&MAGIC) {
+            return Ok(body);
+        }
+
+        let mut audio = Vec::new();
+        MultiGzDecoder::new(body.as_ref())
+            .take(limit + 1)
+            .read_to_end(&mut audio)
+            .with_context(|| format!("Decompress gzip layer {layer}"))?;
+        if audio.len() as u64 > limit {
+            let beyond = format!("Gzip layer {layer} decompresses beyond {limit} bytes");
+            if max_bytes == Some(limit) {
+                return Err(anyhow::Error::msg(SegmentTooLarge { limit }).context(beyond));
+            }
+            bail!(beyond);
+        }
+        body = audio.into();
+    }
+
+    if body.starts_with(&MAGIC) {
+        bail!("Gzipped more than {MAX_LAYERS} times");
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bytes::Bytes;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::{decompress, is_gzip_encoding};
+    use crate::SegmentTooLarge;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test() {
+        // ADTS frame header.
+        let audio = b"\xff\xf1\x50\x80\x02\x1f\xfc audio".to_vec();
+        assert_eq!(decompress(Bytes::from(audio.clone()), None).unwrap(), audio);
+        assert_eq!(decompress(gzip(&audio).into(), None).unwrap(), audio);
+        assert_eq!(decompress(gzip(&gzip(&audio)).into(), None).unwrap(), audio);
+
+        let wrapped = (0..4).fold(audio, |body, _| gzip(&body));
+        assert!(decompress(wrapped.into(), None).is_err());
+        assert!(decompress(Bytes::from_static(b"\x1f\x8b truncated"), None).is_err());
+
+        let silence = gzip(&[0; 4096]);
+        assert_eq!(
+            decompress(silence.clone().into(), Some(4096))
+                .unwrap()
+                .len(),
+            4096
+        );
+        let e = decompress(silence.into(), Some(4095)).unwrap_err();
+        assert!(e.is::<SegmentTooLarge>());
+
+        assert!(is_gzip_encoding("gzip"));
+        assert!(is_gzip_encoding("identity, X-Gzip"));
+        assert!(!is_gzip_encoding("br"));
+    }
+}
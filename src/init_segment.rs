@@ -0,0 +1,87 @@
+use bytes::{Bytes, BytesMut};
+use reqwest::Url;
+
+/// The `EXT-X-MAP` init segment fetched last, streams rarely switch between several.
+#[derive(Default)]
+pub struct InitSegments {
+    latest: Option<(Url, Bytes)>,
+}
+
+impl InitSegments {
+    pub fn get(&self, url: &Url) -> Option<Bytes> {
+        self.latest
+            .as_ref()
+            .filter(|(latest, _)| latest == url)
+            .map(|(_, bytes)| bytes.clone())
+    }
+
+    pub fn insert(&mut self, url: Url, bytes: Bytes) {
+        self.latest = Some((url, bytes));
+    }
+}
+
+/// Type of the first ISO BMFF box, if `bytes` start with one.
+fn first_box_type(bytes: &[u8]) -> Option<&[u8]> {
+    bytes.get(4..8)
+}
+
+/// An fMP4 init segment starts with `ftyp`, an MPEG-TS one with a sync byte instead.
+pub fn is_fmp4_init(bytes: &[u8]) -> bool {
+    first_box_type(bytes) == Some(b"ftyp")
+}
+
+/// A media segment carrying its own `ftyp`/`moov` needs no init segment.
+pub fn is_self_initialized(bytes: &[u8]) -> bool {
+    matches!(first_box_type(bytes), Some(b"ftyp" | b"moov"))
+}
+
+/// The init segment followed by the media segment, decodable on its own.
+pub fn prepend(init: &[u8], segment: &[u8]) -> Bytes {
+    let mut bytes = BytesMut::with_capacity(init.len() + segment.len());
+    bytes.extend_from_slice(init);
+    bytes.extend_from_slice(segment);
+    bytes.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{is_fmp4_init, is_self_initialized, prepend, InitSegments};
+
+    const INIT: &[u8] = b"\0\0\0\x10ftypiso6\0\0\0\0";
+    const FRAGMENT: &[u8] = b"\0\0\0\x08moof";
+
+    #[test]
+    fn test_detection() {
+        assert!(is_fmp4_init(INIT));
+        assert!(!is_fmp4_init(b"\x47\x40\x00\x10"));
+        assert!(!is_fmp4_init(b""));
+
+        assert!(is_self_initialized(INIT));
+        assert!(!is_self_initialized(FRAGMENT));
+        assert!(!is_self_initialized(b"ID3\x04"));
+    }
+
+    #[test]
+    fn test_prepend() {
+        let bytes = prepend(INIT, FRAGMENT);
+        assert!(bytes.starts_with(INIT));
+        assert!(bytes.ends_with(FRAGMENT));
+        assert!(is_self_initialized(&bytes));
+    }
+
+    #[test]
+    fn test_cache() {
+        let first: reqwest::Url = "https://example.com/init-1.mp4".parse().unwrap();
+        let second: reqwest::Url = "https://example.com/init-2.mp4".parse().unwrap();
+        let mut cache = InitSegments::default();
+
+        cache.insert(first.clone(), Bytes::from_static(INIT));
+        assert_eq!(cache.get(&first), Some(Bytes::from_static(INIT)));
+        assert_eq!(cache.get(&second), None);
+
+        cache.insert(second.clone(), Bytes::from_static(b"other"));
+        assert_eq!(cache.get(&first), None);
+    }
+}
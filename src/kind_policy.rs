@@ -0,0 +1,108 @@
+use anyhow::{anyhow, bail};
+
+use crate::storage::AudioKind;
+
+/// What to do with segments of one kind, every step is on by default.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KindPolicy {
+    /// Query emysound for matches, otherwise the segment counts as unmatched
+    pub query: bool,
+    /// Insert into emysound when nothing matches
+    pub insert: bool,
+    /// Keep the audio of inserted segments locally, metadata is stored either way
+    pub store_audio: bool,
+}
+
+impl Default for KindPolicy {
+    fn default() -> Self {
+        Self {
+            query: true,
+            insert: true,
+            store_audio: true,
+        }
+    }
+}
+
+impl KindPolicy {
+    /// The last policy given for `kind`, or the default one.
+    pub fn for_kind(policies: &[(AudioKind, KindPolicy)], kind: AudioKind) -> Self {
+        policies
+            .iter()
+            .rev()
+            .find(|(k, _)| *k == kind)
+            .map_or_else(Self::default, |(_, policy)| *policy)
+    }
+
+    /// Whether the segment has to be downloaded at all.
+    pub fn is_ignored(&self) -> bool {
+        !self.query && !self.insert
+    }
+}
+
+/// Parses `KIND=STEPS`, e.g. `talk=none` or `advertisement=query,insert`.
+/// Steps are `query`, `insert` and `store-audio`, the ones not listed are off.
+pub fn parse_kind_policy(value: &str) -> anyhow::Result<(AudioKind, KindPolicy)> {
+    let (kind, steps) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected KIND=STEPS"))?;
+
+    let mut policy = KindPolicy {
+        query: false,
+        insert: false,
+        store_audio: false,
+    };
+    for step in steps.split(',').map(str::trim) {
+        match step {
+            "query" => policy.query = true,
+            "insert" => policy.insert = true,
+            "store-audio" => policy.store_audio = true,
+            "none" | "" => {}
+            _ => bail!("Unknown step `{step}`, expected query, insert, store-audio or none"),
+        }
+    }
+
+    Ok((kind.trim().try_into()?, policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_kind_policy, KindPolicy};
+    use crate::storage::AudioKind;
+
+    #[test]
+    fn test_parse() {
+        let (kind, policy) = parse_kind_policy("advertisement=query, insert").unwrap();
+        assert_eq!(kind, AudioKind::Advertisement);
+        assert_eq!(
+            policy,
+            KindPolicy {
+                query: true,
+                insert: true,
+                store_audio: false,
+            }
+        );
+
+        let (kind, policy) = parse_kind_policy("talk=none").unwrap();
+        assert_eq!(kind, AudioKind::Talk);
+        assert!(policy.is_ignored());
+
+        assert!(parse_kind_policy("talk").is_err());
+        assert!(parse_kind_policy("talk=skip").is_err());
+        assert!(parse_kind_policy("jingle=query").is_ok());
+        assert!(parse_kind_policy("sweeper=query").is_err());
+    }
+
+    #[test]
+    fn test_for_kind() {
+        let policies = [
+            parse_kind_policy("talk=none").unwrap(),
+            parse_kind_policy("talk=query").unwrap(),
+        ];
+        assert!(KindPolicy::for_kind(&policies, AudioKind::Talk).query);
+        assert!(!KindPolicy::for_kind(&policies, AudioKind::Talk).insert);
+        assert_eq!(
+            KindPolicy::for_kind(&policies, AudioKind::Music),
+            KindPolicy::default()
+        );
+    }
+}
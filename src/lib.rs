@@ -0,0 +1,4799 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clap::{ArgEnum, CommandFactory, Parser, Subcommand};
+use futures_util::future::try_join_all;
+use futures_util::stream::FuturesUnordered;
+use hls_m3u8::{MediaPlaylist, MediaSegment};
+use rand::Rng;
+use reqwest::header::{
+    HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED,
+};
+use reqwest::{StatusCode, Url};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
+use tracing::{info_span, Instrument, Span};
+use uuid::Uuid;
+
+mod backpressure;
+#[doc(hidden)]
+pub mod bench;
+mod byte_budget;
+mod circuit_breaker;
+mod dash;
+#[cfg(feature = "decode")]
+mod decode;
+mod drift;
+mod emysound;
+mod enrich;
+mod error_policy;
+mod exit_code;
+mod export;
+mod filename;
+mod fingerprinter;
+mod fleet;
+mod gzip;
+mod init_segment;
+mod kind_policy;
+mod match_cache;
+#[cfg(feature = "otlp")]
+mod otlp;
+mod pause;
+mod rate_limit;
+mod recent_inserts;
+mod replay;
+mod schedule;
+mod segment_filter;
+mod segment_info;
+mod sequence_gap;
+#[cfg(feature = "serve")]
+mod serve;
+mod shutdown;
+mod stall;
+mod storage;
+mod summary;
+mod tags;
+mod variant;
+mod wav;
+
+use crate::backpressure::LoadShedder;
+use crate::byte_budget::{parse_byte_budget, parse_size, ByteBudgets};
+use crate::circuit_breaker::{CircuitBreaker, CircuitOpen};
+use crate::drift::{Cadence, DriftTracker};
+use crate::emysound::{EmySound, Inserted, QueryResult, TrackInfo};
+use crate::enrich::Enricher;
+use crate::error_policy::{ErrorPolicy, FailureTracker};
+pub use crate::exit_code::exit_code;
+use crate::exit_code::{Failure, EXIT_CODES};
+use crate::export::ExportFormat;
+use crate::filename::{Field, FilenameTemplate};
+use crate::fingerprinter::Fingerprinter;
+use crate::fleet::{FleetStream, StreamsFrom};
+use crate::init_segment::InitSegments;
+use crate::kind_policy::{parse_kind_policy, KindPolicy};
+use crate::match_cache::{CachedMatch, MatchCache};
+use crate::pause::PauseSwitch;
+use crate::recent_inserts::RecentInserts;
+use crate::replay::ReplayPlaylists;
+use crate::schedule::{parse_schedule_window, Schedule, ScheduleWindow};
+use crate::segment_filter::{SegmentDownloadFilter, SegmentKey, SegmentNumberFilter};
+use crate::segment_info::{
+    extract_attributes, parse_song_spot, read_media_base_ids, AdContext, IcyParser,
+    KostaRadioParser, MediaBaseIdBlacklist, SegmentMetadataParser, SongSpots, SpotKinds,
+    SuggestedSegmentContentKind, TimedMetadata, UntitledFallback,
+};
+use crate::sequence_gap::SequenceGapDetector;
+use crate::shutdown::{cancellable, sleep, Cancelled};
+use crate::stall::{StallAction, StallDetector};
+use crate::storage::{
+    AudioData, AudioKind, KindSource, MatchData, Metadata, PlaylistResponse, SegmentTiming,
+    TrackIds,
+};
+use crate::storage::{
+    AudioStorage, AudioStore, DiagnosticsStorage, Durability, FileAudioStore, IdMapStorage,
+    MatchesStorage, MetadataStorage, OnCorrupt, Recovery,
+};
+use crate::summary::{Summary, SUMMARY_TARGET};
+use crate::tags::SegmentTags;
+use crate::variant::{Variant, VariantChoice};
+
+// Within the directory of the storages, see `--data-dir` and `--db`.
+const METADATA_STORAGE_PATH: &str = "metadata.sqlite3";
+const AUDIO_STORAGE_PATH: &str = "audio.sqlite3";
+const MATCHES_STORAGE_PATH: &str = "matches.sqlite3";
+const ID_MAP_STORAGE_PATH: &str = "id_map.sqlite3";
+const DIAGNOSTICS_STORAGE_PATH: &str = "diagnostics.sqlite3";
+const AUDIO_S3_INDEX_PATH: &str = "audio_s3_index.sqlite3";
+
+/// Delay before polling again after a failed or unusable playlist response.
+const PLAYLIST_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Characters of an unexpected playlist response kept for diagnostics.
+const PLAYLIST_BODY_SAMPLE_CHARS: usize = 512;
+
+/// Score recorded for a segment matched to a recent insert rather than by emysound.
+const RECENT_INSERT_SCORE: u8 = 100;
+
+/// How long after an insert an empty query result may be due to emysound still indexing it,
+/// see `--emysound-index-delay`.
+const INDEXING_WINDOW: Duration = Duration::from_secs(300);
+
+/// Short audio whose plays are counted at most, see `--jingle-min-plays`.
+const JINGLE_ROTATION_KEYS: usize = 10_000;
+
+/// Minimal score of a match to an id mapped but not stored to take it for our own
+/// interrupted insert.
+const INTERRUPTED_INSERT_SCORE: u8 = 95;
+
+#[derive(Debug, Parser)]
+#[clap(subcommand_negates_reqs = true, after_help = EXIT_CODES)]
+struct Args {
+    /// Stream URL (m3u8 file). This and paths given as options may refer to environment
+    /// variables as `${NAME}` or `$NAME`, `$$` stands for a literal `$`
+    #[clap(
+        required_unless_present_any = &["replay-dir", "stream", "streams-from"],
+        parse(try_from_str = expand_env)
+    )]
+    stream_url: Option<String>,
+
+    /// Capture the stream at `URL` as `NAME=URL`, `NAME` being its stream id, instead of the
+    /// stream URL. Can be repeated to capture several streams at once
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        value_name = "NAME=URL",
+        conflicts_with_all = &["stream-url", "replay-dir", "stream-id"],
+        parse(try_from_str = parse_named_stream)
+    )]
+    stream: Vec<(String, Url)>,
+
+    /// Directory of the storages that streams without a `--db` share, and that subcommands
+    /// read. `${NAME}` and `$NAME` take the value of environment variables, as in
+    /// `${DATA_DIR}/feeder`
+    #[clap(
+        long,
+        default_value = ".",
+        value_name = "DIR",
+        parse(try_from_str = expand_env_path)
+    )]
+    data_dir: PathBuf,
+
+    /// Keep the storages of the `--stream` `NAME` in `DIR` as `NAME=DIR`, apart from the
+    /// ones in `--data-dir` that other streams share. A relative `--audio-dir` is taken
+    /// within `DIR`. Subcommands read `--data-dir`, point it at `DIR` for the storages of
+    /// the stream
+    #[clap(
+        long,
+        multiple_occurrences = true,
+        value_name = "NAME=DIR",
+        parse(try_from_str = parse_stream_db)
+    )]
+    db: Vec<(String, PathBuf)>,
+
+    /// Capture the streams listed by a file, re-read at SIGHUP, or by stdin as `-`, a stream
+    /// per line as `URL` or `NAME=URL`. Lists on stdin end with an empty line. Streams
+    /// start and stop as the list changes, a stream that fails stays stopped until
+    /// the next list. Every stream uses the storages of `--data-dir`
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["stream-url", "stream", "replay-dir", "stream-id"],
+    )]
+    streams_from: Option<StreamsFrom>,
+
+    /// Replay `.m3u8` files of this directory in filename order instead of polling the stream,
+    /// reading segments from the files of the same name next to them
+    #[clap(long, value_name = "DIR", parse(try_from_str = expand_env_path))]
+    replay_dir: Option<PathBuf>,
+
+    /// Write each fetched playlist to a timestamped `.m3u8` file of this directory, to debug
+    /// the parser with or to replay with `--replay-dir`. DASH manifests are written as they
+    /// came in an `.mpd` file too, and bodies that are no playlist in a `.txt` file
+    #[clap(
+        long,
+        value_name = "DIR",
+        parse(try_from_str = expand_env_path),
+        conflicts_with = "replay-dir"
+    )]
+    dump_playlist: Option<PathBuf>,
+
+    /// Only write the playlists that fail to parse, and the manifests that fail to convert,
+    /// see `--dump-playlist`
+    #[clap(long, requires = "dump-playlist")]
+    dump_playlist_on_failure: bool,
+
+    /// Variant to capture if the stream URL is a master playlist: `highest` or `lowest`
+    /// bandwidth, a `BANDWIDTH` of the master playlist, or `all` of them at once. Captured
+    /// metadata records the bandwidth and resolution of the variant
+    #[clap(long, default_value = "highest")]
+    variant: VariantChoice,
+
+    /// Label stored with captured metadata to tell streams apart, the stream host if not set
+    #[clap(long)]
+    stream_id: Option<String>,
+
+    /// Reconcile content kinds of matched segments with the stored metadata of the match.
+    #[clap(long)]
+    learn_from_matches: bool,
+
+    /// Drop matches to stored tracks of another kind than the segment, e.g. an ad matching
+    /// a music track. emysound can't scope queries by kind, matches are filtered after.
+    /// Segments and tracks of unknown kind keep their matches
+    #[clap(long)]
+    filter_matches_by_kind: bool,
+
+    /// Score the best match must lead matches of other tracks by to be confident,
+    /// closer ones are ambiguous, see `--ambiguous-matches`
+    #[clap(long)]
+    min_score_margin: Option<u8>,
+
+    /// What becomes of ambiguous matches, see `--min-score-margin`
+    #[clap(long, arg_enum, default_value = "match")]
+    ambiguous_matches: AmbiguousMatches,
+
+    /// Treat a backward jump of segment numbers larger than this as a sequence reset
+    /// (e.g. a server restarting `EXT-X-MEDIA-SEQUENCE` daily) instead of old segments.
+    #[clap(long)]
+    segment_number_offset: Option<usize>,
+
+    /// Randomly stretch or shrink each poll interval by up to this percentage,
+    /// so several feeders started together don't poll in lockstep.
+    #[clap(long, default_value = "10")]
+    poll_jitter: u8,
+
+    /// Format of the segment metadata carried in EXTINF titles
+    #[clap(long, arg_enum, default_value = "kostaradio")]
+    metadata_format: MetadataFormat,
+
+    /// Classify segments of this `media_base_id` as unknown, whatever their metadata says,
+    /// e.g. station promos that pass for music. Can be repeated.
+    #[clap(long, multiple_occurrences = true, value_name = "ID")]
+    blacklist_media_base_id: Vec<i64>,
+
+    /// File of `media_base_id`s to blacklist, one per line, `#` starts a comment
+    #[clap(long, value_name = "PATH", parse(try_from_str = expand_env_path))]
+    blacklist_media_base_ids_from: Option<PathBuf>,
+
+    /// Where to keep segment audio
+    #[clap(long, arg_enum, default_value = "sqlite", global = true)]
+    audio_backend: AudioBackend,
+
+    /// Deflate the audio of segments in formats that aren't compressed already, like WAV
+    /// and MPEG-TS, in the `sqlite` audio backend. Rows stored either way read back the same
+    #[clap(long)]
+    compress_audio: bool,
+
+    /// How ids of stored segments are made. `content` ids let stores of several feeders
+    /// be merged, with the same audio under the same id.
+    #[clap(long, arg_enum, default_value = "random", global = true)]
+    id_scheme: IdScheme,
+
+    /// Directory for the `files` audio backend
+    #[clap(long, default_value = "./audio", global = true, parse(try_from_str = expand_env_path))]
+    audio_dir: PathBuf,
+
+    /// Names segment files of the `files` audio backend instead of `{id}.{ext}`, see
+    /// `--emysound-filename-template` for the placeholders. Names must include `{id}` to stay
+    /// apart, slashes become `_`
+    #[clap(long, global = true, parse(try_from_str = parse_audio_filename_template))]
+    audio_filename_template: Option<FilenameTemplate>,
+
+    /// Bucket for the `s3` audio backend
+    #[clap(long, global = true)]
+    s3_bucket: Option<String>,
+
+    /// Region of the `s3` bucket
+    #[clap(long, default_value = "us-east-1", global = true)]
+    s3_region: String,
+
+    /// Endpoint of an S3-compatible service, AWS if not set
+    #[clap(long, global = true, parse(try_from_str = expand_env))]
+    s3_endpoint: Option<String>,
+
+    /// Access key for the `s3` backend, `AWS_ACCESS_KEY_ID` if not set
+    #[clap(long, global = true)]
+    s3_access_key: Option<String>,
+
+    /// Secret key for the `s3` backend, `AWS_SECRET_ACCESS_KEY` if not set
+    #[clap(long, global = true)]
+    s3_secret_key: Option<String>,
+
+    /// What to do when playlist polls or segment ingestion keep failing.
+    /// Either way a failed playlist poll is retried after a fixed 5s delay, there is no backoff,
+    /// but for 429 Too Many Requests, retried after as long as its `Retry-After` asks.
+    #[clap(long, arg_enum, default_value = "fail-fast")]
+    error_policy: ErrorPolicy,
+
+    /// Consecutive failures after which `fail-fast` exits
+    #[clap(long, default_value = "5")]
+    max_consecutive_failures: u32,
+
+    /// Polls in a row without new segments after which the stream counts as stalled,
+    /// reported again every as many polls. Off if not set.
+    #[clap(long, value_name = "POLLS")]
+    stall_polls: Option<u32>,
+
+    /// What to do about a stalled stream
+    #[clap(long, arg_enum, default_value = "warn")]
+    on_stall: StallAction,
+
+    /// URL to POST `{"stream_id": .., "stalled_polls": ..}` to when the stream stalls
+    #[clap(long, value_name = "URL")]
+    stall_webhook: Option<Url>,
+
+    /// Seconds after inserting a music track during which an unmatched segment with the same
+    /// artist and title counts as a match of that track, 0 disables.
+    /// Covers the delay before emysound indexes a fresh insert.
+    #[clap(long, default_value = "0")]
+    dedup_window: u64,
+
+    /// How many segments to remember by the hash of their audio along with what they
+    /// resolved to, 0 disables. A segment with the very same audio is then counted as a match
+    /// without querying emysound, which saves most queries of repeated jingles and ads.
+    #[clap(long, default_value = "0")]
+    match_cache_size: usize,
+
+    /// Seconds between `PRAGMA wal_checkpoint(TRUNCATE)` of every storage, checked after
+    /// each poll. Bounds the `-wal` files of databases in WAL mode and so the time to
+    /// recover them after a crash. Off if not set, sqlite then checkpoints on its own
+    /// without ever shrinking the files.
+    #[clap(long, value_name = "SECS")]
+    wal_checkpoint_interval: Option<u64>,
+
+    /// Seconds to wait before querying emysound once more when a music segment finds no match
+    /// while a track with the same artist and title was inserted moments ago, in case emysound
+    /// hasn't indexed it yet. Off if not set.
+    #[clap(long, value_name = "SECS")]
+    emysound_index_delay: Option<u64>,
+
+    /// emysound failures in a row after which it isn't called for `--emysound-cooldown`,
+    /// segments that would be inserted are stored locally meanwhile and flagged pending sync.
+    /// Once emysound is back they are queried again, and inserted unless they match.
+    /// Off if not set, each segment calls emysound and counts its failure towards
+    /// `--error-policy`
+    #[clap(long, value_name = "COUNT")]
+    emysound_failure_threshold: Option<u32>,
+
+    /// Seconds emysound isn't called once `--emysound-failure-threshold` is reached, a single
+    /// call is tried after then
+    #[clap(long, default_value = "60", value_name = "SECS")]
+    emysound_cooldown: u64,
+
+    /// Stop storing new segments of a kind once this much of it was stored within
+    /// `--byte-budget-period`, e.g. `advertisement=500M`. Segments are still matched and logged.
+    #[clap(long, value_name = "KIND=SIZE", parse(try_from_str = parse_byte_budget))]
+    max_bytes_per_kind: Vec<(AudioKind, u64)>,
+
+    /// Hours after which the `--max-bytes-per-kind` budgets start over
+    #[clap(long, default_value = "24")]
+    byte_budget_period: u64,
+
+    /// Skip segments larger than this, e.g. `16M`, told by their `Content-Length` or else
+    /// by reading no further. Guards against segment URLs of whole files. Not capped if not set
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    max_segment_bytes: Option<u64>,
+
+    /// Which emysound steps segments of a kind go through, e.g. `talk=none` or
+    /// `advertisement=query,insert`. Steps are `query`, `insert` on no match and `store-audio`,
+    /// the ones not listed are off. Kinds not given go through all of them.
+    #[clap(long, value_name = "KIND=STEPS", parse(try_from_str = parse_kind_policy))]
+    kind_policy: Vec<(AudioKind, KindPolicy)>,
+
+    /// Kinds a station-specific KostaRadio `song_spot` code stands for, e.g. `C=advertisement`
+    /// or `S=music,advertisement`, the track attributes decide between them. Kinds are `music`,
+    /// `talk`, `advertisement` or `none`. Defaults are `M=music`, `F=music,advertisement` and
+    /// `T=talk`, segments of other codes are classified by `adContext` only.
+    #[clap(long, value_name = "CODE=KINDS", parse(try_from_str = parse_song_spot))]
+    song_spot: Vec<(char, SpotKinds)>,
+
+    /// Capture only within these weekly windows, e.g. `Mon-Fri 18:00-20:00 Europe/Amsterdam`
+    /// or `Sat,Sun 22:00-02:00`. Polling goes on outside of them, so that segments aired
+    /// meanwhile are not downloaded once a window opens. Captures all the time if not given.
+    #[clap(long, value_name = "DAYS HH:MM-HH:MM [TZ]", parse(try_from_str = parse_schedule_window))]
+    schedule: Vec<ScheduleWindow>,
+
+    /// Accept any TLS certificate of the stream server, e.g. a self-signed one
+    #[clap(long)]
+    insecure: bool,
+
+    /// Additional PEM certificate to trust for the stream server, e.g. of a private CA
+    #[clap(long, parse(try_from_str = expand_env_path))]
+    ca_cert: Option<PathBuf>,
+
+    /// Talk HTTP/2 to the stream server right away, without negotiating it.
+    /// Over TLS HTTP/2 is already picked whenever the server offers it,
+    /// this is for servers known to speak it over plain HTTP.
+    #[clap(long)]
+    http2_prior_knowledge: bool,
+
+    /// Seconds an idle connection to the stream server is kept for reuse, 0 keeps it forever.
+    /// Should exceed the segment duration, or every poll opens a new connection.
+    #[clap(long, default_value = "90")]
+    pool_idle_timeout: u64,
+
+    /// Idle connections kept per host, a playlist and a segment download at a time need two
+    #[clap(long, default_value = "4")]
+    pool_max_idle_per_host: usize,
+
+    /// Seconds between TCP keep-alive probes on connections to the stream server, 0 disables.
+    /// Keeps idle pooled connections from being dropped silently by NATs and load balancers.
+    #[clap(long, default_value = "60")]
+    tcp_keepalive: u64,
+
+    /// Start dropping segments of `--drop-order` kinds once more than this many
+    /// wait for ingestion, which happens when emysound answers slower than the stream plays
+    #[clap(long)]
+    max_pending_segments: Option<usize>,
+
+    /// Kinds of segments to drop when too many are pending, in order.
+    /// Kinds not listed are never dropped.
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        default_value = "advertisement,talk,unknown",
+        parse(try_from_str = parse_kind)
+    )]
+    drop_order: Vec<AudioKind>,
+
+    /// Seconds a segment may spend in download and emysound stages together before it is
+    /// abandoned and counted as a failure
+    #[clap(long, default_value = "120")]
+    segment_pipeline_timeout: u64,
+
+    /// Segment content types to accept, e.g. `audio/aac,audio/mpeg`; segments served with
+    /// any other type are skipped without reading their body. Accepts everything if empty.
+    #[clap(long, use_value_delimiter = true, value_name = "TYPES")]
+    segment_content_type_allowlist: Vec<String>,
+
+    /// Threads of the async runtime, the number of CPUs if not set.
+    /// Segments are still ingested one at a time, more threads only let storage writes,
+    /// downloads and the `serve` subcommand run alongside each other.
+    /// Blocking sqlite calls use a separate thread pool and don't count here.
+    #[clap(long, global = true)]
+    worker_threads: Option<usize>,
+
+    /// Export the spans of segment ingestion to this OTLP collector over gRPC,
+    /// e.g. `http://localhost:4317`. Needs the `otlp` feature
+    #[clap(long, value_name = "URL")]
+    otlp_endpoint: Option<Url>,
+
+    /// Open the databases read-only, for `stats` and `serve` next to a feeder run by another
+    /// user or on a read-only mount. Capturing needs write access and refuses this flag.
+    #[clap(long, global = true)]
+    read_only: bool,
+
+    /// What to do when a database is corrupt at startup, e.g. after a power loss
+    #[clap(long, arg_enum, default_value = "fail", global = true)]
+    on_corrupt: OnCorrupt,
+
+    /// How hard the databases work for stored segments to outlast an OS crash or a power
+    /// loss, trading off insert throughput. Journal syncs dominate the time of an insert,
+    /// which tells on streams of short segments
+    #[clap(long, arg_enum, default_value = "full", global = true)]
+    durability: Durability,
+
+    /// IANA time zone of segment filenames, of `--schedule` windows without their own one
+    /// and of times printed by `stats` and `tail`, e.g. `Europe/Berlin`.
+    /// Databases and exports keep UTC.
+    #[clap(long, global = true, default_value = "UTC", parse(try_from_str = parse_timezone))]
+    timezone: Tz,
+
+    /// Store a low-res spectrogram of each new segment for a quick look at captures.
+    /// Needs the `decode` feature.
+    #[clap(long)]
+    generate_preview: bool,
+
+    /// Classify segments whose playlist metadata tells no kind by their decoded audio,
+    /// speech as talk and anything steadier as music. Segments it can't tell stay unknown.
+    /// Needs the `decode` feature.
+    #[clap(long)]
+    classify_audio: bool,
+
+    /// Split segments whose audio changes content midway, e.g. from an ad to a song, where
+    /// loudness and timbre change the most, classifying and storing each part by its audio as
+    /// WAV. Needs the `decode` feature.
+    #[clap(long, requires = "classify-audio")]
+    split_segments: bool,
+
+    /// Cut the audio of each segment to its EXTINF duration, without the encoder delay and
+    /// padding of its container, querying and storing it as WAV. Segments shorter than
+    /// declared are kept whole, ones that can't be decoded as they are.
+    /// Needs the `decode` feature.
+    #[clap(long)]
+    trim_to_extinf: bool,
+
+    /// Count the plays of segments of unknown kind by the hash of their audio, classifying
+    /// the ones heard this many times as jingles. Their rotation shows in `stats`
+    #[clap(long, value_name = "PLAYS")]
+    jingle_min_plays: Option<u64>,
+
+    /// Longest jingle, told by its `song_spot` or counted by `--jingle-min-plays`, in seconds
+    #[clap(long, default_value = "12", value_name = "SECONDS")]
+    jingle_max_duration: u64,
+
+    /// Read the tags of each segment, taking artist and title from them where they say more
+    /// than the playlist, and storing album and year
+    #[clap(long)]
+    probe_tags: bool,
+
+    /// Take artist and title from the tags of each segment whenever they have them, for the
+    /// emysound filename and stored metadata alike. Implies `--probe-tags`
+    #[clap(long)]
+    segment_name_from_tags: bool,
+
+    /// Take artist and title from in-band ID3 timed metadata of each segment where it has
+    /// them, over those of the playlist and of `--probe-tags`
+    #[clap(long, conflicts_with = "segment-name-from-tags")]
+    timed_metadata: bool,
+
+    /// Name segments for emysound with the extension of their format instead of the one in
+    /// their URL, for extensionless or mislabeled URLs like `.ts` serving ADTS: the type
+    /// detected in the audio, else the declared content type
+    #[clap(long)]
+    segment_extension_override: bool,
+
+    /// Name of segments sent to emysound, which takes it as the display name of the file and
+    /// its extension as a format hint. The track itself is named by artist and title apart.
+    /// Placeholders: `{time}`, `{number}`, `{kind}`, `{artist}`, `{title}`, `{stream}`,
+    /// `{name}` as the last URL path segment, `{ext}` as its extension, e.g.
+    /// `{artist} - {title}.{ext}`
+    #[clap(
+        long,
+        default_value = filename::DETAILED,
+        parse(try_from_str = parse_emysound_filename_template)
+    )]
+    emysound_filename_template: FilenameTemplate,
+
+    /// Shell command run before the metadata of a new track is stored, reading it as a JSON
+    /// object on stdin and writing it back to stdout: `artist`, `title`, `album` and `year`
+    /// replace the stored ones, `external_ids` adds ids of other catalogs, e.g.
+    /// `{"musicbrainz": "..."}`. The metadata is stored as it was if the command fails
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        conflicts_with = "enrich-url",
+        global = true
+    )]
+    enrich_command: Option<String>,
+
+    /// Endpoint the metadata of new tracks is POSTed to as JSON before it is stored,
+    /// answering like `--enrich-command`
+    #[clap(long, value_name = "URL", global = true)]
+    enrich_url: Option<Url>,
+
+    /// Seconds to wait for `--enrich-command` or `--enrich-url`
+    #[clap(long, default_value = "5", global = true)]
+    enrich_timeout: u64,
+
+    /// Print what became of each segment to stdout, one JSON object per line,
+    /// and keep the logs on stderr
+    #[clap(long)]
+    emit_ndjson: bool,
+
+    /// Log only warnings and errors, plus a summary line of each poll that ingested segments
+    /// and one at the end of the run
+    #[clap(long, global = true)]
+    quiet: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Copy, Clone, ArgEnum)]
+enum MetadataFormat {
+    /// KostaRadio/iHeart `title="..",artist="..",url="song_spot=.."` attributes
+    #[clap(name = "kostaradio")]
+    KostaRadio,
+    /// ICY `StreamTitle='Artist - Title';`
+    Icy,
+}
+
+impl MetadataFormat {
+    fn parser(
+        self,
+        song_spots: &[(char, SpotKinds)],
+        max_jingle_length: Duration,
+    ) -> Box<dyn SegmentMetadataParser> {
+        match self {
+            MetadataFormat::KostaRadio => Box::new(KostaRadioParser::new(
+                SongSpots::new(song_spots),
+                max_jingle_length,
+            )),
+            MetadataFormat::Icy => Box::new(IcyParser),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, ArgEnum)]
+enum AudioBackend {
+    /// Blobs in a single sqlite database
+    Sqlite,
+    /// A file per segment plus a sqlite index
+    Files,
+    /// An object per segment in S3-compatible storage plus a local sqlite index
+    S3,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+enum AmbiguousMatches {
+    /// Record the matches anyway, the ambiguity is only logged
+    Match,
+    /// Take the segment for unmatched and insert it as new audio
+    Insert,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+enum IdScheme {
+    /// A random id per stored segment
+    Random,
+    /// An id derived from the audio bytes, the same on every feeder capturing them
+    Content,
+}
+
+/// Namespace of [`IdScheme::Content`] ids, never to be changed or ids stop matching.
+const CONTENT_ID_NAMESPACE: Uuid = Uuid::from_u128(0x5b0c_6a49_8a5e_4d3f_9f0e_2c1d_7e64_a3b1);
+
+impl IdScheme {
+    fn new_id(self, bytes: &[u8]) -> Uuid {
+        match self {
+            IdScheme::Random => Uuid::new_v4(),
+            IdScheme::Content => Uuid::new_v5(&CONTENT_ID_NAMESPACE, bytes),
+        }
+    }
+}
+
+/// The client for playlists and segments.
+fn http_client(args: &Args) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(path) = &args.ca_cert {
+        let pem = std::fs::read(path).with_context(|| format!("Read {}", path.display()))?;
+        builder = builder.add_root_certificate(
+            reqwest::Certificate::from_pem(&pem)
+                .with_context(|| format!("Parse certificate {}", path.display()))?,
+        );
+    }
+
+    if args.insecure {
+        log::warn!("!!! TLS certificates are NOT verified, the stream can be tampered with !!!");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    if args.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+
+    let seconds = |secs: u64| (secs > 0).then(|| Duration::from_secs(secs));
+
+    builder
+        .pool_idle_timeout(seconds(args.pool_idle_timeout))
+        .pool_max_idle_per_host(args.pool_max_idle_per_host)
+        .tcp_keepalive(seconds(args.tcp_keepalive))
+        .build()
+        .context("Build HTTP client")
+}
+
+fn blacklisting_parser(args: &Args) -> Result<Box<dyn SegmentMetadataParser>> {
+    let mut ids = args.blacklist_media_base_id.clone();
+    if let Some(path) = &args.blacklist_media_base_ids_from {
+        ids.extend(read_media_base_ids(path)?);
+    }
+
+    let max_jingle_length = Duration::from_secs(args.jingle_max_duration);
+    let parser = args
+        .metadata_format
+        .parser(&args.song_spot, max_jingle_length);
+    if ids.is_empty() {
+        return Ok(parser);
+    }
+
+    log::info!("Blacklisted {} media_base_ids", ids.len());
+    Ok(Box::new(MediaBaseIdBlacklist::new(parser, ids)))
+}
+
+/// Checks the databases a feeder writes before opening them, see `--on-corrupt`.
+fn recover_storages(args: &Args, dir: &Path) -> Result<()> {
+    let audio = match args.audio_backend {
+        AudioBackend::Sqlite => storage_path(dir, AUDIO_STORAGE_PATH),
+        AudioBackend::Files => storage_path(dir, &args.audio_dir).join("index.sqlite3"),
+        AudioBackend::S3 => storage_path(dir, AUDIO_S3_INDEX_PATH),
+    };
+    let paths = [
+        storage_path(dir, METADATA_STORAGE_PATH),
+        audio,
+        storage_path(dir, MATCHES_STORAGE_PATH),
+        storage_path(dir, ID_MAP_STORAGE_PATH),
+        storage_path(dir, DIAGNOSTICS_STORAGE_PATH),
+    ];
+
+    for path in &paths {
+        match storage::recover(path, args.on_corrupt)? {
+            Recovery::Intact => {}
+            Recovery::Reindexed => {
+                log::warn!("{} had corrupt indexes, rebuilt them", path.display());
+            }
+            Recovery::Salvaged {
+                backup,
+                lost_tables,
+            } => {
+                log::warn!(
+                    "{} was corrupt, salvaged it and kept the corrupt file as {}",
+                    path.display(),
+                    backup.display()
+                );
+                if !lost_tables.is_empty() {
+                    log::warn!("Lost the rows of {}", lost_tables.join(", "));
+                }
+            }
+            Recovery::Recreated { backup } => {
+                log::warn!(
+                    "{} was corrupt, starting afresh and kept the corrupt file as {}",
+                    path.display(),
+                    backup.display()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `path` of a storage kept in `dir`, see `--data-dir` and `--db`.
+fn storage_path(dir: &Path, path: impl AsRef<Path>) -> PathBuf {
+    dir.join(path)
+}
+
+fn open_audio_store(args: &Args, dir: &Path) -> Result<Box<dyn AudioStore>> {
+    let sqlite = storage_path(dir, AUDIO_STORAGE_PATH);
+    let audio_dir = storage_path(dir, &args.audio_dir);
+    Ok(match (args.audio_backend, args.read_only) {
+        (AudioBackend::Sqlite, false) => {
+            Box::new(AudioStorage::new(&sqlite)?.with_compression(args.compress_audio))
+        }
+        (AudioBackend::Sqlite, true) => Box::new(AudioStorage::read_only(&sqlite)?),
+        (AudioBackend::Files, false) => Box::new(FileAudioStore::new(&audio_dir)?),
+        (AudioBackend::Files, true) => Box::new(FileAudioStore::read_only(&audio_dir)?),
+        (AudioBackend::S3, false) => open_s3_audio_store(args, dir)?,
+        (AudioBackend::S3, true) => bail!("`--read-only` is not supported by the s3 audio backend"),
+    })
+}
+
+#[cfg(feature = "s3")]
+fn open_s3_audio_store(args: &Args, dir: &Path) -> Result<Box<dyn AudioStore>> {
+    let config = storage::S3Config {
+        bucket: args
+            .s3_bucket
+            .clone()
+            .ok_or_else(|| anyhow!("`--s3-bucket` is required for the s3 audio backend"))?,
+        region: args.s3_region.clone(),
+        endpoint: args.s3_endpoint.clone(),
+        access_key: args.s3_access_key.clone(),
+        secret_key: args.s3_secret_key.clone(),
+    };
+
+    Ok(Box::new(storage::S3AudioStore::new(
+        &config,
+        &storage_path(dir, AUDIO_S3_INDEX_PATH),
+    )?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3_audio_store(_args: &Args, _dir: &Path) -> Result<Box<dyn AudioStore>> {
+    bail!("Built without the `s3` feature")
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serve read-only JSON endpoints over the stored data
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+    },
+    /// Print capture statistics
+    Stats {
+        /// Number of days to report, including today
+        #[clap(long, default_value = "7")]
+        days: u32,
+
+        /// Maximum number of tracks and of ads to list
+        #[clap(long, default_value = "20")]
+        limit: usize,
+    },
+    /// Write captured metadata to a file for spreadsheets and analysis
+    ExportMetadata {
+        #[clap(long, arg_enum, default_value = "csv")]
+        format: ExportFormat,
+
+        /// File to write
+        #[clap(long, parse(try_from_str = expand_env_path))]
+        out: PathBuf,
+
+        /// Include captures from this time on, RFC 3339 or YYYY-MM-DD
+        #[clap(long, parse(try_from_str = parse_time))]
+        from: Option<DateTime<Utc>>,
+
+        /// Include captures before this time, RFC 3339 or YYYY-MM-DD
+        #[clap(long, parse(try_from_str = parse_time))]
+        to: Option<DateTime<Utc>>,
+    },
+    /// Print new captures as they are stored, like `tail -f`
+    Tail {
+        /// Number of latest captures to print first
+        #[clap(long, default_value = "10")]
+        lines: usize,
+
+        /// Seconds between checks for new captures
+        #[clap(long, default_value = "2")]
+        interval: u64,
+    },
+    /// Query emysound once to check it is reachable, without touching the stream or storages
+    Check {
+        /// Audio clip to query with, a second of silence if not set
+        #[clap(long, parse(try_from_str = expand_env_path))]
+        clip: Option<PathBuf>,
+    },
+    /// Download one segment and print its format, tags and size, without touching emysound
+    /// or storages, to tell whether a station's segments can be captured
+    Probe {
+        /// Segment to download
+        url: Url,
+    },
+    /// Insert audio files of a local library into emysound and the storages,
+    /// taking artist and title from their tags or from `Artist - Title` file names
+    Import {
+        /// Directory to import, including subdirectories
+        #[clap(long, parse(try_from_str = expand_env_path))]
+        dir: PathBuf,
+
+        /// Kind to store the imported tracks as
+        #[clap(long, default_value = "music", parse(try_from_str = parse_kind))]
+        kind: AudioKind,
+    },
+    /// Print a shell completion script to stdout
+    #[clap(hide = true)]
+    Completions {
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Parses the command line and runs the feeder until it is done or shut down.
+pub fn start() -> Result<()> {
+    let args = Args::parse();
+
+    let mode = if args.emit_ndjson {
+        simplelog::TerminalMode::Stderr
+    } else {
+        simplelog::TerminalMode::Mixed
+    };
+    let color = simplelog::ColorChoice::Auto;
+    if args.quiet {
+        let summaries = simplelog::ConfigBuilder::new()
+            .add_filter_allow_str(SUMMARY_TARGET)
+            .build();
+        simplelog::CombinedLogger::init(vec![
+            simplelog::TermLogger::new(
+                simplelog::LevelFilter::Warn,
+                simplelog::Config::default(),
+                mode,
+                color,
+            ),
+            simplelog::TermLogger::new(simplelog::LevelFilter::Info, summaries, mode, color),
+        ])?;
+    } else {
+        simplelog::TermLogger::init(
+            simplelog::LevelFilter::Info,
+            simplelog::Config::default(),
+            mode,
+            color,
+        )?;
+    }
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    if let Some(worker_threads) = args.worker_threads {
+        if worker_threads == 0 {
+            return Err(anyhow!("`--worker-threads` must be at least 1").context(Failure::Config));
+        }
+        runtime.worker_threads(worker_threads);
+    }
+
+    let runtime = runtime.enable_all().build()?;
+    // Dropped before the runtime, which sends the last spans.
+    let _exporter = {
+        let _entered = runtime.enter();
+        export_spans(&args).context(Failure::Config)?
+    };
+    runtime.block_on(run(args))
+}
+
+#[cfg(feature = "otlp")]
+fn export_spans(args: &Args) -> Result<Option<otlp::Exporter>> {
+    args.otlp_endpoint
+        .as_ref()
+        .map(otlp::Exporter::install)
+        .transpose()
+}
+
+#[cfg(not(feature = "otlp"))]
+fn export_spans(args: &Args) -> Result<Option<std::convert::Infallible>> {
+    if args.otlp_endpoint.is_some() {
+        bail!("Built without the `otlp` feature");
+    }
+    Ok(None)
+}
+
+async fn run(args: Args) -> Result<()> {
+    if let Some(command) = &args.command {
+        return match command {
+            Command::Serve { addr } => run_server(&args, *addr).await,
+            Command::Stats { days, limit } => print_stats(&args, *days, *limit).await,
+            Command::ExportMetadata {
+                format,
+                out,
+                from,
+                to,
+            } => export_metadata(&args.data_dir, *format, out, *from, *to).await,
+            Command::Tail { lines, interval } => {
+                let interval = Duration::from_secs(*interval);
+                tail(&args.data_dir, args.timezone, *lines, interval).await
+            }
+            Command::Check { clip } => check(&EmySound, clip.as_deref()).await,
+            Command::Probe { url } => probe(&args, url).await,
+            Command::Import { dir, kind } => import(&args, &EmySound, dir, *kind).await,
+            Command::Completions { shell } => {
+                clap_complete::generate(
+                    *shell,
+                    &mut Args::command(),
+                    env!("CARGO_PKG_NAME"),
+                    &mut std::io::stdout(),
+                );
+                Ok(())
+            }
+        };
+    }
+
+    let streams = match &args.streams_from {
+        Some(_) => Vec::new(),
+        None => capture_streams(&args).context(Failure::Config)?,
+    };
+
+    let client = http_client(&args).context(Failure::Config)?;
+    let parser = blacklisting_parser(&args).context(Failure::Config)?;
+    let fingerprinter: Box<dyn Fingerprinter> = match args.emysound_failure_threshold {
+        None => Box::new(EmySound),
+        Some(threshold) => Box::new(CircuitBreaker::new(
+            Box::new(EmySound),
+            threshold,
+            Duration::from_secs(args.emysound_cooldown),
+        )),
+    };
+
+    // Streams kept in the same directory share its storages.
+    let mut storages: Vec<(PathBuf, Storages)> = Vec::new();
+    if args.streams_from.is_some() {
+        let shared = args.data_dir.clone();
+        storages.push((shared.clone(), Storages::open(&args, &shared).await?));
+    }
+    for stream in &streams {
+        if storages.iter().all(|(dir, _)| *dir != stream.dir) {
+            storages.push((
+                stream.dir.clone(),
+                Storages::open(&args, &stream.dir).await?,
+            ));
+        }
+    }
+
+    let pause = PauseSwitch::default();
+    pause.toggle_on_sigusr1()?;
+    let shutdown = CancellationToken::new();
+    shutdown::cancel_on_signals(&shutdown)?;
+
+    if let Some(from) = &args.streams_from {
+        let shared = Capture {
+            args: &args,
+            client: &client,
+            parser: parser.as_ref(),
+            fingerprinter: fingerprinter.as_ref(),
+            storages: &storages[0].1,
+            pause: &pause,
+            shutdown: &shutdown,
+            stream_id: String::new(),
+            last_checkpoint: Cell::new(Instant::now()),
+            resync_due: Cell::new(true),
+            unsyncable: RefCell::default(),
+        };
+        let lists = fleet::watch(from).await.context(Failure::Config)?;
+        return capture_fleet(&shared, lists).await;
+    }
+
+    let (mut captures, mut sources) = (Vec::new(), Vec::new());
+    for stream in streams {
+        let storages = storages
+            .iter()
+            .find(|(dir, _)| *dir == stream.dir)
+            .map(|(_, storages)| storages)
+            .expect("Storages of every stream are open");
+        let shared = Capture {
+            args: &args,
+            client: &client,
+            parser: parser.as_ref(),
+            fingerprinter: fingerprinter.as_ref(),
+            storages,
+            pause: &pause,
+            shutdown: &shutdown,
+            stream_id: stream.id,
+            last_checkpoint: Cell::new(Instant::now()),
+            resync_due: Cell::new(true),
+            unsyncable: RefCell::default(),
+        };
+        captures.push(shared);
+        sources.push(stream.source);
+    }
+
+    // The first stream or variant to fail stops the others.
+    try_join_all(
+        captures
+            .iter()
+            .zip(sources)
+            .map(|(shared, source)| capture_stream(shared, source)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Captures `source`, or the variants picked by `--variant` if it is a master playlist.
+async fn capture_stream(shared: &Capture<'_>, source: PlaylistSource) -> Result<()> {
+    let sources = match source {
+        PlaylistSource::Remote(stream_url) => {
+            select_variants(
+                shared.client,
+                stream_url,
+                shared.args.variant,
+                shared.shutdown,
+            )
+            .await?
+        }
+        source => vec![(source, None)],
+    };
+
+    try_join_all(
+        sources
+            .into_iter()
+            .map(|(source, variant)| capture(shared, source, variant)),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Captures the streams of each list of `--streams-from` as it comes, until shutdown.
+/// `fleet` is what the streams share, each one gets a copy with its own id.
+async fn capture_fleet(
+    fleet: &Capture<'_>,
+    mut lists: UnboundedReceiver<Vec<FleetStream>>,
+) -> Result<()> {
+    // Each start of a stream is told apart, one stopped and listed again runs anew while
+    // the capture stopped still winds down.
+    let mut running: HashMap<FleetStream, (u64, CancellationToken)> = HashMap::new();
+    let mut starts = 0u64;
+    let mut captures = FuturesUnordered::new();
+    let mut listening = true;
+
+    loop {
+        tokio::select! {
+            list = lists.recv(), if listening => {
+                let list = match list {
+                    Some(list) => list,
+                    None => {
+                        listening = false;
+                        continue;
+                    }
+                };
+
+                running.retain(|stream, (_, stop)| {
+                    let listed = list.contains(stream);
+                    if !listed {
+                        log::info!("Stopping {} at {}, it is no longer listed", stream.0, stream.1);
+                        stop.cancel();
+                    }
+                    listed
+                });
+                for stream in list {
+                    if running.contains_key(&stream) {
+                        continue;
+                    }
+                    log::info!("Starting {} at {}", stream.0, stream.1);
+                    starts += 1;
+                    let (start, stop) = (starts, fleet.shutdown.child_token());
+                    running.insert(stream.clone(), (start, stop.clone()));
+                    captures.push(async move {
+                        let shared = Capture {
+                            shutdown: &stop,
+                            stream_id: stream.0.clone(),
+                            last_checkpoint: Cell::new(Instant::now()),
+                            resync_due: Cell::new(true),
+                            unsyncable: RefCell::default(),
+                            ..*fleet
+                        };
+                        let source = PlaylistSource::Remote(stream.1.clone());
+                        let captured = capture_stream(&shared, source).await;
+                        (stream, start, captured)
+                    });
+                }
+            }
+            Some((stream, start, captured)) = captures.next() => {
+                if matches!(running.get(&stream), Some((running, _)) if *running == start) {
+                    running.remove(&stream);
+                }
+                match captured {
+                    Ok(()) => log::info!("Stopped {} at {}", stream.0, stream.1),
+                    Err(e) => log::error!(
+                        "Stopped {} at {}, until it is listed again: {e:#}",
+                        stream.0,
+                        stream.1
+                    ),
+                }
+            }
+            _ = fleet.shutdown.cancelled() => break,
+        }
+    }
+
+    // Every capture checks the shutdown and ends soon, with a summary of its own.
+    while captures.next().await.is_some() {}
+    Ok(())
+}
+
+/// The media playlists to capture of `stream_url`: itself, or the variants picked by
+/// `--variant` if it is a master playlist.
+async fn select_variants(
+    client: &reqwest::Client,
+    stream_url: Url,
+    choice: VariantChoice,
+    shutdown: &CancellationToken,
+) -> Result<Vec<(PlaylistSource, Option<Variant>)>> {
+    let fetched = async {
+        let response = client.get(stream_url.clone()).send().await?;
+        response.error_for_status()?.text().await
+    };
+    let fetched = match cancellable(shutdown, fetched).await {
+        Ok(fetched) => fetched,
+        // Nothing to capture.
+        Err(_) => return Ok(Vec::new()),
+    };
+    let content = match fetched {
+        Ok(content) => content,
+        // Polling retries by `--error-policy`, a master playlist fails to parse then.
+        Err(e) => {
+            log::warn!("Failed to tell whether {stream_url} is a master playlist: {e:#}");
+            return Ok(vec![(PlaylistSource::Remote(stream_url), None)]);
+        }
+    };
+    if !variant::is_master(&content) {
+        return Ok(vec![(PlaylistSource::Remote(stream_url), None)]);
+    }
+
+    let variants = variant::variants(&content, &stream_url).context(Failure::Network)?;
+    let available = variants
+        .iter()
+        .map(|variant| variant.bandwidth.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    let selected = variant::select(variants, choice);
+    if selected.is_empty() {
+        let e = anyhow!("No variant of {stream_url} to capture, the bandwidths are {available}");
+        return Err(e.context(Failure::Config));
+    }
+
+    Ok(selected
+        .into_iter()
+        .map(|variant| {
+            log::info!("Capturing variant {variant} at {}", variant.url);
+            (PlaylistSource::Remote(variant.url.clone()), Some(variant))
+        })
+        .collect())
+}
+
+/// What the captures of the variants of a stream share.
+struct Capture<'a> {
+    args: &'a Args,
+    client: &'a reqwest::Client,
+    parser: &'a dyn SegmentMetadataParser,
+    fingerprinter: &'a dyn Fingerprinter,
+    storages: &'a Storages,
+    pause: &'a PauseSwitch,
+    /// Stops the captures at SIGINT and SIGTERM, abandoning downloads in flight.
+    shutdown: &'a CancellationToken,
+    stream_id: String,
+    /// See `--wal-checkpoint-interval`.
+    last_checkpoint: Cell<Instant>,
+    /// Segments may be pending sync, see [`resync_pending`].
+    resync_due: Cell<bool>,
+    /// Segments pending sync that failed to sync, left for the next run.
+    unsyncable: RefCell<HashSet<Uuid>>,
+}
+
+/// Polls the playlists of `source` and ingests their segments, until the replay ends or
+/// an error stops it. Segment numbers, gaps, stalls and drift are followed per variant.
+async fn capture(
+    shared: &Capture<'_>,
+    mut source: PlaylistSource,
+    variant: Option<Variant>,
+) -> Result<()> {
+    let Capture {
+        args,
+        client,
+        parser,
+        fingerprinter,
+        storages,
+        pause,
+        shutdown,
+        ..
+    } = *shared;
+    // Diagnostics and stall alerts tell variants apart.
+    let capture_id = variant.as_ref().map_or_else(
+        || shared.stream_id.clone(),
+        |variant| format!("{}@{}", shared.stream_id, variant.bandwidth),
+    );
+    let dump = args.dump_playlist.as_deref().map(|dir| PlaylistDump {
+        dir,
+        capture_id: &capture_id,
+        on_failure: args.dump_playlist_on_failure,
+    });
+    // As do summaries, and streams when there are several.
+    let several = args.stream.len() > 1 || args.streams_from.is_some();
+    let of_variant = match (variant.as_ref(), several) {
+        (Some(variant), true) => format!(" of {} {variant}", shared.stream_id),
+        (Some(variant), false) => format!(" of {variant}"),
+        (None, true) => format!(" of {}", shared.stream_id),
+        (None, false) => String::new(),
+    };
+
+    let mut segment_number_filter = SegmentNumberFilter::new(args.segment_number_offset);
+    let mut sequence_gaps = SequenceGapDetector::default();
+    let mut drift = DriftTracker::default();
+    let mut last_cadence = None;
+    let mut stall = args.stall_polls.map(StallDetector::new);
+
+    let mut failures = FailureTracker::new(args.error_policy, args.max_consecutive_failures);
+    let mut state = IngestState::new(args, client);
+    let mut total = Summary::default();
+
+    let mut load_shedder = LoadShedder::new(args.max_pending_segments, args.drop_order.clone());
+
+    let mut schedule = Schedule::new(args.schedule.clone(), args.timezone);
+
+    let mut validators = PlaylistValidators::default();
+    let mut poll_interval = PLAYLIST_RETRY_INTERVAL;
+
+    // The end of the run is summarized however it ends, a fatal error included.
+    let captured: Result<()> = async {
+        loop {
+            if shutdown.is_cancelled() {
+                return Ok(());
+            }
+
+            let fetched = match &mut source {
+                PlaylistSource::Remote(stream_url) => {
+                    let diagnostics = &storages.diagnostics;
+                    let validators = &mut validators;
+                    let dump = dump.as_ref();
+                    fetch_playlist(client, stream_url, validators, diagnostics, dump, shutdown)
+                        .await
+                }
+                PlaylistSource::Replay(replay) => match replay.next()? {
+                    Some(content) => Ok(Poll::Playlist(Playlist {
+                        content,
+                        from_dash: false,
+                    })),
+                    None => {
+                        log::info!("Replay finished");
+                        return Ok(());
+                    }
+                },
+            };
+
+            let playlist = match fetched {
+                Ok(Poll::Playlist(playlist)) => playlist,
+                Ok(Poll::NotModified) => {
+                    failures.success();
+                    log::debug!("Playlist not modified");
+                    let stalled = stall.as_mut().and_then(StallDetector::observe_unchanged);
+                    if let Some(stalled_polls) = stalled {
+                        report_stall(args, client, &capture_id, stalled_polls).await?;
+                    }
+                    sleep(shutdown, jittered(poll_interval, args.poll_jitter)).await;
+                    continue;
+                }
+                Ok(Poll::Unexpected) => {
+                    sleep(
+                        shutdown,
+                        jittered(PLAYLIST_RETRY_INTERVAL, args.poll_jitter),
+                    )
+                    .await;
+                    continue;
+                }
+                Err(_) if shutdown.is_cancelled() => return Ok(()),
+                Err(e) => {
+                    failures.failure(e.context("Fetch playlist").context(Failure::Network))?;
+                    sleep(
+                        shutdown,
+                        jittered(PLAYLIST_RETRY_INTERVAL, args.poll_jitter),
+                    )
+                    .await;
+                    continue;
+                }
+            };
+
+            let parsed = MediaPlaylist::try_from(playlist.content.as_str());
+            if let Some(dump) = &dump {
+                dump.write("m3u8", &playlist.content, parsed.is_err()).await;
+            }
+
+            let m3u8 = match parsed {
+                Ok(m3u8) => m3u8,
+                Err(e) => {
+                    let e = anyhow::Error::from(e).context("Parse playlist");
+                    failures.failure(e.context(Failure::Network))?;
+                    sleep(
+                        shutdown,
+                        jittered(PLAYLIST_RETRY_INTERVAL, args.poll_jitter),
+                    )
+                    .await;
+                    continue;
+                }
+            };
+            failures.success();
+            poll_interval = m3u8.duration() / 2;
+
+            if let Some(missed) = sequence_gaps.observe(&m3u8) {
+                log::warn!(
+                    "Missed about {missed} segments since the last poll, \
+                    the playlist moved on faster than it was polled"
+                );
+            }
+
+            // Replayed playlists come at no cadence worth timing.
+            if matches!(source, PlaylistSource::Remote(_)) {
+                let segments = m3u8
+                    .segments
+                    .iter()
+                    .map(|(_, segment)| (segment.number(), segment.duration.duration()));
+                if let Some(cadence) = drift.observe(Instant::now(), segments) {
+                    record_cadence(&storages.diagnostics, &capture_id, &cadence).await;
+                    last_cadence = Some(cadence);
+                }
+            }
+
+            if let Some(stalled_polls) = stall.as_mut().and_then(|stall| stall.observe(&m3u8)) {
+                report_stall(args, client, &capture_id, stalled_polls).await?;
+            }
+
+            let playlist_url = match &source {
+                PlaylistSource::Remote(stream_url) => Some(stream_url),
+                PlaylistSource::Replay(_) => None,
+            };
+            let untitled = UntitledFallback::new(parser);
+            let downloads = segment_downloads(
+                &m3u8,
+                &mut segment_number_filter,
+                if playlist.from_dash {
+                    &untitled
+                } else {
+                    parser
+                },
+                &shared.stream_id,
+                variant.as_ref(),
+                playlist_url,
+            )
+            .await;
+
+            if pause.is_paused() {
+                log::info!("Paused, skipping {} segments", downloads.len());
+                sleep(shutdown, jittered(poll_interval, args.poll_jitter)).await;
+                continue;
+            }
+
+            if !schedule.is_active(Utc::now()) {
+                log::debug!(
+                    "Outside of the schedule, skipping {} segments",
+                    downloads.len()
+                );
+                sleep(shutdown, jittered(poll_interval, args.poll_jitter)).await;
+                continue;
+            }
+
+            let downloads = load_shedder.shed(downloads, |info| info.kind.into());
+
+            let mut stream = tokio_stream::iter(downloads);
+            while let Some(info) = stream.next().await {
+                // Stages run in child spans, `emit_decision` records the outcome on this one
+                // and is called outside of them. Events go to the log, spans are exported by
+                // `--otlp-endpoint`.
+                let span = info_span!(
+                    "segment",
+                    stream_id = %info.stream_id,
+                    number = info.key.number(),
+                    kind = %info.kind,
+                    decision = tracing::field::Empty,
+                );
+                let state = &mut state;
+                let ingesting = ingest_segment(
+                    args,
+                    client,
+                    fingerprinter,
+                    storages,
+                    state,
+                    &info,
+                    shutdown,
+                );
+                let ingested = ingesting.instrument(span).await;
+                // Whatever was abandoned or failed at shutdown is no failure of the stream.
+                if shutdown.is_cancelled() {
+                    return Ok(());
+                }
+                match ingested {
+                    Ok(()) => failures.success(),
+                    Err(e) => {
+                        state.cycle.record("failed");
+                        failures.failure(e.context(format!("Ingest {}", info.key)))?
+                    }
+                }
+            }
+
+            if !state.cycle.is_empty() {
+                log::info!(target: SUMMARY_TARGET, "Poll{of_variant} ingested {}", state.cycle);
+                state.cycle.drain_into(&mut total);
+            }
+
+            if std::mem::take(&mut state.stored_pending) {
+                shared.resync_due.set(true);
+            }
+            if shared.resync_due.replace(false) && !resync_pending(shared).await? {
+                shared.resync_due.set(true);
+            }
+
+            if let Some(interval) = args.wal_checkpoint_interval {
+                let last_checkpoint = shared.last_checkpoint.get();
+                if last_checkpoint.elapsed() >= Duration::from_secs(interval) {
+                    shared.last_checkpoint.set(Instant::now());
+                    storages.checkpoint().await;
+                }
+            }
+
+            // Replayed playlists are not live, there is nothing to wait for.
+            if matches!(source, PlaylistSource::Remote(_)) {
+                sleep(shutdown, jittered(poll_interval, args.poll_jitter)).await;
+            }
+        }
+    }
+    .await;
+
+    state.cycle.drain_into(&mut total);
+    log::info!(target: SUMMARY_TARGET, "Run{of_variant} ingested {total}");
+    if let Some(cadence) = last_cadence {
+        log::info!(
+            target: SUMMARY_TARGET,
+            "Segment cadence{of_variant} drifted {:+.2}s from declared durations in {:.0}s",
+            cadence.drift,
+            cadence.elapsed.as_secs_f64()
+        );
+    }
+    captured
+}
+
+/// Logs and stores the cadence of new segments, failing to store it is no reason to stop.
+async fn record_cadence(diagnostics: &DiagnosticsStorage, stream_id: &str, cadence: &Cadence) {
+    log::debug!(
+        "{} new segments of {:.1}s in {:.1}s, drifted {:+.2}s in {:.0}s",
+        cadence.segments,
+        cadence.declared.as_secs_f64(),
+        cadence.interval.as_secs_f64(),
+        cadence.drift,
+        cadence.elapsed.as_secs_f64()
+    );
+
+    let timing = SegmentTiming {
+        timestamp: Utc::now(),
+        stream_id: stream_id.to_owned(),
+        number: cadence.last_number as u64,
+        segments: cadence.segments as u32,
+        declared_seconds: cadence.declared.as_secs_f64(),
+        interval_seconds: cadence.interval.as_secs_f64(),
+        drift_seconds: cadence.drift,
+        elapsed_seconds: cadence.elapsed.as_secs_f64(),
+    };
+    if let Err(e) = diagnostics.record_segment_timing(&timing).await {
+        log::warn!("Failed to record segment timing: {e:#}");
+    }
+}
+
+/// Checks the capture arguments and opens the sources of playlists.
+fn capture_streams(args: &Args) -> Result<Vec<Stream>> {
+    if args.generate_preview && !cfg!(feature = "decode") {
+        bail!("`--generate-preview` needs a build with the `decode` feature");
+    }
+
+    if args.classify_audio && !cfg!(feature = "decode") {
+        bail!("`--classify-audio` needs a build with the `decode` feature");
+    }
+
+    if args.trim_to_extinf && !cfg!(feature = "decode") {
+        bail!("`--trim-to-extinf` needs a build with the `decode` feature");
+    }
+
+    if args.split_segments && !cfg!(feature = "decode") {
+        bail!("`--split-segments` needs a build with the `decode` feature");
+    }
+
+    if args.read_only {
+        bail!("`--read-only` only applies to query subcommands, capturing writes the databases");
+    }
+
+    if args.stream.is_empty() && !args.db.is_empty() {
+        bail!("`--db` applies to `--stream` only, other streams use `--data-dir`");
+    }
+
+    let shared = args.data_dir.clone();
+    if let Some(dir) = &args.replay_dir {
+        return Ok(vec![Stream {
+            id: args
+                .stream_id
+                .clone()
+                .unwrap_or_else(|| "replay".to_owned()),
+            source: PlaylistSource::Replay(ReplayPlaylists::new(dir)?),
+            dir: shared,
+        }]);
+    }
+
+    if args.stream.is_empty() {
+        let stream_url: Url = args
+            .stream_url
+            .as_deref()
+            .ok_or_else(|| anyhow!("No stream URL"))?
+            .parse()?;
+        log::debug!("Fetching {stream_url} ");
+        return Ok(vec![Stream {
+            id: args
+                .stream_id
+                .clone()
+                .or_else(|| stream_url.host_str().map(str::to_owned))
+                .unwrap_or_default(),
+            source: PlaylistSource::Remote(stream_url),
+            dir: shared,
+        }]);
+    }
+
+    for (i, (name, _)) in args.stream.iter().enumerate() {
+        if args.stream[..i].iter().any(|(other, _)| other == name) {
+            bail!("`--stream {name}=..` is given twice, stream names tell streams apart");
+        }
+    }
+    for (name, _) in &args.db {
+        if args.stream.iter().all(|(stream, _)| stream != name) {
+            bail!("`--db {name}=..` names no `--stream`");
+        }
+    }
+
+    Ok(args
+        .stream
+        .iter()
+        .map(|(name, url)| Stream {
+            id: name.clone(),
+            source: PlaylistSource::Remote(url.clone()),
+            dir: args
+                .db
+                .iter()
+                .rev()
+                .find(|(db, _)| db == name)
+                .map_or_else(|| shared.clone(), |(_, dir)| dir.clone()),
+        })
+        .collect())
+}
+
+/// A stream to capture, see `--stream`.
+struct Stream {
+    id: String,
+    source: PlaylistSource,
+    /// Where its storages are, see `--db`.
+    dir: PathBuf,
+}
+
+/// Where playlists come from: the live stream or a directory of captured ones.
+enum PlaylistSource {
+    Remote(Url),
+    Replay(ReplayPlaylists),
+}
+
+/// Outcome of a playlist poll.
+enum Poll {
+    Playlist(Playlist),
+    /// The server answered the conditional request with 304, the last playlist still holds.
+    NotModified,
+    /// Something else than a playlist, see the diagnostics.
+    Unexpected,
+}
+
+/// `ETag` and `Last-Modified` of the last playlist, sent back for conditional requests.
+#[derive(Default)]
+struct PlaylistValidators {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+/// Warns of a stall, alerts the webhook if any, and fails if stalls are to end the capture.
+async fn report_stall(
+    args: &Args,
+    client: &reqwest::Client,
+    stream_id: &str,
+    stalled_polls: u32,
+) -> Result<()> {
+    log::warn!("No new segments in {stalled_polls} polls, the stream looks stalled");
+
+    if let Some(webhook) = &args.stall_webhook {
+        let sent = send_stall_alert(client, webhook, stream_id, stalled_polls).await;
+        if let Err(e) = sent {
+            log::error!("Failed to send the stall alert to {webhook}: {e:#}");
+        }
+    }
+
+    if args.on_stall == StallAction::Exit {
+        bail!("Stream stalled for {stalled_polls} polls");
+    }
+    Ok(())
+}
+
+/// An HLS media playlist, maybe made of a DASH manifest.
+struct Playlist {
+    content: String,
+    /// DASH segments have no titles, see [`UntitledFallback`].
+    from_dash: bool,
+}
+
+/// Where the playlists of a capture are written, see `--dump-playlist`.
+struct PlaylistDump<'a> {
+    dir: &'a Path,
+    capture_id: &'a str,
+    /// See `--dump-playlist-on-failure`.
+    on_failure: bool,
+}
+
+impl PlaylistDump<'_> {
+    /// Writes `content` as a file of `extension`, unless only failures are written and it
+    /// didn't fail. Failing to write it is only logged.
+    async fn write(&self, extension: &str, content: &str, failed: bool) {
+        if self.on_failure && !failed {
+            return;
+        }
+        match replay::dump(self.dir, self.capture_id, Utc::now(), extension, content).await {
+            Ok(path) if failed => log::info!("Wrote the failed playlist to {}", path.display()),
+            Ok(path) => log::debug!("Wrote the playlist to {}", path.display()),
+            Err(e) => log::warn!("Failed to dump the playlist: {e:#}"),
+        }
+    }
+}
+
+async fn send_stall_alert(
+    client: &reqwest::Client,
+    webhook: &Url,
+    stream_id: &str,
+    stalled_polls: u32,
+) -> Result<()> {
+    client
+        .post(webhook.clone())
+        .json(&serde_json::json!({
+            "stream_id": stream_id,
+            "stalled_polls": stalled_polls,
+        }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// Polls the playlist, conditionally once the server sent validators for it.
+/// DASH manifests, told by the content type or the `.mpd` extension, are turned into playlists.
+async fn fetch_playlist(
+    client: &reqwest::Client,
+    url: &Url,
+    validators: &mut PlaylistValidators,
+    diagnostics: &DiagnosticsStorage,
+    dump: Option<&PlaylistDump<'_>>,
+    shutdown: &CancellationToken,
+) -> Result<Poll> {
+    let mut request = client.get(url.clone());
+    if let Some(etag) = &validators.etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        request = request.header(IF_MODIFIED_SINCE, last_modified);
+    }
+    let response = rate_limit::send(request, None, shutdown).await?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(Poll::NotModified);
+    }
+    if response.status() != StatusCode::OK {
+        bail!(
+            "Failed to get playlist {}: {}",
+            response.status(),
+            cancellable(shutdown, response.text()).await??
+        );
+    }
+
+    log::debug!("Received stream playlist.");
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|content_type| content_type.to_str().map(|s| s.to_owned()))
+        .transpose()?;
+
+    // Only HLS playlists are polled conditionally, a DASH manifest may stay the same
+    // while its segment template moves on with the clock.
+    let received = PlaylistValidators {
+        etag: response.headers().get(ETAG).cloned(),
+        last_modified: response.headers().get(LAST_MODIFIED).cloned(),
+    };
+    *validators = PlaylistValidators::default();
+
+    let (content, body_sample) = match content_type.as_deref() {
+        Some("application/vnd.apple.mpegurl; charset=UTF-8") => {
+            *validators = received;
+            let content = cancellable(shutdown, response.text()).await??;
+            (
+                Some(Playlist {
+                    content,
+                    from_dash: false,
+                }),
+                None,
+            )
+        }
+        content_type if dash::is_dash(content_type, url) => {
+            let mpd = cancellable(shutdown, response.text()).await??;
+            let converted = dash::media_playlist(&mpd, url, Utc::now());
+            if let Some(dump) = dump {
+                dump.write("mpd", &mpd, converted.is_err()).await;
+            }
+            let content = converted.context("Read DASH")?;
+            (
+                Some(Playlist {
+                    content,
+                    from_dash: true,
+                }),
+                None,
+            )
+        }
+        _ => {
+            let body = cancellable(shutdown, response.text()).await??;
+            if let Some(dump) = dump {
+                dump.write("txt", &body, true).await;
+            }
+            let sample: String = body.chars().take(PLAYLIST_BODY_SAMPLE_CHARS).collect();
+            log::warn!("Unexpected playlist content type {content_type:?}: {sample}");
+            (None, Some(sample))
+        }
+    };
+
+    // Operators find out from the stats why nothing gets ingested.
+    diagnostics
+        .record_playlist_response(&PlaylistResponse {
+            timestamp: Utc::now(),
+            content_type,
+            body_sample,
+        })
+        .await
+        .context("Record playlist response")?;
+
+    Ok(content.map_or(Poll::Unexpected, Poll::Playlist))
+}
+
+/// Sorts `segments` by number, which [`SegmentNumberFilter`] needs to see ascending, and
+/// pairs them with their discontinuity sequence.
+fn in_number_order<'a, 'b>(
+    discontinuity_sequence: u64,
+    segments: impl Iterator<Item = &'b MediaSegment<'a>>,
+) -> Vec<(u64, &'b MediaSegment<'a>)> {
+    let mut segments: Vec<_> = segments.collect();
+    segments.sort_by_key(|segment| segment.number());
+
+    // EXT-X-DISCONTINUITY-SEQUENCE counts discontinuities before the first segment,
+    // every segment following an EXT-X-DISCONTINUITY starts the next one.
+    let mut discontinuity_sequence = discontinuity_sequence;
+    segments
+        .into_iter()
+        .map(|segment| {
+            if segment.has_discontinuity {
+                discontinuity_sequence += 1;
+            }
+            (discontinuity_sequence, segment)
+        })
+        .collect()
+}
+
+/// URL of the `EXT-X-MAP` init segment of `segment`, relative to `playlist_url`. Byte
+/// ranges of a larger file are not supported, nor relative URIs in replays, which have no
+/// playlist URL.
+fn init_url(segment: &MediaSegment, playlist_url: Option<&Url>) -> Option<Url> {
+    let map = segment.map.as_ref()?;
+    if map.range().is_some() {
+        log::warn!(
+            "Segment#{} init segment {} is a byte range, left out",
+            segment.number(),
+            map.uri()
+        );
+        return None;
+    }
+
+    let url = match playlist_url {
+        Some(playlist_url) => playlist_url.join(map.uri()),
+        None => map.uri().parse(),
+    };
+    match url {
+        Ok(url) => Some(url),
+        Err(e) => {
+            log::error!(
+                "Segment#{} invalid init segment url {}: {e}",
+                segment.number(),
+                map.uri()
+            );
+            None
+        }
+    }
+}
+
+/// Picks segments of `m3u8` not seen before and describes them for download.
+async fn segment_downloads(
+    m3u8: &MediaPlaylist<'_>,
+    segment_number_filter: &mut SegmentNumberFilter,
+    parser: &dyn SegmentMetadataParser,
+    stream_id: &str,
+    variant: Option<&Variant>,
+    playlist_url: Option<&Url>,
+) -> Vec<SegmentDownloadInfo> {
+    let segments = in_number_order(
+        m3u8.discontinuity_sequence as u64,
+        m3u8.segments.iter().map(|(_, segment)| segment),
+    )
+    .into_iter()
+    .filter(|(_, segment)| segment_number_filter.need_download(&SegmentKey::of(segment)))
+    .collect::<Vec<_>>();
+
+    let mut downloads = Vec::new();
+    for (discontinuity_sequence, segment) in segments {
+        let url: Option<Url> = segment.uri().parse().ok();
+        if url.is_none() {
+            log::error!("Segment#{} invalid url {}", segment.number(), segment.uri());
+            continue;
+        }
+        let url = url.unwrap();
+
+        match parser.parse(segment).await {
+            Ok(parsed) => {
+                let download_info = SegmentDownloadInfo {
+                    key: SegmentKey::of(segment),
+                    url,
+                    artist: parsed.artist,
+                    title: parsed.title,
+                    kind: parsed.kind,
+                    kind_source: (parsed.kind != SuggestedSegmentContentKind::None)
+                        .then(|| KindSource::Metadata),
+                    duration: segment.duration.duration(),
+                    ad_context: parsed.ad_context,
+                    ids: parsed.ids,
+                    stream_id: stream_id.to_owned(),
+                    discontinuity_sequence,
+                    discontinuity: segment.has_discontinuity,
+                    attributes: segment
+                        .duration
+                        .title()
+                        .as_deref()
+                        .map(extract_attributes)
+                        .unwrap_or_default(),
+                    album: None,
+                    year: None,
+                    init_url: init_url(segment, playlist_url),
+                    extension: None,
+                    variant: variant.cloned(),
+                };
+                let (artist, title) = (&download_info.artist, &download_info.title);
+                match download_info.kind {
+                    SuggestedSegmentContentKind::None => {
+                        log::info!(
+                            "Segment#{} DOWNLOAD: unknown kind, artist={artist}, title={title}",
+                            segment.number()
+                        );
+                        log::info!(
+                            "Segment#{} title={:?}",
+                            segment.number(),
+                            segment.duration.title()
+                        );
+                    }
+                    SuggestedSegmentContentKind::Talk => {
+                        log::info!(
+                            "Segment#{} DOWNLOAD: likely talk, artist: {artist}, title: {title}",
+                            segment.number()
+                        );
+                    }
+                    SuggestedSegmentContentKind::Advertisement => {
+                        log::info!("Segment#{} DOWNLOAD: likely advertisment, artist: {artist}, title: {title}", segment.number());
+                    }
+                    SuggestedSegmentContentKind::Music => {
+                        log::info!(
+                            "Segment#{} DOWNLOAD: likely music, artist: {artist}, title: {title}",
+                            segment.number()
+                        );
+                    }
+                    SuggestedSegmentContentKind::Jingle => {
+                        log::info!(
+                            "Segment#{} DOWNLOAD: likely jingle, artist: {artist}, title: {title}",
+                            segment.number()
+                        );
+                    }
+                }
+                downloads.push(download_info);
+            }
+            Err(e) => {
+                // Happens at the first download and sometimes in the middle then section changes. ignore.
+                log::info!("Segment#{} SKIPPED: no info: {e:#}", segment.number());
+                log::debug!(
+                    "Segment#{} title={:?}",
+                    segment.number(),
+                    segment.duration.title()
+                );
+            }
+        }
+    }
+    downloads
+}
+
+struct Storages {
+    metadata: MetadataStorage,
+    audio: Box<dyn AudioStore>,
+    matches: MatchesStorage,
+    id_map: IdMapStorage,
+    diagnostics: DiagnosticsStorage,
+}
+
+impl Storages {
+    /// Checks and opens the storages kept in `dir`, see `--db`.
+    async fn open(args: &Args, dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Create {}", dir.display()))
+            .context(Failure::Storage)?;
+        recover_storages(args, dir).context(Failure::Storage)?;
+
+        let storages = Self {
+            metadata: MetadataStorage::new(&storage_path(dir, METADATA_STORAGE_PATH))?,
+            audio: open_audio_store(args, dir)?,
+            matches: MatchesStorage::new(&storage_path(dir, MATCHES_STORAGE_PATH))?,
+            id_map: IdMapStorage::new(&storage_path(dir, ID_MAP_STORAGE_PATH))?,
+            diagnostics: DiagnosticsStorage::new(&storage_path(dir, DIAGNOSTICS_STORAGE_PATH))?,
+        };
+
+        let durability = args.durability;
+        storages.metadata.set_durability(durability).await?;
+        storages.audio.set_durability(durability).await?;
+        storages.matches.set_durability(durability).await?;
+        storages.id_map.set_durability(durability).await?;
+        storages.diagnostics.set_durability(durability).await?;
+        Ok(storages)
+    }
+
+    /// Checkpoints the write-ahead log of every storage, see `--wal-checkpoint-interval`.
+    /// A failed checkpoint is only logged, the next one catches up.
+    async fn checkpoint(&self) {
+        let checkpoints = [
+            ("metadata", self.metadata.checkpoint().await),
+            ("audio", self.audio.checkpoint().await),
+            ("matches", self.matches.checkpoint().await),
+            ("id map", self.id_map.checkpoint().await),
+            ("diagnostics", self.diagnostics.checkpoint().await),
+        ];
+
+        for (storage, checkpoint) in checkpoints {
+            match checkpoint {
+                Ok(checkpoint) if !checkpoint.is_wal() => {
+                    log::debug!("The {storage} storage is not in WAL mode, nothing to checkpoint")
+                }
+                Ok(checkpoint) if checkpoint.busy => log::warn!(
+                    "Checkpoint of the {storage} WAL blocked by a reader or writer, \
+                    {} of {} frames moved",
+                    checkpoint.checkpointed_frames,
+                    checkpoint.log_frames
+                ),
+                Ok(checkpoint) => log::info!(
+                    "Checkpointed {} frames of the {storage} WAL",
+                    checkpoint.checkpointed_frames
+                ),
+                Err(e) => log::warn!("Failed to checkpoint the {storage} WAL: {e:#}"),
+            }
+        }
+    }
+}
+
+/// What ingestion remembers from one segment to the next.
+struct IngestState {
+    recent_inserts: RecentInserts,
+    /// Music inserted recently enough to be still indexing, see `--emysound-index-delay`.
+    indexing: RecentInserts,
+    byte_budgets: ByteBudgets,
+    match_cache: MatchCache,
+    /// Decisions since the last poll, see `--quiet`.
+    cycle: Summary,
+    init_segments: InitSegments,
+    enricher: Option<Enricher>,
+    /// Track the segment before aired, see [`add_airplay`].
+    last_aired: Option<Uuid>,
+    /// A segment was stored pending sync since the last poll, see [`resync_pending`].
+    stored_pending: bool,
+}
+
+impl IngestState {
+    fn new(args: &Args, client: &reqwest::Client) -> Self {
+        Self {
+            recent_inserts: RecentInserts::new(Duration::from_secs(args.dedup_window)),
+            indexing: RecentInserts::new(if args.emysound_index_delay.is_some() {
+                INDEXING_WINDOW
+            } else {
+                Duration::ZERO
+            }),
+            byte_budgets: ByteBudgets::new(
+                &args.max_bytes_per_kind,
+                Duration::from_secs(args.byte_budget_period * 3600),
+            ),
+            match_cache: MatchCache::new(args.match_cache_size),
+            cycle: Summary::default(),
+            init_segments: InitSegments::default(),
+            enricher: enricher(args, client),
+            last_aired: None,
+            stored_pending: false,
+        }
+    }
+}
+
+/// The enricher of `--enrich-command` or `--enrich-url`, if any.
+fn enricher(args: &Args, client: &reqwest::Client) -> Option<Enricher> {
+    let timeout = Duration::from_secs(args.enrich_timeout);
+    match (&args.enrich_command, &args.enrich_url) {
+        (Some(command), _) => Some(Enricher::command(command.clone(), timeout)),
+        (None, Some(url)) => Some(Enricher::http(client.clone(), url.clone(), timeout)),
+        (None, None) => None,
+    }
+}
+
+/// Extension detected in the audio or else declared by `content_type`, see
+/// `--segment-extension-override`. `None` if neither tells.
+fn format_extension(content_type: &str, bytes: &[u8]) -> Option<&'static str> {
+    tags::detected_extension(bytes)
+        .or_else(|| Some(storage::extension(content_type)).filter(|ext| *ext != "bin"))
+}
+
+/// `matches` but those to stored tracks of another kind than `info`, see
+/// `--filter-matches-by-kind`.
+async fn matches_of_kind(
+    storages: &Storages,
+    matches: Vec<QueryResult>,
+    info: &SegmentDownloadInfo,
+) -> Result<Vec<QueryResult>> {
+    let kind: AudioKind = info.kind.into();
+    if kind == AudioKind::Unknown {
+        return Ok(matches);
+    }
+
+    let mut kept = Vec::with_capacity(matches.len());
+    for result in matches {
+        let id = storages
+            .id_map
+            .local_id(result.id())
+            .await?
+            .unwrap_or_else(|| result.id());
+        match storages.metadata.get(id).await {
+            Ok(matched) if matched.kind() != kind && matched.kind() != AudioKind::Unknown => {
+                log::info!(
+                    "`{}`/`{}` {} doesn't match {id} `{}`/`{}` {}",
+                    &info.artist,
+                    &info.title,
+                    kind.to_string(),
+                    matched.artist(),
+                    matched.title(),
+                    matched.kind().to_string()
+                );
+            }
+            _ => kept.push(result),
+        }
+    }
+    Ok(kept)
+}
+
+fn skip_ignored(args: &Args, state: &mut IngestState, info: &SegmentDownloadInfo) {
+    log::info!(
+        "`{}`/`{}` skipped, its kind is neither queried nor inserted",
+        &info.artist,
+        &info.title
+    );
+    emit_decision(args, state, info, Decision::Skipped, None, None);
+}
+
+async fn ingest_segment(
+    args: &Args,
+    client: &reqwest::Client,
+    fingerprinter: &dyn Fingerprinter,
+    storages: &Storages,
+    state: &mut IngestState,
+    info: &SegmentDownloadInfo,
+    shutdown: &CancellationToken,
+) -> Result<()> {
+    // Local storage writes run to completion once started, only the remote stages before them
+    // are abandoned, so that a timed out segment leaves nothing half-written behind.
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(args.segment_pipeline_timeout);
+
+    // Segments of unknown kind are downloaded to classify them, and all of them to split them,
+    // their policy applies after.
+    let classify = args.classify_audio && info.kind == SuggestedSegmentContentKind::None;
+    let counts_plays = counts_jingle_plays(args, info);
+    let policy = KindPolicy::for_kind(&args.kind_policy, info.kind.into());
+    if policy.is_ignored() && !classify && !counts_plays && !args.split_segments {
+        skip_ignored(args, state, info);
+        return Ok(());
+    }
+
+    let downloaded = match &args.replay_dir {
+        Some(dir) => replay::segment(dir, &info.url),
+        None => {
+            let allowlist = &args.segment_content_type_allowlist;
+            let max_bytes = args.max_segment_bytes;
+            let downloading = download(
+                client,
+                &info.url,
+                allowlist,
+                max_bytes,
+                Some(deadline),
+                shutdown,
+            );
+            within(deadline, downloading.instrument(info_span!("download"))).await?
+        }
+    };
+
+    let (audio_format, bytes) = match downloaded {
+        Ok(downloaded) => downloaded,
+        Err(e) if e.downcast_ref::<Cancelled>().is_some() => return Err(e),
+        Err(e) if e.is::<SegmentTooLarge>() => {
+            tracing::warn!("Skipped {}: {e:#}", info.url);
+            emit_decision(args, state, info, Decision::TooLarge, None, None);
+            return Ok(());
+        }
+        Err(e) => {
+            tracing::error!("Failed to download {}: {e:#}", info.url);
+            emit_decision(args, state, info, Decision::DownloadFailed, None, None);
+            return Ok(());
+        }
+    };
+
+    // fMP4 fragments are only decodable, probeable and queryable with their init segment.
+    let bytes = match info.init_url.as_ref() {
+        Some(url) if !init_segment::is_self_initialized(&bytes) => {
+            let fetched = fetch_init_segment(args, client, state, url, deadline, shutdown);
+            match within(deadline, fetched).await? {
+                Ok(init) if init_segment::is_fmp4_init(&init) => {
+                    init_segment::prepend(&init, &bytes)
+                }
+                Ok(_) => bytes,
+                Err(e) if e.downcast_ref::<Cancelled>().is_some() => return Err(e),
+                Err(e) => {
+                    tracing::warn!("Failed to fetch init segment {url}, continuing without: {e:#}");
+                    bytes
+                }
+            }
+        }
+        _ => bytes,
+    };
+
+    // A segment straddling a change of content, e.g. the end of an ad and the start of a song,
+    // is ambiguous as a whole. Its parts are classified and stored apart.
+    let parts = if args.split_segments {
+        split_at_boundary(&audio_format, &bytes).await
+    } else {
+        None
+    };
+    match parts {
+        Some(parts) => {
+            tracing::info!(
+                "`{}`/`{}` changes content after {:?}, ingesting its parts apart",
+                &info.artist,
+                &info.title,
+                parts[0].0
+            );
+            for (part, (duration, bytes)) in parts.into_iter().enumerate() {
+                let info = info.with_part(part + 1, duration);
+                let audio_format = "audio/wav".to_owned();
+                let ingesting = ingest_audio(
+                    args,
+                    fingerprinter,
+                    storages,
+                    state,
+                    &info,
+                    audio_format,
+                    bytes,
+                    deadline,
+                );
+                ingesting.await?;
+            }
+            Ok(())
+        }
+        None => {
+            let ingesting = ingest_audio(
+                args,
+                fingerprinter,
+                storages,
+                state,
+                info,
+                audio_format,
+                bytes,
+                deadline,
+            );
+            ingesting.await
+        }
+    }
+}
+
+/// Classifies, queries and stores the downloaded audio of a segment.
+#[allow(clippy::too_many_arguments)]
+async fn ingest_audio(
+    args: &Args,
+    fingerprinter: &dyn Fingerprinter,
+    storages: &Storages,
+    state: &mut IngestState,
+    info: &SegmentDownloadInfo,
+    audio_format: String,
+    bytes: Bytes,
+    deadline: tokio::time::Instant,
+) -> Result<()> {
+    let classify = args.classify_audio && info.kind == SuggestedSegmentContentKind::None;
+    let counts_plays = counts_jingle_plays(args, info);
+    let mut policy = KindPolicy::for_kind(&args.kind_policy, info.kind.into());
+
+    let recurring;
+    let info = match args.jingle_min_plays.filter(|_| counts_plays) {
+        Some(min_plays) => {
+            let plays = storages
+                .metadata
+                .record_jingle(
+                    &audio_key(&MatchCache::key(&bytes)),
+                    Utc::now(),
+                    &info.artist,
+                    &info.title,
+                    JINGLE_ROTATION_KEYS,
+                )
+                .await
+                .context("Record jingle")?;
+            if info.kind == SuggestedSegmentContentKind::None && plays >= min_plays {
+                tracing::info!(
+                    "`{}`/`{}` heard {plays} times, classified as jingle",
+                    &info.artist,
+                    &info.title
+                );
+                let kind = SuggestedSegmentContentKind::Jingle;
+                recurring = info.with_kind(kind, KindSource::Recurrence);
+                policy = KindPolicy::for_kind(&args.kind_policy, kind.into());
+                &recurring
+            } else {
+                info
+            }
+        }
+        None => info,
+    };
+
+    let classify = classify && info.kind == SuggestedSegmentContentKind::None;
+    let classified;
+    let info = match classify.then(|| classify_audio(&audio_format, &bytes)) {
+        Some(classifying) => match classifying.await {
+            Some(kind) => {
+                tracing::info!(
+                    "`{}`/`{}` classified as {kind} by its audio",
+                    &info.artist,
+                    &info.title
+                );
+                classified = info.with_kind(kind, KindSource::Audio);
+                policy = KindPolicy::for_kind(&args.kind_policy, kind.into());
+                &classified
+            }
+            None => info,
+        },
+        None => info,
+    };
+    if policy.is_ignored() {
+        skip_ignored(args, state, info);
+        return Ok(());
+    }
+
+    // Tags only enrich metadata, a segment lofty can't parse is still queried and stored.
+    let probe_tags = args.probe_tags || args.segment_name_from_tags;
+    let tagged;
+    let info = match probe_tags.then(|| info_span!("probe").in_scope(|| tags::probe(&bytes))) {
+        Some(Ok(tags)) => {
+            tagged = info.with_tags(&tags, args.segment_name_from_tags);
+            &tagged
+        }
+        Some(Err(e)) => {
+            tracing::warn!(
+                "Failed to probe {}, continuing as {audio_format}: {e:#}",
+                info.url
+            );
+            info
+        }
+        None => info,
+    };
+
+    // In-band timed metadata is sent along with the audio, so it beats playlist titles
+    // and tags alike.
+    let timed;
+    let info = match args
+        .timed_metadata
+        .then(|| segment_info::timed_metadata(&bytes))
+        .flatten()
+    {
+        Some(metadata) => {
+            timed = info.with_timed_metadata(&metadata);
+            &timed
+        }
+        None => info,
+    };
+
+    // Trimmed after tags and timed metadata are read, the WAV carries none.
+    let trimmed = if args.trim_to_extinf {
+        trim_to_extinf(&audio_format, &bytes, info.duration).await
+    } else {
+        None
+    };
+    let (audio_format, bytes) = match trimmed {
+        Some(wav) => ("audio/wav".to_owned(), wav),
+        None => (audio_format, bytes),
+    };
+
+    let renamed;
+    let info = match args
+        .segment_extension_override
+        .then(|| format_extension(&audio_format, &bytes))
+        .flatten()
+    {
+        Some(extension) => {
+            renamed = info.with_extension(extension);
+            &renamed
+        }
+        None => info,
+    };
+
+    let cache_key = MatchCache::key(&bytes);
+    if let Some(cached) = policy
+        .query
+        .then(|| state.match_cache.get(&cache_key))
+        .flatten()
+    {
+        tracing::info!(
+            "`{}`/`{}` is the same audio as {} seen recently, counting as a match",
+            &info.artist,
+            &info.title,
+            cached.id
+        );
+        let mut kind = info.kind.into();
+        if args.learn_from_matches {
+            if let Ok(matched) = storages.metadata.get(cached.id).await {
+                kind = learn_from_match(&storages.metadata, info, &matched)
+                    .await
+                    .context("Learn from match")?;
+            }
+        }
+        storages
+            .matches
+            .insert(
+                &MatchData::new(cached.id, Utc::now(), cached.score)
+                    .with_snapshot(Some(info.artist.clone()), Some(info.title.clone()))
+                    .with_kind(kind),
+            )
+            .await?;
+        add_airplay(storages, state, cached.id, info).await?;
+        // Later segments of the track with audio of their own that emysound doesn't match
+        // count as the cached track within `--dedup-window`, as they do after an insert.
+        if info.kind == SuggestedSegmentContentKind::Music {
+            state
+                .recent_inserts
+                .insert(&info.artist, &info.title, cached.id);
+        }
+
+        let score = Some(cached.score);
+        emit_decision(
+            args,
+            state,
+            info,
+            Decision::MatchedCached,
+            Some(cached.id),
+            score,
+        );
+        return Ok(());
+    }
+
+    let filename = info.filename(&args.emysound_filename_template, args.timezone, None);
+    // While emysound is unavailable a segment goes through the checks of an insert, and is
+    // stored pending sync instead of being inserted, see `resync_pending`.
+    let mut unavailable = false;
+    let mut matches = if policy.query {
+        let querying = fingerprinter.query(&filename, &bytes);
+        match within(deadline, querying.instrument(info_span!("query"))).await? {
+            Err(e) if e.is::<CircuitOpen>() => {
+                unavailable = true;
+                Vec::new()
+            }
+            matches => matches?,
+        }
+    } else {
+        Vec::new()
+    };
+
+    let is_music = info.kind == SuggestedSegmentContentKind::Music;
+
+    if let Some(delay) = args
+        .emysound_index_delay
+        .filter(|_| policy.query && !unavailable)
+    {
+        if matches.is_empty()
+            && is_music
+            && state
+                .recent_inserts
+                .get(&info.artist, &info.title)
+                .is_none()
+        {
+            if let Some(id) = state.indexing.get(&info.artist, &info.title) {
+                tracing::info!(
+                    "`{}`/`{}` didn't match {id} inserted moments ago, querying again in {delay}s",
+                    &info.artist,
+                    &info.title
+                );
+                within(deadline, tokio::time::sleep(Duration::from_secs(delay))).await?;
+                // Left unmatched if emysound became unavailable meanwhile, it is stored
+                // pending sync instead of being inserted.
+                let querying = fingerprinter.query(&filename, &bytes);
+                matches = match within(deadline, querying.instrument(info_span!("query"))).await? {
+                    Err(e) if e.is::<CircuitOpen>() => Vec::new(),
+                    matches => matches?,
+                };
+            }
+        }
+    }
+
+    if args.filter_matches_by_kind {
+        matches = matches_of_kind(storages, matches, info).await?;
+    }
+
+    let ambiguous = emysound::score_margin(&matches)
+        .filter(|margin| args.min_score_margin.map_or(false, |min| *margin < min));
+    if let Some(margin) = ambiguous {
+        tracing::warn!(
+            "`{}`/`{}` matches ambiguously, the best match leads another track by {margin} only",
+            &info.artist,
+            &info.title
+        );
+        if args.ambiguous_matches == AmbiguousMatches::Insert {
+            matches.clear();
+        }
+    }
+
+    if matches.is_empty() {
+        if let Some(id) = is_music
+            .then(|| state.recent_inserts.get(&info.artist, &info.title))
+            .flatten()
+        {
+            tracing::info!(
+                "`{}`/`{}` was inserted as {id} moments ago, counting as a match",
+                &info.artist,
+                &info.title
+            );
+
+            storages
+                .matches
+                .insert(
+                    &MatchData::new(id, Utc::now(), RECENT_INSERT_SCORE)
+                        .with_snapshot(Some(info.artist.clone()), Some(info.title.clone()))
+                        .with_kind(info.kind.into()),
+                )
+                .await?;
+            add_airplay(storages, state, id, info).await?;
+
+            let score = Some(RECENT_INSERT_SCORE);
+            emit_decision(
+                args,
+                state,
+                info,
+                Decision::MatchedRecentInsert,
+                Some(id),
+                score,
+            );
+            return Ok(());
+        }
+
+        let kind: AudioKind = info.kind.into();
+        if !policy.insert {
+            tracing::info!(
+                "`{}`/`{}` not inserted, the {} policy leaves out inserts",
+                &info.artist,
+                &info.title,
+                kind.to_string()
+            );
+            emit_decision(args, state, info, Decision::NotInserted, None, None);
+            return Ok(());
+        }
+        if !state.byte_budgets.try_spend(kind, bytes.len() as u64) {
+            tracing::info!(
+                "`{}`/`{}` not stored, the {} budget is exhausted",
+                &info.artist,
+                &info.title,
+                kind.to_string()
+            );
+            emit_decision(args, state, info, Decision::OverBudget, None, None);
+            return Ok(());
+        }
+
+        let id = args.id_scheme.new_id(&bytes);
+        // emysound accepts the id we give it, the mapping lets both sides diverge later.
+        let remote_id = id;
+
+        // The very same audio was stored before but emysound didn't match it, e.g. still
+        // indexing. Stored again it would clash with itself.
+        let stored = match args.id_scheme {
+            IdScheme::Content => match storages.metadata.get(id).await {
+                Ok(_) => true,
+                Err(e) if is_not_found(&e) => false,
+                Err(e) => return Err(e.context("Get metadata")),
+            },
+            IdScheme::Random => false,
+        };
+        if stored {
+            tracing::info!(
+                "`{}`/`{}` is stored already as {id}, counting as a match",
+                &info.artist,
+                &info.title
+            );
+            storages
+                .matches
+                .insert(
+                    &MatchData::new(id, Utc::now(), RECENT_INSERT_SCORE)
+                        .with_snapshot(Some(info.artist.clone()), Some(info.title.clone()))
+                        .with_kind(info.kind.into()),
+                )
+                .await?;
+            add_airplay(storages, state, id, info).await?;
+            let cached = CachedMatch {
+                id,
+                score: RECENT_INSERT_SCORE,
+            };
+            state.match_cache.insert(cache_key, cached);
+            let score = Some(RECENT_INSERT_SCORE);
+            emit_decision(args, state, info, Decision::MatchedStored, Some(id), score);
+            return Ok(());
+        }
+
+        // Enriched first, emysound gets the names the track is stored with.
+        let metadata = new_metadata(state, info, id).await;
+        if unavailable {
+            let storing =
+                store_unsynced(args, storages, state, info, metadata, audio_format, &bytes);
+            return storing.await;
+        }
+
+        tracing::info!(
+            "Insert new audio segment `{}`/`{}` {id}",
+            &info.artist,
+            &info.title
+        );
+        // Mapped before the insert, so that an insert interrupted by a restart is told apart
+        // from the tracks of others when its audio matches later.
+        storages
+            .id_map
+            .insert(id, remote_id)
+            .await
+            .context("Insert id mapping")?;
+        let track_info = TrackInfo::new(
+            remote_id,
+            metadata.artist().to_owned(),
+            metadata.title().to_owned(),
+        );
+        let inserting = fingerprinter.insert(track_info, &filename, &bytes);
+        let inserted = match within(deadline, inserting.instrument(info_span!("insert"))).await? {
+            Err(e) if e.is::<CircuitOpen>() => {
+                let storing =
+                    store_unsynced(args, storages, state, info, metadata, audio_format, &bytes);
+                return storing.await;
+            }
+            inserted => inserted?,
+        };
+        if inserted == Inserted::Existing {
+            tracing::warn!("emysound already has {remote_id}, storing it locally only");
+        }
+
+        let remote_id = Some(remote_id);
+        let storing = store_segment(
+            args,
+            storages,
+            state,
+            info,
+            metadata,
+            remote_id,
+            audio_format,
+            &bytes,
+        );
+        storing.instrument(info_span!("store")).await?;
+        let cached = CachedMatch {
+            id,
+            score: RECENT_INSERT_SCORE,
+        };
+        state.match_cache.insert(cache_key, cached);
+        emit_decision(args, state, info, Decision::Inserted, Some(id), None);
+
+        if is_music {
+            state.recent_inserts.insert(&info.artist, &info.title, id);
+            state.indexing.insert(&info.artist, &info.title, id);
+        }
+    } else {
+        let mut best: Option<(Uuid, u8)> = None;
+
+        for result in &matches {
+            tracing::info!(
+                "`{}`/`{}` matches  {} `{}`/`{}` {}",
+                &info.artist,
+                &info.title,
+                result.id(),
+                result.artist().as_ref().unwrap_or(&String::new()),
+                result.title().as_ref().unwrap_or(&String::new()),
+                result.score()
+            );
+
+            // Tracks inserted before the id mapping existed share the id with emysound.
+            let mapped = storages.id_map.local_id(result.id()).await?;
+            let id = mapped.unwrap_or_else(|| result.id());
+
+            let matched = storages.metadata.get(id).await;
+
+            // The same audio in emysound without a local record, under an id mapped before
+            // inserting it, is our own insert interrupted before the local storages were
+            // written. Complete it instead of recording a match to a track we know nothing
+            // about. Unmapped ids are someone else's, matched as usual.
+            if mapped.is_some()
+                && matched.as_ref().err().map_or(false, is_not_found)
+                && result.score() >= INTERRUPTED_INSERT_SCORE
+            {
+                tracing::warn!("{id} is in emysound only, completing its interrupted insert");
+                let remote_id = Some(result.id());
+                let metadata = new_metadata(state, info, id).await;
+                let storing = store_segment(
+                    args,
+                    storages,
+                    state,
+                    info,
+                    metadata,
+                    remote_id,
+                    audio_format,
+                    &bytes,
+                );
+                storing.instrument(info_span!("store")).await?;
+                let cached = CachedMatch {
+                    id,
+                    score: result.score(),
+                };
+                state.match_cache.insert(cache_key, cached);
+                let score = Some(result.score());
+                emit_decision(
+                    args,
+                    state,
+                    info,
+                    Decision::CompletedInsert,
+                    Some(id),
+                    score,
+                );
+                return Ok(());
+            }
+
+            let mut kind = info.kind.into();
+            if args.learn_from_matches {
+                if let Ok(matched) = &matched {
+                    kind = learn_from_match(&storages.metadata, info, matched)
+                        .await
+                        .context("Learn from match")?;
+                }
+            }
+
+            storages
+                .matches
+                .insert(
+                    &MatchData::new(id, Utc::now(), result.score())
+                        .with_snapshot(result.artist().clone(), result.title().clone())
+                        .with_kind(kind),
+                )
+                .await?;
+
+            if best.map_or(true, |(_, score)| result.score() > score) {
+                best = Some((id, result.score()));
+            }
+        }
+
+        // The segment aired once, credit its airtime to the most confident match only.
+        if let Some((id, score)) = best {
+            add_airplay(storages, state, id, info).await?;
+            state
+                .match_cache
+                .insert(cache_key, CachedMatch { id, score });
+        }
+        let (id, score) = (best.map(|(id, _)| id), best.map(|(_, score)| score));
+        emit_decision(args, state, info, Decision::Matched, id, score);
+    }
+
+    Ok(())
+}
+
+/// Identifies the creative of an ad across its plays, by the `spotInstanceId` the station
+/// sends or else by the track `id` its segments resolved to.
+fn ad_key(info: &SegmentDownloadInfo, id: Uuid) -> String {
+    match info.ids.spot_instance_id {
+        Some(spot) => format!("spot:{spot}"),
+        None => format!("track:{id}"),
+    }
+}
+
+/// Identifies audio across its plays by its SHA-256.
+fn audio_key(audio_hash: &[u8; 32]) -> String {
+    audio_hash
+        .iter()
+        .fold(String::from("sha256:"), |key, byte| {
+            key + &format!("{byte:02x}")
+        })
+}
+
+/// Whether the plays of `info` are counted, to tell jingles by, see `--jingle-min-plays`.
+/// Segments already told to be jingles are counted too, for their rotation.
+fn counts_jingle_plays(args: &Args, info: &SegmentDownloadInfo) -> bool {
+    args.jingle_min_plays.is_some()
+        && matches!(
+            info.kind,
+            SuggestedSegmentContentKind::None | SuggestedSegmentContentKind::Jingle
+        )
+        && info.duration <= Duration::from_secs(args.jingle_max_duration)
+}
+
+/// What became of a segment, see `--emit-ndjson`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Decision {
+    /// The kind policy neither queries nor inserts it.
+    Skipped,
+    DownloadFailed,
+    /// Larger than `--max-segment-bytes`.
+    TooLarge,
+    Matched,
+    /// Counted as a match of the same artist and title inserted within `--dedup-window`.
+    MatchedRecentInsert,
+    /// Counted as a match of the same audio stored already, see `--id-scheme content`.
+    MatchedStored,
+    /// Unmatched, and the kind policy leaves out inserts.
+    NotInserted,
+    /// Unmatched, and the `--max-bytes-per-kind` budget is exhausted.
+    OverBudget,
+    Inserted,
+    /// Found in emysound only, stored locally to complete an interrupted insert.
+    CompletedInsert,
+    /// Counted as a match of what the same audio resolved to, see `--match-cache-size`.
+    MatchedCached,
+    /// Stored without asking emysound, which is unavailable.
+    PendingSync,
+}
+
+impl Decision {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Decision::Skipped => "skipped",
+            Decision::DownloadFailed => "download_failed",
+            Decision::TooLarge => "too_large",
+            Decision::Matched => "matched",
+            Decision::MatchedRecentInsert => "matched_recent_insert",
+            Decision::MatchedStored => "matched_stored",
+            Decision::NotInserted => "not_inserted",
+            Decision::OverBudget => "over_budget",
+            Decision::Inserted => "inserted",
+            Decision::CompletedInsert => "completed_insert",
+            Decision::MatchedCached => "matched_cached",
+            Decision::PendingSync => "pending_sync",
+        }
+    }
+}
+
+/// Counts the decision for the summaries and prints it as a line of NDJSON
+/// if `--emit-ndjson` is set.
+fn emit_decision(
+    args: &Args,
+    state: &mut IngestState,
+    info: &SegmentDownloadInfo,
+    decision: Decision,
+    id: Option<Uuid>,
+    score: Option<u8>,
+) {
+    state.cycle.record(decision.as_str());
+    // Whatever aired instead ends the play of the track before. A failed segment tells
+    // nothing of what aired.
+    if matches!(
+        decision,
+        Decision::Skipped | Decision::NotInserted | Decision::OverBudget
+    ) {
+        state.last_aired = None;
+    }
+    Span::current().record("decision", decision.as_str());
+    if args.emit_ndjson {
+        println!("{}", decision_json(info, decision, id, score, Utc::now()));
+    }
+}
+
+/// Credits the airtime of `info` to track `id`, as a new play unless the segment before on the
+/// stream aired the same track. Ads count in their rotation alike.
+async fn add_airplay(
+    storages: &Storages,
+    state: &mut IngestState,
+    id: Uuid,
+    info: &SegmentDownloadInfo,
+) -> Result<()> {
+    let starts_play = state.last_aired != Some(id);
+    state.last_aired = Some(id);
+
+    if info.kind == SuggestedSegmentContentKind::Advertisement {
+        storages
+            .metadata
+            .record_ad(
+                &ad_key(info, id),
+                Utc::now(),
+                &info.artist,
+                &info.title,
+                info.campaign(),
+                starts_play,
+            )
+            .await
+            .context("Record ad")?;
+    }
+
+    storages
+        .metadata
+        .add_airplay(id, info.duration, starts_play)
+        .await
+        .context("Add airplay")
+}
+
+fn decision_json(
+    info: &SegmentDownloadInfo,
+    decision: Decision,
+    id: Option<Uuid>,
+    score: Option<u8>,
+    now: DateTime<Utc>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp": now.to_rfc3339(),
+        "decision": decision.as_str(),
+        "id": id.map(|id| id.to_string()),
+        "score": score,
+        "stream_id": info.stream_id,
+        "segment": info.key.to_string(),
+        "url": info.url.as_str(),
+        "kind": info.kind.to_string(),
+        "artist": info.artist,
+        "title": info.title,
+        "album": info.album,
+        "year": info.year,
+        "duration": info.duration.as_secs_f64(),
+    })
+}
+
+/// Fails if `future` doesn't complete before `deadline`.
+async fn within<F: Future>(deadline: tokio::time::Instant, future: F) -> Result<F::Output> {
+    tokio::time::timeout_at(deadline, future)
+        .await
+        .map_err(|_| anyhow!("Segment pipeline timed out"))
+}
+
+/// The `EXT-X-MAP` init segment at `url`, fetched once for all segments sharing it.
+async fn fetch_init_segment(
+    args: &Args,
+    client: &reqwest::Client,
+    state: &mut IngestState,
+    url: &Url,
+    deadline: tokio::time::Instant,
+    shutdown: &CancellationToken,
+) -> Result<Bytes> {
+    if let Some(bytes) = state.init_segments.get(url) {
+        return Ok(bytes);
+    }
+
+    let bytes = match &args.replay_dir {
+        Some(dir) => replay::segment(dir, url)?.1,
+        None => {
+            let request = client.get(url.clone());
+            let response = rate_limit::send(request, Some(deadline), shutdown).await?;
+            let body = cancellable(shutdown, response.error_for_status()?.bytes()).await??;
+            gzip::decompress(body, None).with_context(|| format!("Decompress {url}"))?
+        }
+    };
+    log::info!("Fetched init segment {url}, {} bytes", bytes.len());
+
+    state.init_segments.insert(url.clone(), bytes.clone());
+    Ok(bytes)
+}
+
+/// Writes a segment just inserted into emysound to the local storages, or one emysound
+/// wasn't available for, without a `remote_id`, flagged pending sync.
+#[allow(clippy::too_many_arguments)]
+async fn store_segment(
+    args: &Args,
+    storages: &Storages,
+    state: &mut IngestState,
+    info: &SegmentDownloadInfo,
+    metadata: Metadata,
+    remote_id: Option<Uuid>,
+    audio_format: String,
+    bytes: &Bytes,
+) -> Result<()> {
+    let id = metadata.id;
+    let name = args.audio_filename_template.as_ref().map(|template| {
+        info.filename(template, args.timezone, Some(id))
+            .replace(['/', '\\'], "_")
+    });
+    let audio = AudioData::new(id, audio_format, bytes.clone()).with_name(name);
+    store_track(args, storages, audio, metadata, remote_id).await?;
+
+    add_airplay(storages, state, id, info).await
+}
+
+/// The metadata `info` is stored with as `id`, enriched by `--enrich-command` or
+/// `--enrich-url`.
+async fn new_metadata(state: &IngestState, info: &SegmentDownloadInfo, id: Uuid) -> Metadata {
+    let metadata = info.to_metadata(id);
+    match &state.enricher {
+        Some(enricher) => enricher.enrich(metadata).await,
+        None => metadata,
+    }
+}
+
+/// Writes a track to the local storages, the audio as the `--kind-policy` of its kind
+/// says. Without a `remote_id`, it is flagged pending sync and its audio is stored until
+/// it is synced, see [`resync_pending`].
+async fn store_track(
+    args: &Args,
+    storages: &Storages,
+    audio: AudioData,
+    metadata: Metadata,
+    remote_id: Option<Uuid>,
+) -> Result<()> {
+    let id = audio.id();
+    if let Some(remote_id) = remote_id {
+        storages
+            .id_map
+            .insert(id, remote_id)
+            .await
+            .context("Insert id mapping")?;
+    }
+
+    let analysis = analyze(audio.format(), audio.bytes(), args.generate_preview).await;
+
+    let pending_sync = remote_id.is_none();
+    if pending_sync || KindPolicy::for_kind(&args.kind_policy, metadata.kind()).store_audio {
+        storages
+            .audio
+            .insert(&audio)
+            .await
+            .context("Insert audio")
+            .context(Failure::Storage)?;
+    }
+
+    let metadata = metadata
+        .with_loudness_lufs(analysis.loudness_lufs)
+        .with_pending_sync(pending_sync);
+    // A segment processed again, e.g. after a state reset, updates its row.
+    storages
+        .metadata
+        .insert_or_update(&metadata)
+        .await
+        .context("Insert metadata")?;
+
+    if let Some(preview) = analysis.preview {
+        storages
+            .metadata
+            .insert_preview(id, preview)
+            .await
+            .context("Insert preview")?;
+    }
+
+    Ok(())
+}
+
+/// Stores a segment emysound was unavailable for, flagged pending sync.
+async fn store_unsynced(
+    args: &Args,
+    storages: &Storages,
+    state: &mut IngestState,
+    info: &SegmentDownloadInfo,
+    metadata: Metadata,
+    audio_format: String,
+    bytes: &Bytes,
+) -> Result<()> {
+    let id = metadata.id;
+    log::warn!(
+        "`{}`/`{}` stored as {id} pending sync, emysound is unavailable",
+        &info.artist,
+        &info.title
+    );
+    let storing = store_segment(
+        args,
+        storages,
+        state,
+        info,
+        metadata,
+        None,
+        audio_format,
+        bytes,
+    );
+    storing.instrument(info_span!("store")).await?;
+    state.stored_pending = true;
+    emit_decision(args, state, info, Decision::PendingSync, Some(id), None);
+    Ok(())
+}
+
+/// Inserts the segments stored pending sync into emysound, once it is available again.
+/// A segment emysound matches now is recorded as a match of that track rather than
+/// inserted, it stays stored locally only. `false` if emysound isn't available yet,
+/// the rest is left for later.
+async fn resync_pending(shared: &Capture<'_>) -> Result<bool> {
+    let Capture {
+        args,
+        fingerprinter,
+        storages,
+        shutdown,
+        ..
+    } = shared;
+
+    for metadata in storages.metadata.pending_sync().await? {
+        let id = metadata.id;
+        if shared.unsyncable.borrow().contains(&id) {
+            continue;
+        }
+        if shutdown.is_cancelled() {
+            return Ok(false);
+        }
+
+        let audio = match storages.audio.get(id).await {
+            Ok(audio) => audio,
+            Err(e) => {
+                log::warn!("{id} is left pending sync, its audio is not stored: {e:#}");
+                shared.unsyncable.borrow_mut().insert(id);
+                continue;
+            }
+        };
+        let filename = format!("{id}.{}", storage::extension(audio.format()));
+        let matches =
+            match cancellable(shutdown, fingerprinter.query(&filename, audio.bytes())).await {
+                Ok(Ok(matches)) => matches,
+                // Abandoned at shutdown, it is tried again at the next run.
+                Err(_) => return Ok(false),
+                Ok(Err(e)) if e.is::<CircuitOpen>() => return Ok(false),
+                Ok(Err(e)) => {
+                    log::warn!("{id} is left pending sync, failed to query it: {e:#}");
+                    shared.unsyncable.borrow_mut().insert(id);
+                    continue;
+                }
+            };
+        if let Some(best) = matches.iter().max_by_key(|result| result.score()) {
+            let matched = storages
+                .id_map
+                .local_id(best.id())
+                .await?
+                .unwrap_or_else(|| best.id());
+            if matched == id {
+                // Its own insert, interrupted before it was cleared pending sync.
+                storages.metadata.clear_pending_sync(id).await?;
+                drop_synced_audio(args, storages, &metadata).await?;
+                continue;
+            }
+            storages
+                .matches
+                .insert(
+                    &MatchData::new(matched, metadata.date(), best.score())
+                        .with_snapshot(best.artist().clone(), best.title().clone())
+                        .with_kind(metadata.kind()),
+                )
+                .await?;
+            storages.metadata.clear_pending_sync(id).await?;
+            drop_synced_audio(args, storages, &metadata).await?;
+            log::info!(
+                "`{}`/`{}` {id} stored while emysound was unavailable matches {matched}, \
+                recorded as its match",
+                metadata.artist(),
+                metadata.title()
+            );
+            continue;
+        }
+
+        // emysound accepts the id we give it, as at the insert of a new segment. Mapped
+        // before the insert, so that an interrupted insert is told apart when it matches.
+        storages.id_map.insert(id, id).await?;
+        let track_info = TrackInfo::new(
+            id,
+            metadata.artist().to_owned(),
+            metadata.title().to_owned(),
+        );
+        let inserting = fingerprinter.insert(track_info, &filename, audio.bytes());
+        match cancellable(shutdown, inserting).await {
+            Ok(Ok(_)) => {}
+            Err(_) => return Ok(false),
+            Ok(Err(e)) if e.is::<CircuitOpen>() => return Ok(false),
+            Ok(Err(e)) => {
+                log::warn!("{id} is left pending sync, failed to insert it: {e:#}");
+                shared.unsyncable.borrow_mut().insert(id);
+                continue;
+            }
+        }
+
+        storages.metadata.clear_pending_sync(id).await?;
+        drop_synced_audio(args, storages, &metadata).await?;
+        log::info!(
+            "Synced `{}`/`{}` {id} stored while emysound was unavailable",
+            metadata.artist(),
+            metadata.title()
+        );
+    }
+
+    Ok(true)
+}
+
+/// Removes the audio a track was stored with pending sync, once synced, if the
+/// `--kind-policy` of its kind doesn't keep audio.
+async fn drop_synced_audio(args: &Args, storages: &Storages, metadata: &Metadata) -> Result<()> {
+    if !KindPolicy::for_kind(&args.kind_policy, metadata.kind()).store_audio {
+        storages
+            .audio
+            .remove(metadata.id)
+            .await
+            .context("Remove audio")
+            .context(Failure::Storage)?;
+    }
+    Ok(())
+}
+
+/// What decoding tells about a segment, stored along with its metadata.
+#[derive(Default)]
+struct Analysis {
+    loudness_lufs: Option<f64>,
+    /// Low-res spectrogram, see `--generate-preview`.
+    preview: Option<Vec<u8>>,
+}
+
+/// Decodes a segment to analyze it, a segment that can't be decoded gets no analysis
+/// rather than failing.
+#[cfg(feature = "decode")]
+async fn analyze(content_type: &str, bytes: &Bytes, with_preview: bool) -> Analysis {
+    let (content_type, bytes) = (content_type.to_owned(), bytes.clone());
+
+    tokio::task::spawn_blocking(move || -> Result<Analysis> {
+        let pcm = decode::decode(&bytes, &content_type)?;
+        let loudness_lufs = decode::loudness_lufs(&pcm).unwrap_or_else(|e| {
+            log::warn!("Failed to measure loudness: {e:#}");
+            None
+        });
+
+        Ok(Analysis {
+            loudness_lufs,
+            preview: with_preview.then(|| decode::preview(&pcm)),
+        })
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|analysis| analysis)
+    .unwrap_or_else(|e| {
+        log::warn!("Failed to decode segment: {e:#}");
+        Analysis::default()
+    })
+}
+
+/// Kind of a segment by its decoded audio, see `--classify-audio`.
+#[cfg(feature = "decode")]
+async fn classify_audio(content_type: &str, bytes: &Bytes) -> Option<SuggestedSegmentContentKind> {
+    let (content_type, bytes) = (content_type.to_owned(), bytes.clone());
+
+    tokio::task::spawn_blocking(move || {
+        decode::decode(&bytes, &content_type).map(|pcm| decode::classify(&pcm))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|classified| classified)
+    .unwrap_or_else(|e| {
+        log::warn!("Failed to decode segment to classify it: {e:#}");
+        None
+    })
+    .map(|sound| match sound {
+        decode::Sound::Speech => SuggestedSegmentContentKind::Talk,
+        decode::Sound::Music => SuggestedSegmentContentKind::Music,
+    })
+}
+
+/// The audio of a segment cut to `duration` as WAV, see `--trim-to-extinf`. `None` if it
+/// can't be decoded.
+#[cfg(feature = "decode")]
+async fn trim_to_extinf(content_type: &str, bytes: &Bytes, duration: Duration) -> Option<Bytes> {
+    let (content_type, bytes) = (content_type.to_owned(), bytes.clone());
+
+    tokio::task::spawn_blocking(move || {
+        let pcm = decode::trim(&bytes, &content_type, duration)?;
+        if pcm.duration() < duration {
+            log::debug!(
+                "Segment is {:?} long, shorter than declared {duration:?}",
+                pcm.duration()
+            );
+        }
+        Ok(Bytes::from(decode::wav(&pcm)))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|trimmed: Result<Bytes>| trimmed)
+    .map_err(|e| log::warn!("Failed to decode segment to trim it, keeping it whole: {e:#}"))
+    .ok()
+}
+
+/// The parts of a segment before and after a change of its content as WAV, with their
+/// durations, see `--split-segments`. `None` if its content doesn't change or it can't be
+/// decoded.
+#[cfg(feature = "decode")]
+async fn split_at_boundary(content_type: &str, bytes: &Bytes) -> Option<[(Duration, Bytes); 2]> {
+    let (content_type, bytes) = (content_type.to_owned(), bytes.clone());
+
+    tokio::task::spawn_blocking(move || {
+        let pcm = decode::decode(&bytes, &content_type)?;
+        Ok(decode::boundary(&pcm).map(|frame| {
+            pcm.split_at(frame)
+                .map(|part| (part.duration(), Bytes::from(decode::wav(&part))))
+        }))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|split: Result<_>| split)
+    .unwrap_or_else(|e| {
+        log::warn!("Failed to decode segment to split it, keeping it whole: {e:#}");
+        None
+    })
+}
+
+#[cfg(not(feature = "decode"))]
+async fn split_at_boundary(_content_type: &str, _bytes: &Bytes) -> Option<[(Duration, Bytes); 2]> {
+    None
+}
+
+#[cfg(not(feature = "decode"))]
+async fn trim_to_extinf(_content_type: &str, _bytes: &Bytes, _duration: Duration) -> Option<Bytes> {
+    None
+}
+
+#[cfg(not(feature = "decode"))]
+async fn classify_audio(
+    _content_type: &str,
+    _bytes: &Bytes,
+) -> Option<SuggestedSegmentContentKind> {
+    None
+}
+
+#[cfg(not(feature = "decode"))]
+async fn analyze(_content_type: &str, _bytes: &Bytes, _with_preview: bool) -> Analysis {
+    Analysis::default()
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::QueryReturnedNoRows)
+    )
+}
+
+async fn print_stats(args: &Args, days: u32, limit: usize) -> Result<()> {
+    let read_only = args.read_only;
+    let metadata_storage = if read_only {
+        MetadataStorage::read_only(&storage_path(&args.data_dir, METADATA_STORAGE_PATH))?
+    } else {
+        MetadataStorage::new(&storage_path(&args.data_dir, METADATA_STORAGE_PATH))?
+    };
+    // Formats only, the audio itself isn't read.
+    let audio_store = open_audio_store(args, &args.data_dir)?;
+    let since = Utc::today().naive_utc() - chrono::Duration::days(i64::from(days.max(1)) - 1);
+
+    println!("Airplay since {since}:");
+    for airplay in metadata_storage.airplay(since, limit).await? {
+        // Kinds the `--kind-policy` doesn't store the audio of have none.
+        let format = audio_store
+            .format(airplay.metadata.id)
+            .await
+            .unwrap_or_else(|_| "no audio".to_owned());
+        println!(
+            "{:>8.1} min {:>5} plays  {:<13} {:<12} {} - {}",
+            airplay.play_seconds / 60f64,
+            airplay.plays,
+            airplay.metadata.kind().to_string(),
+            format,
+            airplay.metadata.artist(),
+            airplay.metadata.title()
+        );
+    }
+
+    println!("Ads heard since {since}:");
+    for ad in metadata_storage.ad_rotation(since, limit).await? {
+        println!(
+            "{:>5} plays  first {}  last {}  {} - {}  {}",
+            ad.plays,
+            ad.first_seen.with_timezone(&args.timezone).to_rfc3339(),
+            ad.last_seen.with_timezone(&args.timezone).to_rfc3339(),
+            ad.artist,
+            ad.title,
+            ad.key
+        );
+    }
+
+    println!("Ad campaigns aired since {since}:");
+    for aired in metadata_storage.campaigns(since, limit).await? {
+        println!(
+            "{:>5} plays {:>3} creatives  first {}  last {}  {}",
+            aired.plays,
+            aired.creatives,
+            aired.first_seen.with_timezone(&args.timezone).to_rfc3339(),
+            aired.last_seen.with_timezone(&args.timezone).to_rfc3339(),
+            aired.campaign
+        );
+    }
+
+    println!("Jingles heard since {since}:");
+    let min_plays = args.jingle_min_plays.unwrap_or(1);
+    for jingle in metadata_storage
+        .jingle_rotation(since, min_plays, limit)
+        .await?
+    {
+        println!(
+            "{:>5} plays  first {}  last {}  {} - {}  {}",
+            jingle.plays,
+            jingle.first_seen.with_timezone(&args.timezone).to_rfc3339(),
+            jingle.last_seen.with_timezone(&args.timezone).to_rfc3339(),
+            jingle.artist,
+            jingle.title,
+            jingle.key
+        );
+    }
+
+    let diagnostics = if read_only {
+        DiagnosticsStorage::read_only(&storage_path(&args.data_dir, DIAGNOSTICS_STORAGE_PATH))?
+    } else {
+        DiagnosticsStorage::new(&storage_path(&args.data_dir, DIAGNOSTICS_STORAGE_PATH))?
+    };
+    if let Some(response) = diagnostics.last_playlist_response().await? {
+        println!(
+            "Last playlist response at {}: {}",
+            response
+                .timestamp
+                .with_timezone(&args.timezone)
+                .to_rfc3339(),
+            response
+                .content_type
+                .as_deref()
+                .unwrap_or("no content type")
+        );
+        if let Some(sample) = response.body_sample {
+            println!("Unexpected body: {sample}");
+        }
+    }
+    if let Some(timing) = diagnostics.last_segment_timing().await? {
+        println!(
+            "Segment cadence of {} at {}: drifted {:+.2}s from declared durations in {:.0}s",
+            timing.stream_id,
+            timing.timestamp.with_timezone(&args.timezone).to_rfc3339(),
+            timing.drift_seconds,
+            timing.elapsed_seconds
+        );
+    }
+
+    Ok(())
+}
+
+async fn export_metadata(
+    data_dir: &Path,
+    format: ExportFormat,
+    out: &Path,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<()> {
+    let rows = MetadataStorage::read_only(&storage_path(data_dir, METADATA_STORAGE_PATH))?
+        .between(from, to)
+        .await?;
+
+    let file = std::fs::File::create(out).with_context(|| format!("Create {}", out.display()))?;
+    export::write_metadata(std::io::BufWriter::new(file), format, &rows)?;
+
+    log::info!("Exported {} rows to {}", rows.len(), out.display());
+    Ok(())
+}
+
+fn parse_timezone(value: &str) -> Result<Tz> {
+    value
+        .parse()
+        .map_err(|e: String| anyhow!("Unknown time zone `{value}`: {e}"))
+}
+
+/// Replaces `${NAME}` and `$NAME` with the value of the environment variable, failing if
+/// it is not set, and `$$` with `$`.
+fn expand_env(value: &str) -> Result<String> {
+    expand_with(value, |name| std::env::var(name).ok())
+}
+
+fn expand_env_path(value: &str) -> Result<PathBuf> {
+    expand_env(value).map(PathBuf::from)
+}
+
+fn expand_with(value: &str, var: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed `${{` in `{value}`"))?;
+            (&braced[..end], &braced[end + 1..])
+        } else if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+
+        if name.is_empty() {
+            bail!("Expected a variable name after `$` in `{value}`, `$$` stands for `$`");
+        }
+        let var = var(name).ok_or_else(|| anyhow!("`{name}` of `{value}` is not set"))?;
+        expanded.push_str(&var);
+        rest = after;
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+fn parse_emysound_filename_template(value: &str) -> Result<FilenameTemplate> {
+    let template: FilenameTemplate = value.parse()?;
+    if template.has(Field::Id) {
+        bail!("`{{id}}` is unknown while querying, emysound names can't use it");
+    }
+    Ok(template)
+}
+
+fn parse_audio_filename_template(value: &str) -> Result<FilenameTemplate> {
+    let template: FilenameTemplate = value.parse()?;
+    if !template.has(Field::Id) {
+        bail!(
+            "`{value}` has no `{{id}}`, the files of different segments would overwrite each other"
+        );
+    }
+    Ok(template)
+}
+
+/// Parses `--stream NAME=URL`, the URL may refer to environment variables.
+fn parse_named_stream(value: &str) -> Result<(String, Url)> {
+    let (name, url) = split_name(value, "NAME=URL")?;
+    let url = expand_env(url)?;
+    Ok((
+        name,
+        url.parse()
+            .with_context(|| format!("Invalid URL `{url}`"))?,
+    ))
+}
+
+/// Parses `--db NAME=DIR`.
+fn parse_stream_db(value: &str) -> Result<(String, PathBuf)> {
+    let (name, dir) = split_name(value, "NAME=DIR")?;
+    Ok((name, expand_env_path(dir)?))
+}
+
+fn split_name<'a>(value: &'a str, expected: &str) -> Result<(String, &'a str)> {
+    let (name, rest) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected {expected}"))?;
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("Expected {expected}, the name is empty");
+    }
+    Ok((name.to_owned(), rest.trim()))
+}
+
+fn parse_kind(value: &str) -> Result<AudioKind> {
+    value.try_into()
+}
+
+/// `file://` URL of an imported file, as the source of what is stored from it.
+fn file_url(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    Url::from_file_path(path).ok().map(String::from)
+}
+
+/// Parses RFC 3339, or a date taken as its UTC midnight.
+fn parse_time(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(DateTime::from_utc(date.and_hms(0, 0, 0), Utc));
+    }
+    Ok(DateTime::parse_from_rfc3339(value)
+        .with_context(|| format!("Invalid time `{value}`"))?
+        .with_timezone(&Utc))
+}
+
+async fn tail(data_dir: &Path, timezone: Tz, lines: usize, interval: Duration) -> Result<()> {
+    let metadata_storage =
+        MetadataStorage::read_only(&storage_path(data_dir, METADATA_STORAGE_PATH))?;
+
+    // Rows are followed in the order stored, streams may store them out of date order.
+    let last = metadata_storage.last_seq().await?;
+    let mut cursor = last.saturating_sub(lines as u64);
+
+    loop {
+        for (seq, metadata) in metadata_storage.since(cursor).await? {
+            print_capture(&metadata, timezone);
+            cursor = seq;
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn print_capture(metadata: &Metadata, timezone: Tz) {
+    println!(
+        "{} {:<13} {} - {} {}",
+        metadata
+            .date()
+            .with_timezone(&timezone)
+            .format("%Y-%m-%d %H:%M:%S"),
+        metadata.kind().to_string(),
+        metadata.artist(),
+        metadata.title(),
+        metadata.id
+    );
+}
+
+async fn check(fingerprinter: &dyn Fingerprinter, clip: Option<&Path>) -> Result<()> {
+    let (filename, bytes) = match clip {
+        Some(path) => (
+            path.file_name()
+                .map_or_else(|| "clip".into(), |name| name.to_string_lossy()),
+            Bytes::from(std::fs::read(path).with_context(|| format!("Read {}", path.display()))?),
+        ),
+        None => ("silence.wav".into(), silent_wav(Duration::from_secs(1))),
+    };
+
+    let started = std::time::Instant::now();
+    let result = fingerprinter.query(&filename, &bytes).await;
+    let latency = started.elapsed();
+
+    match result {
+        Ok(matches) => {
+            println!("emysound OK in {latency:?}, {} matches", matches.len());
+            Ok(())
+        }
+        Err(e) => {
+            println!("emysound FAILED in {latency:?}");
+            Err(e)
+        }
+    }
+}
+
+async fn probe(args: &Args, url: &Url) -> Result<()> {
+    let client = http_client(args).context(Failure::Config)?;
+    let allowlist = &args.segment_content_type_allowlist;
+    let max_bytes = args.max_segment_bytes;
+    let shutdown = CancellationToken::new();
+    let (content_type, bytes) = download(&client, url, allowlist, max_bytes, None, &shutdown)
+        .await
+        .with_context(|| format!("Download {url}"))?;
+
+    let text = |value: Option<&str>| value.map_or_else(|| "-".to_owned(), |v| format!("`{v}`"));
+    println!("URL:          {url}");
+    println!("Content type: {content_type}");
+    println!("Size:         {} bytes", bytes.len());
+    println!(
+        "Format:       {}",
+        format_extension(&content_type, &bytes).unwrap_or("unknown")
+    );
+
+    match tags::probe(&bytes) {
+        Ok(tags) => println!(
+            "Tags:         artist {}, title {}, album {}, year {}",
+            text(tags.artist.as_deref()),
+            text(tags.title.as_deref()),
+            text(tags.album.as_deref()),
+            tags.year
+                .map_or_else(|| "-".to_owned(), |year| year.to_string())
+        ),
+        Err(e) => println!("Tags:         unreadable, {e:#}"),
+    }
+    if let Some(timed) = segment_info::timed_metadata(&bytes) {
+        println!(
+            "Timed ID3:    artist {}, title {}",
+            text(timed.artist.as_deref()),
+            text(timed.title.as_deref())
+        );
+    }
+    println!("Decoded:      {}", probe_decoding(&content_type, &bytes));
+
+    Ok(())
+}
+
+/// What decoding `bytes` yields, see `probe`.
+#[cfg(feature = "decode")]
+fn probe_decoding(content_type: &str, bytes: &[u8]) -> String {
+    match decode::decode(bytes, content_type) {
+        Ok(pcm) => format!(
+            "{} Hz, {} channels, {:.1}s",
+            pcm.sample_rate,
+            pcm.channels,
+            pcm.samples.len() as f64 / (pcm.sample_rate as usize * pcm.channels.max(1)) as f64
+        ),
+        Err(e) => format!("failed, {e:#}"),
+    }
+}
+
+#[cfg(not(feature = "decode"))]
+fn probe_decoding(_content_type: &str, _bytes: &[u8]) -> String {
+    "not checked, needs a build with the `decode` feature".to_owned()
+}
+
+async fn import(
+    args: &Args,
+    fingerprinter: &dyn Fingerprinter,
+    dir: &Path,
+    kind: AudioKind,
+) -> Result<()> {
+    let storages = Storages::open(args, &args.data_dir).await?;
+    let enricher = enricher(args, &http_client(args).context(Failure::Config)?);
+
+    let (mut imported, mut known, mut skipped, mut failed) = (0, 0, 0, 0);
+    for path in files(dir)? {
+        let importing = import_file(
+            args,
+            fingerprinter,
+            &storages,
+            enricher.as_ref(),
+            &path,
+            kind,
+        );
+        match importing.await {
+            Ok(Imported::New) => imported += 1,
+            Ok(Imported::Known) => known += 1,
+            Ok(Imported::Skipped) => skipped += 1,
+            // Files emysound fails on are left for the next run, which skips those imported.
+            Err(e) => {
+                log::error!("Failed to import {}: {e:#}", path.display());
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Imported {imported} tracks, {known} already in emysound, {skipped} files skipped, \
+        {failed} failed"
+    );
+    Ok(())
+}
+
+/// Outcome of [`import_file`].
+enum Imported {
+    New,
+    /// emysound has the track already.
+    Known,
+    /// Not a file lofty understands, most likely not audio at all.
+    Skipped,
+}
+
+/// Inserts the track of `path` into emysound and stores it like an ingested segment.
+async fn import_file(
+    args: &Args,
+    fingerprinter: &dyn Fingerprinter,
+    storages: &Storages,
+    enricher: Option<&Enricher>,
+    path: &Path,
+    kind: AudioKind,
+) -> Result<Imported> {
+    let filename = path.file_name().map_or_else(
+        || "track".into(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let bytes = Bytes::from(
+        tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Read {}", path.display()))?,
+    );
+
+    let tags = match tags::probe(&bytes) {
+        Ok(tags) => tags,
+        Err(e) => {
+            log::warn!("Skipped {}: {e:#}", path.display());
+            return Ok(Imported::Skipped);
+        }
+    };
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem_artist, stem_title) = stem
+        .split_once(" - ")
+        .map_or(("", stem.as_str()), |(artist, title)| {
+            (artist.trim(), title.trim())
+        });
+    let artist = tags.artist.unwrap_or_else(|| stem_artist.to_owned());
+    let title = tags.title.unwrap_or_else(|| stem_title.to_owned());
+
+    // Running the import again must not index the same tracks twice.
+    let matches = fingerprinter.query(&filename, &bytes).await?;
+    if matches
+        .iter()
+        .any(|m| m.score() >= INTERRUPTED_INSERT_SCORE)
+    {
+        log::info!(
+            "`{artist}`/`{title}` is already in emysound, {}",
+            path.display()
+        );
+        return Ok(Imported::Known);
+    }
+
+    let id = args.id_scheme.new_id(&bytes);
+    log::info!("Import `{artist}`/`{title}` {id} from {}", path.display());
+
+    let mut metadata = Metadata::new(id, Utc::now(), kind, artist, title)
+        .with_album(tags.album, tags.year)
+        .with_source_url(file_url(path));
+    // Enriched first, emysound gets the names the track is stored with.
+    if let Some(enricher) = enricher {
+        metadata = enricher.enrich(metadata).await;
+    }
+
+    // Mapped before the insert, as at the insert of a new segment.
+    storages
+        .id_map
+        .insert(id, id)
+        .await
+        .context("Insert id mapping")?;
+    let info = TrackInfo::new(
+        id,
+        metadata.artist().to_owned(),
+        metadata.title().to_owned(),
+    );
+    if fingerprinter.insert(info, &filename, &bytes).await? == Inserted::Existing {
+        log::warn!("emysound already has {id}, storing it locally only");
+    }
+
+    let audio = AudioData::new(id, replay::content_type(path).to_owned(), bytes);
+    store_track(args, storages, audio, metadata, Some(id)).await?;
+
+    Ok(Imported::New)
+}
+
+/// Files of `dir` and its subdirectories, in name order.
+fn files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(files(&path)?);
+        } else {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// 8kHz 16-bit mono PCM WAV of silence.
+fn silent_wav(duration: Duration) -> Bytes {
+    const SAMPLE_RATE: u32 = 8000;
+
+    let samples = (duration.as_secs_f64() * f64::from(SAMPLE_RATE)) as usize;
+    wav::pcm16(SAMPLE_RATE, 1, &vec![0; samples]).into()
+}
+
+#[cfg(feature = "serve")]
+async fn run_server(args: &Args, addr: SocketAddr) -> Result<()> {
+    let (metadata, matches) = if args.read_only {
+        (
+            MetadataStorage::read_only(&storage_path(&args.data_dir, METADATA_STORAGE_PATH))?,
+            MatchesStorage::read_only(&storage_path(&args.data_dir, MATCHES_STORAGE_PATH))?,
+        )
+    } else {
+        (
+            MetadataStorage::new(&storage_path(&args.data_dir, METADATA_STORAGE_PATH))?,
+            MatchesStorage::new(&storage_path(&args.data_dir, MATCHES_STORAGE_PATH))?,
+        )
+    };
+
+    let audio = open_audio_store(args, &args.data_dir)?;
+    serve::run(addr, metadata, audio, matches).await
+}
+
+#[cfg(not(feature = "serve"))]
+async fn run_server(_args: &Args, _addr: SocketAddr) -> Result<()> {
+    bail!("Built without the `serve` feature")
+}
+
+/// Reconciles the suggested kind of a matched segment with the kind stored for the match.
+///
+/// A segment we could not classify adopts the kind of the track it matched, while a stored
+/// track of unknown kind is updated with the kind of a segment classified with confidence.
+/// Returns the kind the match of the segment is recorded with.
+async fn learn_from_match(
+    metadata_storage: &MetadataStorage,
+    info: &SegmentDownloadInfo,
+    matched: &Metadata,
+) -> Result<AudioKind> {
+    let segment_kind: AudioKind = info.kind.into();
+
+    match (segment_kind, matched.kind()) {
+        (AudioKind::Unknown, AudioKind::Unknown) => {}
+        (AudioKind::Unknown, matched_kind) => {
+            log::info!(
+                "`{}`/`{}` reclassified as {} after matching {}",
+                &info.artist,
+                &info.title,
+                matched_kind.to_string(),
+                matched.id
+            );
+            return Ok(matched_kind);
+        }
+        (segment_kind, AudioKind::Unknown) => {
+            log::info!(
+                "Stored {} reclassified as {} after matching `{}`/`{}`",
+                matched.id,
+                segment_kind.to_string(),
+                &info.artist,
+                &info.title
+            );
+            metadata_storage
+                .update_kind(matched.id, segment_kind)
+                .await?;
+        }
+        (segment_kind, matched_kind) if segment_kind != matched_kind => {
+            log::debug!(
+                "`{}`/`{}` is {} but matched {} of kind {}",
+                &info.artist,
+                &info.title,
+                segment_kind.to_string(),
+                matched.id,
+                matched_kind.to_string()
+            );
+        }
+        _ => {}
+    }
+
+    Ok(segment_kind)
+}
+
+/// Returns `interval` changed by a random amount within ±`jitter_percent`.
+fn jittered(interval: Duration, jitter_percent: u8) -> Duration {
+    if jitter_percent == 0 {
+        return interval;
+    }
+
+    let jitter = f64::from(jitter_percent.min(100)) / 100f64;
+    interval.mul_f64(1f64 + rand::thread_rng().gen_range(-jitter..=jitter))
+}
+
+/// Bytes reserved for a segment body up front, whatever its `Content-Length` claims.
+const PREALLOCATED_SEGMENT_BYTES: u64 = 1 << 20;
+
+/// The error of a segment beyond `--max-segment-bytes`, told from failures by downcasting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct SegmentTooLarge {
+    limit: u64,
+}
+
+impl std::fmt::Display for SegmentTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Segment is larger than {} bytes", self.limit)
+    }
+}
+
+async fn download(
+    client: &reqwest::Client,
+    url: &Url,
+    content_type_allowlist: &[String],
+    max_bytes: Option<u64>,
+    deadline: Option<tokio::time::Instant>,
+    shutdown: &CancellationToken,
+) -> Result<(String, Bytes)> {
+    let mut response = rate_limit::send(client.get(url.clone()), deadline, shutdown).await?;
+    let too_large = |limit| anyhow::Error::msg(SegmentTooLarge { limit });
+    if let (Some(limit), Some(length)) = (max_bytes, response.content_length()) {
+        if length > limit {
+            return Err(too_large(limit).context(format!("Content-Length is {length}")));
+        }
+    }
+
+    log::debug!(
+        "Downloaded {url}, {} bytes",
+        response.content_length().unwrap_or_default()
+    );
+
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .ok_or_else(|| anyhow!("Failed to get content type"))
+        .and_then(|h| {
+            h.to_str()
+                .map(|s| s.to_owned())
+                .map_err(|e| anyhow!("Failed to get content type {e:#}"))
+        })?;
+
+    log::debug!("Content type: {:?}", content_type);
+
+    if !is_content_type_allowed(&content_type, content_type_allowlist) {
+        bail!("Content type {content_type:?} is not in the allowlist");
+    }
+
+    let gzip_encoded = matches!(
+        response.headers().get(CONTENT_ENCODING).map(HeaderValue::to_str),
+        Some(Ok(encoding)) if gzip::is_gzip_encoding(encoding)
+    );
+
+    // Read chunk by chunk to stop at the limit, a body may not declare its length or lie about it.
+    let capacity = response.content_length().unwrap_or_default();
+    let mut body = Vec::with_capacity(capacity.min(PREALLOCATED_SEGMENT_BYTES) as usize);
+    while let Some(chunk) = cancellable(shutdown, response.chunk())
+        .await?
+        .context("Retrieve bytes")?
+    {
+        body.extend_from_slice(&chunk);
+        if let Some(limit) = max_bytes.filter(|&limit| body.len() as u64 > limit) {
+            return Err(too_large(limit));
+        }
+    }
+    let body = Bytes::from(body);
+    let received = body.len();
+    let audio = gzip::decompress(body, max_bytes).with_context(|| format!("Decompress {url}"))?;
+    if audio.len() != received {
+        log::debug!("Decompressed {url}, {received} to {} bytes", audio.len());
+    } else if gzip_encoded {
+        log::debug!("{url} is gzip-encoded but arrived decoded");
+    }
+
+    Ok((content_type, audio))
+}
+
+/// Compares media types only, `audio/aac; charset=binary` is allowed by `audio/aac`.
+fn is_content_type_allowed(content_type: &str, allowlist: &[String]) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    allowlist.is_empty()
+        || allowlist
+            .iter()
+            .any(|allowed| allowed.trim().eq_ignore_ascii_case(media_type))
+}
+
+#[derive(Debug, Clone)]
+struct SegmentDownloadInfo {
+    /// Its number keeps filenames of the same second apart.
+    key: SegmentKey,
+    url: Url,
+    artist: String,
+    title: String,
+    kind: SuggestedSegmentContentKind,
+    /// What decided `kind`, none while it is unknown.
+    kind_source: Option<KindSource>,
+    duration: Duration,
+    ad_context: Option<AdContext>,
+    ids: TrackIds,
+    stream_id: String,
+    /// Discontinuity sequence number of the segment, timestamps reset when it changes.
+    discontinuity_sequence: u64,
+    /// The segment follows `EXT-X-DISCONTINUITY`.
+    discontinuity: bool,
+    /// See [`extract_attributes`].
+    attributes: HashMap<String, String>,
+    album: Option<String>,
+    year: Option<i32>,
+    /// `EXT-X-MAP` init segment, which fMP4 media segments can't be decoded without.
+    init_url: Option<Url>,
+    /// Replaces the extension of the URL in the filename, see `--segment-extension-override`.
+    extension: Option<&'static str>,
+    /// Master playlist variant of the segment, see `--variant`.
+    variant: Option<Variant>,
+}
+
+impl SegmentDownloadInfo {
+    fn filename(&self, template: &FilenameTemplate, timezone: Tz, id: Option<Uuid>) -> String {
+        self.filename_at(template, Utc::now(), timezone, id)
+    }
+
+    /// `template` filled in, `{id}` is empty without an `id`.
+    fn filename_at(
+        &self,
+        template: &FilenameTemplate,
+        now: DateTime<Utc>,
+        timezone: Tz,
+        id: Option<Uuid>,
+    ) -> String {
+        let name = self
+            .url
+            .path_segments()
+            .and_then(|s| s.last())
+            .unwrap_or("unknown");
+        let name = match self.extension {
+            Some(extension) => {
+                let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+                format!("{stem}.{extension}")
+            }
+            None => name.to_owned(),
+        };
+
+        template.render(|field| match field {
+            Field::Time => now
+                .with_timezone(&timezone)
+                .format("%Y-%m-%d_%H-%M-%S")
+                .to_string(),
+            Field::Number => self.key.number().to_string(),
+            Field::Kind => self.kind.to_string(),
+            Field::Artist => self.artist.clone(),
+            Field::Title => self.title.clone(),
+            Field::Stream => self.stream_id.clone(),
+            Field::Name => name.clone(),
+            Field::Ext => name
+                .rsplit_once('.')
+                .map_or_else(String::new, |(_, ext)| ext.to_owned()),
+            Field::Id => id.map_or_else(String::new, |id| id.to_string()),
+        })
+    }
+
+    fn campaign(&self) -> Option<&str> {
+        self.ad_context.as_ref().and_then(AdContext::campaign)
+    }
+
+    fn to_metadata(&self, id: Uuid) -> Metadata {
+        Metadata::new(
+            id,
+            Utc::now(),
+            self.kind.into(),
+            self.artist.clone(),
+            self.title.clone(),
+        )
+        .with_ad_context(
+            self.ad_context
+                .as_ref()
+                .map(|context| context.value.clone()),
+        )
+        .with_ad_campaign(self.campaign().map(str::to_owned))
+        .with_ad_offset(self.ad_context.as_ref().and_then(|context| context.offset))
+        .with_ids(self.ids.clone())
+        .with_stream_id(Some(self.stream_id.clone()))
+        .with_discontinuity(Some(self.discontinuity_sequence), self.discontinuity)
+        .with_attributes(self.attributes.clone())
+        .with_album(self.album.clone(), self.year)
+        .with_source_url(Some(self.url.to_string()))
+        .with_kind_source(self.kind_source)
+        .with_variant(
+            self.variant.as_ref().map(|variant| variant.bandwidth),
+            self.variant
+                .as_ref()
+                .and_then(|variant| variant.resolution.clone()),
+        )
+    }
+
+    /// Takes album and year from `tags`, and artist and title where the tags say more,
+    /// or wherever the tags have them if `prefer_tags`.
+    fn with_tags(&self, tags: &SegmentTags, prefer_tags: bool) -> Self {
+        let mut info = self.clone();
+        let pick = if prefer_tags {
+            tags::preferred
+        } else {
+            tags::richer
+        };
+
+        if let Some(artist) = pick(&self.artist, tags.artist.as_deref()) {
+            log::info!("Artist `{}` taken from tags as `{artist}`", self.artist);
+            info.artist = artist.to_owned();
+        }
+        if let Some(title) = pick(&self.title, tags.title.as_deref()) {
+            log::info!("Title `{}` taken from tags as `{title}`", self.title);
+            info.title = title.to_owned();
+        }
+        info.album = tags.album.clone();
+        info.year = tags.year;
+
+        info
+    }
+
+    fn with_extension(&self, extension: &'static str) -> Self {
+        let mut info = self.clone();
+        info.extension = Some(extension);
+        info
+    }
+
+    /// Part `part` of the segment, `duration` long and of a kind yet to classify, see
+    /// `--split-segments`.
+    fn with_part(&self, part: usize, duration: Duration) -> Self {
+        let mut info = self.clone();
+        info.key = self.key.part(part);
+        info.duration = duration;
+        info.kind = SuggestedSegmentContentKind::None;
+        info.kind_source = None;
+        info.extension = Some("wav");
+        info
+    }
+
+    fn with_kind(&self, kind: SuggestedSegmentContentKind, source: KindSource) -> Self {
+        let mut info = self.clone();
+        info.kind = kind;
+        info.kind_source = Some(source);
+        info
+    }
+
+    /// Takes artist and title from in-band ID3 timed metadata where it has them.
+    fn with_timed_metadata(&self, metadata: &TimedMetadata) -> Self {
+        let mut info = self.clone();
+
+        if let Some(artist) = metadata.artist.as_ref().filter(|&a| a != &self.artist) {
+            log::info!(
+                "Artist `{}` taken from timed metadata as `{artist}`",
+                self.artist
+            );
+            info.artist = artist.clone();
+        }
+        if let Some(title) = metadata.title.as_ref().filter(|&t| t != &self.title) {
+            log::info!(
+                "Title `{}` taken from timed metadata as `{title}`",
+                self.title
+            );
+            info.title = title.clone();
+        }
+
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::io::Write;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use bytes::Bytes;
+    use chrono::{TimeZone, Utc};
+    use clap::{CommandFactory, Parser};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use hls_m3u8::tags::ExtInf;
+    use hls_m3u8::{MediaPlaylist, MediaSegment};
+    use reqwest::Url;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_util::sync::CancellationToken;
+    use uuid::Uuid;
+
+    use super::{
+        ad_key, capture_streams, decision_json, download, expand_with, files, format_extension,
+        in_number_order, init_url, is_content_type_allowed, jittered, learn_from_match,
+        parse_emysound_filename_template, parse_time, parse_timezone, resync_pending, silent_wav,
+        store_track, Args, Capture, Decision, IdScheme, KindSource, SegmentDownloadInfo,
+        SegmentTooLarge, Storages, SuggestedSegmentContentKind, TrackIds,
+    };
+    use crate::emysound::{Inserted, QueryResult, TrackInfo};
+    use crate::filename::FilenameTemplate;
+    use crate::fingerprinter::Fingerprinter;
+    use crate::pause::PauseSwitch;
+    use crate::segment_filter::{SegmentDownloadFilter, SegmentKey, SegmentNumberFilter};
+    use crate::segment_info::IcyParser;
+    use crate::storage::{
+        AudioData, AudioKind, AudioStorage, DiagnosticsStorage, IdMapStorage, MatchesStorage,
+        Metadata, MetadataStorage,
+    };
+    use crate::tags;
+
+    #[test]
+    fn test_jittered() {
+        let interval = Duration::from_secs(10);
+        assert_eq!(jittered(interval, 0), interval);
+
+        for _ in 0..100 {
+            let value = jittered(interval, 20);
+            assert!(value >= Duration::from_secs(8) && value <= Duration::from_secs(12));
+        }
+
+        for _ in 0..100 {
+            assert!(jittered(interval, 200) <= Duration::from_secs(20));
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2022-05-01").unwrap().to_rfc3339(),
+            "2022-05-01T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_time("2022-05-01T12:00:00+02:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2022-05-01T10:00:00+00:00"
+        );
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_is_content_type_allowed() {
+        let allowlist = ["audio/aac".to_owned(), "audio/mpeg".to_owned()];
+        assert!(is_content_type_allowed("audio/aac", &allowlist));
+        assert!(is_content_type_allowed(
+            "Audio/MPEG; charset=binary",
+            &allowlist
+        ));
+        assert!(!is_content_type_allowed("text/html", &allowlist));
+        assert!(is_content_type_allowed("text/html", &[]));
+    }
+
+    /// Answers the one request on a local server with `head` and `body`, then closes.
+    async fn serve_once(head: &str, body: Vec<u8>) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/segment.aac", listener.local_addr().unwrap());
+        let response = [head.as_bytes(), b"\r\n", &body].concat();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0];
+                stream.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            // The client may hang up early, at a length beyond the limit.
+            let _ = stream.write_all(&response).await;
+        });
+        Url::parse(&url).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_download_max_bytes() {
+        let (client, shutdown) = (&reqwest::Client::new(), &CancellationToken::new());
+        let download = |url, max_bytes| async move {
+            download(client, &url, &[], Some(max_bytes), None, shutdown).await
+        };
+        const AUDIO: &str = "HTTP/1.1 200 OK\r\nContent-Type: audio/aac\r\nConnection: close\r\n";
+
+        let url = serve_once(&format!("{AUDIO}Content-Length: 8\r\n"), vec![0; 8]).await;
+        assert_eq!(download(url, 8).await.unwrap().1.len(), 8);
+
+        let url = serve_once(&format!("{AUDIO}Content-Length: 9\r\n"), vec![0; 9]).await;
+        let e = download(url, 8).await.unwrap_err();
+        assert!(e.is::<SegmentTooLarge>(), "{e:#}");
+
+        // Without a length to tell, the body is cut at the limit.
+        let url = serve_once(AUDIO, vec![0; 64 << 10]).await;
+        let e = download(url, 1024).await.unwrap_err();
+        assert!(e.is::<SegmentTooLarge>(), "{e:#}");
+
+        let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+        gzipped.write_all(&[0; 64 << 10]).unwrap();
+        let gzipped = gzipped.finish().unwrap();
+        assert!(gzipped.len() < 1024);
+        let head = format!("{AUDIO}Content-Length: {}\r\n", gzipped.len());
+        let url = serve_once(&head, gzipped).await;
+        let e = download(url, 1024).await.unwrap_err();
+        assert!(e.is::<SegmentTooLarge>(), "{e:#}");
+    }
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(
+            parse_timezone("Europe/Berlin").unwrap(),
+            chrono_tz::Europe::Berlin
+        );
+        assert!(parse_timezone("Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn test_id_scheme() {
+        assert_eq!(
+            IdScheme::Content.new_id(b"audio"),
+            IdScheme::Content.new_id(b"audio")
+        );
+        assert_ne!(
+            IdScheme::Content.new_id(b"audio"),
+            IdScheme::Content.new_id(b"other")
+        );
+        assert_ne!(
+            IdScheme::Random.new_id(b"audio"),
+            IdScheme::Random.new_id(b"audio")
+        );
+    }
+
+    #[test]
+    fn test_files() {
+        let dir = std::path::Path::new("./test_import");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        std::fs::write(dir.join("b/2.mp3"), "").unwrap();
+        std::fs::write(dir.join("a.mp3"), "").unwrap();
+
+        assert_eq!(
+            files(dir).unwrap(),
+            vec![dir.join("a.mp3"), dir.join("b/2.mp3")]
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_silent_wav() {
+        let wav = silent_wav(Duration::from_secs(1));
+        assert_eq!(wav.len(), 44 + 16000);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert!(lofty::Probe::new(std::io::Cursor::new(&wav))
+            .guess_file_type()
+            .unwrap()
+            .read(false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_completions() {
+        let mut script = Vec::new();
+        clap_complete::generate(
+            clap_complete::Shell::Bash,
+            &mut Args::command(),
+            "emysound-feeder-rs",
+            &mut script,
+        );
+        assert!(String::from_utf8(script)
+            .unwrap()
+            .contains("--metadata-format"));
+    }
+
+    #[test]
+    fn test_replay_dir_replaces_stream_url() {
+        assert!(Args::try_parse_from(["feeder"]).is_err());
+        assert!(Args::try_parse_from(["feeder", "--replay-dir", "./captured"]).is_ok());
+    }
+
+    #[test]
+    fn test_capture_streams() {
+        let streams = |args: &[&str]| {
+            let args = Args::try_parse_from([&["feeder"], args].concat())?;
+            capture_streams(&args)
+        };
+
+        let shared = streams(&["https://radio.example.com/live.m3u8"]).unwrap();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].id, "radio.example.com");
+        assert_eq!(shared[0].dir, Path::new("."));
+
+        let named = streams(&[
+            "--stream",
+            "jazz=https://jazz.example.com/live.m3u8",
+            "--stream",
+            "rock = https://rock.example.com/live.m3u8",
+            "--db",
+            "rock=./rock",
+        ])
+        .unwrap();
+        let named = named
+            .iter()
+            .map(|stream| (stream.id.as_str(), stream.dir.to_str().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(named, [("jazz", "."), ("rock", "./rock")]);
+
+        let data_dir = streams(&["--data-dir", "/data", "https://radio.example.com/live.m3u8"]);
+        assert_eq!(data_dir.unwrap()[0].dir, Path::new("/data"));
+
+        for invalid in [
+            &["--stream", "https://jazz.example.com/live.m3u8"][..],
+            &["--stream", "=https://jazz.example.com/live.m3u8"],
+            &["--stream", "jazz=live.m3u8"],
+            &[
+                "--stream",
+                "jazz=https://a.example.com/",
+                "--stream",
+                "jazz=https://b.com/",
+            ],
+            &[
+                "--stream",
+                "jazz=https://jazz.example.com/",
+                "--db",
+                "rock=./rock",
+            ],
+            &["--db", "jazz=./jazz", "https://jazz.example.com/live.m3u8"],
+            &[
+                "--stream",
+                "jazz=https://jazz.example.com/",
+                "https://rock.example.com/",
+            ],
+        ] {
+            assert!(streams(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    fn download_info(number: usize) -> SegmentDownloadInfo {
+        SegmentDownloadInfo {
+            key: SegmentKey::new(number, "segment.aac"),
+            url: "https://example.com/live/segment.aac".parse().unwrap(),
+            artist: "Daft Punk".to_owned(),
+            title: "Da Funk".to_owned(),
+            kind: SuggestedSegmentContentKind::Music,
+            duration: Duration::from_secs(10),
+            ad_context: None,
+            ids: Default::default(),
+            stream_id: "stream".to_owned(),
+            discontinuity_sequence: 0,
+            discontinuity: false,
+            attributes: Default::default(),
+            album: None,
+            year: None,
+            init_url: None,
+            kind_source: Some(KindSource::Metadata),
+            extension: None,
+            variant: None,
+        }
+    }
+
+    #[test]
+    fn test_filename_unique_within_second() {
+        let now = Utc.ymd(2022, 5, 1).and_hms(10, 0, 0);
+        let detailed = FilenameTemplate::default();
+
+        let first = download_info(41).filename_at(&detailed, now, chrono_tz::UTC, None);
+        let second = download_info(42).filename_at(&detailed, now, chrono_tz::UTC, None);
+        assert_ne!(first, second);
+        assert!(first.starts_with("2022-05-01_10-00-00_41_"));
+        assert!(first.ends_with(".segment.aac"));
+    }
+
+    #[test]
+    fn test_filename_templates() {
+        let now = Utc.ymd(2022, 5, 1).and_hms(10, 0, 0);
+        let info = download_info(41);
+
+        let clean = parse_emysound_filename_template("{artist} - {title}.{ext}").unwrap();
+        assert_eq!(
+            info.filename_at(&clean, now, chrono_tz::UTC, None),
+            "Daft Punk - Da Funk.aac"
+        );
+        assert!(parse_emysound_filename_template("{id}.{ext}").is_err());
+
+        let args = Args::try_parse_from([
+            "feeder",
+            "--replay-dir",
+            "./captured",
+            "--audio-filename-template",
+            "{stream}_{number}_{id}.{ext}",
+        ])
+        .unwrap();
+        let template = args.audio_filename_template.unwrap();
+        assert_eq!(
+            info.filename_at(&template, now, chrono_tz::UTC, Some(Uuid::nil())),
+            "stream_41_00000000-0000-0000-0000-000000000000.aac"
+        );
+        assert_eq!(args.emysound_filename_template, FilenameTemplate::default());
+
+        let overwriting = Args::try_parse_from([
+            "feeder",
+            "--replay-dir",
+            "./captured",
+            "--audio-filename-template",
+            "{artist} - {title}.{ext}",
+        ]);
+        assert!(overwriting.is_err());
+    }
+
+    #[test]
+    fn test_filename_extension_override() {
+        let now = Utc.ymd(2022, 5, 1).and_hms(10, 0, 0);
+        let detailed = FilenameTemplate::default();
+        let extensionless = SegmentDownloadInfo {
+            url: "https://example.com/live/segment-41".parse().unwrap(),
+            ..download_info(41)
+        };
+        assert!(extensionless
+            .filename_at(&detailed, now, chrono_tz::UTC, None)
+            .ends_with("_Da Funk.segment-41"));
+
+        let adts = format_extension("application/octet-stream", b"\xFF\xF1\x50\x80").unwrap();
+        assert!(extensionless
+            .with_extension(adts)
+            .filename_at(&detailed, now, chrono_tz::UTC, None)
+            .ends_with("_Da Funk.segment-41.aac"));
+
+        // A mislabeled extension is replaced, an unknown format keeps it.
+        let mislabeled = SegmentDownloadInfo {
+            url: "https://example.com/live/segment-41.ts".parse().unwrap(),
+            ..download_info(41)
+        };
+        let declared = format_extension("audio/mpeg", b"unknown").unwrap();
+        assert!(mislabeled
+            .with_extension(declared)
+            .filename_at(&detailed, now, chrono_tz::UTC, None)
+            .ends_with(".segment-41.mp3"));
+        assert_eq!(
+            format_extension("application/octet-stream", b"unknown"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ad_key() {
+        let id = Uuid::new_v4();
+        assert_eq!(ad_key(&download_info(1), id), format!("track:{id}"));
+
+        let spot = SegmentDownloadInfo {
+            ids: TrackIds {
+                spot_instance_id: Some(Uuid::nil()),
+                ..TrackIds::default()
+            },
+            ..download_info(1)
+        };
+        assert_eq!(
+            ad_key(&spot, id),
+            "spot:00000000-0000-0000-0000-000000000000"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_learn_from_match() {
+        let path = "./test_learn_from_match.db";
+        let _ = std::fs::remove_file(path);
+        let storage = MetadataStorage::new(&path).unwrap();
+        let track = |kind| {
+            let (artist, title) = ("Daft Punk".to_owned(), "Da Funk".to_owned());
+            Metadata::new(Uuid::new_v4(), Utc::now(), kind, artist, title)
+        };
+        let (music, unknown) = (track(AudioKind::Music), track(AudioKind::Unknown));
+        storage.insert(&music).await.unwrap();
+        storage.insert(&unknown).await.unwrap();
+
+        // The match of a segment of unknown kind is recorded as the kind of the track.
+        let untitled = SegmentDownloadInfo {
+            kind: SuggestedSegmentContentKind::None,
+            ..download_info(1)
+        };
+        let kind = learn_from_match(&storage, &untitled, &music).await.unwrap();
+        assert_eq!(kind, AudioKind::Music);
+
+        // A stored track of unknown kind takes the kind of the segment.
+        let kind = learn_from_match(&storage, &download_info(1), &unknown)
+            .await
+            .unwrap();
+        assert_eq!(kind, AudioKind::Music);
+        assert_eq!(
+            storage.get(unknown.id).await.unwrap().kind(),
+            AudioKind::Music
+        );
+    }
+
+    #[test]
+    fn test_segment_name_from_tags() {
+        let tags = tags::probe(include_bytes!("../fixtures/tagged.mp3")).unwrap();
+        let info = SegmentDownloadInfo {
+            artist: "Daft Punk".to_owned(),
+            title: "Da Funk".to_owned(),
+            ..download_info(1)
+        };
+
+        // Playlist titles stay unless the tags extend them.
+        assert_eq!(info.with_tags(&tags, false).title, "Da Funk");
+
+        let tagged = info.with_tags(&tags, true);
+        assert_eq!(tagged.title, "Around the World");
+        assert_eq!(tagged.artist, "Daft Punk");
+        assert_eq!(tagged.album.as_deref(), Some("Homework"));
+        let now = Utc.ymd(2022, 5, 1).and_hms(10, 0, 0);
+        assert!(tagged
+            .filename_at(&FilenameTemplate::default(), now, chrono_tz::UTC, None)
+            .contains("_Daft Punk_Around the World."));
+
+        let untagged = info.with_tags(&Default::default(), true);
+        assert_eq!(untagged.title, "Da Funk");
+    }
+
+    #[test]
+    fn test_decision_json() {
+        let id = uuid::Uuid::new_v4();
+        let now = Utc.ymd(2022, 5, 1).and_hms(10, 0, 0);
+        let line = decision_json(
+            &download_info(7),
+            Decision::Matched,
+            Some(id),
+            Some(87),
+            now,
+        );
+
+        assert_eq!(line["decision"], "matched");
+        assert_eq!(line["id"], id.to_string());
+        assert_eq!(line["score"], 87);
+        assert_eq!(line["segment"], "7:segment.aac");
+        assert_eq!(line["artist"], "Daft Punk");
+        assert_eq!(line["duration"], 10.0);
+        assert!(!line.to_string().contains('\n'));
+
+        let line = decision_json(&download_info(8), Decision::OverBudget, None, None, now);
+        assert!(line["id"].is_null());
+        assert!(line["score"].is_null());
+    }
+
+    fn numbered_segment(number: usize, has_discontinuity: bool) -> MediaSegment<'static> {
+        MediaSegment::builder()
+            .duration(ExtInf::new(Duration::from_secs(10)))
+            .uri(format!("https://example.com/{number}.aac"))
+            .number(Some(number))
+            .has_discontinuity(has_discontinuity)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_in_number_order() {
+        let segments = [
+            numbered_segment(12, false),
+            numbered_segment(10, false),
+            numbered_segment(11, true),
+        ];
+
+        let ordered = in_number_order(3, segments.iter());
+        let numbers: Vec<_> = ordered
+            .iter()
+            .map(|(_, segment)| segment.number())
+            .collect();
+        assert_eq!(numbers, vec![10, 11, 12]);
+
+        // 12 comes after the discontinuity before 11 however the playlist lists them.
+        let sequences: Vec<_> = ordered.iter().map(|(sequence, _)| *sequence).collect();
+        assert_eq!(sequences, vec![3, 4, 4]);
+
+        // Out of order, the filter would have skipped 10 and 11 after seeing 12.
+        let mut filter = SegmentNumberFilter::new(None);
+        assert!(ordered
+            .iter()
+            .all(|(_, segment)| filter.need_download(&SegmentKey::of(segment))));
+    }
+
+    #[test]
+    fn test_expand_env() {
+        let var = |name: &str| (name == "DATA_DIR").then(|| "/data".to_owned());
+        let expand = |value| expand_with(value, var);
+
+        assert_eq!(expand("${DATA_DIR}/audio").unwrap(), "/data/audio");
+        assert_eq!(expand("$DATA_DIR/audio").unwrap(), "/data/audio");
+        assert_eq!(expand("$DATA_DIR").unwrap(), "/data");
+        assert_eq!(expand("./audio").unwrap(), "./audio");
+        assert_eq!(expand("cost$$5").unwrap(), "cost$5");
+
+        let error = expand("${STREAM}/live.m3u8").unwrap_err();
+        assert!(error.to_string().contains("`STREAM`"), "{error}");
+        assert!(expand("${DATA_DIR").is_err());
+        assert!(expand("price$").is_err());
+        assert!(expand("${}").is_err());
+    }
+
+    #[test]
+    fn test_init_url() {
+        let playlist = |map: &str| {
+            format!(
+                "#EXTM3U\n\
+                #EXT-X-VERSION:6\n\
+                #EXT-X-TARGETDURATION:10\n\
+                #EXT-X-MAP:URI=\"{map}\"\n\
+                #EXTINF:10,\n\
+                https://cdn.example.com/live/1.m4s\n"
+            )
+        };
+        let playlist_url: Url = "https://example.com/live/stream.m3u8".parse().unwrap();
+
+        let relative = playlist("init.mp4");
+        let relative = MediaPlaylist::try_from(relative.as_str()).unwrap();
+        let (_, segment) = relative.segments.iter().next().unwrap();
+        assert_eq!(
+            init_url(segment, Some(&playlist_url)),
+            Some("https://example.com/live/init.mp4".parse().unwrap())
+        );
+        // Replays have no playlist URL to resolve it against.
+        assert_eq!(init_url(segment, None), None);
+
+        let absolute = playlist("https://cdn.example.com/init.mp4");
+        let absolute = MediaPlaylist::try_from(absolute.as_str()).unwrap();
+        let (_, segment) = absolute.segments.iter().next().unwrap();
+        let expected: Option<Url> = Some("https://cdn.example.com/init.mp4".parse().unwrap());
+        assert_eq!(init_url(segment, Some(&playlist_url)), expected);
+        assert_eq!(init_url(segment, None), expected);
+    }
+
+    /// A fingerprinter which knows no track and takes every insert.
+    struct Unmatched;
+
+    #[async_trait::async_trait]
+    impl Fingerprinter for Unmatched {
+        async fn query(&self, _filename: &str, _bytes: &Bytes) -> anyhow::Result<Vec<QueryResult>> {
+            Ok(Vec::new())
+        }
+
+        async fn insert(
+            &self,
+            _info: TrackInfo,
+            _filename: &str,
+            _bytes: &Bytes,
+        ) -> anyhow::Result<Inserted> {
+            Ok(Inserted::New)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resync_pending_without_stored_audio() {
+        let args = Args::try_parse_from([
+            "feeder",
+            "--kind-policy",
+            "music=query,insert",
+            "http://127.0.0.1/live.m3u8",
+        ])
+        .unwrap();
+        let in_memory = Path::new(":memory:");
+        let storages = Storages {
+            metadata: MetadataStorage::new(&in_memory).unwrap(),
+            audio: Box::new(AudioStorage::new(&in_memory).unwrap()),
+            matches: MatchesStorage::new(&in_memory).unwrap(),
+            id_map: IdMapStorage::new(&in_memory).unwrap(),
+            diagnostics: DiagnosticsStorage::new(&in_memory).unwrap(),
+        };
+
+        let id = Uuid::new_v4();
+        let audio = AudioData::new(
+            id,
+            "audio/wav".to_owned(),
+            silent_wav(Duration::from_secs(1)),
+        );
+        let metadata = Metadata::new(
+            id,
+            Utc::now(),
+            AudioKind::Music,
+            "Artist".to_owned(),
+            "Title".to_owned(),
+        );
+        store_track(&args, &storages, audio, metadata, None)
+            .await
+            .unwrap();
+        // Kept to be synced, though the policy of music keeps no audio.
+        assert!(storages.audio.get(id).await.is_ok());
+
+        let client = reqwest::Client::new();
+        let (pause, shutdown) = (PauseSwitch::default(), CancellationToken::new());
+        let shared = Capture {
+            args: &args,
+            client: &client,
+            parser: &IcyParser,
+            fingerprinter: &Unmatched,
+            storages: &storages,
+            pause: &pause,
+            shutdown: &shutdown,
+            stream_id: String::new(),
+            last_checkpoint: Cell::new(Instant::now()),
+            resync_due: Cell::new(true),
+            unsyncable: RefCell::default(),
+        };
+        assert!(resync_pending(&shared).await.unwrap());
+
+        assert!(shared.unsyncable.borrow().is_empty());
+        assert!(storages.metadata.pending_sync().await.unwrap().is_empty());
+        assert_eq!(storages.id_map.remote_id(id).await.unwrap(), Some(id));
+        assert!(storages.audio.get(id).await.is_err());
+    }
+}
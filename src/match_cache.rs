@@ -0,0 +1,121 @@
+use std::collections::{BTreeMap, HashMap};
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// What a segment resolved to the last time its very audio was seen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CachedMatch {
+    pub id: Uuid,
+    pub score: u8,
+}
+
+/// Recently resolved segments keyed by the SHA-256 of their audio, least recently used
+/// ones evicted first. Keyed by what the audio is rather than by
+/// [`SegmentKey`](crate::segment_filter::SegmentKey), as repeats come in segments of their own.
+///
+/// Repetitive programming like jingles and ads often sends the same file over and over,
+/// a hit resolves it without asking emysound.
+pub struct MatchCache {
+    capacity: usize,
+    entries: HashMap<[u8; 32], (CachedMatch, u64)>,
+    /// Keys of `entries` by their last use, the first one is the least recently used.
+    by_use: BTreeMap<u64, [u8; 32]>,
+    /// Incremented on every access, orders entries by use.
+    clock: u64,
+}
+
+impl MatchCache {
+    /// A zero `capacity` disables the cache.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn key(bytes: &[u8]) -> [u8; 32] {
+        Sha256::digest(bytes).into()
+    }
+
+    pub fn get(&mut self, key: &[u8; 32]) -> Option<CachedMatch> {
+        self.clock += 1;
+        let clock = self.clock;
+        let (matched, used) = self.entries.get_mut(key)?;
+        self.by_use.remove(used);
+        self.by_use.insert(clock, *key);
+        *used = clock;
+        Some(*matched)
+    }
+
+    pub fn insert(&mut self, key: [u8; 32], matched: CachedMatch) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.clock += 1;
+        match self.entries.insert(key, (matched, self.clock)) {
+            Some((_, used)) => {
+                self.by_use.remove(&used);
+            }
+            None if self.entries.len() > self.capacity => {
+                if let Some((&used, &oldest)) = self.by_use.iter().next() {
+                    self.by_use.remove(&used);
+                    self.entries.remove(&oldest);
+                }
+            }
+            None => {}
+        }
+        self.by_use.insert(self.clock, key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{CachedMatch, MatchCache};
+
+    fn matched(score: u8) -> CachedMatch {
+        CachedMatch {
+            id: Uuid::new_v4(),
+            score,
+        }
+    }
+
+    #[test]
+    fn test_lru() {
+        let (a, b, c) = (
+            MatchCache::key(b"a"),
+            MatchCache::key(b"b"),
+            MatchCache::key(b"c"),
+        );
+        let mut cache = MatchCache::new(2);
+
+        let first = matched(90);
+        cache.insert(a, first);
+        cache.insert(b, matched(80));
+        assert_eq!(cache.get(&a), Some(first));
+
+        // `b` is the least recently used one.
+        cache.insert(c, matched(70));
+        assert_eq!(cache.get(&b), None);
+        assert_eq!(cache.get(&a), Some(first));
+        assert_eq!(cache.get(&c).map(|matched| matched.score), Some(70));
+
+        // Replacing an entry evicts nothing.
+        let replaced = matched(100);
+        cache.insert(a, replaced);
+        assert_eq!(cache.get(&a), Some(replaced));
+        assert!(cache.get(&c).is_some());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut cache = MatchCache::new(0);
+        let key = MatchCache::key(b"a");
+        cache.insert(key, matched(90));
+        assert_eq!(cache.get(&key), None);
+    }
+}
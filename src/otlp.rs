@@ -0,0 +1,45 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::Resource;
+use reqwest::Url;
+use tracing_subscriber::layer::SubscriberExt;
+
+/// Exports the tracing spans to an OTLP collector over gRPC, see `--otlp-endpoint`.
+/// Events still go to the log too, by the `log-always` feature of `tracing`.
+///
+/// Spans are sent in batches from the async runtime. Dropping the exporter flushes the
+/// last batch, it must be dropped outside of the runtime.
+pub struct Exporter {
+    provider: TracerProvider,
+}
+
+impl Exporter {
+    /// Installs the exporter as the global tracing subscriber, within the async runtime.
+    pub fn install(endpoint: &Url) -> anyhow::Result<Self> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint.as_str())
+            .build()?;
+        let name = env!("CARGO_PKG_NAME");
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", name)]))
+            .build();
+
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(name));
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+        log::info!("Exporting spans to {endpoint}");
+
+        Ok(Self { provider })
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            log::warn!("Failed to export the last spans: {e}");
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Pauses ingestion while polling goes on, e.g. to back up the databases.
+///
+/// Segments listed while paused are still marked as seen, so resuming doesn't download them.
+#[derive(Clone, Default)]
+pub struct PauseSwitch(Arc<AtomicBool>);
+
+impl PauseSwitch {
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if now paused.
+    pub fn toggle(&self) -> bool {
+        !self.0.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    /// Toggles the switch on every SIGUSR1.
+    #[cfg(unix)]
+    pub fn toggle_on_sigusr1(&self) -> anyhow::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut signal = signal(SignalKind::user_defined1())?;
+        let switch = self.clone();
+        tokio::spawn(async move {
+            while signal.recv().await.is_some() {
+                if switch.toggle() {
+                    log::info!("Paused by SIGUSR1, send it again to resume");
+                } else {
+                    log::info!("Resumed by SIGUSR1");
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn toggle_on_sigusr1(&self) -> anyhow::Result<()> {
+        log::warn!("Pausing by signal is only supported on unix");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PauseSwitch;
+
+    #[test]
+    fn test() {
+        let switch = PauseSwitch::default();
+        assert!(!switch.is_paused());
+        assert!(switch.toggle());
+        assert!(switch.clone().is_paused());
+        assert!(!switch.toggle());
+        assert!(!switch.is_paused());
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::shutdown::{cancellable, sleep};
+
+/// Requests sent at most for one fetch, the last 429 is answered as it is.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Wait of a 429 without a `Retry-After` the feeder can read.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Longest wait honored, a CDN asking for more is retried after this long.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+lazy_static! {
+    /// Until when each host asked to be left alone, for all requests to it to wait out.
+    static ref BACKOFF: Mutex<HashMap<String, Instant>> = Mutex::default();
+}
+
+/// Sends `request`, and again after as long as `Retry-After` asks while the server answers
+/// with 429 Too Many Requests. Requests whose body can't be cloned are sent once.
+///
+/// Requests to a host that rate-limits wait for it too. A wait past `deadline` isn't started:
+/// a 429 is answered as it is then, and a request yet to send fails.
+pub async fn send(
+    mut request: RequestBuilder,
+    deadline: Option<Instant>,
+    shutdown: &CancellationToken,
+) -> anyhow::Result<Response> {
+    let host = request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .map(|request| host(request.url()));
+
+    let mut attempt = 1;
+    loop {
+        if let Some(until) = host.as_deref().and_then(backoff_until) {
+            if matches!(deadline, Some(deadline) if until > deadline) {
+                bail!(
+                    "{} rate-limits requests for {:?} more, longer than the segment may take",
+                    host.as_deref().unwrap_or_default(),
+                    until - Instant::now()
+                );
+            }
+            sleep(shutdown, until - Instant::now()).await;
+        }
+
+        let retry = request.try_clone();
+        let response = cancellable(shutdown, request.send()).await??;
+        request = match retry {
+            Some(retry) if response.status() == StatusCode::TOO_MANY_REQUESTS => retry,
+            _ => return Ok(response),
+        };
+
+        let delay = retry_after(response.headers(), Utc::now())
+            .unwrap_or(DEFAULT_RETRY_AFTER)
+            .min(MAX_RETRY_AFTER);
+        let until = Instant::now() + delay;
+        if let Some(host) = &host {
+            back_off(host, until);
+        }
+        if attempt >= MAX_ATTEMPTS || matches!(deadline, Some(deadline) if until > deadline) {
+            log::warn!(
+                "Rate-limited by {}, honoring Retry-After of {delay:?} without retrying",
+                response.url()
+            );
+            return Ok(response);
+        }
+
+        log::warn!(
+            "Rate-limited by {}, honoring Retry-After and retrying in {delay:?}",
+            response.url()
+        );
+        attempt += 1;
+    }
+}
+
+/// Identifies the host of `url` for [`BACKOFF`], with its port.
+fn host(url: &Url) -> String {
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+/// Until when `host` asked to be left alone, if it still does.
+fn backoff_until(host: &str) -> Option<Instant> {
+    let mut backoff = BACKOFF.lock().unwrap();
+    match backoff.get(host) {
+        Some(until) if *until > Instant::now() => Some(*until),
+        Some(_) => {
+            backoff.remove(host);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Leaves `host` alone until `until`, or longer if it asked so before.
+fn back_off(host: &str, until: Instant) {
+    let mut backoff = BACKOFF.lock().unwrap();
+    let entry = backoff.entry(host.to_owned()).or_insert(until);
+    *entry = (*entry).max(until);
+}
+
+/// The wait `Retry-After` asks for, given as seconds or as an HTTP date. A date passed
+/// already asks for none.
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    // IMF-fixdate, e.g. `Wed, 21 Oct 2015 07:28:00 GMT`, is an RFC 2822 date.
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+    use reqwest::{StatusCode, Url};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time::Instant;
+    use tokio_util::sync::CancellationToken;
+
+    use super::{retry_after, send};
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    fn too_many_requests(retry_after: u64) -> String {
+        format!(
+            "HTTP/1.1 429 Too Many Requests\r\nRetry-After: {retry_after}\r\n\
+            Content-Length: 0\r\nConnection: close\r\n\r\n"
+        )
+    }
+
+    /// Answers a request per connection with `responses` in turn, counting the requests.
+    async fn serve(responses: Vec<String>) -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/segment.aac", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            for response in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0];
+                    stream.read_exact(&mut byte).await.unwrap();
+                    request.push(byte[0]);
+                }
+                counted.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (Url::parse(&url).unwrap(), requests)
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (client, shutdown) = (reqwest::Client::new(), CancellationToken::new());
+
+        let (url, requests) = serve(vec![
+            too_many_requests(0),
+            too_many_requests(0),
+            OK.to_owned(),
+        ])
+        .await;
+        let response = send(client.get(url), None, &shutdown).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // The last 429 is answered as it is.
+        let (url, requests) = serve(vec![too_many_requests(0); 4]).await;
+        let response = send(client.get(url), None, &shutdown).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_send_before_deadline() {
+        let (client, shutdown) = (reqwest::Client::new(), CancellationToken::new());
+        let (url, requests) = serve(vec![too_many_requests(60), OK.to_owned()]).await;
+        let deadline = Some(Instant::now() + Duration::from_secs(5));
+
+        // A wait past the deadline isn't started, nor another request to the host meanwhile.
+        let sending = send(client.get(url.clone()), deadline, &shutdown);
+        let response = tokio::time::timeout(Duration::from_secs(1), sending)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(send(client.get(url), deadline, &shutdown).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = Utc.ymd(2015, 10, 21).and_hms(7, 27, 30);
+        let parse = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            retry_after(&headers, now)
+        };
+
+        assert_eq!(parse("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse("Wed, 21 Oct 2015 07:00:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse("soon"), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+/// Remembers tracks inserted within the last `window`, keyed by artist and title.
+///
+/// Right after an insert emysound may not have indexed the track yet, so the next segment
+/// of the same track finds no match. Looking it up here avoids inserting it a second time.
+pub struct RecentInserts {
+    window: Duration,
+    inserts: HashMap<(String, String), (Uuid, Instant)>,
+}
+
+impl RecentInserts {
+    /// A zero `window` disables the lookup.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            inserts: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, artist: &str, title: &str, id: Uuid) {
+        if self.window.is_zero() {
+            return;
+        }
+        self.inserts
+            .insert((artist.to_owned(), title.to_owned()), (id, Instant::now()));
+    }
+
+    /// Id of the track inserted with the same artist and title within the window.
+    pub fn get(&mut self, artist: &str, title: &str) -> Option<Uuid> {
+        let window = self.window;
+        self.inserts
+            .retain(|_, (_, inserted)| inserted.elapsed() < window);
+
+        self.inserts
+            .get(&(artist.to_owned(), title.to_owned()))
+            .map(|(id, _)| *id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::RecentInserts;
+
+    #[test]
+    fn test() {
+        let id = Uuid::new_v4();
+        let mut inserts = RecentInserts::new(Duration::from_secs(60));
+        inserts.insert("Artist", "Title", id);
+
+        assert_eq!(inserts.get("Artist", "Title"), Some(id));
+        assert_eq!(inserts.get("Artist", "Other"), None);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut inserts = RecentInserts::new(Duration::ZERO);
+        inserts.insert("Artist", "Title", Uuid::new_v4());
+
+        assert_eq!(inserts.get("Artist", "Title"), None);
+    }
+}
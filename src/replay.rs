@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+
+/// Playlists saved to a directory, replayed in filename order instead of polling the stream.
+pub struct ReplayPlaylists {
+    playlists: VecDeque<PathBuf>,
+}
+
+impl ReplayPlaylists {
+    pub fn new(dir: &Path) -> anyhow::Result<Self> {
+        let mut playlists = std::fs::read_dir(dir)
+            .with_context(|| format!("Read replay directory {}", dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        playlists.retain(|path| path.extension().map_or(false, |ext| ext == "m3u8"));
+        playlists.sort();
+
+        log::info!(
+            "Replaying {} playlists from {}",
+            playlists.len(),
+            dir.display()
+        );
+
+        Ok(Self {
+            playlists: playlists.into(),
+        })
+    }
+
+    /// Content of the next playlist, `None` once all were replayed.
+    pub fn next(&mut self) -> anyhow::Result<Option<String>> {
+        self.playlists
+            .pop_front()
+            .map(|path| {
+                log::info!("Replaying {}", path.display());
+                std::fs::read_to_string(&path).with_context(|| format!("Read {}", path.display()))
+            })
+            .transpose()
+    }
+}
+
+/// Reads the segment at `url` from the file of the same name in `dir`,
+/// with the content type guessed from its extension.
+pub fn segment(dir: &Path, url: &Url) -> anyhow::Result<(String, Bytes)> {
+    let name = url
+        .path_segments()
+        .and_then(|segments| segments.last())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow!("No file name in {url}"))?;
+    let path = dir.join(name);
+
+    let bytes = std::fs::read(&path).with_context(|| format!("Read {}", path.display()))?;
+    Ok((content_type(&path).to_owned(), bytes.into()))
+}
+
+/// Writes the playlist of `stream_id` fetched `at` to `dir` as a file of `extension`, see
+/// `--dump-playlist`. Replaying `dir` goes through the `.m3u8` playlists of a stream in the
+/// order they were fetched.
+pub async fn dump(
+    dir: &Path,
+    stream_id: &str,
+    at: DateTime<Utc>,
+    extension: &str,
+    content: &str,
+) -> anyhow::Result<PathBuf> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Create {}", dir.display()))?;
+    let path = dir.join(dump_name(stream_id, at, extension));
+    tokio::fs::write(&path, content)
+        .await
+        .with_context(|| format!("Write {}", path.display()))?;
+    Ok(path)
+}
+
+fn dump_name(stream_id: &str, at: DateTime<Utc>, extension: &str) -> String {
+    let stream_id: String = stream_id
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c,
+            '-' | '_' | '.' | '@' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "{}-{stream_id}.{extension}",
+        at.format("%Y%m%dT%H%M%S%.3fZ")
+    )
+}
+
+/// Content type of an audio file guessed from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("aac") => "audio/aac",
+        Some("mp3") => "audio/mpeg",
+        Some("m4a" | "mp4") => "audio/mp4",
+        Some("ts") => "video/mp2t",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{dump, dump_name, segment, ReplayPlaylists};
+
+    #[test]
+    fn test() {
+        let dir = Path::new("./test_replay");
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("002.m3u8"), "second").unwrap();
+        std::fs::write(dir.join("001.m3u8"), "first").unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        std::fs::write(dir.join("segment-42.aac"), "audio").unwrap();
+
+        let mut playlists = ReplayPlaylists::new(dir).unwrap();
+        assert_eq!(playlists.next().unwrap().as_deref(), Some("first"));
+        assert_eq!(playlists.next().unwrap().as_deref(), Some("second"));
+        assert_eq!(playlists.next().unwrap(), None);
+
+        let url = "https://cdn.example.com/live/segment-42.aac?token=1"
+            .parse()
+            .unwrap();
+        let (content_type, bytes) = segment(dir, &url).unwrap();
+        assert_eq!(content_type, "audio/aac");
+        assert_eq!(bytes.as_ref(), b"audio");
+    }
+
+    #[tokio::test]
+    async fn test_dump() {
+        let at = Utc.ymd(2022, 3, 1).and_hms_milli(9, 5, 0, 42);
+        assert_eq!(
+            dump_name("jazz.example.com@128000", at, "m3u8"),
+            "20220301T090500.042Z-jazz.example.com@128000.m3u8"
+        );
+        assert_eq!(
+            dump_name("rock/live fm", at, "mpd"),
+            "20220301T090500.042Z-rock_live_fm.mpd"
+        );
+
+        let dir = Path::new("./test_dump");
+        let _ = std::fs::remove_dir_all(dir);
+        let second = at + chrono::Duration::seconds(5);
+        dump(dir, "jazz", second, "m3u8", "second").await.unwrap();
+        dump(dir, "jazz", at, "m3u8", "first").await.unwrap();
+        // Manifests and other bodies are not replayed.
+        dump(dir, "jazz", at, "mpd", "<MPD/>").await.unwrap();
+
+        let mut playlists = ReplayPlaylists::new(dir).unwrap();
+        assert_eq!(playlists.next().unwrap().as_deref(), Some("first"));
+        assert_eq!(playlists.next().unwrap().as_deref(), Some("second"));
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+
+/// Weekly time window to capture in, e.g. `Mon-Fri 18:00-20:00 Europe/Amsterdam`.
+///
+/// A window ending before it starts runs over midnight into the next day.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleWindow {
+    /// Indexed by days from Monday.
+    days: [bool; 7],
+    start: NaiveTime,
+    end: NaiveTime,
+    /// `--timezone` if not given.
+    timezone: Option<Tz>,
+}
+
+impl ScheduleWindow {
+    pub fn contains(&self, now: DateTime<Utc>, timezone: Tz) -> bool {
+        let local = now.with_timezone(&self.timezone.unwrap_or(timezone));
+        let time = local.time();
+        let day = local.weekday();
+        let starts_on = |day: Weekday| self.days[day.num_days_from_monday() as usize];
+
+        if self.start < self.end {
+            starts_on(day) && self.start <= time && time < self.end
+        } else {
+            (starts_on(day) && self.start <= time) || (starts_on(day.pred()) && time < self.end)
+        }
+    }
+}
+
+/// Parses `DAYS HH:MM-HH:MM [TIMEZONE]`, days being a day, a range like `Mon-Fri`
+/// or a list of them like `Mon,Wed,Sat-Sun`.
+pub fn parse_schedule_window(value: &str) -> anyhow::Result<ScheduleWindow> {
+    let mut parts = value.split_whitespace();
+    let (days, times) = parts
+        .next()
+        .zip(parts.next())
+        .ok_or_else(|| anyhow!("Expected DAYS HH:MM-HH:MM [TIMEZONE]"))?;
+    let timezone = parts
+        .next()
+        .map(|tz| {
+            tz.parse()
+                .map_err(|e: String| anyhow!("Unknown time zone `{tz}`: {e}"))
+        })
+        .transpose()?;
+    if parts.next().is_some() {
+        bail!("Expected DAYS HH:MM-HH:MM [TIMEZONE]");
+    }
+
+    let mut window_days = [false; 7];
+    for range in days.split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let mut day = parse_weekday(first)?;
+        let last = parse_weekday(last)?;
+        window_days[day.num_days_from_monday() as usize] = true;
+        while day != last {
+            day = day.succ();
+            window_days[day.num_days_from_monday() as usize] = true;
+        }
+    }
+
+    let (start, end) = times
+        .split_once('-')
+        .ok_or_else(|| anyhow!("Expected HH:MM-HH:MM, got `{times}`"))?;
+    let parse_time = |time: &str| {
+        NaiveTime::parse_from_str(time, "%H:%M").with_context(|| format!("Invalid time `{time}`"))
+    };
+    let (start, end) = (parse_time(start)?, parse_time(end)?);
+    if start == end {
+        bail!("Empty window {times}");
+    }
+
+    Ok(ScheduleWindow {
+        days: window_days,
+        start,
+        end,
+        timezone,
+    })
+}
+
+fn parse_weekday(value: &str) -> anyhow::Result<Weekday> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Unknown day `{value}`, expected Mon, Tue, .., Sun"))
+}
+
+/// Tells whether to capture now, logging when the schedule starts and stops capturing.
+pub struct Schedule {
+    windows: Vec<ScheduleWindow>,
+    timezone: Tz,
+    active: Option<bool>,
+}
+
+impl Schedule {
+    /// Without any window capturing never stops.
+    pub fn new(windows: Vec<ScheduleWindow>, timezone: Tz) -> Self {
+        Self {
+            windows,
+            timezone,
+            active: None,
+        }
+    }
+
+    pub fn is_active(&mut self, now: DateTime<Utc>) -> bool {
+        if self.windows.is_empty() {
+            return true;
+        }
+
+        let active = self
+            .windows
+            .iter()
+            .any(|window| window.contains(now, self.timezone));
+        if self.active != Some(active) {
+            if active {
+                log::info!("Within a --schedule window, capturing");
+            } else {
+                log::info!("Outside of --schedule windows, polling without capturing");
+            }
+            self.active = Some(active);
+        }
+
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::{parse_schedule_window, Schedule};
+
+    /// 2022-05-02 is a Monday.
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.ymd(2022, 5, day).and_hms(hour, minute, 0)
+    }
+
+    #[test]
+    fn test_weekdays() {
+        // Amsterdam is UTC+2 in May.
+        let window = parse_schedule_window("Mon-Fri 18:00-20:00 Europe/Amsterdam").unwrap();
+        let contains = |now| window.contains(now, chrono_tz::UTC);
+
+        assert!(contains(at(2, 16, 0)));
+        assert!(contains(at(6, 17, 59)));
+        assert!(!contains(at(2, 18, 0)));
+        assert!(!contains(at(2, 15, 59)));
+        assert!(!contains(at(7, 16, 30)));
+    }
+
+    #[test]
+    fn test_over_midnight() {
+        let window = parse_schedule_window("Sat,Sun 22:00-02:00").unwrap();
+        let contains = |now| window.contains(now, chrono_tz::UTC);
+
+        assert!(contains(at(7, 23, 0)));
+        assert!(contains(at(8, 1, 0)));
+        assert!(contains(at(9, 1, 59)));
+        assert!(!contains(at(9, 2, 0)));
+        assert!(!contains(at(7, 1, 0)));
+        assert!(!contains(at(9, 22, 0)));
+
+        // Without a time zone of its own the window is in the default one.
+        assert!(window.contains(at(8, 20, 0), chrono_tz::Europe::Amsterdam));
+    }
+
+    #[test]
+    fn test_wrapping_days() {
+        let window = parse_schedule_window("Fri-Mon 00:00-12:00").unwrap();
+        assert!(window.contains(at(2, 6, 0), chrono_tz::UTC));
+        assert!(window.contains(at(7, 6, 0), chrono_tz::UTC));
+        assert!(!window.contains(at(3, 6, 0), chrono_tz::UTC));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_schedule_window("Mon-Fri").is_err());
+        assert!(parse_schedule_window("Someday 18:00-20:00").is_err());
+        assert!(parse_schedule_window("Mon 18:00").is_err());
+        assert!(parse_schedule_window("Mon 25:00-26:00").is_err());
+        assert!(parse_schedule_window("Mon 18:00-18:00").is_err());
+        assert!(parse_schedule_window("Mon 18:00-20:00 Mars/Olympus").is_err());
+        assert!(parse_schedule_window("Mon 18:00-20:00 UTC extra").is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let mut always = Schedule::new(vec![], chrono_tz::UTC);
+        assert!(always.is_active(at(2, 3, 0)));
+
+        let window = parse_schedule_window("Mon 18:00-20:00").unwrap();
+        let mut schedule = Schedule::new(vec![window], chrono_tz::UTC);
+        assert!(!schedule.is_active(at(2, 17, 0)));
+        assert!(schedule.is_active(at(2, 19, 0)));
+        assert!(!schedule.is_active(at(3, 19, 0)));
+    }
+}
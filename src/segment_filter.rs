@@ -0,0 +1,140 @@
+use std::fmt;
+
+use hls_m3u8::MediaSegment;
+
+/// Identity of a segment shared by everything that needs to tell segments apart,
+/// so that filtering, logs and retries agree on which segment is which.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SegmentKey {
+    /// Already includes the playlist media sequence.
+    number: usize,
+    /// Without the query, which CDNs use for rotating access tokens.
+    uri: String,
+    /// See `--split-segments`.
+    part: Option<usize>,
+}
+
+impl SegmentKey {
+    pub fn new(number: usize, uri: &str) -> Self {
+        let uri = uri.split_once('?').map_or(uri, |(path, _)| path);
+        Self {
+            number,
+            uri: uri.to_owned(),
+            part: None,
+        }
+    }
+
+    pub fn of(segment: &MediaSegment) -> Self {
+        Self::new(segment.number(), segment.uri())
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    /// Part `part` of the segment.
+    pub fn part(&self, part: usize) -> Self {
+        Self {
+            part: Some(part),
+            ..self.clone()
+        }
+    }
+}
+
+/// `<number>:<uri>`, followed by `#<part>` for parts.
+impl fmt::Display for SegmentKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.number, self.uri)?;
+        match self.part {
+            Some(part) => write!(f, "#{part}"),
+            None => Ok(()),
+        }
+    }
+}
+
+pub trait SegmentDownloadFilter {
+    /// Returs `true` if the segment of `key` should be downloaded.
+    fn need_download(&mut self, key: &SegmentKey) -> bool;
+}
+
+pub struct SegmentNumberFilter {
+    last_seen_number: usize,
+    reset_threshold: Option<usize>,
+}
+
+impl SegmentNumberFilter {
+    /// A backward jump of more than `reset_threshold` numbers is accepted as a sequence reset.
+    pub fn new(reset_threshold: Option<usize>) -> Self {
+        Self {
+            last_seen_number: 0,
+            reset_threshold,
+        }
+    }
+
+    fn need_download_number(&mut self, number: usize) -> bool {
+        if number > self.last_seen_number {
+            self.last_seen_number = number;
+            return true;
+        }
+
+        match self.reset_threshold {
+            Some(threshold) if self.last_seen_number - number > threshold => {
+                log::info!(
+                    "Segment#{number} accepted as a sequence reset, last seen Segment#{}",
+                    self.last_seen_number
+                );
+                self.last_seen_number = number;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl SegmentDownloadFilter for SegmentNumberFilter {
+    fn need_download(&mut self, key: &SegmentKey) -> bool {
+        self.need_download_number(key.number())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SegmentKey, SegmentNumberFilter};
+
+    #[test]
+    fn test_segment_key() {
+        assert_eq!(
+            SegmentKey::new(42, "https://cdn.example.com/a/42.aac?token=1"),
+            SegmentKey::new(42, "https://cdn.example.com/a/42.aac?token=2")
+        );
+        assert_eq!(SegmentKey::new(42, "42.aac").to_string(), "42:42.aac");
+        assert_eq!(
+            SegmentKey::new(42, "42.aac").part(2).to_string(),
+            "42:42.aac#2"
+        );
+        assert_ne!(SegmentKey::new(42, "42.aac"), SegmentKey::new(43, "42.aac"));
+        assert_ne!(
+            SegmentKey::new(42, "42.aac"),
+            SegmentKey::new(42, "42.aac").part(1)
+        );
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let mut filter = SegmentNumberFilter::new(None);
+        assert!(filter.need_download_number(10));
+        assert!(filter.need_download_number(11));
+        assert!(!filter.need_download_number(11));
+        assert!(!filter.need_download_number(1));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut filter = SegmentNumberFilter::new(Some(100));
+        assert!(filter.need_download_number(5000));
+        assert!(!filter.need_download_number(4950));
+        assert!(filter.need_download_number(1));
+        assert!(filter.need_download_number(2));
+        assert!(!filter.need_download_number(2));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::BTreeMap;
+
+/// KostaRadio `adContext` of an ad break, with the `offset` sent along.
+///
+/// Ad servers put campaign and creative ids into the value, either as `key=value` pairs
+/// separated by `&` or `;`, or as a single opaque id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdContext {
+    pub value: String,
+    /// Seconds into the ad break, if sent.
+    pub offset: Option<i64>,
+    /// The `key=value` pairs of `value`, empty for an opaque one.
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Field names taken for the campaign id, compared case-insensitively.
+const CAMPAIGN_FIELDS: &[&str] = &["campaign", "campaignid", "campaign_id", "cid"];
+
+impl AdContext {
+    pub fn parse(value: &str, offset: Option<&str>) -> Self {
+        let pairs = value
+            .split(['&', ';'])
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                pair.split_once('=')
+                    .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+                    .filter(|(key, _)| !key.is_empty())
+            })
+            .collect::<Option<BTreeMap<_, _>>>();
+
+        Self {
+            value: value.to_owned(),
+            offset: offset.and_then(|offset| offset.trim().parse().ok()),
+            fields: pairs.unwrap_or_default(),
+        }
+    }
+
+    /// The campaign field, or an opaque value as a whole.
+    pub fn campaign(&self) -> Option<&str> {
+        if self.fields.is_empty() {
+            let value = self.value.trim();
+            return (!value.is_empty()).then(|| value);
+        }
+        self.fields
+            .iter()
+            .find(|(key, _)| CAMPAIGN_FIELDS.contains(&key.to_lowercase().as_str()))
+            .map(|(_, value)| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdContext;
+
+    #[test]
+    fn test() {
+        let empty = AdContext::parse("", Some("0"));
+        assert_eq!(empty.offset, Some(0));
+        assert!(empty.fields.is_empty());
+        assert_eq!(empty.campaign(), None);
+
+        let pairs = AdContext::parse("campaignId=ACME-42&creative=7; flight = spring", None);
+        assert_eq!(pairs.fields.len(), 3);
+        assert_eq!(pairs.fields["flight"], "spring");
+        assert_eq!(pairs.campaign(), Some("ACME-42"));
+
+        let opaque = AdContext::parse("ACME Spring 2022", Some("12"));
+        assert!(opaque.fields.is_empty());
+        assert_eq!(opaque.campaign(), Some("ACME Spring 2022"));
+        assert_eq!(opaque.offset, Some(12));
+
+        // Pairs without a campaign, and a value that isn't all pairs.
+        assert_eq!(AdContext::parse("creative=7", None).campaign(), None);
+        let mixed = AdContext::parse("ACME&creative=7", Some("x"));
+        assert!(mixed.fields.is_empty());
+        assert_eq!(mixed.offset, None);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+
+/// Separators tried by [`extract_attributes`], earlier ones winning ties.
+const SEPARATORS: [char; 3] = [',', ';', ' '];
+
+/// Splits `input` into `key=value` pairs separated by `separator`.
+///
+/// Values may be bare (`offset=0`), double-quoted (`title="Earth, Wind & Fire"`)
+/// or single-quoted (`adContext=''`). Inside quotes a backslash escapes the next
+/// character, and a quote that is not followed by a separator (or the end of input)
+/// is kept as part of the value, so separators and stray quotes in titles survive.
+pub fn parse_attributes(input: &str, separator: char) -> anyhow::Result<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    loop {
+        while chars
+            .peek()
+            .map_or(false, |&c| c == separator || c.is_whitespace())
+        {
+            chars.next();
+        }
+
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => break,
+                Some(c) if c == separator => bail!("Attribute `{key}` has no value"),
+                Some(c) => key.push(c),
+                None => bail!("Attribute `{key}` has no value"),
+            }
+        }
+
+        let key = key.trim().to_owned();
+        if key.is_empty() {
+            bail!("Empty attribute name");
+        }
+
+        let mut value = String::new();
+        match chars.peek().copied() {
+            Some(quote @ ('"' | '\'')) => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.push(
+                            chars
+                                .next()
+                                .ok_or_else(|| anyhow!("Dangling escape in `{key}`"))?,
+                        ),
+                        Some(c) if c == quote => {
+                            if chars.peek().map_or(true, |&next| next == separator) {
+                                break;
+                            }
+                            value.push(c);
+                        }
+                        Some(c) => value.push(c),
+                        None => bail!("Unterminated value of `{key}`"),
+                    }
+                }
+            }
+            _ => {
+                while let Some(&c) = chars.peek() {
+                    if c == separator {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+        }
+
+        attributes.push((key, value));
+    }
+
+    Ok(attributes)
+}
+
+/// Every `key=value` pair of a segment title in any station format, for keeping what
+/// no parser models yet. Quoted values that are attribute lists themselves, like the KostaRadio
+/// `url`, are extracted too, their keys prefixed with the outer key: `url.song_spot`.
+///
+/// Never fails, a title without attributes gives an empty map.
+pub fn extract_attributes(input: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    extract_into(input, "", &mut attributes);
+    attributes
+}
+
+fn extract_into(input: &str, prefix: &str, attributes: &mut HashMap<String, String>) {
+    // A wrong separator may still parse, as one pair swallowing the rest, so the most pairs win.
+    let pairs = SEPARATORS
+        .iter()
+        .filter_map(|&separator| parse_attributes(input, separator).ok())
+        .rev()
+        .max_by_key(Vec::len)
+        .unwrap_or_default();
+
+    for (key, value) in pairs {
+        let key = format!("{prefix}{key}");
+        if value.contains("=\"") || value.contains("='") {
+            extract_into(&value, &format!("{key}."), attributes);
+        }
+        attributes.insert(key, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_attributes, parse_attributes};
+
+    fn pairs(input: &str, separator: char) -> Vec<(String, String)> {
+        parse_attributes(input, separator).unwrap()
+    }
+
+    fn owned(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_bare_and_quoted() {
+        assert_eq!(
+            pairs(r#"offset=0,title="Title",adContext=''"#, ','),
+            owned(&[("offset", "0"), ("title", "Title"), ("adContext", "")])
+        );
+    }
+
+    #[test]
+    fn test_commas_and_ampersands() {
+        assert_eq!(
+            pairs(r#"title="September",artist="Earth, Wind & Fire""#, ','),
+            owned(&[("title", "September"), ("artist", "Earth, Wind & Fire")])
+        );
+    }
+
+    #[test]
+    fn test_escaped_quotes() {
+        assert_eq!(
+            pairs(r#"title="The \"Best\", Live",url="a=\"1\" b=\"\"""#, ','),
+            owned(&[("title", r#"The "Best", Live"#), ("url", r#"a="1" b="""#)])
+        );
+        assert_eq!(pairs(r#"a="1" b="""#, ' '), owned(&[("a", "1"), ("b", "")]));
+    }
+
+    #[test]
+    fn test_unescaped_inner_quotes() {
+        assert_eq!(
+            pairs(r#"title="Say "Hi" Again",artist="A""#, ','),
+            owned(&[("title", r#"Say "Hi" Again"#), ("artist", "A")])
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(parse_attributes(r#"title="Unterminated"#, ',').is_err());
+        assert!(parse_attributes("title", ',').is_err());
+        assert!(parse_attributes("=value", ',').is_err());
+    }
+
+    #[test]
+    fn test_extract() {
+        let attributes = extract_attributes(
+            r#"offset=0,title="September",url="song_spot=\"M\" MediaBaseId=\"1234\"""#,
+        );
+        assert_eq!(attributes["offset"], "0");
+        assert_eq!(attributes["title"], "September");
+        assert_eq!(attributes["url.song_spot"], "M");
+        assert_eq!(attributes["url.MediaBaseId"], "1234");
+        assert_eq!(attributes.len(), 5);
+
+        let attributes =
+            extract_attributes("StreamTitle='Daft Punk - One More Time';StreamUrl='';");
+        assert_eq!(attributes["StreamTitle"], "Daft Punk - One More Time");
+        assert_eq!(attributes["StreamUrl"], "");
+
+        assert!(extract_attributes("Daft Punk - One More Time").is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use hls_m3u8::MediaSegment;
+
+use super::{ParsedSegment, SegmentMetadataParser, SuggestedSegmentContentKind};
+
+/// Classifies segments of blacklisted `media_base_id`s as [`SuggestedSegmentContentKind::None`],
+/// whatever the inner parser made of them. Meant for station promos that look like music.
+pub struct MediaBaseIdBlacklist {
+    inner: Box<dyn SegmentMetadataParser>,
+    ids: HashSet<i64>,
+}
+
+impl MediaBaseIdBlacklist {
+    pub fn new(inner: Box<dyn SegmentMetadataParser>, ids: impl IntoIterator<Item = i64>) -> Self {
+        Self {
+            inner,
+            ids: ids.into_iter().collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl SegmentMetadataParser for MediaBaseIdBlacklist {
+    async fn parse(&self, segment: &MediaSegment) -> anyhow::Result<ParsedSegment> {
+        let mut parsed = self.inner.parse(segment).await?;
+        if let Some(id) = parsed.ids.media_base_id.filter(|id| self.ids.contains(id)) {
+            log::debug!("Blacklisted media_base_id={id}, was {}", parsed.kind);
+            parsed.kind = SuggestedSegmentContentKind::None;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Reads `media_base_id`s one per line, skipping blank lines and `#` comments.
+pub fn read_media_base_ids(path: &Path) -> anyhow::Result<Vec<i64>> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("Read {}", path.display()))?;
+    parse_media_base_ids(&content).with_context(|| format!("Parse {}", path.display()))
+}
+
+fn parse_media_base_ids(content: &str) -> anyhow::Result<Vec<i64>> {
+    content
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.parse()
+                .with_context(|| format!("Invalid media_base_id {line:?}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use hls_m3u8::MediaSegment;
+
+    use super::{parse_media_base_ids, MediaBaseIdBlacklist};
+    use crate::segment_info::{ParsedSegment, SegmentMetadataParser, SuggestedSegmentContentKind};
+    use crate::storage::TrackIds;
+
+    struct Fixed(Option<i64>);
+
+    #[async_trait]
+    impl SegmentMetadataParser for Fixed {
+        async fn parse(&self, _: &MediaSegment) -> anyhow::Result<ParsedSegment> {
+            Ok(ParsedSegment {
+                artist: "Station".to_owned(),
+                title: "Promo".to_owned(),
+                kind: SuggestedSegmentContentKind::Music,
+                ad_context: None,
+                ids: TrackIds {
+                    media_base_id: self.0,
+                    ..TrackIds::default()
+                },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let segment = &MediaSegment::builder()
+            .duration(std::time::Duration::from_secs(10))
+            .uri("https://example.com/1.aac")
+            .build()
+            .unwrap();
+        let kind = |id| async move {
+            MediaBaseIdBlacklist::new(Box::new(Fixed(id)), [42])
+                .parse(segment)
+                .await
+                .unwrap()
+                .kind
+        };
+
+        assert_eq!(kind(Some(42)).await, SuggestedSegmentContentKind::None);
+        assert_eq!(kind(Some(7)).await, SuggestedSegmentContentKind::Music);
+        assert_eq!(kind(None).await, SuggestedSegmentContentKind::Music);
+    }
+
+    #[test]
+    fn test_parse() {
+        let ids = parse_media_base_ids("# promos\n42\n\n  7 # jingle\n").unwrap();
+        assert_eq!(ids, vec![42, 7]);
+        assert!(parse_media_base_ids("promo").is_err());
+    }
+}
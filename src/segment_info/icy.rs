@@ -0,0 +1,135 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use hls_m3u8::MediaSegment;
+
+use crate::storage::TrackIds;
+
+use super::attributes::parse_attributes;
+use super::{ParsedSegment, SegmentMetadataParser, SuggestedSegmentContentKind};
+
+/// Words that mark an ICY title as an advertisement rather than a song.
+const AD_KEYWORDS: &[&str] = &["advert", "commercial", "sponsor", "promo"];
+
+/// ICY now-playing metadata: `StreamTitle='Artist - Title';StreamUrl='..';` or bare `Artist - Title`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IcySegmentInfo {
+    pub artist: String,
+    pub title: String,
+}
+
+impl IcySegmentInfo {
+    pub fn suggested_content_kind(&self) -> SuggestedSegmentContentKind {
+        let text = format!("{} {}", self.artist, self.title).to_lowercase();
+        if AD_KEYWORDS.iter().any(|keyword| text.contains(keyword)) {
+            SuggestedSegmentContentKind::Advertisement
+        } else {
+            SuggestedSegmentContentKind::Music
+        }
+    }
+}
+
+impl TryFrom<&str> for IcySegmentInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let stream_title = if value.contains("StreamTitle=") {
+            parse_attributes(value, ';')?
+                .into_iter()
+                .find_map(|(key, value)| (key == "StreamTitle").then(|| value))
+                .ok_or_else(|| anyhow!("Missing `StreamTitle`"))?
+        } else {
+            value.to_owned()
+        };
+
+        let stream_title = stream_title.trim();
+        if stream_title.is_empty() {
+            bail!("Empty stream title");
+        }
+
+        let (artist, title) = stream_title
+            .split_once(" - ")
+            .map(|(artist, title)| (artist.trim(), title.trim()))
+            .unwrap_or(("", stream_title));
+
+        Ok(Self {
+            artist: artist.to_owned(),
+            title: title.to_owned(),
+        })
+    }
+}
+
+pub struct IcyParser;
+
+#[async_trait]
+impl SegmentMetadataParser for IcyParser {
+    async fn parse(&self, segment: &MediaSegment) -> anyhow::Result<ParsedSegment> {
+        let title = segment
+            .duration
+            .title()
+            .as_ref()
+            .ok_or_else(|| anyhow!("No title"))?;
+        let info = IcySegmentInfo::try_from(title.as_ref())?;
+
+        Ok(ParsedSegment {
+            kind: info.suggested_content_kind(),
+            ad_context: None,
+            ids: TrackIds::default(),
+            artist: info.artist,
+            title: info.title,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IcySegmentInfo;
+    use crate::segment_info::SuggestedSegmentContentKind;
+
+    fn parse(value: &str) -> IcySegmentInfo {
+        IcySegmentInfo::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn test_stream_title() {
+        let info = parse("StreamTitle='Daft Punk - One More Time';StreamUrl='';");
+        assert_eq!(info.artist, "Daft Punk");
+        assert_eq!(info.title, "One More Time");
+        assert_eq!(
+            info.suggested_content_kind(),
+            SuggestedSegmentContentKind::Music
+        );
+    }
+
+    #[test]
+    fn test_apostrophes_and_dashes() {
+        let info = parse("StreamTitle='Fleetwood Mac - Don't Stop - Remastered';");
+        assert_eq!(info.artist, "Fleetwood Mac");
+        assert_eq!(info.title, "Don't Stop - Remastered");
+    }
+
+    #[test]
+    fn test_bare() {
+        let info = parse("Earth, Wind & Fire - September");
+        assert_eq!(info.artist, "Earth, Wind & Fire");
+        assert_eq!(info.title, "September");
+
+        let info = parse("Station Jingle");
+        assert_eq!(info.artist, "");
+        assert_eq!(info.title, "Station Jingle");
+    }
+
+    #[test]
+    fn test_advertisement() {
+        let info = parse("StreamTitle='Commercial Break - Sponsored';");
+        assert_eq!(
+            info.suggested_content_kind(),
+            SuggestedSegmentContentKind::Advertisement
+        );
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(IcySegmentInfo::try_from("StreamTitle='';").is_err());
+        assert!(IcySegmentInfo::try_from("   ").is_err());
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+
+use super::attributes::extract_attributes;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+/// PMT stream type of ID3 timed metadata carried in PES packets.
+const STREAM_TYPE_ID3: u8 = 0x15;
+
+/// Now-playing info a segment carries in-band as ID3 timed metadata.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TimedMetadata {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+}
+
+/// Reads ID3 timed metadata of a downloaded segment, either an MPEG-TS with an ID3 stream
+/// or packed audio that starts with an ID3 tag. The last tag of the segment with an artist
+/// or a title wins, being the most current one.
+///
+/// Artist and title come from `TPE1` and `TIT2` frames, or from `artist=".."` and `title=".."`
+/// attributes of `TXXX` frames that some stations use instead.
+pub fn timed_metadata(bytes: &[u8]) -> Option<TimedMetadata> {
+    let tags = if bytes.starts_with(b"ID3") {
+        vec![bytes.to_vec()]
+    } else if is_transport_stream(bytes) {
+        transport_stream_id3(bytes)
+    } else {
+        Vec::new()
+    };
+
+    tags.iter()
+        .rev()
+        .filter_map(|tag| parse_id3(tag))
+        .find(|metadata| metadata.artist.is_some() || metadata.title.is_some())
+}
+
+fn is_transport_stream(bytes: &[u8]) -> bool {
+    bytes.len() >= TS_PACKET_SIZE
+        && bytes[0] == TS_SYNC_BYTE
+        && bytes
+            .get(TS_PACKET_SIZE)
+            .map_or(true, |&b| b == TS_SYNC_BYTE)
+}
+
+/// ID3 tags of the PES streams the PMT declares as ID3 timed metadata.
+fn transport_stream_id3(bytes: &[u8]) -> Vec<Vec<u8>> {
+    let mut pmt_pids = HashSet::new();
+    let mut id3_pids = HashSet::new();
+    let mut pending: HashMap<u16, Vec<u8>> = HashMap::new();
+    let mut complete = Vec::new();
+
+    for packet in bytes.chunks_exact(TS_PACKET_SIZE) {
+        if packet[0] != TS_SYNC_BYTE {
+            continue;
+        }
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from(packet[1] & 0x1f) << 8 | u16::from(packet[2]);
+        let payload = match (packet[3] >> 4) & 0x3 {
+            0b01 => &packet[4..],
+            0b11 => match packet.get(5 + usize::from(packet[4])..) {
+                Some(payload) => payload,
+                None => continue,
+            },
+            _ => continue,
+        };
+
+        if pid == 0 && unit_start {
+            pmt_pids.extend(psi_section(payload).map(pat_pmt_pids).unwrap_or_default());
+        } else if pmt_pids.contains(&pid) && unit_start {
+            id3_pids.extend(psi_section(payload).map(pmt_id3_pids).unwrap_or_default());
+        } else if id3_pids.contains(&pid) {
+            if unit_start {
+                complete.extend(pending.insert(pid, payload.to_vec()));
+            } else if let Some(pes) = pending.get_mut(&pid) {
+                pes.extend_from_slice(payload);
+            }
+        }
+    }
+    complete.extend(pending.into_values());
+
+    complete
+        .iter()
+        .filter_map(|pes| pes_payload(pes).map(<[u8]>::to_vec))
+        .collect()
+}
+
+/// The section a PSI payload points to, without its CRC.
+fn psi_section(payload: &[u8]) -> Option<&[u8]> {
+    let section = payload.get(1 + usize::from(*payload.first()?)..)?;
+    let length = usize::from(section.get(1)? & 0x0f) << 8 | usize::from(*section.get(2)?);
+    section.get(..(3 + length).checked_sub(4)?)
+}
+
+fn pat_pmt_pids(section: &[u8]) -> Vec<u16> {
+    section
+        .get(8..)
+        .unwrap_or_default()
+        .chunks_exact(4)
+        .filter(|entry| entry[0] != 0 || entry[1] != 0)
+        .map(|entry| u16::from(entry[2] & 0x1f) << 8 | u16::from(entry[3]))
+        .collect()
+}
+
+fn pmt_id3_pids(section: &[u8]) -> Vec<u16> {
+    let mut pids = Vec::new();
+    let program_info_length = section
+        .get(10..12)
+        .map_or(0, |b| usize::from(b[0] & 0x0f) << 8 | usize::from(b[1]));
+
+    let mut streams = section.get(12 + program_info_length..).unwrap_or_default();
+    while streams.len() >= 5 {
+        let pid = u16::from(streams[1] & 0x1f) << 8 | u16::from(streams[2]);
+        let info_length = usize::from(streams[3] & 0x0f) << 8 | usize::from(streams[4]);
+        if streams[0] == STREAM_TYPE_ID3 {
+            pids.push(pid);
+        }
+        streams = streams.get(5 + info_length..).unwrap_or_default();
+    }
+    pids
+}
+
+fn pes_payload(pes: &[u8]) -> Option<&[u8]> {
+    if pes.get(..3)? != [0, 0, 1] {
+        return None;
+    }
+    pes.get(9 + usize::from(*pes.get(8)?)..)
+}
+
+fn parse_id3(tag: &[u8]) -> Option<TimedMetadata> {
+    if tag.get(..3)? != b"ID3" {
+        return None;
+    }
+    let version = *tag.get(3)?;
+    let flags = *tag.get(5)?;
+    let size = syncsafe(tag.get(6..10)?);
+    let mut frames = tag.get(10..(10 + size).min(tag.len()))?;
+
+    if flags & 0x40 != 0 {
+        // v2.4 counts the extended header size in, v2.3 leaves its own 4 bytes out.
+        let extended = if version >= 4 {
+            syncsafe(frames.get(..4)?)
+        } else {
+            big_endian(frames.get(..4)?) + 4
+        };
+        frames = frames.get(extended..)?;
+    }
+
+    let mut metadata = TimedMetadata::default();
+    let mut txxx = HashMap::new();
+    while frames.len() >= 10 && frames[0] != 0 {
+        let size = if version >= 4 {
+            syncsafe(&frames[4..8])
+        } else {
+            big_endian(&frames[4..8])
+        };
+        let (id, data) = (&frames[..4], frames.get(10..10 + size)?);
+        frames = &frames[10 + size..];
+
+        match id {
+            b"TIT2" => metadata.title = text(data),
+            b"TPE1" => metadata.artist = text(data),
+            b"TXXX" => {
+                if let Some(text) = text(data) {
+                    let value = text
+                        .split_once('\0')
+                        .map_or(text.as_str(), |(_, value)| value);
+                    txxx.extend(extract_attributes(value));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    metadata.artist = metadata.artist.or_else(|| txxx.remove("artist"));
+    metadata.title = metadata.title.or_else(|| txxx.remove("title"));
+    Some(metadata)
+}
+
+/// A text frame, its first byte telling the encoding. Trailing terminators are dropped.
+fn text(data: &[u8]) -> Option<String> {
+    let (&encoding, content) = data.split_first()?;
+    let text = match encoding {
+        0 => content.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => {
+            let units = content.chunks_exact(2).map(|pair| [pair[0], pair[1]]);
+            let little_endian = encoding == 1 && content.starts_with(&[0xff, 0xfe]);
+            let units = units
+                .map(|pair| {
+                    if little_endian {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }
+                })
+                .filter(|&unit| unit != 0xfeff)
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(content).into_owned(),
+    };
+
+    let text = text.trim_end_matches('\0').trim().to_owned();
+    (!text.is_empty()).then(|| text)
+}
+
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |size, &b| size << 7 | usize::from(b & 0x7f))
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |size, &b| size << 8 | usize::from(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{timed_metadata, TimedMetadata, TS_PACKET_SIZE};
+
+    fn frame(id: &[u8], encoding: u8, text: &[u8]) -> Vec<u8> {
+        let size = (text.len() + 1) as u32;
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&size.to_be_bytes());
+        frame.extend_from_slice(&[0, 0, encoding]);
+        frame.extend_from_slice(text);
+        frame
+    }
+
+    /// An ID3v2.3 tag, plain frame sizes are the same as syncsafe ones this small.
+    fn id3(frames: &[Vec<u8>]) -> Vec<u8> {
+        let body = frames.concat();
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        tag.extend_from_slice(&[0, 0, 0, body.len() as u8]);
+        tag.extend(body);
+        tag
+    }
+
+    fn packet(pid: u16, unit_start: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x47, (pid >> 8) as u8 | if unit_start { 0x40 } else { 0 }];
+        packet.extend_from_slice(&[pid as u8, 0x10]);
+        packet.extend_from_slice(payload);
+        packet.resize(TS_PACKET_SIZE, 0xff);
+        packet
+    }
+
+    #[test]
+    fn test_packed_audio() {
+        let tag = id3(&[
+            frame(b"TPE1", 3, b"Daft Punk"),
+            frame(b"TIT2", 0, b"Da Funk\0"),
+        ]);
+        assert_eq!(
+            timed_metadata(&[tag, vec![0xff, 0xf1, 0x50]].concat()),
+            Some(TimedMetadata {
+                artist: Some("Daft Punk".to_owned()),
+                title: Some("Da Funk".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_txxx_attributes() {
+        let tag = id3(&[frame(
+            b"TXXX",
+            3,
+            b"NowPlaying\0title=\"One\",artist=\"U2\"",
+        )]);
+        let metadata = timed_metadata(&tag).unwrap();
+        assert_eq!(metadata.artist.as_deref(), Some("U2"));
+        assert_eq!(metadata.title.as_deref(), Some("One"));
+    }
+
+    #[test]
+    fn test_transport_stream() {
+        let pat = [
+            0, 0x00, 0xb0, 13, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00, 0, 0, 0, 0,
+        ];
+        let pmt = [
+            0, 0x02, 0xb0, 18, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00, 0x15, 0xe1, 0x02, 0xf0,
+            0x00, 0, 0, 0, 0,
+        ];
+        let tag = id3(&[frame(b"TIT2", 3, b"Around the World")]);
+        let pes = [
+            &[0, 0, 1, 0xbd, 0, 0, 0x84, 0x80, 5, 0x21, 0, 1, 0, 1][..],
+            &tag[..],
+        ]
+        .concat();
+
+        let ts = [
+            packet(0, true, &pat),
+            packet(0x1000, true, &pmt),
+            packet(0x101, true, &[0; 8]),
+            packet(0x102, true, &pes),
+        ]
+        .concat();
+
+        let metadata = timed_metadata(&ts).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Around the World"));
+        assert_eq!(metadata.artist, None);
+    }
+
+    #[test]
+    fn test_none() {
+        assert_eq!(timed_metadata(&[0xff, 0xf1, 0x50, 0x80]), None);
+        assert_eq!(
+            timed_metadata(&id3(&[frame(b"TALB", 3, b"Homework")])),
+            None
+        );
+    }
+}
@@ -0,0 +1,611 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+use hls_m3u8::MediaSegment;
+use reqwest::Url;
+use uuid::Uuid;
+
+use crate::storage::TrackIds;
+
+use super::ad_context::AdContext;
+use super::attributes::parse_attributes;
+use super::song_spot::{SongSpots, SpotKinds};
+use super::{ParsedSegment, SegmentMetadataParser, SuggestedSegmentContentKind};
+
+/// `spotInstanceId`, set to a UUID for ad spots.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpotInstanceId {
+    /// Missing or empty
+    Absent,
+    /// A number instead of an id, in practice `-1` for anything but ad spots
+    Sentinel(i64),
+    Id(Uuid),
+}
+
+impl SpotInstanceId {
+    pub fn uuid(&self) -> Option<Uuid> {
+        match self {
+            SpotInstanceId::Id(id) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for SpotInstanceId {
+    /// Values that are neither numbers nor ids, e.g. `null`, are taken for absent, they
+    /// say nothing about the segment worth losing its metadata over.
+    fn from(value: &str) -> Self {
+        if value.is_empty() {
+            return SpotInstanceId::Absent;
+        }
+        if let Ok(sentinel) = value.parse() {
+            return SpotInstanceId::Sentinel(sentinel);
+        }
+        match Uuid::try_parse(value) {
+            Ok(id) => SpotInstanceId::Id(id),
+            Err(e) => {
+                log::debug!("Unrecognized `spotInstanceId` {value}, taken for absent: {e}");
+                SpotInstanceId::Absent
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct KostaRadioSegmentInfo {
+    pub title: String,
+    pub artist: String,
+    song_spot: char,
+    media_base_id: i64,
+    itunes_track_id: i64,
+    amg_track_id: i64,
+    amg_artist_id: i64,
+    ta_id: i64,
+    tp_id: i64,
+    cartcut_id: i64,
+    amg_artwork_url: Option<Url>,
+    length: Duration,
+    uns_id: i64,
+    spot_instance_id: SpotInstanceId,
+    ad_context: Option<AdContext>,
+    /// False for ad breaks, whose track attributes above are placeholders.
+    has_track_attributes: bool,
+}
+
+#[allow(dead_code)]
+impl KostaRadioSegmentInfo {
+    fn is_music(&self, spot: SpotKinds) -> bool {
+        spot.music
+            && self.length > Duration::new(90, 0)
+            && (self.media_base_id > 0
+                || self.itunes_track_id > 0
+                || (self.amg_artist_id > 0 && self.amg_track_id > 0)
+                || (self.tp_id > 0)
+                || self.amg_artwork_url.is_some())
+    }
+
+    fn is_talk(&self, spot: SpotKinds) -> bool {
+        // song_spot=T MediaBaseId=0 itunesTrackId=0 amgTrackId=0 amgArtistId=0 TAID=0 TPID=0 cartcutId=0 amgArtworkURL="" length="00:00:00" unsID=0 spotInstanceId=-1
+        spot.talk
+            && self.media_base_id == 0
+            && self.itunes_track_id == 0
+            && self.amg_artist_id == 0
+            && self.amg_track_id == 0
+            && self.ta_id == 0
+            && self.tp_id == 0
+            && self.amg_artwork_url.is_none()
+            && self.spot_instance_id.uuid().is_none()
+            && self.length == Duration::ZERO
+    }
+
+    fn is_advertisment(&self, spot: SpotKinds) -> bool {
+        if self.ad_context.is_some() {
+            // #EXTINF:10,offset=0,adContext=''
+            return true;
+        }
+
+        // song_spot=F MediaBaseId=0 itunesTrackId=0 amgTrackId=\"-1\" amgArtistId=\"0\" TAID=\"0\" TPID=\"0\" cartcutId=\"0\" amgArtworkURL=\"null\" length=\"00:02:03\" unsID=\"-1\" spotInstanceId=\"688d6785-f34c-35a8-3255-1a9dd167fbd2\""
+        spot.advertisement
+            && self.media_base_id == 0
+            && self.itunes_track_id == 0
+            && self.amg_artist_id == 0
+            && self.amg_track_id == -1
+            && self.ta_id == 0
+            && self.tp_id == 0
+            && self.cartcut_id == 0
+            && self.amg_artwork_url.is_none()
+            && self.spot_instance_id.uuid().is_some()
+    }
+
+    /// Longer imaging than `max_length` is more likely a promo worth fingerprinting.
+    fn is_jingle(&self, spot: SpotKinds, max_length: Duration) -> bool {
+        // song_spot=I MediaBaseId=0 ... length="00:00:07" spotInstanceId=-1
+        spot.jingle && self.length <= max_length && self.spot_instance_id.uuid().is_none()
+    }
+
+    pub fn ad_context(&self) -> Option<&str> {
+        self.ad_context
+            .as_ref()
+            .map(|context| context.value.as_str())
+    }
+
+    pub fn track_ids(&self) -> TrackIds {
+        if !self.has_track_attributes {
+            return TrackIds::default();
+        }
+
+        TrackIds {
+            song_spot: Some(self.song_spot),
+            media_base_id: Some(self.media_base_id),
+            itunes_track_id: Some(self.itunes_track_id),
+            amg_track_id: Some(self.amg_track_id),
+            amg_artist_id: Some(self.amg_artist_id),
+            ta_id: Some(self.ta_id),
+            tp_id: Some(self.tp_id),
+            cartcut_id: Some(self.cartcut_id),
+            uns_id: Some(self.uns_id),
+            spot_instance_id: self.spot_instance_id.uuid(),
+        }
+    }
+
+    /// Ad breaks carry only `offset` and `adContext`, without any track attributes.
+    fn advertisement(ad_context: AdContext) -> Self {
+        Self {
+            title: "Advertisement".to_string(),
+            artist: "Advertisement".to_string(),
+            song_spot: 'F',
+            media_base_id: 0,
+            itunes_track_id: 0,
+            amg_track_id: -1,
+            amg_artist_id: 0,
+            ta_id: 0,
+            tp_id: 0,
+            cartcut_id: 0,
+            amg_artwork_url: None,
+            length: Duration::ZERO,
+            uns_id: -1,
+            spot_instance_id: SpotInstanceId::Absent,
+            ad_context: Some(ad_context),
+            has_track_attributes: false,
+        }
+    }
+
+    /// Codes missing from `spots` only count as advertisements by `adContext`. Jingles are
+    /// `max_jingle_length` long at most, see `--jingle-max-duration`.
+    pub fn suggested_content_kind(
+        &self,
+        spots: &SongSpots,
+        max_jingle_length: Duration,
+    ) -> SuggestedSegmentContentKind {
+        let spot = spots.get(self.song_spot).unwrap_or_default();
+        if self.is_music(spot) {
+            return SuggestedSegmentContentKind::Music;
+        }
+        if self.is_talk(spot) {
+            return SuggestedSegmentContentKind::Talk;
+        }
+        if self.is_advertisment(spot) {
+            return SuggestedSegmentContentKind::Advertisement;
+        }
+        if self.is_jingle(spot, max_jingle_length) {
+            return SuggestedSegmentContentKind::Jingle;
+        }
+        SuggestedSegmentContentKind::None
+    }
+}
+
+/// Looks up `key` in parsed attributes, failing with the attribute name.
+fn field<'a>(attributes: &'a HashMap<String, String>, key: &str) -> anyhow::Result<&'a str> {
+    attributes
+        .get(key)
+        .map(String::as_str)
+        .ok_or_else(|| anyhow!("Missing `{key}`"))
+}
+
+fn id_field(attributes: &HashMap<String, String>, key: &str) -> anyhow::Result<i64> {
+    field(attributes, key)?
+        .parse::<i64>()
+        .with_context(|| format!("Failed to parse `{key}`"))
+}
+
+/// Parses `HH:MM:SS` or `MM:SS`, with an optional fraction of a second like `.500`.
+/// The leading field is not limited, to a day or an hour.
+fn parse_length(value: &str) -> anyhow::Result<Duration> {
+    let error = || anyhow!("Failed to parse `length` {value}, expected HH:MM:SS[.fff] or MM:SS");
+
+    let (whole, nanos) = match value.split_once('.') {
+        Some((whole, fraction)) => {
+            if fraction.is_empty()
+                || fraction.len() > 9
+                || !fraction.bytes().all(|b| b.is_ascii_digit())
+            {
+                return Err(error());
+            }
+            let nanos: u32 = format!("{fraction:0<9}").parse().map_err(|_| error())?;
+            (whole, nanos)
+        }
+        None => (value, 0),
+    };
+
+    let parts = whole
+        .split(':')
+        .map(|part| part.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse `length` {value}"))?;
+
+    let seconds = match *parts.as_slice() {
+        [hours, minutes, seconds] if minutes < 60 && seconds < 60 => {
+            hours * 3600 + minutes * 60 + seconds
+        }
+        [minutes, seconds] if seconds < 60 => minutes * 60 + seconds,
+        _ => return Err(error()),
+    };
+    Ok(Duration::new(seconds, nanos))
+}
+
+impl TryFrom<&str> for KostaRadioSegmentInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // offset=0,title="Title",artist="Artist",url="song_spot=\"M\" MediaBaseId=\"0\" ..."
+        let outer: HashMap<String, String> = parse_attributes(value, ',')
+            .context("Failed to match")?
+            .into_iter()
+            .collect();
+
+        let ad_context = outer
+            .get("adContext")
+            .map(|value| AdContext::parse(value, outer.get("offset").map(String::as_str)));
+        if let (Some(ad_context), None) = (&ad_context, outer.get("url")) {
+            return Ok(Self::advertisement(ad_context.clone()));
+        }
+
+        let inner: HashMap<String, String> = parse_attributes(field(&outer, "url")?, ' ')
+            .context("Failed to match url")?
+            .into_iter()
+            .collect();
+
+        Ok(Self {
+            title: field(&outer, "title")?.to_owned(),
+            artist: field(&outer, "artist")?.to_owned(),
+            song_spot: field(&inner, "song_spot")?
+                .chars()
+                .next()
+                .ok_or_else(|| anyhow!("Failed to parse song_spot"))?,
+            media_base_id: id_field(&inner, "MediaBaseId")?,
+            itunes_track_id: id_field(&inner, "itunesTrackId")?,
+            amg_track_id: id_field(&inner, "amgTrackId")?,
+            amg_artist_id: id_field(&inner, "amgArtistId")?,
+            ta_id: id_field(&inner, "TAID")?,
+            tp_id: id_field(&inner, "TPID")?,
+            cartcut_id: id_field(&inner, "cartcutId")?,
+            amg_artwork_url: field(&inner, "amgArtworkURL")?.parse().ok(),
+            length: parse_length(field(&inner, "length")?)?,
+            uns_id: id_field(&inner, "unsID")?,
+            spot_instance_id: inner
+                .get("spotInstanceId")
+                .map_or(SpotInstanceId::Absent, |id| id.as_str().into()),
+            ad_context,
+            has_track_attributes: true,
+        })
+    }
+}
+
+impl TryFrom<&MediaSegment<'_>> for KostaRadioSegmentInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(segment: &MediaSegment) -> Result<Self, Self::Error> {
+        if let &Some(title) = &segment.duration.title() {
+            KostaRadioSegmentInfo::try_from(title.as_ref())
+        } else {
+            Err(anyhow!("No title"))
+        }
+    }
+}
+
+pub struct KostaRadioParser {
+    spots: SongSpots,
+    max_jingle_length: Duration,
+    /// Unknown `song_spot` codes logged already.
+    unknown: Mutex<HashSet<char>>,
+}
+
+impl KostaRadioParser {
+    pub fn new(spots: SongSpots, max_jingle_length: Duration) -> Self {
+        Self {
+            spots,
+            max_jingle_length,
+            unknown: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl SegmentMetadataParser for KostaRadioParser {
+    async fn parse(&self, segment: &MediaSegment) -> anyhow::Result<ParsedSegment> {
+        let info = KostaRadioSegmentInfo::try_from(segment)?;
+        log::debug!("Segment#{} info: {info:?}", segment.number());
+
+        if info.has_track_attributes
+            && self.spots.get(info.song_spot).is_none()
+            && self.unknown.lock().unwrap().insert(info.song_spot)
+        {
+            log::warn!(
+                "Unknown song_spot `{}` of `{}`/`{}`, map it with --song-spot",
+                info.song_spot,
+                info.artist,
+                info.title
+            );
+        }
+
+        Ok(ParsedSegment {
+            kind: info.suggested_content_kind(&self.spots, self.max_jingle_length),
+            ids: info.track_ids(),
+            ad_context: info.ad_context,
+            artist: info.artist,
+            title: info.title,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use hls_m3u8::MediaPlaylist;
+
+    use super::{parse_length, KostaRadioParser, KostaRadioSegmentInfo, SpotInstanceId};
+    use crate::segment_info::song_spot::{parse_song_spot, SongSpots};
+    use crate::segment_info::{SegmentMetadataParser, SuggestedSegmentContentKind};
+    use crate::storage::TrackIds;
+
+    const MAX_JINGLE_LENGTH: Duration = Duration::from_secs(12);
+
+    const COMMAS_AND_AMPERSANDS: &str = r#"offset=0,title="Let's Groove",artist="Earth, Wind & Fire",url="song_spot=\"M\" MediaBaseId=\"1234\" itunesTrackId=\"0\" amgTrackId=\"-1\" amgArtistId=\"0\" TAID=\"0\" TPID=\"0\" cartcutId=\"0\" amgArtworkURL=\"\" length=\"00:05:37\" unsID=\"-1\" spotInstanceId=\"-1\"""#;
+
+    const COMMA_IN_TITLE: &str = r#"title="Me, Myself & I",artist="G-Eazy & Bebe Rexha",url="song_spot=\"M\" MediaBaseId=\"42\" itunesTrackId=\"0\" amgTrackId=\"-1\" amgArtistId=\"0\" TAID=\"0\" TPID=\"0\" cartcutId=\"0\" amgArtworkURL=\"\" length=\"00:04:11\" unsID=\"-1\" spotInstanceId=\"-1\"""#;
+
+    const ESCAPED_QUOTES: &str = r#"offset=0,title="The \"Real\" Slim Shady",artist="Eminem",url="song_spot=\"M\" MediaBaseId=\"7\" itunesTrackId=\"0\" amgTrackId=\"-1\" amgArtistId=\"0\" TAID=\"0\" TPID=\"0\" cartcutId=\"0\" amgArtworkURL=\"\" length=\"00:04:44\" unsID=\"-1\" spotInstanceId=\"-1\"""#;
+
+    #[test]
+    fn test_commas_and_ampersands() {
+        let info = KostaRadioSegmentInfo::try_from(COMMAS_AND_AMPERSANDS).unwrap();
+        assert_eq!(info.title, "Let's Groove");
+        assert_eq!(info.artist, "Earth, Wind & Fire");
+        assert_eq!(info.media_base_id, 1234);
+        assert_eq!(info.track_ids().song_spot, Some('M'));
+        assert_eq!(info.track_ids().amg_track_id, Some(-1));
+        assert_eq!(info.track_ids().spot_instance_id, None);
+
+        let info = KostaRadioSegmentInfo::try_from(COMMA_IN_TITLE).unwrap();
+        assert_eq!(info.title, "Me, Myself & I");
+        assert_eq!(info.artist, "G-Eazy & Bebe Rexha");
+        assert_eq!(info.media_base_id, 42);
+    }
+
+    #[test]
+    fn test_escaped_quotes() {
+        let info = KostaRadioSegmentInfo::try_from(ESCAPED_QUOTES).unwrap();
+        assert_eq!(info.title, r#"The "Real" Slim Shady"#);
+        assert_eq!(info.artist, "Eminem");
+        assert_eq!(info.length.as_secs(), 4 * 60 + 44);
+    }
+
+    /// `<kind> <title>` lines, see the header of the fixture.
+    const CLASSIFIED_TITLES: &str = include_str!("../../fixtures/segment_titles/kostaradio.txt");
+
+    #[test]
+    fn test_classification() {
+        for line in CLASSIFIED_TITLES
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        {
+            let (kind, title) = line.split_once(' ').unwrap();
+            let info = KostaRadioSegmentInfo::try_from(title)
+                .unwrap_or_else(|e| panic!("Failed to parse {title}: {e:#}"));
+            assert_eq!(
+                info.suggested_content_kind(&SongSpots::default(), MAX_JINGLE_LENGTH)
+                    .to_string(),
+                kind,
+                "{title}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_spot_instance_id() {
+        assert_eq!(SpotInstanceId::from("-1"), SpotInstanceId::Sentinel(-1));
+        assert_eq!(SpotInstanceId::from(""), SpotInstanceId::Absent);
+        assert_eq!(
+            SpotInstanceId::from("688d6785-f34c-35a8-3255-1a9dd167fbd2"),
+            SpotInstanceId::Id(Uuid::try_parse("688d6785-f34c-35a8-3255-1a9dd167fbd2").unwrap())
+        );
+        assert_eq!(SpotInstanceId::from("null?"), SpotInstanceId::Absent);
+
+        let info = KostaRadioSegmentInfo::try_from(COMMA_IN_TITLE).unwrap();
+        assert_eq!(info.spot_instance_id, SpotInstanceId::Sentinel(-1));
+
+        // Segments with an unrecognized id keep the rest of their metadata.
+        let unrecognized =
+            COMMA_IN_TITLE.replace(r#"spotInstanceId=\"-1\""#, r#"spotInstanceId=\"null\""#);
+        assert_ne!(unrecognized, COMMA_IN_TITLE);
+        let info = KostaRadioSegmentInfo::try_from(unrecognized.as_str()).unwrap();
+        assert_eq!(info.spot_instance_id, SpotInstanceId::Absent);
+        assert_eq!(info.title, "Me, Myself & I");
+        assert_eq!(info.media_base_id, 42);
+    }
+
+    #[test]
+    fn test_length() {
+        assert_eq!(
+            parse_length("25:10:00").unwrap().as_secs(),
+            25 * 3600 + 10 * 60
+        );
+        assert_eq!(parse_length("00:00:00").unwrap(), std::time::Duration::ZERO);
+        assert_eq!(parse_length("00:03:07").unwrap().as_secs(), 187);
+        assert_eq!(parse_length("03:45").unwrap().as_secs(), 225);
+        assert_eq!(parse_length("75:00").unwrap().as_secs(), 75 * 60);
+        assert_eq!(
+            parse_length("00:02:03.500").unwrap(),
+            std::time::Duration::from_millis(123_500)
+        );
+        assert_eq!(parse_length("03:45.25").unwrap().as_millis(), 225_250);
+        assert!(parse_length("00:61:00").is_err());
+        assert!(parse_length("03:60").is_err());
+        assert!(parse_length("45").is_err());
+        assert!(parse_length("00:02:03.").is_err());
+        assert!(parse_length("00:02:03.5x").is_err());
+        assert!(parse_length("1:00:02:03").is_err());
+        assert!(parse_length("-1").is_err());
+        assert!(parse_length("").is_err());
+    }
+
+    #[test]
+    fn test_no_url() {
+        assert!(KostaRadioSegmentInfo::try_from(r#"offset=0,title="Title""#).is_err());
+    }
+
+    #[test]
+    fn test_ad_context() {
+        let info = KostaRadioSegmentInfo::try_from(r#"offset=0,adContext=''"#).unwrap();
+        assert_eq!(info.ad_context(), Some(""));
+        assert_eq!(
+            info.suggested_content_kind(&SongSpots::default(), MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::Advertisement
+        );
+        assert_eq!(info.track_ids(), TrackIds::default());
+    }
+
+    #[test]
+    fn test_ad_context_values() {
+        let info = KostaRadioSegmentInfo::try_from(
+            r#"offset=5,adContext='campaign=ACME-42&creative=spring\'s-best'"#,
+        )
+        .unwrap();
+        let context = info.ad_context.as_ref().unwrap();
+        assert_eq!(context.value, "campaign=ACME-42&creative=spring's-best");
+        assert_eq!(context.offset, Some(5));
+        assert_eq!(context.campaign(), Some("ACME-42"));
+        assert_eq!(context.fields["creative"], "spring's-best");
+        assert_eq!(
+            info.suggested_content_kind(&SongSpots::default(), MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::Advertisement
+        );
+
+        let info = KostaRadioSegmentInfo::try_from(r#"offset=0,adContext='ACME Spring'"#).unwrap();
+        assert_eq!(info.ad_context(), Some("ACME Spring"));
+        assert_eq!(
+            info.ad_context
+                .as_ref()
+                .and_then(|context| context.campaign()),
+            Some("ACME Spring")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ad_context_playlist() {
+        let playlist = MediaPlaylist::try_from(
+            "#EXTM3U\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXT-X-MEDIA-SEQUENCE:1\n\
+            #EXTINF:10,offset=0,adContext='cid=9001;flight=Q3'\n\
+            https://example.com/1.aac\n",
+        )
+        .unwrap();
+        let segment = playlist.segments.values().next().unwrap();
+
+        let parsed = KostaRadioParser::new(SongSpots::default(), MAX_JINGLE_LENGTH)
+            .parse(segment)
+            .await
+            .unwrap();
+        assert_eq!(parsed.kind, SuggestedSegmentContentKind::Advertisement);
+        let context = parsed.ad_context.unwrap();
+        assert_eq!(context.campaign(), Some("9001"));
+        assert_eq!(context.fields["flight"], "Q3");
+        assert_eq!(context.offset, Some(0));
+    }
+
+    /// The examples quoted in the comments of `KostaRadioSegmentInfo`, as station EXTINF lines.
+    const DOCUMENTED_EXAMPLES: [(&str, SuggestedSegmentContentKind); 4] = [
+        (
+            r#"offset=0,title="Title",artist="Artist",url="song_spot=\"M\" MediaBaseId=\"0\" itunesTrackId=\"0\" amgTrackId=\"-1\" amgArtistId=\"0\" TAID=\"0\" TPID=\"0\" cartcutId=\"0\" amgArtworkURL=\"https://example.com/artwork.jpg\" length=\"00:03:30\" unsID=\"-1\" spotInstanceId=\"-1\"""#,
+            SuggestedSegmentContentKind::Music,
+        ),
+        (
+            r#"offset=0,title="Morning Show",artist="KOST 103.5",url="song_spot=T MediaBaseId=0 itunesTrackId=0 amgTrackId=0 amgArtistId=0 TAID=0 TPID=0 cartcutId=0 amgArtworkURL=\"\" length=\"00:00:00\" unsID=0 spotInstanceId=-1""#,
+            SuggestedSegmentContentKind::Talk,
+        ),
+        (
+            r#"offset=0,title="Spot Block",artist="",url="song_spot=F MediaBaseId=0 itunesTrackId=0 amgTrackId=\"-1\" amgArtistId=\"0\" TAID=\"0\" TPID=\"0\" cartcutId=\"0\" amgArtworkURL=\"null\" length=\"00:02:03\" unsID=\"-1\" spotInstanceId=\"688d6785-f34c-35a8-3255-1a9dd167fbd2\"""#,
+            SuggestedSegmentContentKind::Advertisement,
+        ),
+        (
+            "offset=0,adContext=''",
+            SuggestedSegmentContentKind::Advertisement,
+        ),
+    ];
+
+    #[tokio::test]
+    async fn test_documented_examples() {
+        let playlist = DOCUMENTED_EXAMPLES.iter().enumerate().fold(
+            String::from("#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXT-X-MEDIA-SEQUENCE:1\n"),
+            |playlist, (number, (title, _))| {
+                playlist + &format!("#EXTINF:10,{title}\nhttps://example.com/{number}.aac\n")
+            },
+        );
+        let playlist = MediaPlaylist::try_from(playlist.as_str()).unwrap();
+        assert_eq!(playlist.segments.num_elements(), DOCUMENTED_EXAMPLES.len());
+
+        let parser = KostaRadioParser::new(SongSpots::default(), MAX_JINGLE_LENGTH);
+        for ((_, segment), (title, kind)) in playlist.segments.iter().zip(DOCUMENTED_EXAMPLES) {
+            let parsed = parser
+                .parse(segment)
+                .await
+                .unwrap_or_else(|e| panic!("Failed to parse {title}: {e:#}"));
+            assert_eq!(parsed.kind, kind, "{title}");
+        }
+    }
+
+    #[test]
+    fn test_song_spot_codes() {
+        let talk = COMMAS_AND_AMPERSANDS.replace(r#"song_spot=\"M\""#, r#"song_spot=\"N\""#);
+        let info = KostaRadioSegmentInfo::try_from(talk.as_str()).unwrap();
+        assert_eq!(
+            info.suggested_content_kind(&SongSpots::default(), MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::None
+        );
+
+        let spots = SongSpots::new(&[parse_song_spot("N=music").unwrap()]);
+        assert_eq!(
+            info.suggested_content_kind(&spots, MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::Music
+        );
+
+        let spots = SongSpots::new(&[parse_song_spot("M=talk").unwrap()]);
+        let info = KostaRadioSegmentInfo::try_from(COMMAS_AND_AMPERSANDS).unwrap();
+        assert_eq!(
+            info.suggested_content_kind(&spots, MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::None
+        );
+
+        // A jingle code on a track of five minutes.
+        let spots = SongSpots::new(&[parse_song_spot("N=jingle").unwrap()]);
+        let info = KostaRadioSegmentInfo::try_from(talk.as_str()).unwrap();
+        assert_eq!(
+            info.suggested_content_kind(&spots, MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::None
+        );
+        let jingle = talk.replace(r#"length=\"00:05:37\""#, r#"length=\"00:00:07\""#);
+        let info = KostaRadioSegmentInfo::try_from(jingle.as_str()).unwrap();
+        assert_eq!(
+            info.suggested_content_kind(&spots, MAX_JINGLE_LENGTH),
+            SuggestedSegmentContentKind::Jingle
+        );
+    }
+}
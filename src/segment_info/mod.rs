@@ -0,0 +1,82 @@
+mod ad_context;
+mod attributes;
+mod blacklist;
+mod icy;
+mod id3;
+mod kostaradio;
+mod song_spot;
+mod untitled;
+
+use std::fmt::Display;
+
+use async_trait::async_trait;
+use hls_m3u8::MediaSegment;
+
+use crate::storage::{AudioKind, TrackIds};
+
+pub use ad_context::AdContext;
+pub use attributes::extract_attributes;
+pub use blacklist::{read_media_base_ids, MediaBaseIdBlacklist};
+pub use icy::IcyParser;
+pub use id3::{timed_metadata, TimedMetadata};
+pub use kostaradio::KostaRadioParser;
+pub use song_spot::{parse_song_spot, SongSpots, SpotKinds};
+pub use untitled::UntitledFallback;
+
+/// Artist, title and content kind of a segment, as told by its playlist metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedSegment {
+    pub artist: String,
+    pub title: String,
+    pub kind: SuggestedSegmentContentKind,
+    /// KostaRadio `adContext`, kept to report on ad campaigns.
+    pub ad_context: Option<AdContext>,
+    pub ids: TrackIds,
+}
+
+/// Extracts segment metadata in a station-specific format.
+///
+/// Parsing is async so that a parser may look metadata up, e.g. resolve a `media_base_id`
+/// against an external catalog. The segments of a poll are parsed one after another before
+/// any is downloaded, so a lookup delays every segment after it, and lookups slower than
+/// the target duration of the playlist make the feeder fall behind the stream. Such a parser
+/// should cache what it looks up and fall back to the playlist metadata on a timeout.
+#[async_trait]
+pub trait SegmentMetadataParser: Send + Sync {
+    /// Fails if the segment carries no metadata in this parser's format.
+    async fn parse(&self, segment: &MediaSegment) -> anyhow::Result<ParsedSegment>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SuggestedSegmentContentKind {
+    None,
+    Talk,
+    Advertisement,
+    Music,
+    /// Station imaging, told by its `song_spot` or by short audio heard again and again.
+    Jingle,
+}
+
+impl Display for SuggestedSegmentContentKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SuggestedSegmentContentKind::None => f.write_str("none"),
+            SuggestedSegmentContentKind::Talk => f.write_str("talk"),
+            SuggestedSegmentContentKind::Advertisement => f.write_str("advertisement"),
+            SuggestedSegmentContentKind::Music => f.write_str("music"),
+            SuggestedSegmentContentKind::Jingle => f.write_str("jingle"),
+        }
+    }
+}
+
+impl From<SuggestedSegmentContentKind> for AudioKind {
+    fn from(kind: SuggestedSegmentContentKind) -> Self {
+        match kind {
+            SuggestedSegmentContentKind::None => AudioKind::Unknown,
+            SuggestedSegmentContentKind::Talk => AudioKind::Talk,
+            SuggestedSegmentContentKind::Advertisement => AudioKind::Advertisement,
+            SuggestedSegmentContentKind::Music => AudioKind::Music,
+            SuggestedSegmentContentKind::Jingle => AudioKind::Jingle,
+        }
+    }
+}
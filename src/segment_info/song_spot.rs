@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+
+/// Kinds a KostaRadio `song_spot` code may stand for, the track attributes decide between them.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SpotKinds {
+    pub music: bool,
+    pub talk: bool,
+    pub advertisement: bool,
+    pub jingle: bool,
+}
+
+/// `song_spot` codes and their kinds: `M` music, `F` music or advertisement, `T` talk,
+/// plus station-specific ones given with `--song-spot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SongSpots(HashMap<char, SpotKinds>);
+
+impl Default for SongSpots {
+    fn default() -> Self {
+        Self(HashMap::from([
+            (
+                'M',
+                SpotKinds {
+                    music: true,
+                    ..SpotKinds::default()
+                },
+            ),
+            (
+                'F',
+                SpotKinds {
+                    music: true,
+                    advertisement: true,
+                    ..SpotKinds::default()
+                },
+            ),
+            (
+                'T',
+                SpotKinds {
+                    talk: true,
+                    ..SpotKinds::default()
+                },
+            ),
+        ]))
+    }
+}
+
+impl SongSpots {
+    /// The defaults with `codes` added, replacing the defaults of the same code.
+    pub fn new(codes: &[(char, SpotKinds)]) -> Self {
+        let mut spots = Self::default();
+        spots.0.extend(codes.iter().copied());
+        spots
+    }
+
+    /// None for a code neither known by default nor given.
+    pub fn get(&self, code: char) -> Option<SpotKinds> {
+        self.0.get(&code).copied()
+    }
+}
+
+/// Parses `CODE=KINDS`, e.g. `C=advertisement` or `S=music,advertisement`.
+/// Kinds are `music`, `talk`, `advertisement`, `jingle` or `none`.
+pub fn parse_song_spot(value: &str) -> anyhow::Result<(char, SpotKinds)> {
+    let (code, kinds) = value
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected CODE=KINDS"))?;
+
+    let mut chars = code.trim().chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(code), None) => code,
+        _ => bail!("Expected a single character code, got `{code}`"),
+    };
+
+    let mut spot = SpotKinds::default();
+    for kind in kinds.split(',').map(str::trim) {
+        match kind {
+            "music" => spot.music = true,
+            "talk" => spot.talk = true,
+            "advertisement" => spot.advertisement = true,
+            "jingle" => spot.jingle = true,
+            "none" | "" => {}
+            _ => {
+                bail!("Unknown kind `{kind}`, expected music, talk, advertisement, jingle or none")
+            }
+        }
+    }
+
+    Ok((code, spot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_song_spot, SongSpots, SpotKinds};
+
+    #[test]
+    fn test_parse() {
+        let (code, kinds) = parse_song_spot("S=music, advertisement").unwrap();
+        assert_eq!(code, 'S');
+        assert_eq!(
+            kinds,
+            SpotKinds {
+                music: true,
+                talk: false,
+                advertisement: true,
+                jingle: false,
+            }
+        );
+        assert!(parse_song_spot("I=jingle").unwrap().1.jingle);
+
+        assert_eq!(parse_song_spot("N=none").unwrap().1, SpotKinds::default());
+
+        assert!(parse_song_spot("N").is_err());
+        assert!(parse_song_spot("NS=talk").is_err());
+        assert!(parse_song_spot("=talk").is_err());
+        assert!(parse_song_spot("N=news").is_err());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let commercial = parse_song_spot("C=advertisement").unwrap();
+        let feature = parse_song_spot("F=music").unwrap();
+        let spots = SongSpots::new(&[commercial, feature]);
+
+        assert_eq!(spots.get('C'), Some(commercial.1));
+        assert_eq!(spots.get('F'), Some(feature.1));
+        assert_eq!(spots.get('M'), SongSpots::default().get('M'));
+        assert_eq!(spots.get('X'), None);
+    }
+}
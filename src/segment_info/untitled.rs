@@ -0,0 +1,73 @@
+use async_trait::async_trait;
+use hls_m3u8::MediaSegment;
+
+use crate::storage::TrackIds;
+
+use super::{ParsedSegment, SegmentMetadataParser, SuggestedSegmentContentKind};
+
+/// Takes segments without any title as [`SuggestedSegmentContentKind::None`] instead of
+/// skipping them, for sources like DASH manifests that never title their segments.
+/// Artist and title are left to in-band metadata then.
+pub struct UntitledFallback<'a> {
+    inner: &'a dyn SegmentMetadataParser,
+}
+
+impl<'a> UntitledFallback<'a> {
+    pub fn new(inner: &'a dyn SegmentMetadataParser) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl SegmentMetadataParser for UntitledFallback<'_> {
+    async fn parse(&self, segment: &MediaSegment) -> anyhow::Result<ParsedSegment> {
+        if segment.duration.title().is_some() {
+            return self.inner.parse(segment).await;
+        }
+
+        Ok(ParsedSegment {
+            artist: String::new(),
+            title: String::new(),
+            kind: SuggestedSegmentContentKind::None,
+            ad_context: None,
+            ids: TrackIds::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hls_m3u8::tags::ExtInf;
+    use hls_m3u8::MediaSegment;
+
+    use super::UntitledFallback;
+    use crate::segment_info::{IcyParser, SegmentMetadataParser, SuggestedSegmentContentKind};
+
+    fn segment(duration: ExtInf) -> MediaSegment {
+        MediaSegment::builder()
+            .duration(duration)
+            .uri("https://example.com/1.aac")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test() {
+        let ten = std::time::Duration::from_secs(10);
+        let parser = UntitledFallback::new(&IcyParser);
+
+        let parsed = parser.parse(&segment(ExtInf::new(ten))).await.unwrap();
+        assert_eq!(parsed.kind, SuggestedSegmentContentKind::None);
+        assert_eq!(parsed.title, "");
+
+        let parsed = parser
+            .parse(&segment(ExtInf::with_title(ten, "Daft Punk - Da Funk")))
+            .await
+            .unwrap();
+        assert_eq!(parsed.kind, SuggestedSegmentContentKind::Music);
+        assert!(parser
+            .parse(&segment(ExtInf::with_title(ten, "  ")))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,44 @@
+use hls_m3u8::MediaPlaylist;
+
+/// Notices segments that left the playlist between two polls before we saw them.
+///
+/// Happens when polls are too far apart for the playlist window,
+/// or when the CDN trims the playlist aggressively.
+#[derive(Default)]
+pub struct SequenceGapDetector {
+    last_number: Option<usize>,
+}
+
+impl SequenceGapDetector {
+    /// Returns the number of segments missed since the previous poll, if any.
+    pub fn observe(&mut self, m3u8: &MediaPlaylist) -> Option<usize> {
+        let last = m3u8
+            .segments
+            .iter()
+            .map(|(_, segment)| segment.number())
+            .max()?;
+        self.observe_numbers(m3u8.media_sequence, last)
+    }
+
+    fn observe_numbers(&mut self, first: usize, last: usize) -> Option<usize> {
+        let previous = self.last_number.replace(last)?;
+
+        // A backward jump is a sequence reset, not a gap.
+        (first > previous + 1).then(|| first - previous - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SequenceGapDetector;
+
+    #[test]
+    fn test() {
+        let mut detector = SequenceGapDetector::default();
+        assert_eq!(detector.observe_numbers(10, 15), None);
+        assert_eq!(detector.observe_numbers(12, 17), None);
+        assert_eq!(detector.observe_numbers(18, 23), None);
+        assert_eq!(detector.observe_numbers(30, 35), Some(6));
+        assert_eq!(detector.observe_numbers(0, 5), None);
+    }
+}
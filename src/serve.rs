@@ -0,0 +1,214 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::storage::{AudioStore, MatchData, MatchesStorage, Metadata, MetadataStorage};
+
+const DEFAULT_LIMIT: usize = 50;
+/// Size of the reads of `/audio/{id}`.
+const AUDIO_CHUNK_SIZE: usize = 64 * 1024;
+
+struct State {
+    metadata: MetadataStorage,
+    audio: Box<dyn AudioStore>,
+    matches: MatchesStorage,
+}
+
+/// Serves read-only endpoints:
+/// `/recent`, `/matches` (both accept `?limit=N`), `/track/{id}` and `/audio/{id}`.
+pub async fn run(
+    addr: SocketAddr,
+    metadata: MetadataStorage,
+    audio: Box<dyn AudioStore>,
+    matches: MatchesStorage,
+) -> anyhow::Result<()> {
+    let state = Arc::new(State {
+        metadata,
+        audio,
+        matches,
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, req).await) }
+            }))
+        }
+    });
+
+    log::info!("Serving on http://{addr}");
+
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+async fn handle(state: &State, req: Request<Body>) -> Response<Body> {
+    if req.method() != Method::GET {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let path = req.uri().path().trim_matches('/').to_owned();
+    let limit = limit(req.uri().query());
+    let segments: Vec<&str> = path.split('/').collect();
+
+    let response = match segments.as_slice() {
+        ["recent"] => recent(state, limit).await,
+        ["matches"] => matches(state, limit).await,
+        ["track", id] => with_id(id, |id| track(state, id)).await,
+        ["audio", id] => with_id(id, |id| audio(state, id)).await,
+        _ => Ok(status(StatusCode::NOT_FOUND)),
+    };
+
+    response.unwrap_or_else(|e| {
+        if is_not_found(&e) {
+            status(StatusCode::NOT_FOUND)
+        } else {
+            log::error!("Failed to serve {}: {e:#}", req.uri());
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    })
+}
+
+async fn recent(state: &State, limit: usize) -> anyhow::Result<Response<Body>> {
+    let recent = state.metadata.recent(limit).await?;
+    Ok(json_response(
+        recent.iter().map(metadata_json).collect::<Vec<_>>().into(),
+    ))
+}
+
+async fn matches(state: &State, limit: usize) -> anyhow::Result<Response<Body>> {
+    let matches = state.matches.recent(limit).await?;
+    Ok(json_response(
+        matches.iter().map(match_json).collect::<Vec<_>>().into(),
+    ))
+}
+
+async fn track(state: &State, id: Uuid) -> anyhow::Result<Response<Body>> {
+    let metadata = state.metadata.get(id).await?;
+    let matches = state.matches.get(id).await?;
+
+    let mut value = metadata_json(&metadata);
+    value["matches"] = matches.iter().map(match_json).collect::<Vec<_>>().into();
+
+    Ok(json_response(value))
+}
+
+/// Sends the audio as it is read, multi-megabyte segments aren't buffered whole.
+async fn audio(state: &State, id: Uuid) -> anyhow::Result<Response<Body>> {
+    let (format, mut reader) = state.audio.stream(id).await?;
+    let (mut sender, body) = Body::channel();
+
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let mut chunk = vec![0; AUDIO_CHUNK_SIZE];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    let sending = sender.send_data(Bytes::copy_from_slice(&chunk[..read]));
+                    // The client went away.
+                    if runtime.block_on(sending).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to read audio {id}: {e:#}");
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    Response::builder()
+        .header(CONTENT_TYPE, format)
+        .body(body)
+        .map_err(|e| e.into())
+}
+
+fn metadata_json(metadata: &Metadata) -> Value {
+    json!({
+        "id": metadata.id.to_string(),
+        "date": metadata.date().to_rfc3339(),
+        "kind": metadata.kind().to_string(),
+        "artist": metadata.artist(),
+        "title": metadata.title(),
+    })
+}
+
+fn match_json(data: &MatchData) -> Value {
+    json!({
+        "id": data.id().to_string(),
+        "timestamp": data.timestamp().to_rfc3339(),
+        "score": data.score(),
+        "artist": data.artist(),
+        "title": data.title(),
+    })
+}
+
+async fn with_id<F, Fut>(id: &str, f: F) -> anyhow::Result<Response<Body>>
+where
+    F: FnOnce(Uuid) -> Fut,
+    Fut: Future<Output = anyhow::Result<Response<Body>>>,
+{
+    match Uuid::try_parse(id) {
+        Ok(id) => f(id).await,
+        Err(_) => Ok(status(StatusCode::BAD_REQUEST)),
+    }
+}
+
+fn limit(query: Option<&str>) -> usize {
+    query
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .find_map(|pair| pair.strip_prefix("limit="))
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_LIMIT)
+}
+
+fn is_not_found(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<rusqlite::Error>(),
+        Some(rusqlite::Error::QueryReturnedNoRows)
+    )
+}
+
+fn json_response(value: Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::limit;
+
+    #[test]
+    fn test_limit() {
+        assert_eq!(limit(None), super::DEFAULT_LIMIT);
+        assert_eq!(limit(Some("limit=5")), 5);
+        assert_eq!(limit(Some("a=b&limit=7")), 7);
+        assert_eq!(limit(Some("limit=x")), super::DEFAULT_LIMIT);
+    }
+}
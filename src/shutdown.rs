@@ -0,0 +1,110 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+/// The error of network I/O abandoned at shutdown, told from failures by downcasting.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cancelled by shutdown")
+    }
+}
+
+/// Runs `future` unless `shutdown` is cancelled first, which fails with [`Cancelled`].
+pub async fn cancellable<F: Future>(
+    shutdown: &CancellationToken,
+    future: F,
+) -> anyhow::Result<F::Output> {
+    tokio::select! {
+        biased;
+        _ = shutdown.cancelled() => Err(anyhow::Error::msg(Cancelled)),
+        output = future => Ok(output),
+    }
+}
+
+/// Sleeps for `duration`, or until `shutdown` is cancelled.
+pub async fn sleep(shutdown: &CancellationToken, duration: Duration) {
+    let _ = cancellable(shutdown, tokio::time::sleep(duration)).await;
+}
+
+/// Exit code of a process killed by SIGINT.
+const INTERRUPTED: i32 = 130;
+
+/// Exits right away at the second Ctrl-C, when the graceful shutdown takes too long.
+async fn exit_on_second_interrupt() {
+    if tokio::signal::ctrl_c().await.is_ok() {
+        log::warn!("Interrupted again, exiting without finishing the shutdown");
+        std::process::exit(INTERRUPTED);
+    }
+}
+
+/// Cancels `shutdown` at SIGINT or SIGTERM, exits at the second SIGINT.
+#[cfg(unix)]
+pub fn cancel_on_signals(shutdown: &CancellationToken) -> anyhow::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let signal = tokio::select! {
+            _ = tokio::signal::ctrl_c() => "SIGINT",
+            _ = terminate.recv() => "SIGTERM",
+        };
+        log::info!("Shutting down on {signal}, abandoning downloads in flight");
+        shutdown.cancel();
+        exit_on_second_interrupt().await;
+    });
+
+    Ok(())
+}
+
+/// Cancels `shutdown` at Ctrl-C, exits at the second one.
+#[cfg(not(unix))]
+pub fn cancel_on_signals(shutdown: &CancellationToken) -> anyhow::Result<()> {
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::info!("Shutting down on Ctrl-C, abandoning downloads in flight");
+            shutdown.cancel();
+            exit_on_second_interrupt().await;
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+
+    use super::{cancellable, sleep, Cancelled};
+
+    #[tokio::test]
+    async fn test() {
+        let shutdown = CancellationToken::new();
+        assert_eq!(cancellable(&shutdown, async { 42 }).await.unwrap(), 42);
+
+        let slow = tokio::time::sleep(Duration::from_secs(60));
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            shutdown.cancel();
+        };
+        let (cancelled, ()) = tokio::join!(cancellable(&shutdown, slow), cancel);
+        assert!(cancelled.unwrap_err().downcast_ref::<Cancelled>().is_some());
+
+        // Cancelled already, nothing waits.
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            sleep(&shutdown, Duration::from_secs(60)),
+        )
+        .await
+        .unwrap();
+        assert!(cancellable(&shutdown, async { 42 }).await.is_err());
+    }
+}
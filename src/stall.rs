@@ -0,0 +1,76 @@
+use clap::ArgEnum;
+use hls_m3u8::MediaPlaylist;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum StallAction {
+    /// Log a warning and keep polling
+    Warn,
+    /// Exit with an error, for a supervisor to restart us
+    Exit,
+}
+
+/// Counts polls in a row that brought no new segments, the sign of a dead stream
+/// whose playlist stopped updating.
+pub struct StallDetector {
+    threshold: u32,
+    stalled_polls: u32,
+    last_number: Option<usize>,
+}
+
+impl StallDetector {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            stalled_polls: 0,
+            last_number: None,
+        }
+    }
+
+    /// Returns the number of stalled polls in a row each time it reaches a multiple
+    /// of the threshold, so a long stall is reported again and again.
+    pub fn observe(&mut self, m3u8: &MediaPlaylist) -> Option<u32> {
+        self.observe_number(
+            m3u8.segments
+                .iter()
+                .map(|(_, segment)| segment.number())
+                .max(),
+        )
+    }
+
+    /// Counts a poll the server answered with "not modified", which brings nothing new.
+    pub fn observe_unchanged(&mut self) -> Option<u32> {
+        self.observe_number(self.last_number)
+    }
+
+    fn observe_number(&mut self, last: Option<usize>) -> Option<u32> {
+        // Any change counts as progress, including a sequence reset.
+        let advanced = last.is_some() && last != self.last_number;
+        if advanced {
+            self.last_number = last;
+            self.stalled_polls = 0;
+            return None;
+        }
+
+        self.stalled_polls += 1;
+        (self.stalled_polls % self.threshold == 0).then(|| self.stalled_polls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StallDetector;
+
+    #[test]
+    fn test() {
+        let mut detector = StallDetector::new(2);
+        assert_eq!(detector.observe_number(Some(10)), None);
+        assert_eq!(detector.observe_number(Some(10)), None);
+        assert_eq!(detector.observe_number(Some(10)), Some(2));
+        assert_eq!(detector.observe_number(None), None);
+        assert_eq!(detector.observe_number(Some(10)), Some(4));
+        assert_eq!(detector.observe_number(Some(11)), None);
+        assert_eq!(detector.observe_number(Some(1)), None);
+        assert_eq!(detector.observe_unchanged(), None);
+        assert_eq!(detector.observe_unchanged(), Some(2));
+    }
+}
@@ -1,41 +1,94 @@
-#![allow(dead_code)]
-
-use std::cell::RefCell;
 use std::io::{Read, Write};
 use std::path::Path;
 
+use anyhow::bail;
+use async_trait::async_trait;
 use bytes::Bytes;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, ValueRef};
-use rusqlite::{params, Connection, DatabaseName, OpenFlags, ToSql};
+use rusqlite::{params, DatabaseName, ToSql};
 use uuid::Uuid;
 
+use super::{
+    add_column, migrate, open_read_only, open_writable, Durability, Migration, SharedConnection,
+    WalCheckpoint,
+};
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct AudioData {
     id: Uuid,
     format: String,
     bytes: Bytes,
+    /// File name for the `files` backend, see `--audio-filename-template`.
+    name: Option<String>,
 }
 
 impl AudioData {
     pub fn new(id: Uuid, format: String, bytes: Bytes) -> Self {
-        Self { id, format, bytes }
+        Self {
+            id,
+            format,
+            bytes,
+            name: None,
+        }
+    }
+
+    pub fn with_name(mut self, name: Option<String>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
     }
 }
 
-pub struct AudioStorage {
-    conn: RefCell<Connection>,
+/// Keeps segment audio by id.
+#[async_trait]
+pub trait AudioStore: Send + Sync {
+    async fn insert(&self, data: &AudioData) -> anyhow::Result<()>;
+    async fn get(&self, id: Uuid) -> anyhow::Result<AudioData>;
+    /// Removes the audio of `id`, if there is any.
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()>;
+    /// Format of the segment, without reading its audio where the store can.
+    async fn format(&self, id: Uuid) -> anyhow::Result<String> {
+        Ok(self.get(id).await?.format)
+    }
+    /// Format of the segment and a reader of its audio, which reads as it goes where the
+    /// store can. Reads may block, do them on a blocking thread.
+    #[cfg(feature = "serve")]
+    async fn stream(&self, id: Uuid) -> anyhow::Result<(String, Box<dyn Read + Send>)> {
+        let data = self.get(id).await?;
+        Ok((data.format, Box::new(std::io::Cursor::new(data.bytes))))
+    }
+    /// Checkpoints the sqlite database of the store, see [`WalCheckpoint`].
+    async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint>;
+    /// Sets the durability of the sqlite database of the store, see [`Durability`].
+    async fn set_durability(&self, durability: Durability) -> anyhow::Result<()>;
 }
 
-impl AudioStorage {
-    pub fn new<P>(path: &P) -> anyhow::Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        let conn = Connection::open_with_flags(
-            path,
-            OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
-        )?;
+pub struct AudioStorage {
+    conn: SharedConnection,
+    /// See [`AudioStorage::with_compression`].
+    compress: bool,
+}
 
+/// Schema steps of [`AudioStorage`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[
+    |conn| {
         conn.execute_batch(
             r#"
             CREATE TABLE IF NOT EXISTS audio(
@@ -43,65 +96,262 @@ impl AudioStorage {
                 format STRING NOT NULL,
                 bytes BLOB NOT NULL
             )"#,
-        )?;
+        )
+    },
+    |conn| add_column(conn, "audio", "compression", "STRING"),
+];
+
+/// `compression` of blobs stored deflated, it is NULL for ones stored as they are.
+const DEFLATE: &str = "deflate";
+
+/// Formats that aren't compressed already: PCM, and MPEG-TS with its packet headers
+/// and stuffing.
+fn is_compressible(format: &str) -> bool {
+    matches!(
+        format.split(';').next().unwrap_or_default().trim(),
+        "audio/wav"
+            | "audio/x-wav"
+            | "audio/wave"
+            | "audio/aiff"
+            | "audio/x-aiff"
+            | "audio/L16"
+            | "video/mp2t"
+            | "audio/mp2t"
+    )
+}
+
+fn deflate(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+impl AudioStorage {
+    pub fn new<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut conn = open_writable(path.as_ref())?;
+        migrate(&mut conn, "audio", MIGRATIONS)?;
 
         Ok(Self {
-            conn: RefCell::new(conn),
+            conn: SharedConnection::new(conn),
+            compress: false,
         })
     }
 
-    pub fn insert(&self, data: &AudioData) -> anyhow::Result<()> {
-        let mut conn: std::cell::RefMut<Connection> = self.conn.borrow_mut();
-        conn.transaction().and_then(|tx| {
-            tx.execute(
-                &format!(
-                    "INSERT INTO audio VALUES(?, ?, ZEROBLOB({}))",
-                    data.bytes.len()
-                ),
-                params![data.id.to_string(), data.format],
-            )?;
-
-            tx.blob_open(
-                DatabaseName::Main,
-                "audio",
-                "bytes",
-                tx.last_insert_rowid(),
-                false,
-            )?
-            .write_all(data.bytes.as_ref())
-            .map_err(|_| rusqlite::Error::BlobSizeError)?;
-
-            tx.commit()
-        })?;
-
-        Ok(())
-    }
-
-    pub fn get(&self, id: Uuid) -> anyhow::Result<AudioData> {
-        let conn = self.conn.borrow();
-        let mut stmt = conn.prepare("SELECT rowid, format FROM audio WHERE id=?")?;
-        let data = stmt.query_row([id.to_string()], |row| {
-            let rowid = row.get(0)?;
-            let format = row.get(1)?;
-
-            let mut blob = conn.blob_open(DatabaseName::Main, "audio", "bytes", rowid, true)?;
-            let mut buffer = Vec::new();
-            blob.read_to_end(&mut buffer)
-                .map_err(|e| FromSqlError::Other(Box::new(e)))?;
-            Ok(AudioData::new(id, format, buffer.into()))
-        })?;
-        Ok(data)
+    /// Deflates the audio of formats that aren't compressed already when it gets smaller,
+    /// see `--compress-audio`. Rows read back either way.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Opens an existing database for the query-only subcommands.
+    pub fn read_only<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            conn: SharedConnection::new(open_read_only(path.as_ref())?),
+            compress: false,
+        })
+    }
+
+    /// Id and format of the segment, without reading its audio.
+    pub async fn get_format_only(&self, id: Uuid) -> anyhow::Result<(Uuid, String)> {
+        self.conn
+            .call(move |conn| {
+                let format = conn
+                    .prepare_cached("SELECT format FROM audio WHERE id=?")?
+                    .query_row([id.to_string()], |row| row.get(0))?;
+                Ok((id, format))
+            })
+            .await
+    }
+
+    /// Format of the segment and a reader of its audio, which reads from the database
+    /// as it goes instead of buffering it all.
+    pub async fn stream_blob(&self, id: Uuid) -> anyhow::Result<(String, BlobReader)> {
+        let (rowid, format, len, compression): (_, _, _, Option<String>) = self
+            .conn
+            .call(move |conn| {
+                Ok(conn
+                    .prepare_cached(
+                        "SELECT rowid, format, length(bytes), compression FROM audio WHERE id=?",
+                    )?
+                    .query_row([id.to_string()], |row| {
+                        Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                    })?)
+            })
+            .await?;
+
+        let chunks = BlobChunks {
+            conn: self.conn.clone(),
+            rowid,
+            len,
+            position: 0,
+        };
+        let source: Box<dyn Read + Send> = match compression.as_deref() {
+            None => Box::new(chunks),
+            Some(DEFLATE) => Box::new(DeflateDecoder::new(chunks)),
+            Some(compression) => bail!("Unknown audio compression `{compression}`"),
+        };
+
+        Ok((format, BlobReader { source, len }))
+    }
+}
+
+/// Reads a segment blob in chunks, see [`AudioStorage::stream_blob`].
+///
+/// Reads block on the database, do them on a blocking thread.
+pub struct BlobReader {
+    /// Inflates the chunks of blobs stored compressed.
+    source: Box<dyn Read + Send>,
+    len: usize,
+}
+
+impl BlobReader {
+    /// Size of the blob as stored, a compressed one reads into more.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.source.read(buf)
+    }
+}
+
+struct BlobChunks {
+    conn: SharedConnection,
+    rowid: i64,
+    len: usize,
+    position: usize,
+}
+
+impl Read for BlobChunks {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let (rowid, position) = (self.rowid, self.position);
+        let read = self
+            .conn
+            .call_blocking(|conn| {
+                let blob = conn.blob_open(DatabaseName::Main, "audio", "bytes", rowid, true)?;
+                Ok(blob.read_at(buf, position)?)
+            })
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+        self.position += read;
+        Ok(read)
+    }
+}
+
+#[async_trait]
+impl AudioStore for AudioStorage {
+    async fn insert(&self, data: &AudioData) -> anyhow::Result<()> {
+        let data = data.clone();
+        let compress = self.compress && is_compressible(&data.format);
+        self.conn
+            .call(move |conn| {
+                let deflated = compress
+                    .then(|| deflate(&data.bytes))
+                    .transpose()?
+                    .filter(|deflated| deflated.len() < data.bytes.len());
+                let (bytes, compression) = match deflated.as_deref() {
+                    Some(deflated) => (deflated, Some(DEFLATE)),
+                    None => (data.bytes.as_ref(), None),
+                };
+
+                let tx = conn.transaction()?;
+                let inserted = tx.execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO audio(id, format, bytes, compression) \
+                        VALUES(?, ?, ZEROBLOB({}), ?)",
+                        bytes.len()
+                    ),
+                    params![data.id.to_string(), data.format, compression],
+                )?;
+                if inserted == 0 {
+                    // Stored already, e.g. on a retry: fine as long as it is the same audio.
+                    let same = tx.query_row(
+                        "SELECT format=? AND compression IS ? AND LENGTH(bytes)=? \
+                        FROM audio WHERE id=?",
+                        params![data.format, compression, bytes.len(), data.id.to_string()],
+                        |row| row.get::<_, bool>(0),
+                    )?;
+                    anyhow::ensure!(same, "Audio {} is stored already with other data", data.id);
+                    return Ok(());
+                }
+
+                tx.blob_open(
+                    DatabaseName::Main,
+                    "audio",
+                    "bytes",
+                    tx.last_insert_rowid(),
+                    false,
+                )?
+                .write_all(bytes)
+                .map_err(|_| rusqlite::Error::BlobSizeError)?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get(&self, id: Uuid) -> anyhow::Result<AudioData> {
+        let (format, mut reader) = self.stream_blob(id).await?;
+        let bytes = tokio::task::spawn_blocking(move || {
+            let mut bytes = Vec::with_capacity(reader.len());
+            reader.read_to_end(&mut bytes).map(|_| bytes)
+        })
+        .await??;
+        Ok(AudioData::new(id, format, bytes.into()))
+    }
+
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("DELETE FROM audio WHERE id=?")?
+                    .execute([id.to_string()])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn format(&self, id: Uuid) -> anyhow::Result<String> {
+        Ok(self.get_format_only(id).await?.1)
+    }
+
+    #[cfg(feature = "serve")]
+    async fn stream(&self, id: Uuid) -> anyhow::Result<(String, Box<dyn Read + Send>)> {
+        let (format, reader) = self.stream_blob(id).await?;
+        Ok((format, Box::new(reader)))
+    }
+
+    async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::io::Read;
+
     use uuid::Uuid;
 
-    use super::{AudioData, AudioStorage};
+    use super::{AudioData, AudioStorage, AudioStore};
 
-    #[test]
-    fn test() {
+    #[tokio::test]
+    async fn test() {
         let data = AudioData::new(
             Uuid::new_v4(),
             "audio/aac".to_owned(),
@@ -109,9 +359,104 @@ mod tests {
         );
 
         let db = AudioStorage::new(&"./test_audio.db").unwrap();
-        db.insert(&data).unwrap();
+        db.insert(&data).await.unwrap();
 
-        let result = db.get(data.id).unwrap();
+        let result = db.get(data.id).await.unwrap();
         assert_eq!(result, data);
+
+        db.remove(data.id).await.unwrap();
+        assert!(db.get(data.id).await.is_err());
+        db.remove(data.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_insert_twice() {
+        let data = AudioData::new(
+            Uuid::new_v4(),
+            "audio/aac".to_owned(),
+            b"1234567890".as_ref().into(),
+        );
+
+        let db = AudioStorage::new(&"./test_audio.db").unwrap();
+        db.insert(&data).await.unwrap();
+        db.insert(&data).await.unwrap();
+        assert_eq!(db.get(data.id).await.unwrap(), data);
+
+        let other = AudioData::new(data.id, "audio/aac".to_owned(), b"123".as_ref().into());
+        assert!(db.insert(&other).await.is_err());
+        assert_eq!(db.get(data.id).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn test_without_buffering() {
+        let bytes: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let data = AudioData::new(Uuid::new_v4(), "audio/aac".to_owned(), bytes.clone().into());
+
+        let db = AudioStorage::new(&"./test_audio.db").unwrap();
+        db.insert(&data).await.unwrap();
+
+        let (id, format) = db.get_format_only(data.id()).await.unwrap();
+        assert_eq!((id, format.as_str()), (data.id(), "audio/aac"));
+        assert!(db.get_format_only(Uuid::new_v4()).await.is_err());
+
+        let (format, mut reader) = db.stream_blob(data.id()).await.unwrap();
+        assert_eq!(format, "audio/aac");
+        assert_eq!(reader.len(), bytes.len());
+
+        let read = tokio::task::spawn_blocking(move || {
+            let mut chunk = [0; 4096];
+            let mut read = Vec::new();
+            loop {
+                match reader.read(&mut chunk).unwrap() {
+                    0 => break read,
+                    n => read.extend_from_slice(&chunk[..n]),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(read, bytes);
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        // A second of 16-bit mono hum, as redundant as ads get.
+        let hum: Vec<i16> = (0..16_000).map(|n| n % 7 - 3).collect();
+        let wav = crate::wav::pcm16(16_000, 1, &hum);
+        let pcm = AudioData::new(Uuid::new_v4(), "audio/wav".to_owned(), wav.clone().into());
+        let aac = AudioData::new(Uuid::new_v4(), "audio/aac".to_owned(), vec![0; 1000].into());
+
+        let plain = AudioStorage::new(&"./test_audio.db").unwrap();
+        let uncompressed = AudioData::new(Uuid::new_v4(), "audio/wav".to_owned(), wav.into());
+        plain.insert(&uncompressed).await.unwrap();
+
+        let db = AudioStorage::new(&"./test_audio.db")
+            .unwrap()
+            .with_compression(true);
+        db.insert(&pcm).await.unwrap();
+        db.insert(&aac).await.unwrap();
+
+        let stored = |id: Uuid| {
+            let db = AudioStorage::read_only(&"./test_audio.db").unwrap();
+            async move { db.stream_blob(id).await.unwrap().1.len() }
+        };
+        let compressed = stored(pcm.id()).await;
+        assert!(compressed * 10 < pcm.bytes().len(), "{compressed}");
+        // Formats compressed already are stored as they are.
+        assert_eq!(stored(aac.id()).await, aac.bytes().len());
+
+        assert_eq!(db.get(pcm.id()).await.unwrap(), pcm);
+        assert_eq!(db.get(aac.id()).await.unwrap(), aac);
+        assert_eq!(db.get(uncompressed.id()).await.unwrap(), uncompressed);
+
+        let (_, mut reader) = db.stream_blob(pcm.id()).await.unwrap();
+        let read = tokio::task::spawn_blocking(move || {
+            let mut read = Vec::new();
+            reader.read_to_end(&mut read).unwrap();
+            read
+        })
+        .await
+        .unwrap();
+        assert_eq!(read, pcm.bytes().as_ref());
     }
 }
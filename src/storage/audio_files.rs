@@ -0,0 +1,253 @@
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use rusqlite::{params, OptionalExtension};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::audio::{AudioData, AudioStore};
+use super::{
+    migrate, open_read_only, open_writable, Durability, Migration, SharedConnection, WalCheckpoint,
+};
+
+/// Writes each segment to `<dir>/<id>.<ext>`, or the name given with it, and indexes path,
+/// format and hash in sqlite.
+pub struct FileAudioStore {
+    dir: PathBuf,
+    conn: SharedConnection,
+}
+
+/// Schema steps of [`FileAudioStore`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[|conn| {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS audio_files(
+            id STRING PRIMARY KEY,
+            path STRING NOT NULL,
+            format STRING NOT NULL,
+            sha256 STRING NOT NULL
+        ) WITHOUT ROWID"#,
+    )
+}];
+
+impl FileAudioStore {
+    pub fn new<P>(dir: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Create audio directory {}", dir.display()))?;
+
+        let mut conn = open_writable(&dir.join("index.sqlite3"))?;
+        migrate(&mut conn, "audio_files", MIGRATIONS)?;
+
+        Ok(Self {
+            dir,
+            conn: SharedConnection::new(conn),
+        })
+    }
+
+    /// Opens an existing store for the query-only subcommands.
+    pub fn read_only<P>(dir: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let dir = dir.as_ref().to_path_buf();
+        let conn = open_read_only(&dir.join("index.sqlite3"))?;
+
+        Ok(Self {
+            dir,
+            conn: SharedConnection::new(conn),
+        })
+    }
+
+    /// Format and hash of the audio stored under `id`, if any.
+    async fn stored(&self, id: Uuid) -> anyhow::Result<Option<(String, String)>> {
+        self.conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT format, sha256 FROM audio_files WHERE id=?",
+                    [id.to_string()],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl AudioStore for FileAudioStore {
+    async fn insert(&self, data: &AudioData) -> anyhow::Result<()> {
+        let hash = sha256(data.bytes());
+        if let Some((format, stored_hash)) = self.stored(data.id()).await? {
+            // Stored already, e.g. on a retry: fine as long as it is the same audio.
+            ensure!(
+                format == data.format() && stored_hash == hash,
+                "Audio {} is stored already with other data",
+                data.id()
+            );
+            return Ok(());
+        }
+
+        let filename = data.name().map_or_else(
+            || format!("{}.{}", data.id(), extension(data.format())),
+            str::to_owned,
+        );
+        let path = self.dir.join(&filename);
+
+        tokio::fs::write(&path, data.bytes())
+            .await
+            .with_context(|| format!("Write audio file {}", path.display()))?;
+
+        let id = data.id();
+        let format = data.format().to_owned();
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("INSERT INTO audio_files VALUES(?, ?, ?, ?)")?
+                    .execute(params![id.to_string(), filename, format, hash])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get(&self, id: Uuid) -> anyhow::Result<AudioData> {
+        let (filename, format, hash): (String, String, String) = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT path, format, sha256 FROM audio_files WHERE id=?",
+                    [id.to_string()],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .map_err(|e| e.into())
+            })
+            .await?;
+
+        let path = self.dir.join(filename);
+        let bytes = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Read audio file {}", path.display()))?;
+
+        if sha256(&bytes) != hash {
+            bail!("Audio file {} does not match its hash", path.display());
+        }
+
+        Ok(AudioData::new(id, format, bytes.into()))
+    }
+
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        let filename: Option<String> = self
+            .conn
+            .call(move |conn| {
+                let filename = conn
+                    .query_row(
+                        "SELECT path FROM audio_files WHERE id=?",
+                        [id.to_string()],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                conn.prepare_cached("DELETE FROM audio_files WHERE id=?")?
+                    .execute([id.to_string()])?;
+                Ok(filename)
+            })
+            .await?;
+
+        // Indexed no more, a file left behind is only a stray.
+        if let Some(filename) = filename {
+            let path = self.dir.join(filename);
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(e).with_context(|| format!("Remove audio file {}", path.display()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
+    }
+}
+
+pub(super) fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// File extension for a segment content type.
+pub fn extension(format: &str) -> &'static str {
+    match format.split(';').next().unwrap_or_default().trim() {
+        "audio/aac" | "audio/aacp" | "audio/x-aac" => "aac",
+        "audio/mpeg" | "audio/mp3" => "mp3",
+        "audio/mp4" | "audio/x-m4a" => "m4a",
+        "video/mp2t" | "audio/mp2t" => "ts",
+        "audio/ogg" => "ogg",
+        "audio/wav" | "audio/x-wav" => "wav",
+        _ => "bin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{extension, FileAudioStore};
+    use crate::storage::audio::{AudioData, AudioStore};
+
+    #[tokio::test]
+    async fn test() {
+        let data = AudioData::new(
+            Uuid::new_v4(),
+            "audio/aac".to_owned(),
+            b"1234567890".as_ref().into(),
+        );
+
+        let store = FileAudioStore::new(&"./test_audio_files").unwrap();
+        store.insert(&data).await.unwrap();
+
+        assert!(std::path::Path::new(&format!("./test_audio_files/{}.aac", data.id())).exists());
+        assert_eq!(store.get(data.id()).await.unwrap(), data);
+
+        store.insert(&data).await.unwrap();
+        let other = AudioData::new(data.id(), "audio/aac".to_owned(), b"123".as_ref().into());
+        assert!(store.insert(&other).await.is_err());
+        assert_eq!(store.get(data.id()).await.unwrap(), data);
+
+        let named = AudioData::new(
+            Uuid::new_v4(),
+            "audio/mpeg".to_owned(),
+            b"123".as_ref().into(),
+        );
+        let name = format!("{}_Artist - Title.mp3", named.id());
+        let named = named.with_name(Some(name.clone()));
+        store.insert(&named).await.unwrap();
+
+        assert!(std::path::Path::new("./test_audio_files")
+            .join(&name)
+            .exists());
+        assert_eq!(store.get(named.id()).await.unwrap().bytes(), named.bytes());
+
+        store.remove(named.id()).await.unwrap();
+        assert!(!std::path::Path::new("./test_audio_files")
+            .join(&name)
+            .exists());
+        assert!(store.get(named.id()).await.is_err());
+        store.remove(named.id()).await.unwrap();
+    }
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("audio/aac"), "aac");
+        assert_eq!(extension("audio/mpeg; charset=binary"), "mp3");
+        assert_eq!(extension("application/octet-stream"), "bin");
+    }
+}
@@ -0,0 +1,214 @@
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context};
+use async_trait::async_trait;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use s3::creds::Credentials;
+use s3::{Bucket, Region};
+use uuid::Uuid;
+
+use super::audio::{AudioData, AudioStore};
+use super::audio_files::{extension, sha256};
+use super::{migrate, open_writable, Durability, Migration, SharedConnection, WalCheckpoint};
+
+/// Where and as whom to upload segments.
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Endpoint of an S3-compatible service, e.g. MinIO. Implies path-style bucket addressing.
+    pub endpoint: Option<String>,
+    /// Taken from `AWS_ACCESS_KEY_ID`/`AWS_SECRET_ACCESS_KEY` or the AWS profile if not set.
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+}
+
+/// Uploads each segment to `<YYYY-MM-DD>/<id>.<ext>` and indexes key, format and hash in sqlite.
+///
+/// The kind is left out of the key, it may still change once the segment matches a known track.
+pub struct S3AudioStore {
+    bucket: Bucket,
+    conn: SharedConnection,
+}
+
+/// Schema steps of [`S3AudioStore`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[|conn| {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS audio_objects(
+            id STRING PRIMARY KEY,
+            key STRING NOT NULL,
+            format STRING NOT NULL,
+            sha256 STRING NOT NULL
+        ) WITHOUT ROWID"#,
+    )
+}];
+
+impl S3AudioStore {
+    pub fn new<P>(config: &S3Config, index_path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config.region.parse()?,
+        };
+
+        let credentials = Credentials::new(
+            config.access_key.as_deref(),
+            config.secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )
+        .context("S3 credentials")?;
+
+        let mut bucket = Bucket::new(&config.bucket, region, credentials)?;
+        if config.endpoint.is_some() {
+            bucket = bucket.with_path_style();
+        }
+
+        let mut conn = open_writable(index_path.as_ref())?;
+        migrate(&mut conn, "audio_objects", MIGRATIONS)?;
+
+        Ok(Self {
+            bucket,
+            conn: SharedConnection::new(conn),
+        })
+    }
+
+    /// Format and hash of the audio stored under `id`, if any.
+    async fn stored(&self, id: Uuid) -> anyhow::Result<Option<(String, String)>> {
+        self.conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT format, sha256 FROM audio_objects WHERE id=?",
+                    [id.to_string()],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl AudioStore for S3AudioStore {
+    async fn insert(&self, data: &AudioData) -> anyhow::Result<()> {
+        let hash = sha256(data.bytes());
+        if let Some((format, stored_hash)) = self.stored(data.id()).await? {
+            // Stored already, e.g. on a retry: fine as long as it is the same audio.
+            ensure!(
+                format == data.format() && stored_hash == hash,
+                "Audio {} is stored already with other data",
+                data.id()
+            );
+            return Ok(());
+        }
+
+        let key = format!(
+            "{}/{}.{}",
+            Utc::now().format("%Y-%m-%d"),
+            data.id(),
+            extension(data.format())
+        );
+
+        let (_, code) = self
+            .bucket
+            .put_object_with_content_type(&key, data.bytes(), data.format())
+            .await
+            .with_context(|| format!("Upload {key}"))?;
+        if code != 200 {
+            bail!("Failed to upload {key}: status {code}");
+        }
+
+        let id = data.id();
+        let format = data.format().to_owned();
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("INSERT INTO audio_objects VALUES(?, ?, ?, ?)")?
+                    .execute(params![id.to_string(), key, format, hash])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn get(&self, id: Uuid) -> anyhow::Result<AudioData> {
+        let (key, format, hash): (String, String, String) = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT key, format, sha256 FROM audio_objects WHERE id=?",
+                    [id.to_string()],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .map_err(|e| e.into())
+            })
+            .await?;
+
+        let (bytes, code) = self
+            .bucket
+            .get_object(&key)
+            .await
+            .with_context(|| format!("Download {key}"))?;
+        if code != 200 {
+            bail!("Failed to download {key}: status {code}");
+        }
+
+        if sha256(&bytes) != hash {
+            bail!("Object {key} does not match its hash");
+        }
+
+        Ok(AudioData::new(id, format, bytes.into()))
+    }
+
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        let key: Option<String> = self
+            .conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT key FROM audio_objects WHERE id=?",
+                    [id.to_string()],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await?;
+        let key = match key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+
+        let (_, code) = self
+            .bucket
+            .delete_object(&key)
+            .await
+            .with_context(|| format!("Delete {key}"))?;
+        if code != 200 && code != 204 {
+            bail!("Failed to delete {key}: status {code}");
+        }
+
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("DELETE FROM audio_objects WHERE id=?")?
+                    .execute([id.to_string()])?;
+                Ok(())
+            })
+            .await
+    }
+
+    async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
+    }
+}
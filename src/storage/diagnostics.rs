@@ -0,0 +1,239 @@
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, OptionalExtension};
+
+use super::{
+    migrate, open_read_only, open_writable, Durability, Migration, SharedConnection, WalCheckpoint,
+};
+
+/// What the stream server answered to the last playlist request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaylistResponse {
+    pub timestamp: DateTime<Utc>,
+    pub content_type: Option<String>,
+    /// Beginning of the body, kept only if the content type was unexpected.
+    pub body_sample: Option<String>,
+}
+
+/// Cadence of segments new to a poll, see `DriftTracker`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentTiming {
+    pub timestamp: DateTime<Utc>,
+    pub stream_id: String,
+    /// Number of the last new segment.
+    pub number: u64,
+    pub segments: u32,
+    pub declared_seconds: f64,
+    pub interval_seconds: f64,
+    /// Drift accumulated since the feeder started or the sequence last broke.
+    pub drift_seconds: f64,
+    pub elapsed_seconds: f64,
+}
+
+/// Keeps what helps to find out why the feeder doesn't ingest anything.
+pub struct DiagnosticsStorage {
+    conn: SharedConnection,
+}
+
+/// Schema steps of [`DiagnosticsStorage`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[
+    |conn| {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS playlist_response(
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                timestamp DATETIME NOT NULL,
+                content_type STRING,
+                body_sample STRING
+            )"#,
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS segment_timing(
+                timestamp DATETIME NOT NULL,
+                stream_id STRING NOT NULL,
+                number INTEGER NOT NULL,
+                segments INTEGER NOT NULL,
+                declared_seconds REAL NOT NULL,
+                interval_seconds REAL NOT NULL,
+                drift_seconds REAL NOT NULL,
+                elapsed_seconds REAL NOT NULL
+            )"#,
+        )
+    },
+];
+
+impl DiagnosticsStorage {
+    pub fn new<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut conn = open_writable(path.as_ref())?;
+        migrate(&mut conn, "diagnostics", MIGRATIONS)?;
+
+        Ok(Self {
+            conn: SharedConnection::new(conn),
+        })
+    }
+
+    /// See [`WalCheckpoint`].
+    pub async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    /// See [`Durability`].
+    pub async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
+    }
+
+    /// Opens an existing database for the query-only subcommands.
+    pub fn read_only<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            conn: SharedConnection::new(open_read_only(path.as_ref())?),
+        })
+    }
+
+    /// Replaces the previously recorded response.
+    pub async fn record_playlist_response(
+        &self,
+        response: &PlaylistResponse,
+    ) -> anyhow::Result<()> {
+        let response = response.clone();
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("INSERT OR REPLACE INTO playlist_response VALUES(1, ?, ?, ?)")?
+                    .execute(params![
+                        response.timestamp,
+                        response.content_type,
+                        response.body_sample
+                    ])?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn last_playlist_response(&self) -> anyhow::Result<Option<PlaylistResponse>> {
+        self.conn
+            .call(|conn| {
+                conn.query_row(
+                    "SELECT timestamp, content_type, body_sample FROM playlist_response",
+                    [],
+                    |row| {
+                        Ok(PlaylistResponse {
+                            timestamp: row.get(0)?,
+                            content_type: row.get(1)?,
+                            body_sample: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+
+    pub async fn record_segment_timing(&self, timing: &SegmentTiming) -> anyhow::Result<()> {
+        let timing = timing.clone();
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("INSERT INTO segment_timing VALUES(?, ?, ?, ?, ?, ?, ?, ?)")?
+                    .execute(params![
+                        timing.timestamp,
+                        timing.stream_id,
+                        timing.number,
+                        timing.segments,
+                        timing.declared_seconds,
+                        timing.interval_seconds,
+                        timing.drift_seconds,
+                        timing.elapsed_seconds
+                    ])?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn last_segment_timing(&self) -> anyhow::Result<Option<SegmentTiming>> {
+        self.conn
+            .call(|conn| {
+                conn.query_row(
+                    r#"SELECT timestamp, stream_id, number, segments, declared_seconds,
+                        interval_seconds, drift_seconds, elapsed_seconds
+                    FROM segment_timing ORDER BY rowid DESC LIMIT 1"#,
+                    [],
+                    |row| {
+                        Ok(SegmentTiming {
+                            timestamp: row.get(0)?,
+                            stream_id: row.get(1)?,
+                            number: row.get(2)?,
+                            segments: row.get(3)?,
+                            declared_seconds: row.get(4)?,
+                            interval_seconds: row.get(5)?,
+                            drift_seconds: row.get(6)?,
+                            elapsed_seconds: row.get(7)?,
+                        })
+                    },
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{DiagnosticsStorage, PlaylistResponse, SegmentTiming};
+
+    #[tokio::test]
+    async fn test() {
+        let storage = DiagnosticsStorage::new(&"./test_diagnostics.db").unwrap();
+
+        let expected = PlaylistResponse {
+            timestamp: Utc::now(),
+            content_type: Some("application/vnd.apple.mpegurl".to_owned()),
+            body_sample: None,
+        };
+        let unexpected = PlaylistResponse {
+            timestamp: Utc::now(),
+            content_type: Some("text/html".to_owned()),
+            body_sample: Some("<html>".to_owned()),
+        };
+
+        storage.record_playlist_response(&expected).await.unwrap();
+        storage.record_playlist_response(&unexpected).await.unwrap();
+
+        assert_eq!(
+            storage.last_playlist_response().await.unwrap(),
+            Some(unexpected)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_segment_timing() {
+        let storage = DiagnosticsStorage::new(&"./test_diagnostics.db").unwrap();
+
+        let timing = SegmentTiming {
+            timestamp: Utc::now(),
+            stream_id: "station".to_owned(),
+            number: 42,
+            segments: 2,
+            declared_seconds: 20.0,
+            interval_seconds: 20.5,
+            drift_seconds: 1.5,
+            elapsed_seconds: 600.0,
+        };
+        storage.record_segment_timing(&timing).await.unwrap();
+
+        assert_eq!(storage.last_segment_timing().await.unwrap(), Some(timing));
+    }
+}
@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use anyhow::Context;
+use rusqlite::{params, OptionalExtension};
+use uuid::Uuid;
+
+use super::{
+    migrate, open_writable, uuid_column, Durability, Migration, SharedConnection, WalCheckpoint,
+};
+
+/// Links local track ids to the ids of the same tracks in emysound.
+pub struct IdMapStorage {
+    conn: SharedConnection,
+}
+
+/// Schema steps of [`IdMapStorage`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[|conn| {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS id_map(
+            local_id STRING PRIMARY KEY,
+            remote_id STRING NOT NULL UNIQUE
+        ) WITHOUT ROWID"#,
+    )
+}];
+
+impl IdMapStorage {
+    pub fn new<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut conn = open_writable(path.as_ref())?;
+        migrate(&mut conn, "id_map", MIGRATIONS)?;
+
+        Ok(Self {
+            conn: SharedConnection::new(conn),
+        })
+    }
+
+    /// See [`WalCheckpoint`].
+    pub async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    /// See [`Durability`].
+    pub async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
+    }
+
+    /// Maps `local_id` to `remote_id`. Repeating an existing mapping is a no-op,
+    /// mapping either id to a different one is an error.
+    pub async fn insert(&self, local_id: Uuid, remote_id: Uuid) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                let inserted = conn
+                    .prepare_cached(
+                        "INSERT OR IGNORE INTO id_map(local_id, remote_id) VALUES(?, ?)",
+                    )
+                    .context("Prepare statement")?
+                    .execute(params![local_id.to_string(), remote_id.to_string()])
+                    .context("Execute statement")?;
+                if inserted == 0 {
+                    let existing = conn
+                        .query_row(
+                            "SELECT remote_id FROM id_map WHERE local_id=?",
+                            [local_id.to_string()],
+                            |row| uuid_column(row, 0),
+                        )
+                        .optional()?;
+                    anyhow::ensure!(
+                        existing == Some(remote_id),
+                        "{local_id} or {remote_id} is already mapped to another id"
+                    );
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn local_id(&self, remote_id: Uuid) -> anyhow::Result<Option<Uuid>> {
+        self.conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT local_id FROM id_map WHERE remote_id=?",
+                    [remote_id.to_string()],
+                    |row| uuid_column(row, 0),
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+
+    pub async fn remote_id(&self, local_id: Uuid) -> anyhow::Result<Option<Uuid>> {
+        self.conn
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT remote_id FROM id_map WHERE local_id=?",
+                    [local_id.to_string()],
+                    |row| uuid_column(row, 0),
+                )
+                .optional()
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::IdMapStorage;
+
+    #[tokio::test]
+    async fn test() {
+        let local_id = Uuid::new_v4();
+        let remote_id = Uuid::new_v4();
+
+        let db = IdMapStorage::new(&"./test_id_map.db").unwrap();
+        db.insert(local_id, remote_id).await.unwrap();
+        db.insert(local_id, remote_id).await.unwrap();
+
+        assert_eq!(db.local_id(remote_id).await.unwrap(), Some(local_id));
+        assert_eq!(db.remote_id(local_id).await.unwrap(), Some(remote_id));
+        assert_eq!(db.local_id(Uuid::new_v4()).await.unwrap(), None);
+        assert!(db.insert(local_id, Uuid::new_v4()).await.is_err());
+        assert!(db.insert(Uuid::new_v4(), remote_id).await.is_err());
+    }
+}
@@ -1,18 +1,28 @@
 #![allow(dead_code)]
 
-use std::cell::RefCell;
 use std::path::Path;
 
 use anyhow::Context;
 use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection, OpenFlags};
+use rusqlite::params;
 use uuid::Uuid;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+use super::{
+    add_column, migrate, open_read_only, open_writable, uuid_column, AudioKind, Durability,
+    Migration, SharedConnection, WalCheckpoint,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MatchData {
     id: Uuid,
     timestamp: DateTime<Utc>,
     score: u8,
+    /// Artist and title of the match at the time it matched, unknown for old rows.
+    artist: Option<String>,
+    title: Option<String>,
+    /// Kind of the matched segment, learned from the match if it had none, see
+    /// `--learn-from-matches`. Unknown for old rows.
+    kind: Option<AudioKind>,
 }
 
 impl MatchData {
@@ -21,24 +31,55 @@ impl MatchData {
             id,
             timestamp,
             score,
+            artist: None,
+            title: None,
+            kind: None,
         }
     }
+
+    pub fn with_snapshot(mut self, artist: Option<String>, title: Option<String>) -> Self {
+        self.artist = artist;
+        self.title = title;
+        self
+    }
+
+    pub fn with_kind(mut self, kind: AudioKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn score(&self) -> u8 {
+        self.score
+    }
+
+    pub fn artist(&self) -> Option<&str> {
+        self.artist.as_deref()
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn kind(&self) -> Option<AudioKind> {
+        self.kind
+    }
 }
 
 pub struct MatchesStorage {
-    conn: RefCell<Connection>,
+    conn: SharedConnection,
 }
 
-impl MatchesStorage {
-    pub fn new<P>(path: &P) -> anyhow::Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        let conn = Connection::open_with_flags(
-            path,
-            OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
-        )?;
-
+/// Schema steps of [`MatchesStorage`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[
+    |conn| {
         conn.execute_batch(
             r#"
             CREATE TABLE IF NOT EXISTS matches(
@@ -46,34 +87,114 @@ impl MatchesStorage {
                 timestamp DATETIME NOT NULL,
                 score INTEGER NOT NULL
             )"#,
-        )?;
+        )
+    },
+    |conn| {
+        add_column(conn, "matches", "artist", "TEXT")?;
+        add_column(conn, "matches", "title", "TEXT")
+    },
+    |conn| add_column(conn, "matches", "kind", "STRING"),
+];
+
+impl MatchesStorage {
+    pub fn new<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut conn = open_writable(path.as_ref())?;
+        migrate(&mut conn, "matches", MIGRATIONS)?;
 
         Ok(Self {
-            conn: RefCell::new(conn),
+            conn: SharedConnection::new(conn),
         })
     }
 
-    pub fn insert(&self, data: &MatchData) -> anyhow::Result<()> {
-        let conn = self.conn.borrow_mut();
-        conn.prepare_cached("INSERT INTO matches VALUES(?, ?, ?)")
-            .context("Prepare statement")?
-            .execute(params![data.id.to_string(), data.timestamp, data.score])
-            .context("Execute statement")?;
-        Ok(())
-    }
-
-    pub fn get(&self, id: Uuid) -> anyhow::Result<Vec<MatchData>> {
-        let conn = self.conn.borrow();
-        let mut stmt = conn
-            .prepare("SELECT timestamp, score FROM matches WHERE id=? ORDER BY timestamp DESC")?;
-        let rows = stmt.query([id.to_string()])?;
-        rows.mapped(|row| {
-            let timestamp: DateTime<Utc> = row.get(0)?;
-            let score: u8 = row.get(1)?;
-            Ok(MatchData::new(id, timestamp, score))
+    /// See [`WalCheckpoint`].
+    pub async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    /// See [`Durability`].
+    pub async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
+    }
+
+    /// Opens an existing database for the query-only subcommands.
+    pub fn read_only<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            conn: SharedConnection::new(open_read_only(path.as_ref())?),
         })
-        .map(|m| m.map_err(|e| e.into()))
-        .collect()
+    }
+
+    pub async fn insert(&self, data: &MatchData) -> anyhow::Result<()> {
+        let data = data.clone();
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached(
+                    "INSERT INTO matches(id, timestamp, score, artist, title, kind) \
+                    VALUES(?, ?, ?, ?, ?, ?)",
+                )
+                .context("Prepare statement")?
+                .execute(params![
+                    data.id.to_string(),
+                    data.timestamp,
+                    data.score,
+                    data.artist,
+                    data.title,
+                    data.kind
+                ])
+                .context("Execute statement")?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: Uuid) -> anyhow::Result<Vec<MatchData>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT timestamp, score, artist, title, kind FROM matches WHERE id=? \
+                    ORDER BY timestamp DESC",
+                )?;
+                let rows = stmt.query([id.to_string()])?;
+                rows.mapped(|row| {
+                    let timestamp: DateTime<Utc> = row.get(0)?;
+                    let score: u8 = row.get(1)?;
+                    let kind: Option<AudioKind> = row.get(4)?;
+                    let data = MatchData::new(id, timestamp, score)
+                        .with_snapshot(row.get(2)?, row.get(3)?);
+                    Ok(MatchData { kind, ..data })
+                })
+                .map(|m| m.map_err(|e| e.into()))
+                .collect()
+            })
+            .await
+    }
+
+    pub async fn recent(&self, limit: usize) -> anyhow::Result<Vec<MatchData>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT id, timestamp, score, artist, title, kind FROM matches \
+                    ORDER BY timestamp DESC LIMIT ?",
+                )?;
+                let rows = stmt.query([limit])?;
+                rows.mapped(|row| {
+                    let id = uuid_column(row, 0)?;
+                    let timestamp: DateTime<Utc> = row.get(1)?;
+                    let score: u8 = row.get(2)?;
+                    let kind: Option<AudioKind> = row.get(5)?;
+                    let data = MatchData::new(id, timestamp, score)
+                        .with_snapshot(row.get(3)?, row.get(4)?);
+                    Ok(MatchData { kind, ..data })
+                })
+                .map(|m| m.map_err(|e| e.into()))
+                .collect()
+            })
+            .await
     }
 }
 
@@ -83,18 +204,28 @@ mod tests {
     use uuid::Uuid;
 
     use crate::storage::matches::{MatchData, MatchesStorage};
+    use crate::storage::AudioKind;
 
-    #[test]
-    fn test() {
+    #[tokio::test]
+    async fn test() {
         let id = Uuid::new_v4();
         let data1 = MatchData::new(id, Utc::now(), 25);
-        let data2 = MatchData::new(id, Utc::now() - chrono::Duration::seconds(1), 95);
+        let data2 = MatchData::new(id, Utc::now() - chrono::Duration::seconds(1), 95)
+            .with_snapshot(Some("Artist".to_owned()), Some("Title".to_owned()))
+            .with_kind(AudioKind::Music);
 
         let db = MatchesStorage::new(&"./test_matches.db").unwrap();
-        db.insert(&data1).unwrap();
-        db.insert(&data2).unwrap();
+        db.insert(&data1).await.unwrap();
+        db.insert(&data2).await.unwrap();
 
-        let result = db.get(id).unwrap();
+        let result = db.get(id).await.unwrap();
         assert_eq!(&result, &[data1, data2]);
+
+        // Numeric names stay text.
+        let id = Uuid::new_v4();
+        let numeric = MatchData::new(id, Utc::now(), 95)
+            .with_snapshot(Some("Prince".to_owned()), Some("1999".to_owned()));
+        db.insert(&numeric).await.unwrap();
+        assert_eq!(db.get(id).await.unwrap(), [numeric]);
     }
 }
@@ -1,24 +1,32 @@
 #![allow(dead_code)]
 
-use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::path::Path;
+use std::time::Duration;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use lazy_static::__Deref;
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef};
-use rusqlite::{params, Connection, OpenFlags, ToSql};
+use rusqlite::{params, OptionalExtension, Row, ToSql};
 use uuid::Uuid;
 
+use super::{
+    add_column, migrate, open_read_only, open_writable, uuid_column, Durability, Migration,
+    SharedConnection, WalCheckpoint,
+};
+
 pub struct MetadataStorage {
-    conn: RefCell<Connection>,
+    conn: SharedConnection,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum AudioKind {
     Advertisement,
     Music,
     Talk,
+    /// Station imaging, e.g. jingles and sweepers.
+    Jingle,
     Unknown,
 }
 
@@ -28,6 +36,7 @@ impl ToSql for AudioKind {
             AudioKind::Advertisement => "advertisement",
             AudioKind::Music => "music",
             AudioKind::Talk => "talk",
+            AudioKind::Jingle => "jingle",
             AudioKind::Unknown => "unknown",
         }
         .to_sql()
@@ -47,6 +56,7 @@ impl ToString for AudioKind {
             AudioKind::Advertisement => "advertisement",
             AudioKind::Music => "music",
             AudioKind::Talk => "talk",
+            AudioKind::Jingle => "jingle",
             AudioKind::Unknown => "unknown",
         }
         .to_string()
@@ -61,19 +71,106 @@ impl TryFrom<&str> for AudioKind {
             "advertisement" => Ok(AudioKind::Advertisement),
             "music" => Ok(AudioKind::Music),
             "talk" => Ok(AudioKind::Talk),
+            "jingle" => Ok(AudioKind::Jingle),
             "unknown" => Ok(AudioKind::Unknown),
             _ => Err(anyhow::anyhow!("Invalid kind value={value}")),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// What decided the kind of a segment.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum KindSource {
+    /// Playlist metadata, e.g. EXTINF titles.
+    Metadata,
+    /// The decoded audio, for segments whose metadata didn't tell, see `--classify-audio`.
+    Audio,
+    /// The same short audio heard again and again, see `--jingle-min-plays`.
+    Recurrence,
+}
+
+impl KindSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            KindSource::Metadata => "metadata",
+            KindSource::Audio => "audio",
+            KindSource::Recurrence => "recurrence",
+        }
+    }
+}
+
+impl ToSql for KindSource {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.as_str().to_sql()
+    }
+}
+
+impl FromSql for KindSource {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "metadata" => Ok(KindSource::Metadata),
+            "audio" => Ok(KindSource::Audio),
+            "recurrence" => Ok(KindSource::Recurrence),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Identifiers a station attaches to a track, to cross-reference external music databases.
+///
+/// All are unknown for stations whose metadata carries no ids.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackIds {
+    pub song_spot: Option<char>,
+    pub media_base_id: Option<i64>,
+    pub itunes_track_id: Option<i64>,
+    pub amg_track_id: Option<i64>,
+    pub amg_artist_id: Option<i64>,
+    pub ta_id: Option<i64>,
+    pub tp_id: Option<i64>,
+    pub cartcut_id: Option<i64>,
+    pub uns_id: Option<i64>,
+    pub spot_instance_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Metadata {
     pub id: Uuid,
     date: DateTime<Utc>,
     kind: AudioKind,
     artist: String,
     title: String,
+    ad_context: Option<String>,
+    ids: TrackIds,
+    /// Label of the captured stream, see `--stream-id`.
+    stream_id: Option<String>,
+    /// Integrated EBU R128 loudness, measured when built with the `decode` feature.
+    loudness_lufs: Option<f64>,
+    /// HLS discontinuity sequence, unknown for rows captured before it was recorded.
+    discontinuity_sequence: Option<u64>,
+    /// The segment follows a discontinuity and its timestamps restart.
+    discontinuity: bool,
+    /// Every `key=value` pair of the segment title, stored as a JSON object.
+    attributes: HashMap<String, String>,
+    /// From segment tags, see `--probe-tags`.
+    album: Option<String>,
+    year: Option<i32>,
+    /// URL the segment was downloaded from, unknown for rows captured before it was recorded.
+    source_url: Option<String>,
+    /// Unknown for segments of unknown kind and rows captured before it was recorded.
+    kind_source: Option<KindSource>,
+    /// Campaign id told by `ad_context`, see `AdContext::campaign`.
+    ad_campaign: Option<String>,
+    /// Seconds into the ad break, the `offset` sent along with `ad_context`.
+    ad_offset: Option<i64>,
+    /// Bits per second of the master playlist variant the segment came from, see `--variant`.
+    variant_bandwidth: Option<u64>,
+    variant_resolution: Option<String>,
+    /// Ids of other catalogs, e.g. MusicBrainz, added by `--enrich-command` or `--enrich-url`.
+    external_ids: BTreeMap<String, String>,
+    /// Stored while emysound was unavailable and not fingerprinted yet, see
+    /// `--emysound-failure-threshold`.
+    pending_sync: bool,
 }
 
 impl Metadata {
@@ -90,76 +187,928 @@ impl Metadata {
             kind,
             artist,
             title,
+            ad_context: None,
+            ids: TrackIds::default(),
+            stream_id: None,
+            loudness_lufs: None,
+            discontinuity_sequence: None,
+            discontinuity: false,
+            attributes: HashMap::new(),
+            album: None,
+            year: None,
+            source_url: None,
+            kind_source: None,
+            ad_campaign: None,
+            ad_offset: None,
+            variant_bandwidth: None,
+            variant_resolution: None,
+            external_ids: BTreeMap::new(),
+            pending_sync: false,
         }
     }
+
+    pub fn with_track(mut self, artist: String, title: String) -> Self {
+        self.artist = artist;
+        self.title = title;
+        self
+    }
+
+    pub fn with_ad_context(mut self, ad_context: Option<String>) -> Self {
+        self.ad_context = ad_context;
+        self
+    }
+
+    pub fn with_ad_campaign(mut self, ad_campaign: Option<String>) -> Self {
+        self.ad_campaign = ad_campaign;
+        self
+    }
+
+    pub fn with_ad_offset(mut self, ad_offset: Option<i64>) -> Self {
+        self.ad_offset = ad_offset;
+        self
+    }
+
+    pub fn with_ids(mut self, ids: TrackIds) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    pub fn with_stream_id(mut self, stream_id: Option<String>) -> Self {
+        self.stream_id = stream_id;
+        self
+    }
+
+    pub fn with_loudness_lufs(mut self, loudness_lufs: Option<f64>) -> Self {
+        self.loudness_lufs = loudness_lufs;
+        self
+    }
+
+    pub fn with_discontinuity(mut self, sequence: Option<u64>, discontinuity: bool) -> Self {
+        self.discontinuity_sequence = sequence;
+        self.discontinuity = discontinuity;
+        self
+    }
+
+    pub fn with_attributes(mut self, attributes: HashMap<String, String>) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn with_album(mut self, album: Option<String>, year: Option<i32>) -> Self {
+        self.album = album;
+        self.year = year;
+        self
+    }
+
+    pub fn with_source_url(mut self, source_url: Option<String>) -> Self {
+        self.source_url = source_url;
+        self
+    }
+
+    pub fn with_kind_source(mut self, kind_source: Option<KindSource>) -> Self {
+        self.kind_source = kind_source;
+        self
+    }
+
+    pub fn with_variant(mut self, bandwidth: Option<u64>, resolution: Option<String>) -> Self {
+        self.variant_bandwidth = bandwidth;
+        self.variant_resolution = resolution;
+        self
+    }
+
+    pub fn with_external_ids(mut self, external_ids: BTreeMap<String, String>) -> Self {
+        self.external_ids = external_ids;
+        self
+    }
+
+    pub fn with_pending_sync(mut self, pending_sync: bool) -> Self {
+        self.pending_sync = pending_sync;
+        self
+    }
+
+    pub fn date(&self) -> DateTime<Utc> {
+        self.date
+    }
+
+    pub fn kind(&self) -> AudioKind {
+        self.kind
+    }
+
+    pub fn artist(&self) -> &str {
+        &self.artist
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn ad_context(&self) -> Option<&str> {
+        self.ad_context.as_deref()
+    }
+
+    pub fn ad_campaign(&self) -> Option<&str> {
+        self.ad_campaign.as_deref()
+    }
+
+    pub fn ad_offset(&self) -> Option<i64> {
+        self.ad_offset
+    }
+
+    pub fn ids(&self) -> &TrackIds {
+        &self.ids
+    }
+
+    pub fn stream_id(&self) -> Option<&str> {
+        self.stream_id.as_deref()
+    }
+
+    pub fn loudness_lufs(&self) -> Option<f64> {
+        self.loudness_lufs
+    }
+
+    pub fn discontinuity_sequence(&self) -> Option<u64> {
+        self.discontinuity_sequence
+    }
+
+    pub fn discontinuity(&self) -> bool {
+        self.discontinuity
+    }
+
+    pub fn attributes(&self) -> &HashMap<String, String> {
+        &self.attributes
+    }
+
+    pub fn album(&self) -> Option<&str> {
+        self.album.as_deref()
+    }
+
+    pub fn year(&self) -> Option<i32> {
+        self.year
+    }
+
+    pub fn source_url(&self) -> Option<&str> {
+        self.source_url.as_deref()
+    }
+
+    pub fn kind_source(&self) -> Option<KindSource> {
+        self.kind_source
+    }
+
+    pub fn variant_bandwidth(&self) -> Option<u64> {
+        self.variant_bandwidth
+    }
+
+    pub fn variant_resolution(&self) -> Option<&str> {
+        self.variant_resolution.as_deref()
+    }
+
+    pub fn external_ids(&self) -> &BTreeMap<String, String> {
+        &self.external_ids
+    }
+
+    pub fn pending_sync(&self) -> bool {
+        self.pending_sync
+    }
+}
+
+/// Columns read by [`metadata_from_row`], qualified to be usable in joins.
+const METADATA_COLUMNS: &str = "metadata.id, metadata.date, metadata.kind, metadata.artist, \
+    metadata.title, metadata.ad_context, metadata.song_spot, metadata.media_base_id, \
+    metadata.itunes_track_id, metadata.amg_track_id, metadata.amg_artist_id, metadata.ta_id, \
+    metadata.tp_id, metadata.cartcut_id, metadata.uns_id, metadata.spot_instance_id, \
+    metadata.stream_id, metadata.loudness_lufs, metadata.discontinuity_sequence, \
+    metadata.discontinuity, metadata.attributes, metadata.album, metadata.year, \
+    metadata.source_url, metadata.kind_source, metadata.ad_campaign, metadata.ad_offset, \
+    metadata.variant_bandwidth, metadata.variant_resolution, metadata.external_ids, \
+    metadata.pending_sync";
+
+fn metadata_from_row(row: &Row, offset: usize) -> rusqlite::Result<Metadata> {
+    Ok(Metadata::new(
+        uuid_column(row, offset)?,
+        row.get(offset + 1)?,
+        row.get(offset + 2)?,
+        row.get(offset + 3)?,
+        row.get(offset + 4)?,
+    )
+    .with_ad_context(row.get(offset + 5)?)
+    .with_ids(TrackIds {
+        song_spot: row
+            .get::<_, Option<String>>(offset + 6)?
+            .and_then(|v| v.chars().next()),
+        media_base_id: row.get(offset + 7)?,
+        itunes_track_id: row.get(offset + 8)?,
+        amg_track_id: row.get(offset + 9)?,
+        amg_artist_id: row.get(offset + 10)?,
+        ta_id: row.get(offset + 11)?,
+        tp_id: row.get(offset + 12)?,
+        cartcut_id: row.get(offset + 13)?,
+        uns_id: row.get(offset + 14)?,
+        spot_instance_id: row
+            .get::<_, Option<String>>(offset + 15)?
+            .and_then(|v| Uuid::try_parse(&v).ok()),
+    })
+    .with_stream_id(row.get(offset + 16)?)
+    .with_loudness_lufs(row.get(offset + 17)?)
+    .with_discontinuity(row.get(offset + 18)?, row.get(offset + 19)?)
+    .with_attributes(
+        row.get::<_, Option<String>>(offset + 20)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+    )
+    .with_album(row.get(offset + 21)?, row.get(offset + 22)?)
+    .with_source_url(row.get(offset + 23)?)
+    .with_kind_source(row.get(offset + 24)?)
+    .with_ad_campaign(row.get(offset + 25)?)
+    .with_ad_offset(row.get(offset + 26)?)
+    .with_variant(row.get(offset + 27)?, row.get(offset + 28)?)
+    .with_external_ids(
+        row.get::<_, Option<String>>(offset + 29)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+    )
+    .with_pending_sync(row.get(offset + 30)?))
 }
 
+/// Accumulated airtime of a track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Airplay {
+    pub metadata: Metadata,
+    pub play_seconds: f64,
+    pub plays: u64,
+}
+
+/// When an ad creative was heard, keyed by its `spotInstanceId` or the hash of its audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdRotation {
+    pub key: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub plays: u64,
+    /// Artist and title it was last heard with.
+    pub artist: String,
+    pub title: String,
+    /// Campaign it was last heard for, see `AdContext::campaign`.
+    pub campaign: Option<String>,
+}
+
+/// When a short segment of unknown kind or a jingle was heard, keyed by the hash of its audio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JingleRotation {
+    pub key: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub plays: u64,
+    /// Artist and title it was last heard with.
+    pub artist: String,
+    pub title: String,
+}
+
+/// Plays of the creatives of an ad campaign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Campaign {
+    pub campaign: String,
+    pub creatives: u64,
+    pub plays: u64,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+/// Schema steps of [`MetadataStorage`], see [`migrate`].
+const MIGRATIONS: &[Migration] = &[
+    |conn| {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS metadata(
+                id STRING PRIMARY KEY,
+                date DATETIME NOT NULL,
+                kind STRING NOT NULL,
+                artist STRING NOT NULL,
+                title STRING NOT NULL
+            ) WITHOUT ROWID;
+            CREATE TABLE IF NOT EXISTS airplay(
+                id STRING NOT NULL,
+                day DATE NOT NULL,
+                play_seconds REAL NOT NULL,
+                plays INTEGER NOT NULL,
+                PRIMARY KEY (id, day)
+            ) WITHOUT ROWID;
+            CREATE TABLE IF NOT EXISTS preview(
+                id STRING PRIMARY KEY,
+                preview BLOB NOT NULL
+            ) WITHOUT ROWID"#,
+        )
+    },
+    |conn| {
+        add_column(conn, "metadata", "ad_context", "STRING")?;
+        add_column(conn, "metadata", "song_spot", "STRING")?;
+        for column in [
+            "media_base_id",
+            "itunes_track_id",
+            "amg_track_id",
+            "amg_artist_id",
+            "ta_id",
+            "tp_id",
+            "cartcut_id",
+            "uns_id",
+        ] {
+            add_column(conn, "metadata", column, "INTEGER")?;
+        }
+        add_column(conn, "metadata", "spot_instance_id", "STRING")
+    },
+    |conn| add_column(conn, "metadata", "stream_id", "TEXT"),
+    |conn| add_column(conn, "metadata", "loudness_lufs", "REAL"),
+    |conn| {
+        add_column(conn, "metadata", "discontinuity_sequence", "INTEGER")?;
+        add_column(
+            conn,
+            "metadata",
+            "discontinuity",
+            "INTEGER NOT NULL DEFAULT 0",
+        )
+    },
+    |conn| add_column(conn, "metadata", "attributes", "STRING"),
+    |conn| {
+        add_column(conn, "metadata", "album", "STRING")?;
+        add_column(conn, "metadata", "year", "INTEGER")
+    },
+    |conn| add_column(conn, "metadata", "source_url", "STRING"),
+    |conn| add_column(conn, "metadata", "kind_source", "STRING"),
+    |conn| {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS ad_rotation(
+                key STRING PRIMARY KEY,
+                first_seen DATETIME NOT NULL,
+                last_seen DATETIME NOT NULL,
+                plays INTEGER NOT NULL,
+                artist STRING NOT NULL,
+                title STRING NOT NULL
+            ) WITHOUT ROWID"#,
+        )
+    },
+    // TEXT rather than STRING, whose numeric affinity would turn ids like `9001` into numbers.
+    |conn| {
+        add_column(conn, "metadata", "ad_campaign", "TEXT")?;
+        add_column(conn, "metadata", "ad_offset", "INTEGER")?;
+        add_column(conn, "ad_rotation", "campaign", "TEXT")
+    },
+    |conn| {
+        add_column(conn, "metadata", "variant_bandwidth", "INTEGER")?;
+        add_column(conn, "metadata", "variant_resolution", "TEXT")
+    },
+    |conn| add_column(conn, "metadata", "external_ids", "TEXT"),
+    |conn| {
+        add_column(
+            conn,
+            "metadata",
+            "pending_sync",
+            "INTEGER NOT NULL DEFAULT 0",
+        )
+    },
+    |conn| {
+        conn.execute_batch(
+            r#"
+            CREATE TABLE IF NOT EXISTS jingle_rotation(
+                key TEXT PRIMARY KEY,
+                first_seen DATETIME NOT NULL,
+                last_seen DATETIME NOT NULL,
+                plays INTEGER NOT NULL,
+                artist TEXT NOT NULL,
+                title TEXT NOT NULL
+            ) WITHOUT ROWID"#,
+        )
+    },
+    // Numbered in the order stored, which the dates of concurrent streams are not written in.
+    |conn| {
+        add_column(conn, "metadata", "seq", "INTEGER")?;
+        conn.execute_batch(
+            r#"
+            UPDATE metadata SET seq=ordered.seq FROM (
+                SELECT id, ROW_NUMBER() OVER (ORDER BY date, id) AS seq FROM metadata
+            ) AS ordered
+            WHERE metadata.id=ordered.id;
+            CREATE UNIQUE INDEX IF NOT EXISTS metadata_seq ON metadata(seq)"#,
+        )
+    },
+];
+
+const INSERT_METADATA: &str = "INSERT INTO metadata(id, date, kind, artist, title, ad_context,
+    song_spot, media_base_id, itunes_track_id, amg_track_id, amg_artist_id, ta_id, tp_id,
+    cartcut_id, uns_id, spot_instance_id, stream_id, loudness_lufs, discontinuity_sequence,
+    discontinuity, attributes, album, year, source_url, kind_source, ad_campaign, ad_offset,
+    variant_bandwidth, variant_resolution, external_ids, pending_sync, seq)
+    VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+    ?, ?, ?, (SELECT IFNULL(MAX(seq), 0) + 1 FROM metadata))";
+
+/// Every column of [`INSERT_METADATA`] but `id`, `date` and `seq`.
+const UPDATE_ON_CONFLICT: &str = "ON CONFLICT(id) DO UPDATE SET kind=excluded.kind,
+    artist=excluded.artist, title=excluded.title, ad_context=excluded.ad_context,
+    song_spot=excluded.song_spot, media_base_id=excluded.media_base_id,
+    itunes_track_id=excluded.itunes_track_id, amg_track_id=excluded.amg_track_id,
+    amg_artist_id=excluded.amg_artist_id, ta_id=excluded.ta_id, tp_id=excluded.tp_id,
+    cartcut_id=excluded.cartcut_id, uns_id=excluded.uns_id,
+    spot_instance_id=excluded.spot_instance_id, stream_id=excluded.stream_id,
+    loudness_lufs=excluded.loudness_lufs, discontinuity_sequence=excluded.discontinuity_sequence,
+    discontinuity=excluded.discontinuity, attributes=excluded.attributes, album=excluded.album,
+    year=excluded.year, source_url=excluded.source_url, kind_source=excluded.kind_source,
+    ad_campaign=excluded.ad_campaign, ad_offset=excluded.ad_offset,
+    variant_bandwidth=excluded.variant_bandwidth,
+    variant_resolution=excluded.variant_resolution, external_ids=excluded.external_ids,
+    pending_sync=excluded.pending_sync";
+
 impl MetadataStorage {
     pub fn new<P>(path: &P) -> anyhow::Result<Self>
     where
         P: AsRef<Path>,
     {
-        let conn = Connection::open_with_flags(
-            path,
-            OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
-        )?;
+        let mut conn = open_writable(path.as_ref())?;
+        migrate(&mut conn, "metadata", MIGRATIONS)?;
 
-        conn.execute_batch(
-            r#"
-        CREATE TABLE IF NOT EXISTS metadata(
-            id STRING PRIMARY KEY,
-            date DATETIME NOT NULL,
-            kind STRING NOT NULL,
-            artist STRING NOT NULL,
-            title STRING NOT NULL
-        ) WITHOUT ROWID"#,
-        )?;
+        Ok(Self {
+            conn: SharedConnection::new(conn),
+        })
+    }
+
+    /// See [`WalCheckpoint`].
+    pub async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.conn.checkpoint().await
+    }
+
+    /// See [`Durability`].
+    pub async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.conn.set_durability(durability).await
+    }
+
+    /// Opens an existing database without creating or migrating it,
+    /// for readers running alongside the feeder.
+    pub fn read_only<P>(path: &P) -> anyhow::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let conn = open_read_only(path.as_ref())?;
 
         Ok(Self {
-            conn: RefCell::new(conn),
+            conn: SharedConnection::new(conn),
         })
     }
 
-    pub fn insert(&self, metadata: &Metadata) -> anyhow::Result<()> {
+    pub async fn insert(&self, metadata: &Metadata) -> anyhow::Result<()> {
+        self.write(metadata, "").await
+    }
+
+    /// Inserts `metadata`, or updates the row of its id if there is one, for segments
+    /// processed again, e.g. after a state reset. The row keeps the `date` it was first
+    /// stored at, airplay and previews are kept apart.
+    ///
+    /// Matches and audio keep plain inserts. Matches have no key to clash on, each one is
+    /// a play of its own. The audio of an id is what the id stands for, an insert clashing
+    /// with stored audio is a collision of ids that overwriting would hide.
+    pub async fn insert_or_update(&self, metadata: &Metadata) -> anyhow::Result<()> {
+        self.write(metadata, UPDATE_ON_CONFLICT).await
+    }
+
+    async fn write(&self, metadata: &Metadata, on_conflict: &'static str) -> anyhow::Result<()> {
+        let metadata = metadata.clone();
+        self.conn
+            .call(move |conn| {
+                let ids = &metadata.ids;
+                // Sorted keys keep the stored JSON stable.
+                let sorted: BTreeMap<_, _> = metadata.attributes.iter().collect();
+                let attributes = (!sorted.is_empty())
+                    .then(|| serde_json::to_string(&sorted))
+                    .transpose()?;
+                let external_ids = (!metadata.external_ids.is_empty())
+                    .then(|| serde_json::to_string(&metadata.external_ids))
+                    .transpose()?;
+                conn.prepare_cached(&format!("{INSERT_METADATA} {on_conflict}"))?
+                    .execute(params![
+                        metadata.id.to_string(),
+                        metadata.date,
+                        metadata.kind,
+                        metadata.artist,
+                        metadata.title,
+                        metadata.ad_context,
+                        ids.song_spot.map(String::from),
+                        ids.media_base_id,
+                        ids.itunes_track_id,
+                        ids.amg_track_id,
+                        ids.amg_artist_id,
+                        ids.ta_id,
+                        ids.tp_id,
+                        ids.cartcut_id,
+                        ids.uns_id,
+                        ids.spot_instance_id.map(|id| id.to_string()),
+                        metadata.stream_id,
+                        metadata.loudness_lufs,
+                        metadata.discontinuity_sequence,
+                        metadata.discontinuity,
+                        attributes,
+                        metadata.album,
+                        metadata.year,
+                        metadata.source_url,
+                        metadata.kind_source,
+                        metadata.ad_campaign,
+                        metadata.ad_offset,
+                        metadata.variant_bandwidth,
+                        metadata.variant_resolution,
+                        external_ids,
+                        metadata.pending_sync
+                    ])?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn update_kind(&self, id: Uuid, kind: AudioKind) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                let updated = conn
+                    .prepare_cached("UPDATE metadata SET kind=? WHERE id=?")?
+                    .execute(params![kind, id.to_string()])?;
+
+                if updated == 0 {
+                    anyhow::bail!("No metadata for id={id}");
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Clears the pending sync flag of `id`, once emysound has its audio.
+    pub async fn clear_pending_sync(&self, id: Uuid) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                let updated = conn
+                    .prepare_cached("UPDATE metadata SET pending_sync=0 WHERE id=?")?
+                    .execute([id.to_string()])?;
+
+                if updated == 0 {
+                    anyhow::bail!("No metadata for id={id}");
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    /// Rows stored pending sync, oldest first.
+    pub async fn pending_sync(&self) -> anyhow::Result<Vec<Metadata>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {METADATA_COLUMNS} FROM metadata WHERE pending_sync ORDER BY date ASC"
+                ))?;
+                let rows = stmt.query([])?;
+                rows.mapped(|row| metadata_from_row(row, 0))
+                    .map(|m| m.map_err(|e| e.into()))
+                    .collect()
+            })
+            .await
+    }
+
+    pub async fn get(&self, id: Uuid) -> anyhow::Result<Metadata> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {METADATA_COLUMNS} FROM metadata WHERE id=?"
+                ))?;
+                let data = stmt.query_row([id.to_string()], |row| metadata_from_row(row, 0))?;
+                Ok(data)
+            })
+            .await
+    }
+
+    /// Stores the spectrogram preview of track `id`, see `--generate-preview`.
+    pub async fn insert_preview(&self, id: Uuid, preview: Vec<u8>) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached("INSERT OR REPLACE INTO preview(id, preview) VALUES(?, ?)")?
+                    .execute(params![id.to_string(), preview])?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn preview(&self, id: Uuid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.conn
+            .call(move |conn| {
+                Ok(conn
+                    .prepare_cached("SELECT preview FROM preview WHERE id=?")?
+                    .query_row([id.to_string()], |row| row.get(0))
+                    .optional()?)
+            })
+            .await
+    }
+
+    /// Adds `duration` to today's airtime of track `id`, and a play if it starts one rather than
+    /// continues the play of the segment before.
+    pub async fn add_airplay(
+        &self,
+        id: Uuid,
+        duration: Duration,
+        starts_play: bool,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached(
+                    r#"INSERT INTO airplay(id, day, play_seconds, plays) VALUES(?, ?, ?, ?)
+                    ON CONFLICT(id, day) DO UPDATE SET
+                        play_seconds = play_seconds + excluded.play_seconds,
+                        plays = plays + excluded.plays"#,
+                )?
+                .execute(params![
+                    id.to_string(),
+                    Utc::today().naive_utc(),
+                    duration.as_secs_f64(),
+                    u64::from(starts_play)
+                ])?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Tracks with the longest airtime since `since`, longest first.
+    pub async fn airplay(&self, since: NaiveDate, limit: usize) -> anyhow::Result<Vec<Airplay>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    r#"SELECT SUM(airplay.play_seconds), SUM(airplay.plays), {METADATA_COLUMNS}
+                    FROM airplay JOIN metadata ON metadata.id = airplay.id
+                    WHERE airplay.day >= ?
+                    GROUP BY airplay.id
+                    ORDER BY SUM(airplay.play_seconds) DESC
+                    LIMIT ?"#
+                ))?;
+                let rows = stmt.query(params![since, limit])?;
+                rows.mapped(|row| {
+                    Ok(Airplay {
+                        play_seconds: row.get(0)?,
+                        plays: row.get(1)?,
+                        metadata: metadata_from_row(row, 2)?,
+                    })
+                })
+                .map(|m| m.map_err(|e| e.into()))
+                .collect()
+            })
+            .await
+    }
+
+    /// Records the ad creative `key` heard at `seen`, counting a play if `starts_play`, as
+    /// the later segments of an airing don't.
+    pub async fn record_ad(
+        &self,
+        key: &str,
+        seen: DateTime<Utc>,
+        artist: &str,
+        title: &str,
+        campaign: Option<&str>,
+        starts_play: bool,
+    ) -> anyhow::Result<()> {
+        let (key, artist, title) = (key.to_owned(), artist.to_owned(), title.to_owned());
+        let campaign = campaign.map(str::to_owned);
         self.conn
-            .borrow_mut()
-            .prepare_cached(
-                "INSERT INTO metadata(id, date, kind, artist, title) VALUES(?, ?, ?, ?, ?)",
-            )?
-            .execute(params![
-                metadata.id.to_string(),
-                metadata.date,
-                metadata.kind,
-                metadata.artist,
-                metadata.title
-            ])?;
+            .call(move |conn| {
+                conn.prepare_cached(
+                    r#"INSERT INTO ad_rotation(key, first_seen, last_seen, plays, artist, title,
+                        campaign)
+                    VALUES(?1, ?2, ?2, ?6, ?3, ?4, ?5)
+                    ON CONFLICT(key) DO UPDATE SET
+                        first_seen = MIN(first_seen, excluded.first_seen),
+                        last_seen = MAX(last_seen, excluded.last_seen),
+                        plays = plays + excluded.plays,
+                        artist = excluded.artist,
+                        title = excluded.title,
+                        campaign = excluded.campaign"#,
+                )?
+                .execute(params![
+                    key,
+                    seen,
+                    artist,
+                    title,
+                    campaign,
+                    u64::from(starts_play)
+                ])?;
+                Ok(())
+            })
+            .await
+    }
 
-        Ok(())
+    /// Ad creatives last heard since `since`, most recently heard first.
+    pub async fn ad_rotation(
+        &self,
+        since: NaiveDate,
+        limit: usize,
+    ) -> anyhow::Result<Vec<AdRotation>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    r#"SELECT key, first_seen, last_seen, plays, artist, title, campaign
+                    FROM ad_rotation
+                    WHERE last_seen >= ?
+                    ORDER BY last_seen DESC
+                    LIMIT ?"#,
+                )?;
+                let rows = stmt.query(params![since, limit])?;
+                rows.mapped(|row| {
+                    Ok(AdRotation {
+                        key: row.get(0)?,
+                        first_seen: row.get(1)?,
+                        last_seen: row.get(2)?,
+                        plays: row.get(3)?,
+                        artist: row.get(4)?,
+                        title: row.get(5)?,
+                        campaign: row.get(6)?,
+                    })
+                })
+                .map(|m| m.map_err(|e| e.into()))
+                .collect()
+            })
+            .await
     }
 
-    pub fn get(&self, id: Uuid) -> anyhow::Result<Metadata> {
-        let conn = self.conn.borrow();
-        let mut stmt = conn.prepare("SELECT date, kind, artist, title FROM metadata WHERE id=?")?;
-        let data = stmt.query_row([id.to_string()], |row| {
-            let date: DateTime<Utc> = row.get(0)?;
-            let kind: AudioKind = row.get(1)?;
-            let artist = row.get(2)?;
-            let title = row.get(3)?;
-            Ok(Metadata::new(id, date, kind, artist, title))
-        })?;
-        Ok(data)
+    /// Counts a play of the short audio `key` heard at `seen`, returns its plays so far.
+    /// Past `max_keys` audio, what was heard just once and longest ago is forgotten first.
+    pub async fn record_jingle(
+        &self,
+        key: &str,
+        seen: DateTime<Utc>,
+        artist: &str,
+        title: &str,
+        max_keys: usize,
+    ) -> anyhow::Result<u64> {
+        let (key, artist, title) = (key.to_owned(), artist.to_owned(), title.to_owned());
+        self.conn
+            .call(move |conn| {
+                conn.prepare_cached(
+                    r#"INSERT INTO jingle_rotation(key, first_seen, last_seen, plays, artist, title)
+                    VALUES(?1, ?2, ?2, 1, ?3, ?4)
+                    ON CONFLICT(key) DO UPDATE SET
+                        first_seen = MIN(first_seen, excluded.first_seen),
+                        last_seen = MAX(last_seen, excluded.last_seen),
+                        plays = plays + 1,
+                        artist = excluded.artist,
+                        title = excluded.title"#,
+                )?
+                .execute(params![key, seen, artist, title])?;
+                let plays = conn
+                    .prepare_cached("SELECT plays FROM jingle_rotation WHERE key = ?")?
+                    .query_row(params![key], |row| row.get(0))?;
+                // Only new audio adds a row.
+                if plays == 1 {
+                    conn.prepare_cached(
+                        r#"DELETE FROM jingle_rotation WHERE key IN (
+                            SELECT key FROM jingle_rotation
+                            ORDER BY plays > 1 DESC, last_seen DESC
+                            LIMIT -1 OFFSET ?
+                        )"#,
+                    )?
+                    .execute(params![max_keys])?;
+                }
+                Ok(plays)
+            })
+            .await
+    }
+
+    /// Short audio played at least `min_plays` times and last heard since `since`, most
+    /// recently heard first.
+    pub async fn jingle_rotation(
+        &self,
+        since: NaiveDate,
+        min_plays: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<JingleRotation>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    r#"SELECT key, first_seen, last_seen, plays, artist, title
+                    FROM jingle_rotation
+                    WHERE last_seen >= ? AND plays >= ?
+                    ORDER BY last_seen DESC
+                    LIMIT ?"#,
+                )?;
+                let rows = stmt.query(params![since, min_plays, limit])?;
+                rows.mapped(|row| {
+                    Ok(JingleRotation {
+                        key: row.get(0)?,
+                        first_seen: row.get(1)?,
+                        last_seen: row.get(2)?,
+                        plays: row.get(3)?,
+                        artist: row.get(4)?,
+                        title: row.get(5)?,
+                    })
+                })
+                .map(|m| m.map_err(|e| e.into()))
+                .collect()
+            })
+            .await
+    }
+
+    /// Campaigns of the ad creatives last heard since `since`, most played first.
+    pub async fn campaigns(&self, since: NaiveDate, limit: usize) -> anyhow::Result<Vec<Campaign>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    r#"SELECT campaign, COUNT(*), SUM(plays), MIN(first_seen), MAX(last_seen)
+                    FROM ad_rotation
+                    WHERE last_seen >= ? AND campaign IS NOT NULL
+                    GROUP BY campaign
+                    ORDER BY SUM(plays) DESC
+                    LIMIT ?"#,
+                )?;
+                let rows = stmt.query(params![since, limit])?;
+                rows.mapped(|row| {
+                    Ok(Campaign {
+                        campaign: row.get(0)?,
+                        creatives: row.get(1)?,
+                        plays: row.get(2)?,
+                        first_seen: row.get(3)?,
+                        last_seen: row.get(4)?,
+                    })
+                })
+                .map(|m| m.map_err(|e| e.into()))
+                .collect()
+            })
+            .await
+    }
+
+    /// Rows dated within `[from, to)`, oldest first. Either bound is open if not set.
+    pub async fn between(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<Metadata>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    r#"SELECT {METADATA_COLUMNS} FROM metadata
+                    WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date < ?2)
+                    ORDER BY date ASC"#
+                ))?;
+                let rows = stmt.query(params![from, to])?;
+                rows.mapped(|row| metadata_from_row(row, 0))
+                    .map(|m| m.map_err(|e| e.into()))
+                    .collect()
+            })
+            .await
+    }
+
+    /// Number of the row stored last, 0 if there is none, see [`Self::since`].
+    pub async fn last_seq(&self) -> anyhow::Result<u64> {
+        self.conn
+            .call(|conn| {
+                conn.query_row("SELECT IFNULL(MAX(seq), 0) FROM metadata", [], |row| {
+                    row.get(0)
+                })
+                .map_err(|e| e.into())
+            })
+            .await
+    }
+
+    /// Rows stored after the row numbered `seq`, in the order stored and with their
+    /// numbers. Unlike dates, numbers of rows stored later are always greater, so that
+    /// readers following new rows miss none.
+    pub async fn since(&self, seq: u64) -> anyhow::Result<Vec<(u64, Metadata)>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT metadata.seq, {METADATA_COLUMNS} FROM metadata WHERE seq > ?
+                    ORDER BY seq ASC"
+                ))?;
+                let rows = stmt.query([seq])?;
+                rows.mapped(|row| Ok((row.get(0)?, metadata_from_row(row, 1)?)))
+                    .map(|m| m.map_err(|e| e.into()))
+                    .collect()
+            })
+            .await
+    }
+
+    pub async fn recent(&self, limit: usize) -> anyhow::Result<Vec<Metadata>> {
+        self.conn
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {METADATA_COLUMNS} FROM metadata ORDER BY date DESC LIMIT ?"
+                ))?;
+                let rows = stmt.query([limit])?;
+                rows.mapped(|row| metadata_from_row(row, 0))
+                    .map(|m| m.map_err(|e| e.into()))
+                    .collect()
+            })
+            .await
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use chrono::Utc;
     use uuid::Uuid;
 
-    use super::{Metadata, MetadataStorage};
+    use super::{Metadata, MetadataStorage, TrackIds};
 
-    #[test]
-    fn test_existing() {
+    #[tokio::test]
+    async fn test_existing() {
         let metadata = Metadata::new(
             Uuid::new_v4(),
             Utc::now(),
@@ -169,15 +1118,393 @@ mod tests {
         );
 
         let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
-        storage.insert(&metadata).unwrap();
-        let result = storage.get(metadata.id).unwrap();
+        storage.insert(&metadata).await.unwrap();
+        let result = storage.get(metadata.id).await.unwrap();
+
+        assert_eq!(metadata, result);
+    }
+
+    #[tokio::test]
+    async fn test_insert_or_update() {
+        let metadata = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now() - chrono::Duration::days(1),
+            super::AudioKind::Unknown,
+            "Artist".to_string(),
+            "Title".to_string(),
+        );
+
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        storage.insert_or_update(&metadata).await.unwrap();
+        assert!(storage.insert(&metadata).await.is_err());
+
+        let again = Metadata::new(
+            metadata.id,
+            Utc::now(),
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "Title (Remastered)".to_string(),
+        )
+        .with_album(Some("Album".to_owned()), None);
+        storage.insert_or_update(&again).await.unwrap();
+
+        let result = storage.get(metadata.id).await.unwrap();
+        assert_eq!(result.date(), metadata.date());
+        assert_eq!(result.kind(), super::AudioKind::Music);
+        assert_eq!(result.title(), "Title (Remastered)");
+        assert_eq!(result.album(), Some("Album"));
+        assert!(!result.pending_sync());
+
+        storage
+            .insert_or_update(&again.with_pending_sync(true))
+            .await
+            .unwrap();
+        assert!(storage.get(metadata.id).await.unwrap().pending_sync());
+        let pending = storage.pending_sync().await.unwrap();
+        assert!(pending.iter().any(|m| m.id == metadata.id));
+
+        storage.clear_pending_sync(metadata.id).await.unwrap();
+        assert!(!storage.get(metadata.id).await.unwrap().pending_sync());
+        let pending = storage.pending_sync().await.unwrap();
+        assert!(!pending.iter().any(|m| m.id == metadata.id));
+    }
+
+    #[tokio::test]
+    async fn test_update_kind() {
+        let metadata = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            super::AudioKind::Unknown,
+            "Artist".to_string(),
+            "Title".to_string(),
+        );
+
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        storage.insert(&metadata).await.unwrap();
+        storage
+            .update_kind(metadata.id, super::AudioKind::Music)
+            .await
+            .unwrap();
+
+        let result = storage.get(metadata.id).await.unwrap();
+        assert_eq!(result.kind(), super::AudioKind::Music);
+        assert!(storage
+            .update_kind(Uuid::new_v4(), super::AudioKind::Music)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_recent() {
+        let older = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now() + chrono::Duration::days(1),
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "Older".to_string(),
+        );
+        let newer = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now() + chrono::Duration::days(2),
+            super::AudioKind::Talk,
+            "Artist".to_string(),
+            "Newer".to_string(),
+        );
+
+        // Rows of earlier runs, dated later than these, would come first in the shared db.
+        let path = "./test_metadata_recent.db";
+        let _ = std::fs::remove_file(path);
+        let storage = MetadataStorage::new(&path).unwrap();
+        storage.insert(&older).await.unwrap();
+        storage.insert(&newer).await.unwrap();
+
+        assert_eq!(storage.recent(2).await.unwrap(), vec![newer, older]);
+    }
+
+    #[tokio::test]
+    async fn test_since() {
+        let cursor = Utc::now() - chrono::Duration::days(3650);
+        let before = Metadata::new(
+            Uuid::new_v4(),
+            cursor,
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "Before".to_string(),
+        );
+        let after = Metadata::new(
+            Uuid::new_v4(),
+            cursor + chrono::Duration::seconds(1),
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "After".to_string(),
+        );
+
+        let path = "./test_metadata_since.db";
+        let _ = std::fs::remove_file(path);
+        let storage = MetadataStorage::new(&path).unwrap();
+        let reader = MetadataStorage::read_only(&path).unwrap();
+        assert_eq!(reader.last_seq().await.unwrap(), 0);
+
+        // Stored out of date order, as concurrent streams may store them.
+        storage.insert(&after).await.unwrap();
+        let seq = reader.last_seq().await.unwrap();
+        storage.insert(&before).await.unwrap();
+
+        assert_eq!(
+            reader.since(0).await.unwrap(),
+            vec![(1, after.clone()), (2, before.clone())]
+        );
+        assert_eq!(reader.since(seq).await.unwrap(), vec![(2, before.clone())]);
+        assert_eq!(reader.since(2).await.unwrap(), vec![]);
+        assert!(reader.insert(&after).await.is_err());
+
+        // Updated rows keep their number.
+        storage.insert_or_update(&after).await.unwrap();
+        assert_eq!(reader.last_seq().await.unwrap(), 2);
+
+        let between = reader
+            .between(Some(cursor), Some(cursor + chrono::Duration::seconds(1)))
+            .await
+            .unwrap();
+        assert_eq!(between, vec![before]);
+    }
+
+    #[tokio::test]
+    async fn test_airplay() {
+        let metadata = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "Airplay".to_string(),
+        );
+
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        storage.insert(&metadata).await.unwrap();
+        // Two segments of one play, and the first one of another.
+        storage
+            .add_airplay(metadata.id, Duration::from_secs(10), true)
+            .await
+            .unwrap();
+        storage
+            .add_airplay(metadata.id, Duration::from_millis(9500), false)
+            .await
+            .unwrap();
+        storage
+            .add_airplay(metadata.id, Duration::from_secs(10), true)
+            .await
+            .unwrap();
+
+        let airplay = storage
+            .airplay(Utc::today().naive_utc(), 1_000_000)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|airplay| airplay.metadata.id == metadata.id)
+            .unwrap();
+        assert_eq!(airplay.plays, 2);
+        assert!((airplay.play_seconds - 29.5).abs() < f64::EPSILON);
+    }
+
+    #[tokio::test]
+    async fn test_ad_rotation() {
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        let key = format!("spot:{}", Uuid::new_v4());
+        let first = Utc::now() - chrono::Duration::hours(1);
+        let last = Utc::now();
+
+        let campaign = format!("campaign-{}", Uuid::new_v4());
+        let record = |seen, title, starts_play| {
+            storage.record_ad(&key, seen, "Sponsor", title, Some(&campaign), starts_play)
+        };
+        record(last, "Spot", true).await.unwrap();
+        // The next segment of the same airing.
+        record(last, "Spot", false).await.unwrap();
+        record(first, "Spot", true).await.unwrap();
+        record(last, "Spot v2", true).await.unwrap();
+        storage
+            .record_ad(
+                &format!("{key}-v2"),
+                last,
+                "Sponsor",
+                "Spot",
+                Some(&campaign),
+                true,
+            )
+            .await
+            .unwrap();
+
+        let ad = storage
+            .ad_rotation(Utc::today().naive_utc(), 1_000_000)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|ad| ad.key == key)
+            .unwrap();
+        assert_eq!(ad.plays, 3);
+        assert_eq!((ad.first_seen, ad.last_seen), (first, last));
+        assert_eq!(ad.title, "Spot v2");
+        assert_eq!(ad.campaign.as_deref(), Some(campaign.as_str()));
+
+        let aired = storage
+            .campaigns(Utc::today().naive_utc(), 1_000_000)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|aired| aired.campaign == campaign)
+            .unwrap();
+        assert_eq!((aired.creatives, aired.plays), (2, 4));
+        assert_eq!((aired.first_seen, aired.last_seen), (first, last));
+    }
+
+    #[tokio::test]
+    async fn test_jingle_rotation() {
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        let key = format!("sha256:{}", Uuid::new_v4());
+        let first = Utc::now() - chrono::Duration::hours(1);
+        let last = Utc::now();
+
+        let record = |seen| storage.record_jingle(&key, seen, "Station", "Sweeper", 1_000_000);
+        assert_eq!(record(last).await.unwrap(), 1);
+        assert_eq!(record(first).await.unwrap(), 2);
+
+        let (storage, key) = (&storage, &key);
+        let rotation = |min_plays| async move {
+            storage
+                .jingle_rotation(Utc::today().naive_utc(), min_plays, 1_000_000)
+                .await
+                .unwrap()
+                .into_iter()
+                .find(|jingle| jingle.key == *key)
+        };
+        let jingle = rotation(2).await.unwrap();
+        assert_eq!((jingle.first_seen, jingle.last_seen), (first, last));
+        assert_eq!(jingle.title, "Sweeper");
+        assert!(rotation(3).await.is_none());
+    }
 
+    #[tokio::test]
+    async fn test_jingle_rotation_cap() {
+        // Rows of earlier runs would count towards the cap.
+        let path = "./test_metadata_jingles.db";
+        let _ = std::fs::remove_file(path);
+        let storage = MetadataStorage::new(&path).unwrap();
+        let keys: Vec<String> = (0..3)
+            .map(|_| format!("sha256:{}", Uuid::new_v4()))
+            .collect();
+        let now = Utc::now();
+
+        // The sweeper heard twice outlives newer audio heard once.
+        for (minutes, key) in [&keys[0], &keys[0], &keys[1], &keys[2]]
+            .into_iter()
+            .enumerate()
+        {
+            let seen = now + chrono::Duration::minutes(minutes as i64);
+            storage
+                .record_jingle(key, seen, "Station", "Sweeper", 2)
+                .await
+                .unwrap();
+        }
+
+        let rotation = storage
+            .jingle_rotation(Utc::today().naive_utc(), 1, 1_000_000)
+            .await
+            .unwrap();
+        let kept: Vec<&str> = rotation.iter().map(|jingle| jingle.key.as_str()).collect();
+        assert_eq!(kept, [keys[2].as_str(), keys[0].as_str()]);
+    }
+
+    #[tokio::test]
+    async fn test_ad_context() {
+        let metadata = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            super::AudioKind::Advertisement,
+            "Advertisement".to_string(),
+            "Advertisement".to_string(),
+        )
+        .with_ad_context(Some("campaign=42".to_string()))
+        .with_ad_campaign(Some("42".to_string()))
+        .with_ad_offset(Some(0));
+
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        storage.insert(&metadata).await.unwrap();
+        let result = storage.get(metadata.id).await.unwrap();
+
+        assert_eq!(result.ad_context(), Some("campaign=42"));
+        assert_eq!(result.ad_campaign(), Some("42"));
+        assert_eq!(result.ad_offset(), Some(0));
+        assert_eq!(metadata, result);
+    }
+
+    #[tokio::test]
+    async fn test_ids() {
+        let ids = TrackIds {
+            song_spot: Some('M'),
+            media_base_id: Some(1234),
+            amg_track_id: Some(-1),
+            spot_instance_id: Some(Uuid::new_v4()),
+            ..TrackIds::default()
+        };
+        let metadata = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "Ids".to_string(),
+        )
+        .with_ids(ids.clone())
+        .with_loudness_lufs(Some(-14.5))
+        .with_discontinuity(Some(3), true)
+        .with_attributes([("offset".to_owned(), "0".to_owned())].into())
+        .with_album(Some("Album".to_owned()), Some(1999))
+        .with_source_url(Some("https://example.com/live/1.aac".to_owned()))
+        .with_kind_source(Some(super::KindSource::Audio))
+        .with_variant(Some(640_000), Some("640x360".to_owned()))
+        .with_external_ids([("musicbrainz".to_owned(), "8f3471b5".to_owned())].into());
+
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        storage.insert(&metadata).await.unwrap();
+        let result = storage.get(metadata.id).await.unwrap();
+
+        assert_eq!(result.ids(), &ids);
+        assert_eq!(result.loudness_lufs(), Some(-14.5));
+        assert_eq!(result.variant_bandwidth(), Some(640_000));
         assert_eq!(metadata, result);
     }
 
-    #[test]
-    fn test_non_existing() {
+    #[tokio::test]
+    async fn test_numeric_stream_id() {
+        let metadata = Metadata::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            super::AudioKind::Music,
+            "Artist".to_string(),
+            "Title".to_string(),
+        )
+        .with_stream_id(Some("1035".to_owned()));
+
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        storage.insert(&metadata).await.unwrap();
+        let result = storage.get(metadata.id).await.unwrap();
+
+        assert_eq!(result.stream_id(), Some("1035"));
+        assert_eq!(metadata, result);
+    }
+
+    #[tokio::test]
+    async fn test_preview() {
+        let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
+        let id = Uuid::new_v4();
+        assert_eq!(storage.preview(id).await.unwrap(), None);
+
+        storage.insert_preview(id, vec![1, 2, 3]).await.unwrap();
+        assert_eq!(storage.preview(id).await.unwrap(), Some(vec![1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn test_non_existing() {
         let storage = MetadataStorage::new(&"./test_metadata.db").unwrap();
-        assert!(storage.get(Uuid::new_v4()).is_err());
+        assert!(storage.get(Uuid::new_v4()).await.is_err());
     }
 }
@@ -1,15 +1,402 @@
 #![allow(unused_imports)]
 
+use anyhow::Context;
+use clap::ArgEnum;
+
 mod audio;
+mod audio_files;
+#[cfg(feature = "s3")]
+mod audio_s3;
+mod diagnostics;
+mod id_map;
 mod matches;
 mod metadata;
+mod recovery;
 
 pub use audio::AudioData;
 pub use audio::AudioStorage;
+pub use audio::AudioStore;
+pub use audio::BlobReader;
+pub use audio_files::{extension, FileAudioStore};
+#[cfg(feature = "s3")]
+pub use audio_s3::{S3AudioStore, S3Config};
+
+pub use diagnostics::{DiagnosticsStorage, PlaylistResponse, SegmentTiming};
+
+pub use id_map::IdMapStorage;
 
 pub use matches::MatchData;
 pub use matches::MatchesStorage;
 
+pub use metadata::AdRotation;
+pub use metadata::Airplay;
 pub use metadata::AudioKind;
+pub use metadata::Campaign;
+pub use metadata::JingleRotation;
+pub use metadata::KindSource;
 pub use metadata::Metadata;
 pub use metadata::MetadataStorage;
+pub use metadata::TrackIds;
+
+pub use recovery::{recover, OnCorrupt, Recovery};
+
+/// A connection shared between tasks, running statements on the blocking thread pool
+/// so that storage calls can be awaited without stalling the runtime.
+#[derive(Clone)]
+struct SharedConnection(std::sync::Arc<std::sync::Mutex<rusqlite::Connection>>);
+
+impl SharedConnection {
+    fn new(conn: rusqlite::Connection) -> Self {
+        Self(std::sync::Arc::new(std::sync::Mutex::new(conn)))
+    }
+
+    async fn call<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let conn = self.clone();
+        tokio::task::spawn_blocking(move || conn.call_blocking(f)).await?
+    }
+
+    /// Runs `f` on the calling thread, for callers on a blocking thread already.
+    fn call_blocking<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut rusqlite::Connection) -> anyhow::Result<T>,
+    {
+        let mut conn = self
+            .0
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Connection lock poisoned"))?;
+        f(&mut conn)
+    }
+
+    /// Sets `PRAGMA synchronous`, see [`Durability`].
+    async fn set_durability(&self, durability: Durability) -> anyhow::Result<()> {
+        self.call(move |conn| Ok(conn.pragma_update(None, "synchronous", durability.pragma())?))
+            .await
+    }
+
+    /// Moves the write-ahead log into the database and truncates it, see [`WalCheckpoint`].
+    async fn checkpoint(&self) -> anyhow::Result<WalCheckpoint> {
+        self.call(|conn| {
+            let checkpoint = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+                Ok(WalCheckpoint {
+                    busy: row.get::<_, i64>(0)? != 0,
+                    log_frames: row.get(1)?,
+                    checkpointed_frames: row.get(2)?,
+                })
+            })?;
+            Ok(checkpoint)
+        })
+        .await
+    }
+}
+
+/// How hard sqlite works for a committed write to outlast an OS crash or a power loss,
+/// by `PRAGMA synchronous`, see `--durability`. A crash of the feeder alone loses nothing
+/// committed at any level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum Durability {
+    /// Syncs at the critical moments only, fewer syncs per insert. With a WAL journal the
+    /// last commits may roll back at a power loss, with the default rollback journal the
+    /// database may corrupt, as it may at `off`
+    Normal,
+    /// Syncs each commit before it returns, sqlite's default and the slowest
+    Full,
+    /// Leaves syncing to the OS, the fastest. Commits may be lost and the database may
+    /// corrupt at an OS crash or a power loss, see `--on-corrupt`
+    Off,
+}
+
+impl Durability {
+    fn pragma(self) -> &'static str {
+        match self {
+            Durability::Normal => "NORMAL",
+            Durability::Full => "FULL",
+            Durability::Off => "OFF",
+        }
+    }
+}
+
+/// Result of `PRAGMA wal_checkpoint`, a database with `PRAGMA journal_mode=WAL` keeps
+/// writes in a `-wal` file next to it until a checkpoint moves them in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct WalCheckpoint {
+    /// Readers or writers kept the checkpoint from completing, it is retried next time.
+    pub busy: bool,
+    /// Frames in the log before the checkpoint, -1 without a log.
+    pub log_frames: i64,
+    pub checkpointed_frames: i64,
+}
+
+impl WalCheckpoint {
+    /// False for a database in another journal mode, which has nothing to checkpoint.
+    pub fn is_wal(&self) -> bool {
+        self.log_frames >= 0
+    }
+}
+
+/// Opens the database at `path` for writing, creating it if missing.
+///
+/// Write permission is checked right away with a rolled back write, so that a read-only
+/// file or directory fails at startup with its path instead of on the first insert.
+fn open_writable(path: &std::path::Path) -> anyhow::Result<rusqlite::Connection> {
+    use rusqlite::OpenFlags;
+
+    rusqlite::Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
+    )
+    .and_then(|conn| {
+        conn.execute_batch("BEGIN; CREATE TABLE write_probe(x INTEGER); ROLLBACK;")?;
+        Ok(conn)
+    })
+    .map_err(|e| {
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(|| std::path::Path::new("."));
+        let context = if is_permission_error(&e) {
+            format!(
+                "{} is not writable, it needs read and write permission for the file \
+                and for its directory {} where sqlite keeps a journal. \
+                Query-only subcommands can run with `--read-only`",
+                path.display(),
+                dir.display()
+            )
+        } else {
+            format!("Open {}", path.display())
+        };
+        anyhow::Error::from(e).context(context)
+    })
+}
+
+/// Opens the existing database at `path` for reading, never creating or altering it.
+fn open_read_only(path: &std::path::Path) -> anyhow::Result<rusqlite::Connection> {
+    rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| {
+            format!(
+                "Open {} for reading, it needs read permission and must have been created \
+                by a feeder run",
+                path.display()
+            )
+        })
+}
+
+fn is_permission_error(e: &rusqlite::Error) -> bool {
+    use rusqlite::ErrorCode;
+
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error {
+                code: ErrorCode::ReadOnly | ErrorCode::CannotOpen | ErrorCode::PermissionDenied,
+                ..
+            },
+            _
+        )
+    )
+}
+
+/// Reads a UUID stored as text, the way all storages persist ids.
+fn uuid_column(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<uuid::Uuid> {
+    let id: String = row.get(idx)?;
+    uuid::Uuid::try_parse(&id).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+/// A step of a storage schema, see [`migrate`].
+type Migration = fn(&rusqlite::Connection) -> rusqlite::Result<()>;
+
+/// Applies the `migrations` of `storage` not applied yet, each in a transaction with the
+/// `schema_version` it brings the database to. Append new steps, never change applied ones.
+///
+/// Steps stay idempotent, databases from before versioning start at version 0
+/// with some of the tables and columns in place already.
+fn migrate(
+    conn: &mut rusqlite::Connection,
+    storage: &str,
+    migrations: &[Migration],
+) -> anyhow::Result<()> {
+    use rusqlite::OptionalExtension;
+
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version(
+            storage STRING PRIMARY KEY,
+            version INTEGER NOT NULL
+        ) WITHOUT ROWID"#,
+    )?;
+
+    let version: usize = conn
+        .query_row(
+            "SELECT version FROM schema_version WHERE storage=?",
+            [storage],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    if version > migrations.len() {
+        anyhow::bail!(
+            "The {storage} schema is at version {version}, newer than {} known to this feeder",
+            migrations.len()
+        );
+    }
+
+    for (version, migration) in migrations.iter().enumerate().skip(version) {
+        let version = version + 1;
+        let tx = conn.transaction()?;
+        migration(&tx).with_context(|| format!("Migrate {storage} schema to version {version}"))?;
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_version(storage, version) VALUES (?, ?)",
+            rusqlite::params![storage, version],
+        )?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Adds `column` to `table` of a database created before the column existed.
+fn add_column(
+    conn: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{table}') WHERE name=?"
+        ))?
+        .exists([column])?;
+
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+        ))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_column, migrate, open_read_only, open_writable, Durability, Migration, SharedConnection,
+    };
+
+    #[test]
+    fn test_open() {
+        let path = std::path::Path::new("./test_open.sqlite3");
+        let _ = std::fs::remove_file(path);
+
+        let error = open_read_only(path).unwrap_err();
+        assert!(format!("{error:#}").contains("test_open.sqlite3"));
+
+        assert!(open_writable(path).is_ok());
+        assert!(open_read_only(path).is_ok());
+
+        let error = open_writable(std::path::Path::new("./missing/dir/test.sqlite3")).unwrap_err();
+        assert!(format!("{error:#}").contains("is not writable"));
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint() {
+        let path = std::path::Path::new("./test_checkpoint.sqlite3");
+        let wal = std::path::Path::new("./test_checkpoint.sqlite3-wal");
+        let _ = std::fs::remove_file(path);
+
+        let conn = SharedConnection::new(open_writable(path).unwrap());
+        let checkpoint = conn.checkpoint().await.unwrap();
+        assert!(!checkpoint.is_wal());
+
+        conn.call(|conn| {
+            conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()))?;
+            Ok(conn.execute_batch("CREATE TABLE t(x INTEGER); INSERT INTO t VALUES (1);")?)
+        })
+        .await
+        .unwrap();
+        assert!(std::fs::metadata(wal).unwrap().len() > 0);
+
+        let checkpoint = conn.checkpoint().await.unwrap();
+        assert!(checkpoint.is_wal());
+        assert!(!checkpoint.busy);
+        assert_eq!(checkpoint.log_frames, checkpoint.checkpointed_frames);
+        assert_eq!(std::fs::metadata(wal).unwrap().len(), 0);
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_set_durability() {
+        let path = std::path::Path::new("./test_durability.sqlite3");
+        let conn = SharedConnection::new(open_writable(path).unwrap());
+        let synchronous = || {
+            conn.call_blocking(|conn| {
+                Ok(conn.query_row("PRAGMA synchronous", [], |row| row.get::<_, i64>(0))?)
+            })
+            .unwrap()
+        };
+        assert_eq!(synchronous(), 2);
+
+        conn.set_durability(Durability::Off).await.unwrap();
+        assert_eq!(synchronous(), 0);
+        conn.set_durability(Durability::Normal).await.unwrap();
+        assert_eq!(synchronous(), 1);
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    const MIGRATIONS: &[Migration] = &[
+        |conn| conn.execute_batch("CREATE TABLE IF NOT EXISTS steps(step INTEGER)"),
+        |conn| conn.execute_batch("INSERT INTO steps VALUES (2)"),
+        |conn| add_column(conn, "steps", "note", "STRING"),
+    ];
+
+    fn steps(conn: &rusqlite::Connection) -> Vec<i64> {
+        let mut statement = conn.prepare("SELECT step FROM steps").unwrap();
+        let rows = statement.query_map([], |row| row.get(0)).unwrap();
+        rows.collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        migrate(&mut conn, "test", &MIGRATIONS[..2]).unwrap();
+        assert_eq!(steps(&conn), vec![2]);
+
+        // Applied steps are skipped, the rest picks up where they ended.
+        migrate(&mut conn, "test", MIGRATIONS).unwrap();
+        migrate(&mut conn, "test", MIGRATIONS).unwrap();
+        assert_eq!(steps(&conn), vec![2]);
+        assert!(add_column(&conn, "steps", "note", "STRING").is_ok());
+
+        // Other storages keep their own version in the same database.
+        migrate(&mut conn, "other", &MIGRATIONS[..1]).unwrap();
+        assert_eq!(steps(&conn), vec![2]);
+
+        let error = migrate(&mut conn, "test", &MIGRATIONS[..1]).unwrap_err();
+        assert!(error.to_string().contains("newer"), "{error}");
+    }
+
+    #[test]
+    fn test_migrate_failed_step() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        let failing: &[Migration] = &[
+            |conn| conn.execute_batch("CREATE TABLE steps(step INTEGER)"),
+            |conn| conn.execute_batch("INSERT INTO missing VALUES (1)"),
+        ];
+
+        let error = migrate(&mut conn, "test", failing).unwrap_err();
+        assert_eq!(error.to_string(), "Migrate test schema to version 2");
+
+        // The first step stays applied, only the failed one runs again.
+        let error = migrate(&mut conn, "test", failing).unwrap_err();
+        assert_eq!(error.to_string(), "Migrate test schema to version 2");
+    }
+}
@@ -0,0 +1,280 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use chrono::Utc;
+use clap::ArgEnum;
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+
+/// What to do with a database found corrupt at startup, see [`recover`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ArgEnum)]
+pub enum OnCorrupt {
+    /// Refuse to start, naming the file
+    Fail,
+    /// Set the file aside and start afresh, without what it kept
+    Recreate,
+    /// Rebuild its indexes, or else copy what is still readable into a fresh file and set
+    /// the corrupt one aside
+    Repair,
+}
+
+/// What [`recover`] did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// Missing, or not corrupt.
+    Intact,
+    /// Corrupt indexes only, rebuilt in place.
+    Reindexed,
+    /// The readable tables were copied into a fresh file, the corrupt one moved to `backup`.
+    Salvaged {
+        backup: PathBuf,
+        /// Tables that failed to copy, left empty.
+        lost_tables: Vec<String>,
+    },
+    /// Moved to `backup`, the storage starts empty.
+    Recreated { backup: PathBuf },
+}
+
+/// Checks the integrity of the database at `path` before a storage opens it, and deals
+/// with a corrupt one by `policy`. Errors other than corruption are left to the storage.
+pub fn recover(path: &Path, policy: OnCorrupt) -> anyhow::Result<Recovery> {
+    let problem = match corruption(path) {
+        Some(problem) => problem,
+        None => return Ok(Recovery::Intact),
+    };
+
+    match policy {
+        OnCorrupt::Fail => bail!(
+            "{} is corrupt: {problem}. Restore it from a backup, or restart with \
+            `--on-corrupt repair` to salvage what is readable or `--on-corrupt recreate` \
+            to set it aside",
+            path.display()
+        ),
+        OnCorrupt::Recreate => Ok(Recovery::Recreated {
+            backup: set_aside(path)?,
+        }),
+        OnCorrupt::Repair => {
+            let reindexed = Connection::open(path)
+                .and_then(|conn| conn.execute_batch("REINDEX"))
+                .is_ok();
+            if reindexed && corruption(path).is_none() {
+                return Ok(Recovery::Reindexed);
+            }
+            salvage(path)
+        }
+    }
+}
+
+/// What `PRAGMA quick_check` finds wrong with the database, if anything.
+fn corruption(path: &Path) -> Option<String> {
+    if !path.exists() {
+        return None;
+    }
+
+    let problems =
+        Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE).and_then(|conn| {
+            let mut stmt = conn.prepare("PRAGMA quick_check")?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        });
+    match problems {
+        Ok(problems) if problems == ["ok"] => None,
+        Ok(problems) => Some(problems.join(", ")),
+        Err(e) if is_corruption(&e) => Some(e.to_string()),
+        Err(_) => None,
+    }
+}
+
+fn is_corruption(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error {
+                code: ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase,
+                ..
+            },
+            _
+        )
+    )
+}
+
+/// Copies every table that still reads into a fresh database, which replaces the corrupt
+/// one. A database whose schema doesn't read is set aside without a copy.
+fn salvage(path: &Path) -> anyhow::Result<Recovery> {
+    let schema = Connection::open(path).and_then(|conn| {
+        let mut stmt = conn.prepare(
+            "SELECT type, name, sql FROM sqlite_master
+            WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%'",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.collect::<rusqlite::Result<Vec<(String, String, String)>>>()
+    });
+    let schema = match schema {
+        Ok(schema) => schema,
+        Err(_) => {
+            return Ok(Recovery::Recreated {
+                backup: set_aside(path)?,
+            })
+        }
+    };
+
+    let fresh = with_suffix(path, ".salvage");
+    if fresh.exists() {
+        std::fs::remove_file(&fresh)
+            .with_context(|| format!("Remove stale {}", fresh.display()))?;
+    }
+
+    let mut lost_tables = Vec::new();
+    {
+        let conn = Connection::open(&fresh).with_context(|| format!("Open {}", fresh.display()))?;
+        conn.execute("ATTACH DATABASE ? AS corrupt", [path.to_string_lossy()])?;
+        for (_, name, sql) in schema.iter().filter(|(kind, _, _)| kind == "table") {
+            conn.execute_batch(sql)
+                .with_context(|| format!("Create {name} in {}", fresh.display()))?;
+            let copied = conn.execute_batch(&format!(
+                r#"INSERT INTO main."{name}" SELECT * FROM corrupt."{name}""#
+            ));
+            if copied.is_err() {
+                lost_tables.push(name.clone());
+            }
+        }
+        // Indexes of rows that didn't copy might not build, the storages don't need them.
+        for (_, _, sql) in schema.iter().filter(|(kind, _, _)| kind != "table") {
+            let _ = conn.execute_batch(sql);
+        }
+        conn.execute_batch("DETACH DATABASE corrupt")?;
+    }
+
+    let backup = set_aside(path)?;
+    std::fs::rename(&fresh, path)
+        .with_context(|| format!("Move {} to {}", fresh.display(), path.display()))?;
+
+    Ok(Recovery::Salvaged {
+        backup,
+        lost_tables,
+    })
+}
+
+/// Moves the database and its journal files to `<path>.corrupt-<timestamp>`.
+fn set_aside(path: &Path) -> anyhow::Result<PathBuf> {
+    let timestamp = Utc::now().format("%Y%m%dT%H%M%S");
+    let backup = with_suffix(path, &format!(".corrupt-{timestamp}"));
+    std::fs::rename(path, &backup)
+        .with_context(|| format!("Move corrupt {} to {}", path.display(), backup.display()))?;
+
+    for journal in ["-journal", "-wal", "-shm"] {
+        let file = with_suffix(path, journal);
+        if file.exists() {
+            std::fs::rename(&file, with_suffix(&backup, journal))
+                .with_context(|| format!("Move {}", file.display()))?;
+        }
+    }
+
+    Ok(backup)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rusqlite::Connection;
+
+    use super::{recover, OnCorrupt, Recovery};
+
+    fn create(path: &Path) {
+        let _ = std::fs::remove_file(path);
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch("CREATE TABLE t(x INTEGER); INSERT INTO t VALUES (1), (2), (3);")
+            .unwrap();
+    }
+
+    /// Overwrites the header, sqlite takes the file for something else.
+    fn corrupt(path: &Path) {
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[..16].copy_from_slice(b"not a database!!");
+        std::fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn test() {
+        let path = Path::new("./test_recovery.sqlite3");
+        assert_eq!(
+            recover(Path::new("./missing.sqlite3"), OnCorrupt::Fail).unwrap(),
+            Recovery::Intact
+        );
+
+        create(path);
+        assert_eq!(recover(path, OnCorrupt::Fail).unwrap(), Recovery::Intact);
+
+        corrupt(path);
+        let error = recover(path, OnCorrupt::Fail).unwrap_err();
+        assert!(error.to_string().contains("is corrupt"), "{error}");
+        assert!(path.exists());
+
+        let backup = match recover(path, OnCorrupt::Recreate).unwrap() {
+            Recovery::Recreated { backup } => backup,
+            recovery => panic!("{recovery:?}"),
+        };
+        assert!(!path.exists());
+        assert!(backup.exists());
+        std::fs::remove_file(backup).unwrap();
+
+        // Without a readable schema there is nothing to salvage.
+        create(path);
+        corrupt(path);
+        match recover(path, OnCorrupt::Repair).unwrap() {
+            Recovery::Recreated { backup } => std::fs::remove_file(backup).unwrap(),
+            recovery => panic!("{recovery:?}"),
+        }
+    }
+
+    #[test]
+    fn test_salvage() {
+        let path = Path::new("./test_recovery_salvage.sqlite3");
+        create(path);
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE lost(x STRING);
+            INSERT INTO lost SELECT 'row' FROM t;",
+        )
+        .unwrap();
+        let (page_size, root): (usize, usize) = conn
+            .query_row(
+                "SELECT (SELECT page_size FROM pragma_page_size), rootpage
+                FROM sqlite_master WHERE name='lost'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        drop(conn);
+
+        // Garbles the only page of `lost`.
+        let mut bytes = std::fs::read(path).unwrap();
+        bytes[(root - 1) * page_size..root * page_size].fill(0xFF);
+        std::fs::write(path, bytes).unwrap();
+
+        let backup = match recover(path, OnCorrupt::Repair).unwrap() {
+            Recovery::Salvaged {
+                backup,
+                lost_tables,
+            } => {
+                assert_eq!(lost_tables, vec!["lost".to_owned()]);
+                backup
+            }
+            recovery => panic!("{recovery:?}"),
+        };
+        std::fs::remove_file(backup).unwrap();
+
+        let conn = Connection::open(path).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM t", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(recover(path, OnCorrupt::Fail).unwrap(), Recovery::Intact);
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Log target of summaries, logged at INFO even with `--quiet`.
+pub const SUMMARY_TARGET: &str = "summary";
+
+/// Counts of what became of segments, by decision name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary(BTreeMap<&'static str, usize>);
+
+impl Summary {
+    pub fn record(&mut self, decision: &'static str) {
+        *self.0.entry(decision).or_default() += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn segments(&self) -> usize {
+        self.0.values().sum()
+    }
+
+    /// Adds these counts to `total` and clears them.
+    pub fn drain_into(&mut self, total: &mut Summary) {
+        for (decision, count) in std::mem::take(&mut self.0) {
+            *total.0.entry(decision).or_default() += count;
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} segments", self.segments())?;
+        for (i, (decision, count)) in self.0.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { ", " };
+            write!(f, "{separator}{count} {decision}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn test() {
+        let mut total = Summary::default();
+        assert_eq!(total.to_string(), "0 segments");
+
+        let mut cycle = Summary::default();
+        cycle.record("matched");
+        cycle.record("inserted");
+        cycle.record("matched");
+        assert_eq!(cycle.to_string(), "3 segments: 1 inserted, 2 matched");
+
+        cycle.drain_into(&mut total);
+        assert!(cycle.is_empty());
+        cycle.record("matched");
+        cycle.drain_into(&mut total);
+        assert_eq!(total.to_string(), "4 segments: 1 inserted, 3 matched");
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::Cursor;
+
+use lofty::{Accessor, FileType, ItemKey, Probe};
+
+/// Tags of a segment worth keeping, see `--probe-tags`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SegmentTags {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i32>,
+}
+
+/// Reads the tags of a segment, the first tag that has a field wins.
+pub fn probe(bytes: &[u8]) -> anyhow::Result<SegmentTags> {
+    let tagged_file = Probe::new(Cursor::new(bytes))
+        .guess_file_type()?
+        .read(false)?;
+
+    let mut tags = SegmentTags::default();
+    for tag in tagged_file.tags() {
+        for item in tag.items() {
+            log::debug!("{:?} {:?}", item.key(), item.value());
+        }
+
+        let text = |value: Option<&str>| {
+            value
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_owned)
+        };
+        tags.artist = tags.artist.or_else(|| text(tag.artist()));
+        tags.title = tags.title.or_else(|| text(tag.title()));
+        tags.album = tags.album.or_else(|| text(tag.album()));
+        tags.year = tags.year.or_else(|| {
+            [ItemKey::Year, ItemKey::RecordingDate]
+                .iter()
+                .find_map(|key| tag.get_string(key).and_then(parse_year))
+        });
+    }
+
+    Ok(tags)
+}
+
+/// Canonical extension of the format of `bytes`, looking past a leading ID3 tag,
+/// see `--segment-extension-override`.
+///
+/// ADTS and MPEG-TS are told first, lofty knows neither and takes ADTS frames for MP3.
+pub fn detected_extension(bytes: &[u8]) -> Option<&'static str> {
+    let audio = bytes.get(id3_len(bytes)..)?;
+    match audio {
+        [0xFF, second, ..] if second & 0xF6 == 0xF0 => return Some("aac"),
+        [0x47, ..] if audio.get(188).map_or(true, |&sync| sync == 0x47) => return Some("ts"),
+        _ => {}
+    }
+
+    match FileType::from_buffer(audio)? {
+        FileType::AIFF => Some("aiff"),
+        FileType::APE => Some("ape"),
+        FileType::FLAC => Some("flac"),
+        FileType::MP3 => Some("mp3"),
+        FileType::MP4 => Some("m4a"),
+        FileType::Opus => Some("opus"),
+        FileType::Vorbis => Some("ogg"),
+        FileType::Speex => Some("spx"),
+        FileType::WAV => Some("wav"),
+        _ => None,
+    }
+}
+
+/// Length of the ID3v2 tag `bytes` start with, 0 without one.
+fn id3_len(bytes: &[u8]) -> usize {
+    match bytes {
+        [b'I', b'D', b'3', _, _, flags, size @ ..] if size.len() >= 4 => {
+            let size = size[..4]
+                .iter()
+                .fold(0, |len, &byte| len << 7 | usize::from(byte & 0x7F));
+            let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+            10 + size + footer
+        }
+        _ => 0,
+    }
+}
+
+/// `Some(tag)` if the tag says more than the EXTINF value: fills an empty one,
+/// or extends it like `Title (Remastered)` does `Title`.
+pub fn richer<'a>(extinf: &str, tag: Option<&'a str>) -> Option<&'a str> {
+    let tag = tag?;
+    let extinf = extinf.trim();
+
+    let extends = tag.len() > extinf.len() && tag.to_lowercase().contains(&extinf.to_lowercase());
+    (extinf.is_empty() || extends).then(|| tag)
+}
+
+/// `tag` if it tells something else than `extinf`, for when tags are trusted over the playlist.
+pub fn preferred<'a>(extinf: &str, tag: Option<&'a str>) -> Option<&'a str> {
+    tag.filter(|tag| *tag != extinf.trim())
+}
+
+/// Year of `2022`, `2022-05-01` or `2022-05-01T12:00:00`.
+fn parse_year(value: &str) -> Option<i32> {
+    value.get(..4).and_then(|year| year.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detected_extension, parse_year, preferred, richer};
+
+    #[test]
+    fn test_richer() {
+        assert_eq!(richer("", Some("Title")), Some("Title"));
+        assert_eq!(
+            richer("Title", Some("Title (Remastered)")),
+            Some("Title (Remastered)")
+        );
+        assert_eq!(richer("TITLE", Some("Title (Live)")), Some("Title (Live)"));
+        assert_eq!(richer("Title", Some("Title")), None);
+        assert_eq!(richer("Title", Some("Other Song")), None);
+        assert_eq!(richer("Title", None), None);
+    }
+
+    #[test]
+    fn test_preferred() {
+        assert_eq!(preferred("Title", Some("Other Song")), Some("Other Song"));
+        assert_eq!(preferred(" Title ", Some("Title")), None);
+        assert_eq!(preferred("Title", None), None);
+    }
+
+    #[test]
+    fn test_parse_year() {
+        assert_eq!(parse_year("2022"), Some(2022));
+        assert_eq!(parse_year("1999-05-01T12:00:00"), Some(1999));
+        assert_eq!(parse_year("May"), None);
+    }
+
+    #[test]
+    fn test_detected_extension() {
+        assert_eq!(
+            detected_extension(include_bytes!("../fixtures/tagged.mp3")),
+            Some("mp3")
+        );
+        assert_eq!(detected_extension(b"\xFF\xF1\x50\x80"), Some("aac"));
+        assert_eq!(detected_extension(b"\x47\x40\x00\x10"), Some("ts"));
+        assert_eq!(detected_extension(b"\0\0\0\x18ftypM4A "), Some("m4a"));
+        assert_eq!(detected_extension(b"not audio"), None);
+
+        // Packed audio starts with an ID3 tag of its timestamp.
+        let mut packed = b"ID3\x04\0\0\0\0\0\x03PTS".to_vec();
+        packed.extend_from_slice(b"\xFF\xF1\x50\x80");
+        assert_eq!(detected_extension(&packed), Some("aac"));
+        assert_eq!(detected_extension(b"ID3\x04\0\0\0\0\0\x7F"), None);
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use hls_m3u8::tags::VariantStream;
+use hls_m3u8::MasterPlaylist;
+use reqwest::Url;
+
+/// Which variants of a master playlist to capture, see `--variant`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum VariantChoice {
+    Highest,
+    Lowest,
+    /// Every variant at once, each polled and filtered on its own.
+    All,
+    /// The variant of this `BANDWIDTH`.
+    Bandwidth(u64),
+}
+
+impl FromStr for VariantChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value.trim() {
+            "highest" => VariantChoice::Highest,
+            "lowest" => VariantChoice::Lowest,
+            "all" => VariantChoice::All,
+            bandwidth => match bandwidth.parse() {
+                Ok(bandwidth) => VariantChoice::Bandwidth(bandwidth),
+                Err(_) => bail!("Expected highest, lowest, all or a bandwidth, got `{value}`"),
+            },
+        })
+    }
+}
+
+/// A media playlist listed by a master playlist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub url: Url,
+    /// Peak bits per second.
+    pub bandwidth: u64,
+    /// `WIDTHxHEIGHT`, audio-only variants have none.
+    pub resolution: Option<String>,
+}
+
+impl Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bps", self.bandwidth)?;
+        if let Some(resolution) = &self.resolution {
+            write!(f, " {resolution}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Tells a master playlist, which lists variants rather than segments.
+pub fn is_master(content: &str) -> bool {
+    content.contains("#EXT-X-STREAM-INF")
+}
+
+/// Variants of the master playlist `content`, URIs resolved against `master_url`.
+/// I-frame variants carry no audio and are left out.
+pub fn variants(content: &str, master_url: &Url) -> anyhow::Result<Vec<Variant>> {
+    let master = MasterPlaylist::try_from(content).context("Parse master playlist")?;
+    master
+        .variant_streams
+        .iter()
+        .filter_map(|stream| match stream {
+            VariantStream::ExtXStreamInf {
+                uri, stream_data, ..
+            } => Some((uri, stream_data)),
+            VariantStream::ExtXIFrame { .. } => None,
+        })
+        .map(|(uri, stream_data)| {
+            Ok(Variant {
+                url: master_url
+                    .join(uri)
+                    .with_context(|| format!("Invalid variant URI `{uri}`"))?,
+                bandwidth: stream_data.bandwidth(),
+                resolution: stream_data
+                    .resolution()
+                    .map(|resolution| resolution.to_string()),
+            })
+        })
+        .collect()
+}
+
+/// The variants to capture by `choice`, by ascending bandwidth. None if no variant has
+/// the bandwidth asked for.
+pub fn select(mut variants: Vec<Variant>, choice: VariantChoice) -> Vec<Variant> {
+    variants.sort_by_key(|variant| variant.bandwidth);
+    match choice {
+        VariantChoice::Highest => variants.pop().into_iter().collect(),
+        VariantChoice::Lowest => variants.into_iter().take(1).collect(),
+        VariantChoice::All => variants,
+        VariantChoice::Bandwidth(bandwidth) => variants
+            .into_iter()
+            .filter(|variant| variant.bandwidth == bandwidth)
+            .take(1)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use super::{is_master, select, variants, VariantChoice};
+
+    const MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=128000,CODECS=\"mp4a.40.2\"
+128/playlist.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=640000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\"
+https://cdn.example.com/640/playlist.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.5\"
+/live/64/playlist.m3u8
+";
+
+    #[test]
+    fn test() {
+        assert!(is_master(MASTER));
+        assert!(!is_master("#EXTM3U\n#EXTINF:10,\nsegment.aac\n"));
+
+        let master_url: Url = "https://radio.example.com/live/master.m3u8"
+            .parse()
+            .unwrap();
+        let variants = variants(MASTER, &master_url).unwrap();
+        assert_eq!(variants.len(), 3);
+        assert_eq!(
+            variants[0].url.as_str(),
+            "https://radio.example.com/live/128/playlist.m3u8"
+        );
+        assert_eq!(variants[0].bandwidth, 128_000);
+        assert_eq!(variants[0].resolution, None);
+        assert_eq!(variants[1].to_string(), "640000 bps 640x360");
+        assert_eq!(
+            variants[2].url.as_str(),
+            "https://radio.example.com/live/64/playlist.m3u8"
+        );
+
+        let bandwidths = |choice| {
+            select(variants.clone(), choice)
+                .into_iter()
+                .map(|variant| variant.bandwidth)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(bandwidths(VariantChoice::Highest), [640_000]);
+        assert_eq!(bandwidths(VariantChoice::Lowest), [64_000]);
+        assert_eq!(bandwidths(VariantChoice::All), [64_000, 128_000, 640_000]);
+        assert_eq!(bandwidths(VariantChoice::Bandwidth(128_000)), [128_000]);
+        assert!(bandwidths(VariantChoice::Bandwidth(1)).is_empty());
+
+        assert_eq!("all".parse::<VariantChoice>().unwrap(), VariantChoice::All);
+        assert_eq!(
+            "96000".parse::<VariantChoice>().unwrap(),
+            VariantChoice::Bandwidth(96_000)
+        );
+        assert!("best".parse::<VariantChoice>().is_err());
+    }
+}
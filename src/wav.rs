@@ -0,0 +1,41 @@
+/// 16-bit PCM WAV of interleaved `samples`.
+pub fn pcm16(sample_rate: u32, channels: u16, samples: &[i16]) -> Vec<u8> {
+    let block_align = channels * 2;
+    let data_len = (samples.len() * 2) as u32;
+
+    let mut wav = Vec::with_capacity(44 + samples.len() * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pcm16;
+
+    #[test]
+    fn test() {
+        let wav = pcm16(8000, 2, &[0, 1, -1, i16::MAX]);
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], 44u32.to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[22..24], 2u16.to_le_bytes());
+        assert_eq!(wav[28..32], 32000u32.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[44..], [0, 0, 1, 0, 0xff, 0xff, 0xff, 0x7f]);
+    }
+}